@@ -1,18 +1,22 @@
-use std::{convert::TryFrom, mem};
+use std::{collections::VecDeque, convert::TryFrom, mem, sync::Arc};
 
-use dbg::{McbOp, TraceEvent};
+use dbg::{Access, McbOp, TraceEvent};
 
 use crate::{
     dbg,
-    io::{Apu, InterruptSource, IrqController, Joypad, Ppu, Serial, Timer},
+    io::{Apu, InterruptSource, IrqController, Joypad, Ppu, Serial, Sgb, SgbCommand, Timer},
     mem::{MemR, MemRW, MemW, Memory},
 };
 
+use dbg::MemoryType;
+
 // Specifies which Memory Bank Controller (if any) is used in the cartridge.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MbcType {
     None,
     Mbc1,
+    // MBC1 wired for multicarts, where the upper bank register selects bits 4-5 of the ROM bank
+    Mbc1M,
     Mbc3,
     Mbc5,
 }
@@ -35,13 +39,40 @@ impl TryFrom<u8> for MbcType {
     }
 }
 
+// Specifies whether the cartridge supports or requires CGB functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbMode {
+    Dmg,
+    CgbCompatible,
+    CgbOnly,
+}
+
+impl From<u8> for CgbMode {
+    fn from(n: u8) -> Self {
+        match n {
+            0x80 => CgbMode::CgbCompatible,
+            0xC0 => CgbMode::CgbOnly,
+            _ => CgbMode::Dmg,
+        }
+    }
+}
+
+impl CgbMode {
+    /// Returns whether the cartridge runs with CGB functions enabled.
+    ///
+    /// Like on a CGB, this is the case for both CGB-only and CGB-compatible cartridges.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, CgbMode::CgbCompatible | CgbMode::CgbOnly)
+    }
+}
+
 // Specifies the ROM size of the cartridge in 16KB banks.
 #[derive(Debug)]
 pub struct RomBanks(usize);
 
 // The error type returned when a parsing a ROM size code fails.
 #[derive(Debug)]
-pub struct RomSizeError(u8);
+pub struct RomSizeError(#[allow(dead_code)] u8);
 
 impl TryFrom<u8> for RomBanks {
     type Error = RomSizeError;
@@ -71,7 +102,7 @@ pub struct RamBanks(usize);
 
 // The error type returned when a parsing a RAM size code fails.
 #[derive(Debug)]
-pub struct RamSizeError(u8);
+pub struct RamSizeError(#[allow(dead_code)] u8);
 
 impl TryFrom<u8> for RamBanks {
     type Error = RamSizeError;
@@ -89,16 +120,173 @@ impl TryFrom<u8> for RamBanks {
     }
 }
 
+/// The error type returned when writing to a cartridge RAM bank fails.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamBankError {
+    #[error("invalid RAM bank {index}, the cartridge has {count}")]
+    InvalidBank { index: usize, count: usize },
+    #[error("{len} bytes do not fit in an 8KB RAM bank")]
+    DataTooLarge { len: usize },
+}
+
+/// Size of the cartridge header, including the entry point at 0x0100.
+pub const CARTRIDGE_HEADER_END: usize = 0x0150;
+
+/// Nintendo logo stored at 0x0104-0x0133, which the boot ROM compares against its own copy.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Returns whether `rom` looks like an MBC1 multicart (MBC1M).
+///
+/// These are 1MB cartridges containing four 256KB games, each one starting with its own header.
+/// Since the header doesn't tell them apart from regular MBC1 carts, they are detected by the
+/// Nintendo logo found at the start of the second game.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    const GAME_SIZE: usize = 0x10 * 0x4000;
+
+    rom.len() == 4 * GAME_SIZE && rom[GAME_SIZE + 0x104..GAME_SIZE + 0x134] == NINTENDO_LOGO
+}
+
+/// Computes the header checksum over 0x0134-0x014C, as verified by the boot ROM.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Metadata stored in the cartridge header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// Game title, in uppercase ASCII
+    pub title: String,
+    /// Publisher code, either the two-character new licensee code or the old one in hex
+    pub licensee: String,
+    /// Raw cartridge type code (0x0147)
+    pub cartridge_type: u8,
+    /// Memory Bank Controller, if supported
+    pub mbc_type: Option<MbcType>,
+    /// Number of 16KB ROM banks, if the size code is valid
+    pub rom_banks: Option<usize>,
+    /// Number of 8KB external RAM banks, if the size code is valid
+    pub ram_banks: Option<usize>,
+    pub cgb_mode: CgbMode,
+    /// Whether the cartridge supports SGB functions
+    pub sgb_support: bool,
+    /// Header checksum stored in the cartridge (0x014D)
+    pub header_checksum: u8,
+    /// Whether the stored checksum matches the header contents
+    pub header_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    /// Parses the header of a ROM image, which must be at least [`CARTRIDGE_HEADER_END`] bytes.
+    pub fn parse(rom: &[u8]) -> CartridgeHeader {
+        let cgb_mode = CgbMode::from(rom[0x143]);
+
+        // On CGB cartridges, the last byte of the title is taken by the CGB flag
+        let title_end = if rom[0x143] & 0x80 != 0 { 0x143 } else { 0x144 };
+        let title = rom[0x134..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { ' ' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        // An old licensee code of 0x33 means that the new code is used instead
+        let licensee = if rom[0x14B] == 0x33 {
+            String::from_utf8_lossy(&rom[0x144..=0x145]).into_owned()
+        } else {
+            format!("{:02X}", rom[0x14B])
+        };
+
+        CartridgeHeader {
+            title,
+            licensee,
+            cartridge_type: rom[0x147],
+            mbc_type: MbcType::try_from(rom[0x147]).ok(),
+            rom_banks: RomBanks::try_from(rom[0x148]).ok().map(|RomBanks(n)| n),
+            ram_banks: RamBanks::try_from(rom[0x149]).ok().map(|RamBanks(n)| n),
+            cgb_mode,
+            sgb_support: rom[0x146] == 0x03,
+            header_checksum: rom[0x14D],
+            header_checksum_valid: header_checksum(rom) == rom[0x14D],
+        }
+    }
+}
+
+/// Outcome of the checks performed by the boot ROM on the cartridge header.
+///
+/// A real Game Boy locks up if any of them fail, so a failure usually means a corrupt ROM.
+/// The emulator runs the cartridge regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeValidation {
+    /// Whether the header checksum (0x014D) matches the header contents
+    pub header_checksum_valid: bool,
+    /// Whether the Nintendo logo (0x0104-0x0133) is intact
+    pub logo_valid: bool,
+}
+
+impl CartridgeValidation {
+    /// Validates the header of a ROM image, which must be at least [`CARTRIDGE_HEADER_END`] bytes.
+    pub fn validate(rom: &[u8]) -> CartridgeValidation {
+        CartridgeValidation {
+            header_checksum_valid: header_checksum(rom) == rom[0x14D],
+            logo_valid: rom[0x104..0x134] == NINTENDO_LOGO,
+        }
+    }
+
+    /// Returns whether all the checks passed.
+    pub fn is_valid(&self) -> bool {
+        self.header_checksum_valid && self.logo_valid
+    }
+}
+
+/// Number of entries kept in the IO write log.
+pub const IO_WRITE_LOG_LEN: usize = 1024;
+
+/// A write to an IO register (0xFF00-0xFF7F), as recorded in the IO write log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoWrite {
+    /// Clock cycle at which the write happened.
+    pub cycle: u64,
+    pub addr: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+struct IoWriteLog {
+    // Clock cycle of the current access, kept up to date by the caller
+    cycle: u64,
+    writes: VecDeque<IoWrite>,
+}
+
+#[derive(Clone)]
 pub struct Bus {
-    rom_banks: Vec<Memory>,
+    // ROM contents are shared between clones, since they never change after loading
+    rom_banks: Arc<Vec<Memory>>,
     pub rom_nn: usize,
+    // ROM bank mapped to 0x0000-0x3FFF, which only MBC1M can change
+    rom0_nn: usize,
+    // MBC1M bank registers and banking mode
+    rom_bank_lo: u8,
+    rom_bank_hi: u8,
+    banking_mode: bool,
 
     ram_banks: Vec<Memory>,
     pub ram_nn: usize,
+    // Number of RAM banks actually present in the cartridge
+    ram_bank_count: usize,
 
     pub hram: Memory,
-    pub wram_00: Memory,
-    pub wram_nn: Memory,
+
+    // In CGB mode, WRAM bank 1 to 7 can be mapped to 0xD000-0xDFFF through SVBK
+    wram_banks: Vec<Memory>,
+    wram_nn: usize,
+    svbk_reg: u8,
 
     pub apu: Apu,
     pub ppu: Ppu,
@@ -107,21 +295,47 @@ pub struct Bus {
     pub joy: Joypad,
     pub itr: IrqController,
 
+    // SGB functions, if supported by the cartridge
+    sgb: Option<Sgb>,
+
     mbc: MbcType,
+    cgb_mode: CgbMode,
+
+    // CGB double speed mode (KEY1)
+    double_speed: bool,
+    speed_switch_armed: bool,
+    odd_cycle: bool,
+
+    // Whether out-of-spec memory accesses raise a fault
+    strict_memory: bool,
+
+    // Boot ROM, mapped over the cartridge ROM until it is disabled through 0xFF50
+    boot_rom: Option<Arc<Vec<u8>>>,
+    boot_rom_mapped: bool,
+
+    // Log of IO register writes, if enabled
+    io_write_log: Option<IoWriteLog>,
 }
 
 impl Default for Bus {
     fn default() -> Bus {
         Bus {
-            rom_banks: vec![Memory::new(0x4000); 512],
+            rom_banks: Arc::new(vec![Memory::new(0x4000); 512]),
             rom_nn: 1,
+            rom0_nn: 0,
+            rom_bank_lo: 0,
+            rom_bank_hi: 0,
+            banking_mode: false,
 
             ram_banks: vec![Memory::new(0x2000); 16],
             ram_nn: 0,
+            ram_bank_count: 0,
 
             hram: Memory::new(127),
-            wram_00: Memory::new(0x1000),
-            wram_nn: Memory::new(0x1000),
+
+            wram_banks: vec![Memory::new(0x1000); 8],
+            wram_nn: 1,
+            svbk_reg: 0x00,
 
             apu: Apu::default(),
             ppu: Ppu::new(),
@@ -130,7 +344,21 @@ impl Default for Bus {
             joy: Joypad::new(),
             itr: IrqController::new(),
 
+            sgb: None,
+
             mbc: MbcType::None,
+            cgb_mode: CgbMode::Dmg,
+
+            double_speed: false,
+            speed_switch_armed: false,
+            odd_cycle: false,
+
+            strict_memory: false,
+
+            boot_rom: None,
+            boot_rom_mapped: false,
+
+            io_write_log: None,
         }
     }
 }
@@ -145,9 +373,22 @@ impl Bus {
     /// This includes resetting all the connected peripherals and clearning RAM contents.
     /// The contents of the whole ROM are preserved.
     pub fn reset(&mut self) {
-        // Preserve ROM contents and MBC
+        // Preserve ROM contents, MBC, RAM size and CGB mode
         let rom_banks = mem::take(&mut self.rom_banks);
+        let ram_bank_count = self.ram_bank_count;
+        let sgb = self.sgb.as_ref().map(|_| Sgb::new());
         let mbc = self.mbc;
+        let cgb_mode = self.cgb_mode;
+        let strict_memory = self.strict_memory;
+        let filter_opposing = self.joy.filter_opposing();
+        let initial_counter = self.tim.initial_counter();
+        let boot_rom = self.boot_rom.take();
+        let io_write_log = self.io_write_log.as_ref().map(|_| IoWriteLog::default());
+
+        // Preserve rendering options
+        let color_correction = self.ppu.color_correction();
+        let palette = self.ppu.palette();
+        let frame_blend = self.ppu.frame_blend();
 
         // Reset the APU to keep sample rate and audio channel intact, the rest can be recreated
         let mut apu = mem::take(&mut self.apu);
@@ -155,34 +396,328 @@ impl Bus {
 
         *self = Self {
             rom_banks,
+            ram_bank_count,
+            sgb,
             mbc,
+            cgb_mode,
+            strict_memory,
             apu,
+            boot_rom_mapped: boot_rom.is_some(),
+            boot_rom,
+            io_write_log,
             ..Default::default()
         };
+
+        self.ppu.set_cgb_mode(cgb_mode.is_cgb());
+        self.ppu.set_color_correction(color_correction);
+        self.ppu.set_palette(palette);
+        self.ppu.set_frame_blend(frame_blend);
+        self.joy.set_filter_opposing(filter_opposing);
+        self.tim.set_initial_counter(initial_counter);
+
+        if self.boot_rom_mapped {
+            self.power_on_io();
+        }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), TraceEvent> {
-        // Filter out ROMs using unsupported emulator features (eg. CGB-only mode)
-        if rom[0x143] == 0xC0 {
-            return Err(TraceEvent::CgbNotSupported);
+    /// Enables or disables strict memory accesses. See [`GameBoy::set_strict_memory`].
+    ///
+    /// [`GameBoy::set_strict_memory`]: crate::GameBoy::set_strict_memory
+    pub fn set_strict_memory(&mut self, strict: bool) {
+        self.strict_memory = strict;
+    }
+
+    pub fn strict_memory(&self) -> bool {
+        self.strict_memory
+    }
+
+    /// Sets the boot ROM and maps it over the cartridge ROM. See [`GameBoy::with_boot_rom`].
+    ///
+    /// [`GameBoy::with_boot_rom`]: crate::GameBoy::with_boot_rom
+    pub fn set_boot_rom(&mut self, boot_rom: &[u8]) {
+        self.boot_rom = Some(Arc::new(boot_rom.to_vec()));
+        self.boot_rom_mapped = true;
+        self.power_on_io();
+    }
+
+    /// Puts the IO registers initialized by the boot ROM back to their power-on values,
+    /// since the default ones are those left by the boot ROM when it is skipped.
+    fn power_on_io(&mut self) {
+        self.tim.power_on();
+        self.apu.power_on();
+        self.ppu.power_on();
+    }
+
+    /// Returns whether the boot ROM is currently mapped over the cartridge ROM.
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    /// Returns the boot ROM byte at `addr`, if the boot ROM is mapped there.
+    ///
+    /// The DMG boot ROM covers 0x0000-0x00FF, while the CGB one also covers 0x0200-0x08FF,
+    /// leaving the cartridge header visible in between.
+    fn read_boot_rom(&self, addr: u16) -> Option<u8> {
+        match (&self.boot_rom, addr) {
+            (Some(boot_rom), 0x0000..=0x00FF | 0x0200..=0x08FF) if self.boot_rom_mapped => {
+                boot_rom.get(usize::from(addr)).copied()
+            }
+            _ => None,
+        }
+    }
+
+    fn write_boot_rom_disable(&mut self, val: u8) -> Result<(), TraceEvent> {
+        // Once disabled, the boot ROM can only be mapped again by a reset
+        if val != 0 {
+            self.boot_rom_mapped = false;
+        }
+        Ok(())
+    }
+
+    /// Enables or disables the IO write log. Disabling it discards the recorded writes.
+    pub fn set_io_write_logging(&mut self, enable: bool) {
+        if enable != self.io_write_log.is_some() {
+            self.io_write_log = enable.then(IoWriteLog::default);
+        }
+    }
+
+    pub fn io_write_logging(&self) -> bool {
+        self.io_write_log.is_some()
+    }
+
+    /// Sets the clock cycle recorded for the following IO writes, if the log is enabled.
+    pub(crate) fn set_io_write_cycle(&mut self, cycle: u64) {
+        if let Some(ref mut log) = self.io_write_log {
+            log.cycle = cycle;
         }
+    }
+
+    /// Returns the most recent IO register writes, oldest first.
+    ///
+    /// At most [`IO_WRITE_LOG_LEN`] entries are kept. The log is empty if not enabled.
+    pub fn io_write_log(&self) -> impl Iterator<Item = &IoWrite> {
+        self.io_write_log.iter().flat_map(|log| log.writes.iter())
+    }
+
+    fn log_io_write(&mut self, addr: u16, value: u8) {
+        if let Some(ref mut log) = self.io_write_log {
+            if log.writes.len() == IO_WRITE_LOG_LEN {
+                log.writes.pop_front();
+            }
+            log.writes.push_back(IoWrite {
+                cycle: log.cycle,
+                addr,
+                value,
+            });
+        }
+    }
+
+    /// Same as [`Bus::reset`], but also preserves the contents of the cartridge RAM.
+    pub fn reset_preserving_ram(&mut self) {
+        let ram_banks = mem::take(&mut self.ram_banks);
+        self.reset();
+        self.ram_banks = ram_banks;
+    }
+
+    /// Restores the bus and peripherals state from `other`.
+    ///
+    /// The audio channel and sample rate of the APU are preserved.
+    pub(crate) fn restore(&mut self, other: &Bus) {
+        let mut apu = mem::take(&mut self.apu);
+        apu.restore(&other.apu);
+
+        let filter_opposing = self.joy.filter_opposing();
+        let io_write_log = self.io_write_log.take();
+
+        *self = Self {
+            apu,
+            strict_memory: self.strict_memory,
+            io_write_log,
+            ..other.clone()
+        };
+
+        self.joy.set_filter_opposing(filter_opposing);
+    }
+
+    /// Returns the size of the memory regions and buffers owned by the bus, in bytes.
+    ///
+    /// The cartridge ROM is not included, since it is shared between clones.
+    pub(crate) fn heap_size(&self) -> usize {
+        let memories = self.ram_banks.iter().chain(&self.wram_banks);
+        let ram = memories.map(|m| m.data().len()).sum::<usize>() + self.hram.data().len();
+
+        ram + self.ppu.heap_size() + self.apu.heap_size()
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), TraceEvent> {
+        self.cgb_mode = CgbMode::from(rom[0x143]);
+        self.ppu.set_cgb_mode(self.cgb_mode.is_cgb());
+
+        tracing::debug!("Cartridge CGB mode: {:?}", self.cgb_mode);
 
         // Check MBC type in the ROM header
         self.mbc = MbcType::try_from(rom[0x147])
             .map_err(|McbTypeError(n)| TraceEvent::UnsupportedMbcType(n))?;
 
+        if self.mbc == MbcType::Mbc1 && is_mbc1_multicart(rom) {
+            self.mbc = MbcType::Mbc1M;
+        }
+
         tracing::debug!("Cartridge MBC type: {:?}", self.mbc);
 
+        self.ram_bank_count = RamBanks::try_from(rom[0x149]).map_or(0, |RamBanks(n)| n);
+
+        // Like the SGB BIOS, only enable SGB functions if the header says so
+        let sgb = rom[0x146] == 0x03 && rom[0x14B] == 0x33 && !self.cgb_mode.is_cgb();
+        self.sgb = sgb.then(Sgb::new);
+        self.ppu.set_sgb_palette(None);
+
+        tracing::debug!("Cartridge SGB support: {}", sgb);
+
         // Load ROM into its allocated banks
+        let rom_banks = Arc::make_mut(&mut self.rom_banks);
         for (n, chunk) in rom.chunks(0x4000).enumerate() {
             for (i, b) in chunk.iter().enumerate() {
-                self.rom_banks[n].write(i as u16, *b)?;
+                rom_banks[n].write(i as u16, *b)?;
             }
         }
 
         Ok(())
     }
 
+    /// Returns the CGB mode of the loaded cartridge.
+    pub fn cgb_mode(&self) -> CgbMode {
+        self.cgb_mode
+    }
+
+    /// Returns the header of the loaded cartridge.
+    pub fn cartridge_header(&self) -> CartridgeHeader {
+        CartridgeHeader::parse(&self.header_bytes())
+    }
+
+    /// Performs the same checks as the boot ROM on the header of the loaded cartridge.
+    pub fn validate_cartridge(&self) -> CartridgeValidation {
+        CartridgeValidation::validate(&self.header_bytes())
+    }
+
+    /// Returns the first [`CARTRIDGE_HEADER_END`] bytes of the cartridge ROM.
+    fn header_bytes(&self) -> Vec<u8> {
+        (0..CARTRIDGE_HEADER_END as u16)
+            .map(|addr| self.rom_banks[0].read(addr).unwrap_or(0xFF))
+            .collect()
+    }
+
+    /// Returns the SGB functions, if enabled by the loaded cartridge.
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.sgb.as_ref()
+    }
+
+    /// Returns the Memory Bank Controller of the loaded cartridge.
+    pub fn mbc_type(&self) -> MbcType {
+        self.mbc
+    }
+
+    /// Returns the memory region containing `addr`, along with the bank mapped to it.
+    pub fn memory_type_at(&self, addr: u16) -> MemoryType {
+        match MemoryType::at(addr) {
+            MemoryType::RomBank(0) => MemoryType::RomBank(self.rom0_nn as u16),
+            MemoryType::RomBank(_) => MemoryType::RomBank(self.rom_nn as u16),
+            MemoryType::WorkRamBank(n) if n > 0 => MemoryType::WorkRamBank(self.wram_nn as u8),
+            MemoryType::EchoRam(n) if n > 0 => MemoryType::EchoRam(self.wram_nn as u8),
+            mt => mt,
+        }
+    }
+
+    /// Returns the ROM bank currently mapped to 0x4000-0x7FFF.
+    pub fn rom_bank(&self) -> usize {
+        self.rom_nn
+    }
+
+    /// Returns the external RAM bank currently mapped to 0xA000-0xBFFF.
+    pub fn ram_bank(&self) -> usize {
+        self.ram_nn
+    }
+
+    /// Returns the number of 8KB RAM banks in the cartridge.
+    pub fn ram_bank_count(&self) -> usize {
+        self.ram_bank_count
+    }
+
+    /// Returns the contents of the cartridge RAM bank `index`, or `None` if there is no such bank.
+    pub fn ram_bank_data(&self, index: usize) -> Option<&[u8]> {
+        self.ram_banks[..self.ram_bank_count]
+            .get(index)
+            .map(Memory::data)
+    }
+
+    /// Returns the contents of the cartridge RAM bank `index` mutably, or `None` if there is
+    /// no such bank.
+    pub fn ram_bank_data_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        self.ram_banks[..self.ram_bank_count]
+            .get_mut(index)
+            .map(Memory::data_mut)
+    }
+
+    /// Returns the WRAM bank currently mapped to 0xD000-0xDFFF.
+    pub fn wram_bank(&self) -> usize {
+        self.wram_nn
+    }
+
+    /// Returns WRAM bank `index`, or `None` if there is no such bank.
+    ///
+    /// Bank 0 is always mapped to 0xC000-0xCFFF, while one of banks 1 to 7 is mapped
+    /// to 0xD000-0xDFFF (see [`Bus::wram_bank`]). Only bank 1 is used in DMG mode.
+    pub fn wram(&self, index: usize) -> Option<&Memory> {
+        self.wram_banks.get(index)
+    }
+
+    /// Returns WRAM bank `index` mutably, or `None` if there is no such bank.
+    pub fn wram_mut(&mut self, index: usize) -> Option<&mut Memory> {
+        self.wram_banks.get_mut(index)
+    }
+
+    /// Returns WRAM bank 0, formerly the public `wram_00` field.
+    #[deprecated(note = "use `Bus::wram(0)` instead")]
+    pub fn wram_00(&self) -> &Memory {
+        &self.wram_banks[0]
+    }
+
+    /// Returns WRAM bank 0 mutably, formerly the public `wram_00` field.
+    #[deprecated(note = "use `Bus::wram_mut(0)` instead")]
+    pub fn wram_00_mut(&mut self) -> &mut Memory {
+        &mut self.wram_banks[0]
+    }
+
+    /// Returns the WRAM bank mapped to 0xD000-0xDFFF, formerly the public `wram_nn` field.
+    #[deprecated(note = "use `Bus::wram(bus.wram_bank())` instead")]
+    pub fn wram_nn(&self) -> &Memory {
+        &self.wram_banks[self.wram_nn]
+    }
+
+    /// Returns the WRAM bank mapped to 0xD000-0xDFFF mutably,
+    /// formerly the public `wram_nn` field.
+    #[deprecated(note = "use `Bus::wram_mut(bus.wram_bank())` instead")]
+    pub fn wram_nn_mut(&mut self) -> &mut Memory {
+        &mut self.wram_banks[self.wram_nn]
+    }
+
+    /// Returns whether the CPU is running in CGB double speed mode.
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Returns whether a speed switch has been requested through KEY1,
+    /// and will be performed by the next STOP instruction.
+    pub fn speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    /// Toggles between normal and double speed mode, disarming the speed switch.
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+    }
+
     /// Advances the system peripheral/memory bus by a single M-cycle.
     pub fn tick(&mut self) -> Result<(), TraceEvent> {
         if let Some((src, dst)) = self.ppu.advance_dma_xfer() {
@@ -190,10 +725,20 @@ impl Bus {
             self.ppu.write_to_oam(dst, b)?;
         }
 
-        self.ppu.tick();
-        self.apu.tick();
+        // In double speed mode, the PPU and APU keep running at normal speed,
+        // ie. they are only ticked every other M-cycle.
+        self.odd_cycle = !self.odd_cycle;
+
+        if !self.double_speed || self.odd_cycle {
+            self.ppu.tick();
+            self.apu.tick();
+        }
         self.tim.tick();
 
+        // The APU frame sequencer runs off DIV, so that it follows its resets
+        let div_bit = if self.double_speed { 13 } else { 12 };
+        self.apu.tick_div(self.tim.sys_counter.bit(div_bit));
+
         // Fetch interrupt requests from interrupt sources
         if let Some(irq) = self.ppu.get_and_clear_irq() {
             self.itr.set_irq(irq.into());
@@ -207,16 +752,37 @@ impl Bus {
         if let Some(irq) = self.sdt.get_and_clear_irq() {
             self.itr.set_irq(irq.into());
         }
+        if let Some(irq) = self.joy.get_and_clear_irq() {
+            self.itr.set_irq(irq.into());
+        }
 
         Ok(())
     }
 
+    /// Writes the joypad register (P1), which also carries commands to the SGB.
+    fn write_p1(&mut self, val: u8) -> Result<(), TraceEvent> {
+        if let Some(sgb) = self.sgb.as_mut() {
+            if let Some(SgbCommand::SetPalettes { .. }) = sgb.write_p1(val) {
+                // Without ATTR commands, the whole screen uses palette 0
+                self.ppu.set_sgb_palette(Some(sgb.shades(0)));
+            }
+        }
+
+        self.joy.write(0xFF00, val)
+    }
+
     fn ram_enable(&mut self, _val: u8) -> Result<(), TraceEvent> {
         // TODO handle this just in case some ROMs rely on uncorrect behavior
         Ok(())
     }
 
     fn rom_select(&mut self, val: u8) -> Result<(), TraceEvent> {
+        if self.mbc == MbcType::Mbc1M {
+            self.rom_bank_lo = val & 0x1F;
+            self.update_mbc1m_banks();
+            return Ok(());
+        }
+
         self.rom_nn = if val == 0 {
             1
         } else {
@@ -228,6 +794,12 @@ impl Bus {
     }
 
     fn ram_rom_select(&mut self, addr: u16, val: u8) -> Result<(), TraceEvent> {
+        if self.mbc == MbcType::Mbc1M {
+            self.rom_bank_hi = val & 0x03;
+            self.update_mbc1m_banks();
+            return Ok(());
+        }
+
         match val {
             0x00..=0x03 => self.ram_nn = val.into(),
             _ => return Err(TraceEvent::InvalidMbcOp(McbOp::Write(addr), val)),
@@ -237,6 +809,11 @@ impl Bus {
 
     fn mbc_write_op(&mut self, addr: u16, val: u8) -> Result<(), TraceEvent> {
         match self.mbc {
+            MbcType::Mbc1M => {
+                self.banking_mode = val & 0x01 != 0;
+                self.update_mbc1m_banks();
+                Ok(())
+            }
             MbcType::Mbc3 => {
                 // TODO latch RTC register value
                 Ok(())
@@ -245,8 +822,54 @@ impl Bus {
         }
     }
 
-    fn write_to_cgb_functions(&mut self, addr: u16, _val: u8) -> Result<(), TraceEvent> {
+    /// Maps the ROM banks selected by the MBC1M registers.
+    fn update_mbc1m_banks(&mut self) {
+        // The upper register drives bits 4-5 of the bank number, and only 4 bits of the lower
+        // register are connected. Bank 0 is still translated to 1 using all of its 5 bits.
+        let hi = usize::from(self.rom_bank_hi) << 4;
+        let lo = usize::from(self.rom_bank_lo.max(1) & 0x0F);
+
+        self.rom_nn = hi | lo;
+
+        // In advanced banking mode, the upper register also applies to 0x0000-0x3FFF
+        self.rom0_nn = if self.banking_mode { hi } else { 0 };
+    }
+
+    /// Reads the WRAM bank register (SVBK). Only bits 0-2 are used.
+    fn read_svbk(&self) -> u8 {
+        if self.cgb_mode.is_cgb() {
+            self.svbk_reg | 0xF8
+        } else {
+            0xFF
+        }
+    }
+
+    /// Writes the WRAM bank register (SVBK), selecting the WRAM bank mapped to 0xD000-0xDFFF.
+    fn write_svbk(&mut self, val: u8) -> Result<(), TraceEvent> {
+        if self.cgb_mode.is_cgb() {
+            self.svbk_reg = val & 0x07;
+
+            // Writing 0 selects bank 1
+            self.wram_nn = usize::from(self.svbk_reg).max(1);
+        }
+        Ok(())
+    }
+
+    /// Reads the speed switch register (KEY1).
+    fn read_key1(&self) -> u8 {
+        if self.cgb_mode.is_cgb() {
+            (u8::from(self.double_speed) << 7) | 0x7E | u8::from(self.speed_switch_armed)
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_to_cgb_functions(&mut self, addr: u16, val: u8) -> Result<(), TraceEvent> {
         match addr {
+            0xFF4D if self.cgb_mode.is_cgb() => {
+                self.speed_switch_armed = val & 0x01 != 0;
+                Ok(())
+            }
             0xFF4D => Err(TraceEvent::CgbSpeedSwitchReq),
             _ => Ok(()),
         }
@@ -255,23 +878,42 @@ impl Bus {
 
 impl MemR for Bus {
     fn read(&self, addr: u16) -> Result<u8, TraceEvent> {
+        if let Some(val) = self.read_boot_rom(addr) {
+            return Ok(val);
+        }
+
         match addr {
-            0x0000..=0x3FFF => self.rom_banks[0].read(addr),
+            0x0000..=0x3FFF => self.rom_banks[self.rom0_nn].read(addr),
             0x4000..=0x7FFF => self.rom_banks[self.rom_nn].read(addr - 0x4000),
             0x8000..=0x9FFF => self.ppu.read(addr),
             0xA000..=0xBFFF => self.ram_banks[self.ram_nn].read(addr - 0xA000),
-            0xC000..=0xCFFF => self.wram_00.read(addr - 0xC000),
-            0xD000..=0xDFFF => self.wram_nn.read(addr - 0xD000),
-            0xE000..=0xEFFF => self.wram_00.read(addr - 0xE000),
-            0xF000..=0xFDFF => self.wram_nn.read(addr - 0xF000),
+            0xC000..=0xCFFF => self.wram_banks[0].read(addr - 0xC000),
+            0xD000..=0xDFFF => self.wram_banks[self.wram_nn].read(addr - 0xD000),
+            0xE000..=0xEFFF => self.wram_banks[0].read(addr - 0xE000),
+            0xF000..=0xFDFF => self.wram_banks[self.wram_nn].read(addr - 0xF000),
             0xFE00..=0xFE9F => self.ppu.read(addr),
+            0xFEA0..=0xFEFF if self.strict_memory => Err(TraceEvent::UnmappedAccess {
+                access: Access::Read,
+                addr,
+                pc: 0,
+            }),
+            0xFEA0..=0xFEFF => Ok(0x00),
             0xFF00..=0xFF00 => self.joy.read(addr),
             0xFF01..=0xFF02 => self.sdt.read(addr),
             0xFF04..=0xFF07 => self.tim.read(addr),
             0xFF10..=0xFF3F => self.apu.read(addr),
             0xFF40..=0xFF4B => self.ppu.read(addr),
+            0xFF4D => Ok(self.read_key1()),
+            0xFF4F => self.ppu.read(addr),
+            0xFF68..=0xFF6B => self.ppu.read(addr),
+            0xFF70 => Ok(self.read_svbk()),
             0xFF80..=0xFFFE => self.hram.read(addr - 0xFF80),
             0xFF0F | 0xFFFF => self.itr.read(addr),
+            _ if self.strict_memory => Err(TraceEvent::UnmappedAccess {
+                access: Access::Read,
+                addr,
+                pc: 0,
+            }),
             _ => Ok(0xFF),
         }
     }
@@ -279,6 +921,10 @@ impl MemR for Bus {
 
 impl MemW for Bus {
     fn write(&mut self, addr: u16, val: u8) -> Result<(), TraceEvent> {
+        if let 0xFF00..=0xFF7F = addr {
+            self.log_io_write(addr, val);
+        }
+
         match addr {
             0x0000..=0x1FFF => self.ram_enable(val),
             0x2000..=0x3FFF => self.rom_select(val),
@@ -289,23 +935,340 @@ impl MemW for Bus {
                 .ram_banks
                 .get_mut(self.ram_nn)
                 .map_or(Ok(()), |bank| bank.write(addr - 0xA000, val)),
-            0xC000..=0xCFFF => self.wram_00.write(addr - 0xC000, val),
-            0xD000..=0xDFFF => self.wram_nn.write(addr - 0xD000, val),
-            0xE000..=0xEFFF => self.wram_00.write(addr - 0xE000, val),
-            0xF000..=0xFDFF => self.wram_nn.write(addr - 0xF000, val),
+            0xC000..=0xCFFF => self.wram_banks[0].write(addr - 0xC000, val),
+            0xD000..=0xDFFF => self.wram_banks[self.wram_nn].write(addr - 0xD000, val),
+            0xE000..=0xEFFF => self.wram_banks[0].write(addr - 0xE000, val),
+            0xF000..=0xFDFF => self.wram_banks[self.wram_nn].write(addr - 0xF000, val),
             0xFE00..=0xFE9F => self.ppu.write(addr, val),
-            0xFF00..=0xFF00 => self.joy.write(addr, val),
+            0xFEA0..=0xFEFF if self.strict_memory => Err(TraceEvent::UnmappedAccess {
+                access: Access::Write,
+                addr,
+                pc: 0,
+            }),
+            0xFF00..=0xFF00 => self.write_p1(val),
             0xFF01..=0xFF02 => self.sdt.write(addr, val),
             0xFF04..=0xFF07 => self.tim.write(addr, val),
             0xFF10..=0xFF3F => self.apu.write(addr, val),
             0xFF40..=0xFF4B => self.ppu.write(addr, val),
+            0xFF4F => self.ppu.write(addr, val),
             0xFF4C..=0xFF4F => self.write_to_cgb_functions(addr, val),
+            0xFF68..=0xFF6B => self.ppu.write(addr, val),
+            0xFF70 => self.write_svbk(val),
+            0xFF50 => self.write_boot_rom_disable(val),
             0xFF51..=0xFF7F => self.write_to_cgb_functions(addr, val),
             0xFF80..=0xFFFE => self.hram.write(addr - 0xFF80, val),
             0xFF0F | 0xFFFF => self.itr.write(addr, val),
+            _ if self.strict_memory => Err(TraceEvent::UnmappedAccess {
+                access: Access::Write,
+                addr,
+                pc: 0,
+            }),
             _ => Ok(()),
         }
     }
 }
 
 impl MemRW for Bus {}
+
+/// The memory bus as seen by the CPU.
+///
+/// While an OAM DMA transfer is in progress, the bus it reads from (either the external bus or
+/// the video bus) is not accessible by the CPU: reads return 0xFF and writes are ignored.
+/// OAM is not accessible either, while IO registers and HRAM always are.
+pub(crate) struct CpuBus<'a>(pub(crate) &'a mut Bus);
+
+impl CpuBus<'_> {
+    fn dma_conflict(&self, addr: u16) -> bool {
+        let video_bus = |addr| (0x8000..=0x9FFF).contains(&addr);
+
+        match self.0.ppu.dma_source() {
+            Some(src) => addr < 0xFE00 && video_bus(addr) == video_bus(src),
+            None => false,
+        }
+    }
+}
+
+impl MemR for CpuBus<'_> {
+    fn read(&self, addr: u16) -> Result<u8, TraceEvent> {
+        if self.dma_conflict(addr) {
+            Ok(0xFF)
+        } else {
+            self.0.read(addr)
+        }
+    }
+}
+
+impl MemW for CpuBus<'_> {
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), TraceEvent> {
+        if self.dma_conflict(addr) {
+            Ok(())
+        } else {
+            self.0.write(addr, val)
+        }
+    }
+}
+
+impl MemRW for CpuBus<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_ram_boundary() {
+        let mut bus = Bus::new();
+
+        // 0xFDFF is the last echo address, aliasing 0xDDFF
+        bus.write(0xDDFF, 0xAB).unwrap();
+        assert_eq!(bus.read(0xFDFF).unwrap(), 0xAB);
+        bus.write(0xFDFF, 0xCD).unwrap();
+        assert_eq!(bus.read(0xDDFF).unwrap(), 0xCD);
+
+        // 0xFE00 is the first OAM byte, and must not alias 0xDE00
+        bus.write(0xDE00, 0x12).unwrap();
+        bus.write(0xFE00, 0x34).unwrap();
+        assert_eq!(bus.read(0xFE00).unwrap(), 0x34);
+        assert_eq!(bus.read(0xDE00).unwrap(), 0x12);
+        assert_eq!(bus.ppu.read(0xFE00).unwrap(), 0x34);
+    }
+
+    #[test]
+    fn not_usable_region() {
+        let mut bus = Bus::new();
+
+        // Reads return 0x00 and writes are ignored
+        bus.write(0xFEA0, 0x12).unwrap();
+        assert_eq!(bus.read(0xFEA0).unwrap(), 0x00);
+        assert_eq!(bus.read(0xFEFF).unwrap(), 0x00);
+
+        // Strict mode faults instead
+        bus.set_strict_memory(true);
+        assert!(matches!(
+            bus.read(0xFEA0),
+            Err(TraceEvent::UnmappedAccess {
+                access: Access::Read,
+                addr: 0xFEA0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            bus.write(0xFEFF, 0x12),
+            Err(TraceEvent::UnmappedAccess {
+                access: Access::Write,
+                addr: 0xFEFF,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn dma_bus_conflicts() {
+        let mut bus = Bus::new();
+        bus.write(0xC000, 0x42).unwrap();
+        bus.write(0xFF80, 0x24).unwrap();
+
+        // Start a DMA transfer from WRAM, which begins after two cycles
+        CpuBus(&mut bus).write(0xFF46, 0xC0).unwrap();
+        bus.tick().unwrap();
+        bus.tick().unwrap();
+        assert!(bus.ppu.dma_source().is_some());
+
+        // The CPU cannot access the external bus, but HRAM, IO registers and VRAM are available
+        let mut cpu_bus = CpuBus(&mut bus);
+        assert_eq!(cpu_bus.read(0xC000).unwrap(), 0xFF);
+        assert_eq!(cpu_bus.read(0x0100).unwrap(), 0xFF);
+        assert_eq!(cpu_bus.read(0xFF80).unwrap(), 0x24);
+        assert_eq!(cpu_bus.read(0x8000).unwrap(), 0x00);
+        assert_eq!(cpu_bus.read(0xFF46).unwrap(), 0xC0);
+        cpu_bus.write(0xC001, 0x11).unwrap();
+
+        // Other bus users are not affected
+        assert_eq!(bus.read(0xC000).unwrap(), 0x42);
+        assert_ne!(bus.read(0xC001).unwrap(), 0x11);
+
+        for _ in 0..160 {
+            bus.tick().unwrap();
+        }
+        assert!(bus.ppu.dma_source().is_none());
+        assert_eq!(CpuBus(&mut bus).read(0xC000).unwrap(), 0x42);
+        assert_eq!(bus.ppu.read(0xFE00).unwrap(), 0x42);
+    }
+
+    #[test]
+    fn cartridge_header() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13B].copy_from_slice(b"TETRIS\0");
+        rom[0x143] = 0x80;
+        rom[0x146] = 0x03;
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        rom[0x149] = 0x02;
+        rom[0x14B] = 0x33;
+        rom[0x144..=0x145].copy_from_slice(b"01");
+        rom[0x14D] = header_checksum(&rom);
+
+        let mut bus = Bus::new();
+        bus.load_rom(&rom).unwrap();
+
+        let header = bus.cartridge_header();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.licensee, "01");
+        assert_eq!(header.mbc_type, Some(MbcType::Mbc1));
+        assert_eq!((header.rom_banks, header.ram_banks), (Some(4), Some(1)));
+        assert_eq!(header.cgb_mode, CgbMode::CgbCompatible);
+        assert!(header.sgb_support);
+        assert!(header.header_checksum_valid);
+
+        rom[0x14D] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom);
+        assert!(!header.header_checksum_valid);
+    }
+
+    #[test]
+    fn cartridge_validation() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x134..0x13A].copy_from_slice(b"TETRIS");
+        rom[0x14D] = header_checksum(&rom);
+
+        let mut bus = Bus::new();
+        bus.load_rom(&rom).unwrap();
+        assert!(bus.validate_cartridge().is_valid());
+
+        // A wrong checksum byte is reported, but the cartridge can still be loaded
+        rom[0x14D] = rom[0x14D].wrapping_add(1);
+        bus.load_rom(&rom).unwrap();
+        assert_eq!(
+            bus.validate_cartridge(),
+            CartridgeValidation {
+                header_checksum_valid: false,
+                logo_valid: true
+            }
+        );
+
+        rom[0x104] = 0x00;
+        let validation = CartridgeValidation::validate(&rom);
+        assert!(!validation.logo_valid);
+        assert!(!validation.is_valid());
+    }
+
+    #[test]
+    fn mapped_banks() {
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x01; // MBC1
+
+        let mut bus = Bus::new();
+        bus.load_rom(&rom).unwrap();
+        assert_eq!((bus.rom_bank(), bus.ram_bank(), bus.wram_bank()), (1, 0, 1));
+
+        bus.write(0x2000, 0x03).unwrap();
+        bus.write(0x4000, 0x02).unwrap();
+        assert_eq!((bus.rom_bank(), bus.ram_bank()), (3, 2));
+
+        // Selecting ROM bank 0 maps bank 1 instead
+        bus.write(0x2000, 0x00).unwrap();
+        assert_eq!(bus.rom_bank(), 1);
+    }
+
+    #[test]
+    fn mbc1_multicart() {
+        // Four 256KB games, each with its own header and its bank number at 0x0000 in every bank
+        let mut rom = vec![0; 0x100000];
+        for (n, bank) in rom.chunks_mut(0x4000).enumerate() {
+            bank[0] = n as u8;
+            if n % 0x10 == 0 {
+                bank[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+                bank[0x147] = 0x01; // MBC1
+                bank[0x148] = 0x05; // 1MB
+            }
+        }
+
+        let mut bus = Bus::new();
+        bus.load_rom(&rom).unwrap();
+        assert_eq!(bus.mbc_type(), MbcType::Mbc1M);
+
+        // The menu lives in the first game
+        assert_eq!(bus.read(0x0000).unwrap(), 0x00);
+        assert_eq!(bus.read(0x4000).unwrap(), 0x01);
+
+        // The menu boots the third game by mapping its first bank to 0x0000-0x3FFF
+        bus.write(0x4000, 0x02).unwrap();
+        bus.write(0x6000, 0x01).unwrap();
+        assert_eq!(bus.read(0x0000).unwrap(), 0x20);
+        assert_eq!(bus.read(0x4000).unwrap(), 0x21);
+        assert_eq!(bus.memory_type_at(0x0000), MemoryType::RomBank(0x20));
+
+        // The game then switches banks within its own 256KB
+        bus.write(0x2000, 0x0F).unwrap();
+        assert_eq!(bus.read(0x4000).unwrap(), 0x2F);
+
+        // Bit 4 of the lower register is not connected, but still prevents mapping bank 0
+        bus.write(0x2000, 0x10).unwrap();
+        assert_eq!(bus.read(0x4000).unwrap(), 0x20);
+        bus.write(0x2000, 0x00).unwrap();
+        assert_eq!(bus.read(0x4000).unwrap(), 0x21);
+
+        // Without a second header, the same ROM is a regular MBC1 cartridge
+        rom[0x40104] = 0x00;
+        let mut bus = Bus::new();
+        bus.load_rom(&rom).unwrap();
+        assert_eq!(bus.mbc_type(), MbcType::Mbc1);
+    }
+
+    #[test]
+    fn wram_banks() {
+        let mut bus = Bus::new();
+
+        // SVBK is not accessible in DMG mode
+        bus.write(0xFF70, 0x02).unwrap();
+        assert_eq!(bus.read(0xFF70).unwrap(), 0xFF);
+        assert_eq!(bus.wram_nn, 1);
+
+        bus.cgb_mode = CgbMode::CgbOnly;
+
+        // Write a distinct value to the same address in every bank
+        for bank in 1..=7 {
+            bus.write(0xFF70, bank).unwrap();
+            assert_eq!(bus.read(0xFF70).unwrap(), 0xF8 | bank);
+            bus.write(0xD000, bank * 0x11).unwrap();
+        }
+        bus.write(0xC000, 0xAA).unwrap();
+
+        // Banks are isolated from each other, and bank 0 is always mapped to 0xC000
+        for bank in 1..=7 {
+            bus.write(0xFF70, bank).unwrap();
+            assert_eq!(bus.read(0xD000).unwrap(), bank * 0x11);
+            assert_eq!(bus.read(0xF000).unwrap(), bank * 0x11);
+            assert_eq!(bus.read(0xC000).unwrap(), 0xAA);
+        }
+
+        // Selecting bank 0 selects bank 1 instead
+        bus.write(0xFF70, 0x00).unwrap();
+        assert_eq!(bus.read(0xFF70).unwrap(), 0xF8);
+        assert_eq!(bus.read(0xD000).unwrap(), 0x11);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn wram_accessors() {
+        let mut bus = Bus::new();
+        bus.cgb_mode = CgbMode::CgbOnly;
+
+        bus.write(0xC000, 0xAA).unwrap();
+        bus.write(0xFF70, 0x03).unwrap();
+        bus.write(0xD000, 0x33).unwrap();
+
+        assert_eq!(bus.wram(0).unwrap().data()[0], 0xAA);
+        assert_eq!(bus.wram(3).unwrap().data()[0], 0x33);
+        assert!(bus.wram(8).is_none());
+
+        // The deprecated accessors follow the banks mapped in memory
+        assert_eq!(bus.wram_00().data()[0], 0xAA);
+        assert_eq!(bus.wram_nn().data()[0], 0x33);
+
+        bus.wram_nn_mut().write(0x0001, 0x44).unwrap();
+        assert_eq!(bus.read(0xD001).unwrap(), 0x44);
+        bus.wram_00_mut().write(0x0001, 0x55).unwrap();
+        assert_eq!(bus.wram_mut(0).unwrap().data()[1], 0x55);
+    }
+}
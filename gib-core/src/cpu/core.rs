@@ -1,15 +1,31 @@
-use std::{collections::HashSet, mem};
-
-use crate::{cpu::OPCODES, dbg, io::Latch, mem::MemRW};
-
+use std::{collections::HashMap, fmt, io::Write, mem};
+
+use crate::{
+    cpu::{CallProfiler, FunctionProfile, OpcodeHistogram, Profiler, Tracer, OPCODES},
+    dbg,
+    io::Latch,
+    mem::MemRW,
+};
+
+/// Static information about an opcode. See [`opcode_info`] and [`cb_opcode_info`].
+///
+/// [`opcode_info`]: crate::cpu::opcode_info
+/// [`cb_opcode_info`]: crate::cpu::cb_opcode_info
 #[derive(Debug, Clone, Copy)]
 pub struct OpcodeInfo(
-    pub &'static str,    // Mnemonic
-    pub OperandLocation, // Destination
-    pub OperandLocation, // Source
-    pub u8,              // Size
-    pub u8,              // Cycles if branch taken
-    pub u8,              // Cycles if branch not taken
+    /// Mnemonic
+    pub &'static str,
+    /// Destination
+    pub OperandLocation,
+    /// Source
+    pub OperandLocation,
+    /// Size, in bytes
+    pub u8,
+    /// Clock cycles taken if the branch is taken, or by any instruction other than
+    /// a conditional branch
+    pub u8,
+    /// Clock cycles taken if the branch is not taken. Same as above for other instructions
+    pub u8,
 );
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -47,6 +63,136 @@ pub enum WritebackOp {
     Write16(u16, u16),
     Push(u16),
     Return,
+    /// Interrupt dispatch, pushing PC and jumping to the given vector
+    Interrupt(u16),
+}
+
+/// A frame in the call stack tracked for debugging purposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallFrame {
+    /// Frame pushed by a CALL or RST instruction, holding the return address.
+    Call(u16),
+    /// Frame pushed by the dispatch of an interrupt, holding the vector and the return address.
+    Interrupt { vector: u16, ret: u16 },
+}
+
+impl CallFrame {
+    pub fn return_address(&self) -> u16 {
+        match *self {
+            CallFrame::Call(ret) | CallFrame::Interrupt { ret, .. } => ret,
+        }
+    }
+}
+
+/// Register whose value can be checked by a breakpoint [`Condition`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+impl Register {
+    /// Returns the current value of the register in `cpu`.
+    pub fn read(self, cpu: &Cpu) -> u16 {
+        match self {
+            Register::A => cpu.a().into(),
+            Register::B => cpu.b().into(),
+            Register::C => cpu.c().into(),
+            Register::D => cpu.d().into(),
+            Register::E => cpu.e().into(),
+            Register::H => cpu.h().into(),
+            Register::L => cpu.l().into(),
+            Register::AF => cpu.af,
+            Register::BC => cpu.bc,
+            Register::DE => cpu.de,
+            Register::HL => cpu.hl,
+            Register::SP => cpu.sp,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Condition that must hold for a [`Breakpoint`] to fire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// The register holds the given value.
+    Equal(Register, u16),
+    /// The register does not hold the given value.
+    NotEqual(Register, u16),
+}
+
+impl Condition {
+    /// Returns whether the condition holds for the current state of `cpu`.
+    pub fn holds(self, cpu: &Cpu) -> bool {
+        match self {
+            Condition::Equal(reg, v) => reg.read(cpu) == v,
+            Condition::NotEqual(reg, v) => reg.read(cpu) != v,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Equal(reg, v) => write!(f, "{reg} == {v:02X}"),
+            Condition::NotEqual(reg, v) => write!(f, "{reg} != {v:02X}"),
+        }
+    }
+}
+
+/// A breakpoint pausing the execution when the CPU is about to execute its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    /// Disabled breakpoints are kept around, but never fire.
+    pub enabled: bool,
+    /// If set, the breakpoint fires only when the condition holds.
+    pub condition: Option<Condition>,
+    /// One-shot breakpoints are removed after firing once.
+    pub one_shot: bool,
+}
+
+impl Default for Breakpoint {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            condition: None,
+            one_shot: false,
+        }
+    }
+}
+
+impl Breakpoint {
+    /// Returns whether the breakpoint fires for the current state of `cpu`.
+    pub fn fires(&self, cpu: &Cpu) -> bool {
+        self.enabled && self.condition.map_or(true, |cond| cond.holds(cpu))
+    }
+}
+
+/// How the CPU reacts to the execution of an illegal opcode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    /// Lock up the CPU until the next reset, like the real hardware does.
+    Halt,
+    /// Treat the opcode as a NOP.
+    Skip,
+    /// Raise a [`dbg::TraceEvent::IllegalInstructionFault`].
+    #[default]
+    Fault,
 }
 
 #[derive(Clone)]
@@ -62,6 +208,8 @@ pub struct Cpu {
     // Misc
     pub halted: Latch<bool>,
     pub intr_enabled: Latch<bool>,
+    pub(crate) locked_up: bool,
+    illegal_opcode_policy: IllegalOpcodePolicy,
 
     // Execution-related members
     pub state: CpuState,
@@ -73,15 +221,25 @@ pub struct Cpu {
     pub executing: bool,
     pub branch_taken: bool,
     pub remaining_cycles: u8,
+    instr_pc: u16,
+    instr_sp: u16,
 
     // Debug
     paused: bool,
-    breakpoints: HashSet<u16>,
-    pub call_stack: Vec<u16>,
+    breakpoints: HashMap<u16, Breakpoint>,
+    stack_guard: Option<(u16, u16)>,
+    pub(crate) call_stack: Vec<CallFrame>,
+    // Address called by each frame in the call stack
+    call_targets: Vec<u16>,
     rollback_on_error: bool,
+    tracer: Option<Tracer>,
+    trace_disassembly: bool,
+    profiler: Option<Profiler>,
+    call_profiler: Option<CallProfiler>,
 
     // Hacks/workarounds
     pub halt_bug: bool,
+    halt_bug_enabled: bool,
     ignore_next_halt: bool,
 }
 
@@ -96,6 +254,8 @@ impl Default for Cpu {
             pc: 0x0100,
 
             halted: Latch::new(false),
+            locked_up: false,
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
             intr_enabled: Latch::new(false),
 
             state: CpuState::FetchOpcode,
@@ -107,13 +267,22 @@ impl Default for Cpu {
             executing: false,
             branch_taken: false,
             remaining_cycles: 0,
+            instr_pc: 0x0100,
+            instr_sp: 0xFFFE,
 
             paused: false,
-            breakpoints: HashSet::new(),
-            call_stack: vec![0x0100],
+            breakpoints: HashMap::new(),
+            stack_guard: None,
+            call_stack: vec![CallFrame::Call(0x0100)],
+            call_targets: vec![0x0100],
             rollback_on_error: false,
+            tracer: None,
+            trace_disassembly: false,
+            profiler: None,
+            call_profiler: None,
 
             halt_bug: false,
+            halt_bug_enabled: true,
             ignore_next_halt: false,
         }
     }
@@ -128,16 +297,65 @@ impl Cpu {
     pub fn reset(&mut self) {
         // Save fields related to debugging and debug information
         let breakpoints = mem::take(&mut self.breakpoints);
+        let stack_guard = self.stack_guard;
         let rollback_on_error = self.rollback_on_error;
+        let tracer = self.tracer.take();
+        let trace_disassembly = self.trace_disassembly;
+        let profiler = self.profiler.take();
+        let call_profiler = self.call_profiler.take();
+        let halt_bug_enabled = self.halt_bug_enabled;
+        let illegal_opcode_policy = self.illegal_opcode_policy;
 
         // Reset everything else
         *self = Self {
             breakpoints,
+            stack_guard,
             rollback_on_error,
+            tracer,
+            trace_disassembly,
+            profiler,
+            call_profiler,
+            halt_bug_enabled,
+            illegal_opcode_policy,
             ..Default::default()
         };
     }
 
+    /// Moves the program counter to `pc` as the first instruction to run after a reset,
+    /// with a call stack made of `pc` only.
+    pub(crate) fn set_entry_point(&mut self, pc: u16) {
+        self.pc = pc;
+        self.instr_pc = pc;
+        self.call_stack = vec![CallFrame::Call(pc)];
+        self.call_targets = vec![pc];
+    }
+
+    /// Restores the core state from `other`, preserving breakpoints and other debug utilities.
+    pub(crate) fn restore(&mut self, other: &Cpu) {
+        let breakpoints = mem::take(&mut self.breakpoints);
+        let stack_guard = self.stack_guard;
+        let rollback_on_error = self.rollback_on_error;
+        let tracer = self.tracer.take();
+        let trace_disassembly = self.trace_disassembly;
+        let profiler = self.profiler.take();
+        let call_profiler = self.call_profiler.take();
+        let halt_bug_enabled = self.halt_bug_enabled;
+        let illegal_opcode_policy = self.illegal_opcode_policy;
+
+        *self = Self {
+            breakpoints,
+            stack_guard,
+            rollback_on_error,
+            tracer,
+            trace_disassembly,
+            profiler,
+            call_profiler,
+            halt_bug_enabled,
+            illegal_opcode_policy,
+            ..other.clone()
+        };
+    }
+
     pub fn tick(&mut self, bus: &mut impl MemRW) -> Result<(), dbg::TraceEvent> {
         use CpuState::*;
 
@@ -147,11 +365,15 @@ impl Cpu {
         self.intr_enabled.tick();
         self.halted.tick();
 
-        if *self.halted.value() {
+        if *self.halted.value() || self.locked_up {
             return Ok(());
         }
 
-        self.remaining_cycles -= 4;
+        self.remaining_cycles = self.remaining_cycles.wrapping_sub(4);
+
+        if let Some(ref profiler) = self.call_profiler {
+            profiler.record_cycles(&self.call_targets, 4);
+        }
 
         let res = match self.state {
             FetchOpcode => self.fetch_opcode(bus),
@@ -185,6 +407,18 @@ impl Cpu {
                 Ok(())
             }
             Err(e) => {
+                // Report the instruction responsible for unmapped accesses
+                let e = match e {
+                    dbg::TraceEvent::UnmappedAccess { access, addr, .. } => {
+                        dbg::TraceEvent::UnmappedAccess {
+                            access,
+                            addr,
+                            pc: self.instr_pc,
+                        }
+                    }
+                    e => e,
+                };
+
                 // Restore previous state on error. Note that this is for debugging purposes only,
                 // the side effects of the instruction (eg. memory writes) are NOT rolled back.
                 if let Some(ctx) = saved_ctx.take() {
@@ -205,22 +439,42 @@ impl Cpu {
 
     fn fetch_opcode(&mut self, bus: &mut impl MemRW) -> Result<(), dbg::TraceEvent> {
         // Handle breakpoints at the current position
-        if !self.paused() && self.breakpoints.contains(&self.pc) {
+        let bp = self.breakpoints.get(&self.pc).copied();
+        if let Some(bp) = bp.filter(|bp| !self.paused() && bp.fires(self)) {
+            if bp.one_shot {
+                self.breakpoints.remove(&self.pc);
+            }
             self.pause();
             return Err(dbg::TraceEvent::Breakpoint(self.pc));
+        } else if !self.paused() && self.stack_guard_hit() {
+            self.pause();
+            return Err(dbg::TraceEvent::StackBounds(self.sp));
         } else {
             self.resume();
         }
 
+        // Trace the instruction before it gets executed. If the trace cannot be written,
+        // tracing is disabled altogether instead of failing on every instruction.
+        if let Some(ref tracer) = self.tracer {
+            if tracer.trace(self, bus).is_err() {
+                self.tracer = None;
+            }
+        }
+
         // Fetch opcode and reset internal state
+        self.instr_pc = self.pc;
+        self.instr_sp = self.sp;
         self.opcode = self.fetch_pc(bus)?;
+        if let Some(ref profiler) = self.profiler {
+            profiler.record(self.opcode);
+        }
         self.info = OPCODES[self.opcode as usize];
         self.operand = 0;
         self.cb_mode = self.opcode == 0xCB;
         self.write_op = None;
         self.executing = true;
         self.branch_taken = false;
-        self.remaining_cycles = self.info.5 - 4;
+        self.remaining_cycles = self.info.5.wrapping_sub(4);
 
         // Check if we need to fetch more bytes, otherwise execute directly
         if self.info.3 > 1 {
@@ -247,6 +501,9 @@ impl Cpu {
                 // Handle CB opcodes that fetch from memory
                 if self.cb_mode {
                     self.opcode = self.operand as u8;
+                    if let Some(ref profiler) = self.profiler {
+                        profiler.record_cb(self.opcode);
+                    }
 
                     if self.operand & 0x7 == 0x6 {
                         self.info.2 = Memory(HL);
@@ -296,7 +553,7 @@ impl Cpu {
             Memory(A16) => bus.read(self.operand)?,
             Memory(SP) => {
                 let r = bus.read(self.sp)?;
-                self.sp += 1;
+                self.sp = self.sp.wrapping_add(1);
                 r
             }
             _ => unreachable!(),
@@ -347,52 +604,110 @@ impl Cpu {
         Ok(())
     }
 
+    /// Performs the memory accesses of the writeback stage, which take up the last M-cycles of
+    /// the instruction. Counting backwards from the last one (M-cycle 0), they happen at:
+    ///
+    /// ```text
+    /// Operation               M-cycle 3   M-cycle 2   M-cycle 1   M-cycle 0
+    /// -----------------------------------------------------------------------
+    /// Write8                                                      W (addr)
+    /// Write16 (LD (a16),SP)                           W (addr)    W (addr+1)
+    /// Push (PUSH/CALL/RST)                internal    W (SP-1)    W (SP-2)
+    /// Return (RET/RETI)                   R (SP)      R (SP+1)    internal
+    /// Interrupt dispatch      internal    W (SP-1)    W (SP-2)    internal
+    /// ```
+    ///
+    /// Pushes write the high byte first, while the other operations access the low byte first.
+    /// Any earlier cycles of the instruction are internal delays.
     fn writeback(&mut self, bus: &mut impl MemRW) -> Result<(), dbg::TraceEvent> {
         use WritebackOp::*;
 
-        // After a writeback operation, reset state machine for the next instruction
-        if self.remaining_cycles > 0 {
-            self.state = CpuState::Delay((self.remaining_cycles - 1) / 4);
-        } else {
+        let cycle = self.remaining_cycles / 4;
+
+        // After the last access, reset state machine for the next instruction
+        if cycle == 0 {
             self.state = CpuState::FetchOpcode;
             self.executing = false;
         }
 
-        match self.write_op {
-            Some(Write8(dest, d8)) => bus.write(dest, d8),
-            Some(Write16(dest, d16)) => self.store_word(bus, dest, d16),
-            Some(Push(d16)) => {
-                self.sp -= 2;
-                self.store_word(bus, self.sp, d16)
-            }
-            Some(Return) => {
+        match (self.write_op, cycle) {
+            (Some(Write8(dest, d8)), 0) => bus.write(dest, d8),
+            (Some(Write16(dest, d16)), 1) => bus.write(dest, d16 as u8),
+            (Some(Write16(dest, d16)), 0) => bus.write(dest.wrapping_add(1), (d16 >> 8) as u8),
+            (Some(Push(d16)), 1) => self.push_byte(bus, (d16 >> 8) as u8),
+            (Some(Push(d16)), 0) => self.push_byte(bus, d16 as u8),
+            (Some(Return), 2) => {
                 // This is basically a POP PC operation
-                self.pc = self.fetch_word(bus, self.sp)?;
-                self.sp += 2;
+                self.operand = bus.read(self.sp)?.into();
+                self.sp = self.sp.wrapping_add(1);
+                Ok(())
+            }
+            (Some(Return), 1) => {
+                self.operand |= u16::from(bus.read(self.sp)?) << 8;
+                self.sp = self.sp.wrapping_add(1);
+                Ok(())
+            }
+            (Some(Return), 0) => {
+                self.pc = self.operand;
                 Ok(())
             }
-            None => Ok(()),
+            (Some(Interrupt(_)), 2) => self.push_byte(bus, (self.pc >> 8) as u8),
+            (Some(Interrupt(_)), 1) => self.push_byte(bus, self.pc as u8),
+            (Some(Interrupt(vector)), 0) => {
+                self.pc = vector;
+                Ok(())
+            }
+            _ => Ok(()),
         }
     }
 
-    pub fn jump_to_isr(&mut self, bus: &mut impl MemRW, addr: u16) -> Result<(), dbg::TraceEvent> {
-        // Push PC onto the stack
-        self.sp -= 2;
-        self.store_word(bus, self.sp, self.pc)?;
+    /// Tracks a call to `target`, for debugging purposes.
+    pub(crate) fn push_call_frame(&mut self, frame: CallFrame, target: u16) {
+        self.call_stack.push(frame);
+        self.call_targets.push(target);
 
-        // Jump to ISR
-        self.pc = addr;
+        if let Some(ref profiler) = self.call_profiler {
+            profiler.record_call(target);
+        }
+    }
 
-        // Add 5 wait states to match hardware behavior
-        self.executing = true;
-        self.state = CpuState::Delay(4);
+    /// Tracks a return from the innermost call, for debugging purposes.
+    pub(crate) fn pop_call_frame(&mut self) {
+        // Code can RET without a matching CALL (eg. to implement jump tables),
+        // so never drop the entry point frame.
+        if self.call_stack.len() > 1 {
+            self.call_stack.pop();
+            self.call_targets.pop();
+        }
+    }
 
-        Ok(())
+    fn push_byte(&mut self, bus: &mut impl MemRW, val: u8) -> Result<(), dbg::TraceEvent> {
+        self.sp = self.sp.wrapping_sub(1);
+        bus.write(self.sp, val)
+    }
+
+    /// Starts the dispatch of the interrupt with vector `addr`, which takes 5 M-cycles.
+    ///
+    /// PC is pushed onto the stack during the third and fourth M-cycles,
+    /// and the jump to the vector happens during the last one.
+    pub fn jump_to_isr(&mut self, addr: u16) {
+        self.push_call_frame(
+            CallFrame::Interrupt {
+                vector: addr,
+                ret: self.pc,
+            },
+            addr,
+        );
+
+        self.write_op = Some(WritebackOp::Interrupt(addr));
+        self.remaining_cycles = 20;
+        self.executing = true;
+        self.state = CpuState::Writeback;
     }
 
     pub fn fetch_pc(&mut self, bus: &mut impl MemRW) -> Result<u8, dbg::TraceEvent> {
         let v = bus.read(self.pc)?;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         Ok(v)
     }
 
@@ -424,22 +739,79 @@ impl Cpu {
         self.paused
     }
 
+    /// Sets a guard pausing the execution when the last instruction moved SP below `lo`
+    /// or above `hi`, raising a [`dbg::TraceEvent::StackBounds`].
+    pub fn set_stack_guard(&mut self, lo: u16, hi: u16) {
+        self.stack_guard = Some((lo, hi));
+    }
+
+    pub fn clear_stack_guard(&mut self) {
+        self.stack_guard = None;
+    }
+
+    /// Returns the bounds of the stack guard, if set.
+    pub fn stack_guard(&self) -> Option<(u16, u16)> {
+        self.stack_guard
+    }
+
+    /// Returns whether SP was moved out of the stack guard bounds by the last instruction.
+    fn stack_guard_hit(&self) -> bool {
+        self.sp != self.instr_sp
+            && self
+                .stack_guard
+                .is_some_and(|(lo, hi)| self.sp < lo || self.sp > hi)
+    }
+
+    /// Sets an enabled, unconditional breakpoint at `addr`.
     pub fn set_breakpoint(&mut self, addr: u16) {
-        self.breakpoints.insert(addr);
+        self.insert_breakpoint(addr, Breakpoint::default());
+    }
+
+    /// Sets `bp` at `addr`, replacing any breakpoint already there.
+    pub fn insert_breakpoint(&mut self, addr: u16, bp: Breakpoint) {
+        self.breakpoints.insert(addr, bp);
     }
 
     pub fn clear_breakpoint(&mut self, addr: u16) {
         self.breakpoints.remove(&addr);
     }
 
+    /// Returns whether a breakpoint is set at `addr`, even if disabled.
     pub fn breakpoint_at(&self, addr: u16) -> bool {
-        self.breakpoints.contains(&addr)
+        self.breakpoints.contains_key(&addr)
     }
 
-    pub fn breakpoints(&self) -> &HashSet<u16> {
+    /// Enables or disables the breakpoint at `addr`, if any.
+    ///
+    /// Disabled breakpoints are kept in the list, but do not pause the execution.
+    pub fn set_breakpoint_enabled(&mut self, addr: u16, enabled: bool) {
+        if let Some(bp) = self.breakpoints.get_mut(&addr) {
+            bp.enabled = enabled;
+        }
+    }
+
+    /// Returns whether the breakpoint at `addr` exists and is enabled.
+    pub fn breakpoint_enabled(&self, addr: u16) -> bool {
+        self.breakpoints.get(&addr).is_some_and(|bp| bp.enabled)
+    }
+
+    /// Returns the breakpoint at `addr`, if any.
+    pub fn breakpoint(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+
+    /// Returns all the breakpoints, indexed by address.
+    pub fn breakpoints(&self) -> &HashMap<u16, Breakpoint> {
         &self.breakpoints
     }
 
+    /// Returns the active call frames, outermost first.
+    ///
+    /// The first entry is the program entry point, and is never removed by an unmatched RET.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
     pub fn allow_rollback_on_error(&mut self, allow: bool) {
         self.rollback_on_error = allow;
     }
@@ -447,6 +819,99 @@ impl Cpu {
     pub fn rollback_on_error(&self) -> bool {
         self.rollback_on_error
     }
+
+    /// Enables or disables emulation of the DMG HALT bug (enabled by default).
+    ///
+    /// When disabled, a HALT executed with IME = 0 and an interrupt pending is simply skipped.
+    pub fn set_halt_bug_enabled(&mut self, enabled: bool) {
+        self.halt_bug_enabled = enabled;
+    }
+
+    pub fn halt_bug_enabled(&self) -> bool {
+        self.halt_bug_enabled
+    }
+
+    /// Sets how the CPU reacts to illegal opcodes. See [`IllegalOpcodePolicy`].
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    pub fn illegal_opcode_policy(&self) -> IllegalOpcodePolicy {
+        self.illegal_opcode_policy
+    }
+
+    /// Returns whether the CPU has locked up after executing an illegal opcode.
+    pub fn locked_up(&self) -> bool {
+        self.locked_up
+    }
+
+    /// Sets the writer receiving the trace of executed instructions, or disables tracing if `None`.
+    pub fn set_trace_writer(&mut self, writer: Option<Box<dyn Write + Send>>) {
+        self.tracer = writer.map(Tracer::new);
+    }
+
+    /// Enables or disables appending the disassembled instruction to each line of the trace.
+    ///
+    /// This is disabled by default, so that traces can be compared against gameboy-doctor logs.
+    pub fn set_trace_disassembly(&mut self, enable: bool) {
+        self.trace_disassembly = enable;
+    }
+
+    pub fn trace_disassembly(&self) -> bool {
+        self.trace_disassembly
+    }
+
+    /// Enables or disables the call profiler. Disabling it discards the collected profile.
+    ///
+    /// Cycles are attributed to the functions in the call stack, identified by their address,
+    /// as long as the CPU is not halted.
+    pub fn set_call_profiling(&mut self, enable: bool) {
+        if enable != self.call_profiling() {
+            self.call_profiler = enable.then(CallProfiler::default);
+        }
+    }
+
+    pub fn call_profiling(&self) -> bool {
+        self.call_profiler.is_some()
+    }
+
+    /// Returns the profile of each function called since profiling was enabled or reset,
+    /// in no particular order.
+    pub fn call_profile(&self) -> Vec<(u16, FunctionProfile)> {
+        self.call_profiler
+            .as_ref()
+            .map_or_else(Vec::new, CallProfiler::profile)
+    }
+
+    /// Clears the call profile, if profiling is enabled.
+    pub fn reset_call_profile(&self) {
+        if let Some(ref profiler) = self.call_profiler {
+            profiler.reset();
+        }
+    }
+
+    /// Enables or disables the opcode profiler. Disabling it discards the collected counters.
+    pub fn set_profiling(&mut self, enable: bool) {
+        if enable != self.profiling() {
+            self.profiler = enable.then(Profiler::default);
+        }
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    /// Returns the number of executions of each opcode since profiling was enabled or reset.
+    pub fn opcode_histogram(&self) -> Option<OpcodeHistogram> {
+        self.profiler.as_ref().map(Profiler::histogram)
+    }
+
+    /// Clears the counters of the opcode profiler, if enabled.
+    pub fn reset_opcode_histogram(&self) {
+        if let Some(ref profiler) = self.profiler {
+            profiler.reset();
+        }
+    }
 }
 
 #[rustfmt::skip]
@@ -469,6 +934,13 @@ impl Cpu {
     pub fn set_d(&mut self, v: u8) { self.de = (self.de & 0x00FF) | (u16::from(v) << 8); }
     pub fn set_h(&mut self, v: u8) { self.hl = (self.hl & 0x00FF) | (u16::from(v) << 8); }
 
+    pub fn set_af(&mut self, v: u16) { self.af = v & 0xFFF0; }
+    pub fn set_bc(&mut self, v: u16) { self.bc = v; }
+    pub fn set_de(&mut self, v: u16) { self.de = v; }
+    pub fn set_hl(&mut self, v: u16) { self.hl = v; }
+    pub fn set_sp(&mut self, v: u16) { self.sp = v; }
+    pub fn set_pc(&mut self, v: u16) { self.pc = v; }
+
     pub fn zf(&self) -> bool { (self.f() & 0x80) != 0 }
     pub fn sf(&self) -> bool { (self.f() & 0x40) != 0 }
     pub fn hc(&self) -> bool { (self.f() & 0x20) != 0 }
@@ -479,3 +951,230 @@ impl Cpu {
     pub fn set_hc(&mut self, v: bool) { self.set_f((self.f() & (!0x20)) | (u8::from(v) << 5)); }
     pub fn set_cy(&mut self, v: bool) { self.set_f((self.f() & (!0x10)) | (u8::from(v) << 4)); }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::mem::{MemR, MemW};
+
+    /// A memory access performed during an M-cycle
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum BusAccess {
+        Read(u16),
+        Write(u16, u8),
+    }
+
+    /// Flat 64KB memory recording every access, along with the M-cycle it happened in.
+    struct RecordingBus {
+        data: Vec<u8>,
+        cycle: usize,
+        accesses: RefCell<Vec<(usize, BusAccess)>>,
+    }
+
+    impl RecordingBus {
+        fn new(program: &[u8]) -> RecordingBus {
+            let mut data = vec![0; 0x10000];
+            data[0x100..0x100 + program.len()].copy_from_slice(program);
+            RecordingBus {
+                data,
+                cycle: 0,
+                accesses: RefCell::new(Vec::new()),
+            }
+        }
+
+        /// Runs `cpu` for `cycles` M-cycles, returning the accesses performed by the CPU.
+        fn run(&mut self, cpu: &mut Cpu, cycles: usize) -> Vec<(usize, BusAccess)> {
+            self.cycle = 0;
+            self.accesses.borrow_mut().clear();
+
+            for _ in 0..cycles {
+                self.cycle += 1;
+                cpu.tick(self).unwrap();
+            }
+            assert!(!cpu.executing);
+
+            self.accesses.take()
+        }
+    }
+
+    impl MemR for RecordingBus {
+        fn read(&self, addr: u16) -> Result<u8, dbg::TraceEvent> {
+            let access = (self.cycle, BusAccess::Read(addr));
+            self.accesses.borrow_mut().push(access);
+            Ok(self.data[usize::from(addr)])
+        }
+    }
+
+    impl MemW for RecordingBus {
+        fn write(&mut self, addr: u16, val: u8) -> Result<(), dbg::TraceEvent> {
+            let access = (self.cycle, BusAccess::Write(addr, val));
+            self.accesses.get_mut().push(access);
+            self.data[usize::from(addr)] = val;
+            Ok(())
+        }
+    }
+
+    impl MemRW for RecordingBus {}
+
+    #[test]
+    fn stack_guard() {
+        // An endless sequence of PUSH BC
+        let mut bus = RecordingBus::new(&[0xC5; 0x100]);
+        let mut cpu = Cpu::new();
+        cpu.set_stack_guard(0xFFF0, 0xFFFE);
+
+        let mut run = |cpu: &mut Cpu| loop {
+            if let Err(e) = cpu.tick(&mut bus) {
+                break e;
+            }
+        };
+
+        // The guard fires right after the push crossing the low-water mark
+        assert!(matches!(
+            run(&mut cpu),
+            dbg::TraceEvent::StackBounds(0xFFEE)
+        ));
+        assert_eq!(cpu.sp, 0xFFEE);
+        assert_eq!(cpu.pc, 0x0108);
+
+        // After resuming, every push out of bounds fires again
+        assert!(matches!(
+            run(&mut cpu),
+            dbg::TraceEvent::StackBounds(0xFFEC)
+        ));
+        assert_eq!(cpu.pc, 0x0109);
+
+        cpu.clear_stack_guard();
+        for _ in 0..16 {
+            cpu.tick(&mut bus).unwrap();
+        }
+    }
+
+    #[test]
+    fn register_setters() {
+        let mut cpu = Cpu::new();
+
+        // The lower nibble of F is always zero
+        cpu.set_af(0x12FF);
+        assert_eq!(cpu.af, 0x12F0);
+        assert_eq!(cpu.a(), 0x12);
+        assert_eq!(cpu.f(), 0xF0);
+        assert!(cpu.zf() && cpu.sf() && cpu.hc() && cpu.cy());
+
+        cpu.set_bc(0x3456);
+        cpu.set_de(0x789A);
+        cpu.set_hl(0xBCDE);
+        assert_eq!((cpu.b(), cpu.c()), (0x34, 0x56));
+        assert_eq!((cpu.d(), cpu.e()), (0x78, 0x9A));
+        assert_eq!((cpu.h(), cpu.l()), (0xBC, 0xDE));
+
+        cpu.set_sp(0xC000);
+        cpu.set_pc(0x0150);
+        assert_eq!(cpu.sp, 0xC000);
+        assert_eq!(cpu.pc, 0x0150);
+
+        // 8-bit setters only affect their half of the packed register
+        cpu.set_c(0x00);
+        assert_eq!(cpu.bc, 0x3400);
+        cpu.set_f(0x0F);
+        assert_eq!(cpu.af, 0x1200);
+    }
+
+    #[test]
+    fn push_access_pattern() {
+        use BusAccess::*;
+
+        // PUSH BC
+        let mut bus = RecordingBus::new(&[0xC5]);
+        let mut cpu = Cpu::new();
+        cpu.set_bc(0x1234);
+
+        assert_eq!(
+            bus.run(&mut cpu, 4),
+            [
+                (1, Read(0x0100)),
+                (3, Write(0xFFFD, 0x12)),
+                (4, Write(0xFFFC, 0x34)),
+            ]
+        );
+        assert_eq!(cpu.sp, 0xFFFC);
+    }
+
+    #[test]
+    fn call_and_ret_access_pattern() {
+        use BusAccess::*;
+
+        // CALL 0x0200; ...; RET
+        let mut bus = RecordingBus::new(&[0xCD, 0x00, 0x02]);
+        bus.data[0x200] = 0xC9;
+        let mut cpu = Cpu::new();
+
+        assert_eq!(
+            bus.run(&mut cpu, 6),
+            [
+                (1, Read(0x0100)),
+                (2, Read(0x0101)),
+                (3, Read(0x0102)),
+                (5, Write(0xFFFD, 0x01)),
+                (6, Write(0xFFFC, 0x03)),
+            ]
+        );
+        assert_eq!(cpu.pc, 0x0200);
+
+        assert_eq!(
+            bus.run(&mut cpu, 4),
+            [(1, Read(0x0200)), (2, Read(0xFFFC)), (3, Read(0xFFFD)),]
+        );
+        assert_eq!((cpu.pc, cpu.sp), (0x0103, 0xFFFE));
+    }
+
+    #[test]
+    fn interrupt_dispatch_access_pattern() {
+        use BusAccess::*;
+
+        let mut bus = RecordingBus::new(&[]);
+        let mut cpu = Cpu::new();
+
+        cpu.jump_to_isr(0x0040);
+        assert_eq!(
+            bus.run(&mut cpu, 5),
+            [(3, Write(0xFFFD, 0x01)), (4, Write(0xFFFC, 0x00))]
+        );
+        assert_eq!((cpu.pc, cpu.sp), (0x0040, 0xFFFC));
+
+        // The jump only happens in the last M-cycle
+        cpu.jump_to_isr(0x0048);
+        for _ in 0..4 {
+            cpu.tick(&mut bus).unwrap();
+        }
+        assert_eq!(cpu.pc, 0x0040);
+        cpu.tick(&mut bus).unwrap();
+        assert_eq!(cpu.pc, 0x0048);
+    }
+
+    #[test]
+    fn enable_disable_breakpoints() {
+        let mut cpu = Cpu::new();
+
+        cpu.set_breakpoint(0x0150);
+        assert!(cpu.breakpoint_enabled(0x0150));
+
+        // Disabling a breakpoint keeps its address around
+        cpu.set_breakpoint_enabled(0x0150, false);
+        assert!(cpu.breakpoint_at(0x0150));
+        assert!(!cpu.breakpoint_enabled(0x0150));
+        assert_eq!(cpu.breakpoints().len(), 1);
+
+        cpu.set_breakpoint_enabled(0x0150, true);
+        assert!(cpu.breakpoint_enabled(0x0150));
+
+        // Breakpoints that do not exist cannot be enabled
+        cpu.set_breakpoint_enabled(0x0200, true);
+        assert!(!cpu.breakpoint_at(0x0200));
+
+        cpu.clear_breakpoint(0x0150);
+        assert!(cpu.breakpoints().is_empty());
+    }
+}
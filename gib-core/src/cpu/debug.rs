@@ -1,5 +1,12 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
 use crate::{
-    cpu::{Cpu, OPCODES},
+    cpu::{Cpu, CB_OPCODES, OPCODES},
     dbg,
     mem::MemR,
 };
@@ -18,17 +25,164 @@ pub struct Instruction {
     pub size: u8,
 }
 
-impl Cpu {
-    pub fn disasm(&self, mem: &impl MemR, addr: u16) -> Result<Instruction, dbg::TraceEvent> {
+/// Instruction tracer writing one line per executed instruction, in the format
+/// used by [gameboy-doctor](https://github.com/robert/gameboy-doctor).
+///
+/// The opcode and its operands are part of the `PCMEM` field. If enabled with
+/// [`Cpu::set_trace_disassembly`], the disassembled instruction is appended to each line
+/// after a semicolon, which tools comparing against reference traces need to strip.
+#[derive(Clone)]
+pub(crate) struct Tracer(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write + Send>) -> Tracer {
+        Tracer(Arc::new(Mutex::new(writer)))
+    }
+
+    /// Records the CPU state right before executing the instruction at PC.
+    pub fn trace(&self, cpu: &Cpu, mem: &impl MemR) -> std::io::Result<()> {
+        let pcmem = |offset: u16| mem.read(cpu.pc.wrapping_add(offset)).unwrap_or(0xFF);
+
+        let mut writer = self.0.lock().unwrap();
+
+        write!(
+            writer,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            cpu.a(),
+            cpu.f(),
+            cpu.b(),
+            cpu.c(),
+            cpu.d(),
+            cpu.e(),
+            cpu.h(),
+            cpu.l(),
+            cpu.sp,
+            cpu.pc,
+            pcmem(0),
+            pcmem(1),
+            pcmem(2),
+            pcmem(3),
+        )?;
+
+        if cpu.trace_disassembly() {
+            match Instruction::decode(mem, cpu.pc) {
+                Ok(instr) => write!(writer, " ; {}", instr.resolved_mnemonic(cpu.pc))?,
+                Err(_) => write!(writer, " ; ??")?,
+            }
+        }
+
+        writeln!(writer)
+    }
+}
+
+/// Number of executions of each opcode, as counted by the opcode profiler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeHistogram {
+    pub opcodes: [u64; 256],
+    /// CB-prefixed opcodes, which are also counted as 0xCB in `opcodes`.
+    pub cb_opcodes: [u64; 256],
+}
+
+impl Default for OpcodeHistogram {
+    fn default() -> OpcodeHistogram {
+        OpcodeHistogram {
+            opcodes: [0; 256],
+            cb_opcodes: [0; 256],
+        }
+    }
+}
+
+/// Opcode profiler, counting the executions of each opcode.
+///
+/// Like [`Tracer`], it is shared between clones of the CPU, so that the counters are not lost
+/// when the CPU state is rolled back or restored.
+#[derive(Clone, Default)]
+pub(crate) struct Profiler(Arc<Mutex<OpcodeHistogram>>);
+
+impl Profiler {
+    pub fn record(&self, opcode: u8) {
+        self.0.lock().unwrap().opcodes[usize::from(opcode)] += 1;
+    }
+
+    pub fn record_cb(&self, opcode: u8) {
+        self.0.lock().unwrap().cb_opcodes[usize::from(opcode)] += 1;
+    }
+
+    pub fn histogram(&self) -> OpcodeHistogram {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn reset(&self) {
+        *self.0.lock().unwrap() = OpcodeHistogram::default();
+    }
+}
+
+/// Cycles spent in a function, as measured by the call profiler.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Number of times the function was called.
+    pub calls: u64,
+    /// Clock cycles spent in the function, including the functions it called.
+    pub inclusive: u64,
+    /// Clock cycles spent in the function itself.
+    pub exclusive: u64,
+}
+
+/// Function-level profiler, attributing cycles to the target of each active call.
+///
+/// Shared between clones of the CPU, like [`Profiler`].
+#[derive(Clone, Default)]
+pub(crate) struct CallProfiler(Arc<Mutex<HashMap<u16, FunctionProfile>>>);
+
+impl CallProfiler {
+    /// Records a call to the function at `target`.
+    pub fn record_call(&self, target: u16) {
+        self.0.lock().unwrap().entry(target).or_default().calls += 1;
+    }
+
+    /// Attributes `cycles` to the functions in `targets`, innermost last.
+    pub fn record_cycles(&self, targets: &[u16], cycles: u64) {
+        let mut profile = self.0.lock().unwrap();
+
+        if let Some(&current) = targets.last() {
+            profile.entry(current).or_default().exclusive += cycles;
+
+            // Recursive functions are only accounted once
+            for (i, &target) in targets.iter().enumerate() {
+                if !targets[..i].contains(&target) {
+                    profile.entry(target).or_default().inclusive += cycles;
+                }
+            }
+        }
+    }
+
+    pub fn profile(&self) -> Vec<(u16, FunctionProfile)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .collect()
+    }
+
+    pub fn reset(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`.
+    pub fn decode(mem: &impl MemR, addr: u16) -> Result<Instruction, dbg::TraceEvent> {
         let opcode = mem.read(addr)?;
         let info = &OPCODES[opcode as usize];
 
         let imm: Option<Immediate> = match info.3 {
             1 => None,
-            2 => Some(Immediate::Imm8(mem.read(addr + 1)?)),
+            2 => Some(Immediate::Imm8(mem.read(addr.wrapping_add(1))?)),
             3 => {
-                let lo = u16::from(mem.read(addr + 1)?);
-                let hi = u16::from(mem.read(addr + 2)?);
+                let lo = u16::from(mem.read(addr.wrapping_add(1))?);
+                let hi = u16::from(mem.read(addr.wrapping_add(2))?);
                 Some(Immediate::Imm16((hi << 8) | lo))
             }
             _ => unreachable!(),
@@ -41,4 +195,224 @@ impl Cpu {
             size: info.3,
         })
     }
+
+    /// Returns the destination of this instruction, located at `addr`, if it is a JP, JR or CALL
+    /// with an immediate target.
+    pub fn branch_target(&self, addr: u16) -> Option<u16> {
+        match (self.opcode, self.imm) {
+            (0x18 | 0x20 | 0x28 | 0x30 | 0x38, Some(Immediate::Imm8(e))) => {
+                Some(addr.wrapping_add(2).wrapping_add(e as i8 as u16))
+            }
+            (
+                0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC,
+                Some(Immediate::Imm16(nn)),
+            ) => Some(nn),
+            _ => None,
+        }
+    }
+
+    /// Returns the mnemonic of this instruction, located at `addr`, with its operand
+    /// placeholders replaced by the actual values, eg. `LD A,$42` instead of `LD A,d8`.
+    ///
+    /// Relative jumps show their absolute target and CB-prefixed instructions are decoded.
+    pub fn resolved_mnemonic(&self, addr: u16) -> String {
+        match self.imm {
+            None => self.mnemonic.to_string(),
+            Some(Immediate::Imm8(cb)) if self.opcode == 0xCB => {
+                CB_OPCODES[usize::from(cb)].0.to_string()
+            }
+            Some(Immediate::Imm8(n)) => match self.branch_target(addr) {
+                Some(target) => self.mnemonic.replace("r8", &format!("${target:04X}")),
+                None => self
+                    .mnemonic
+                    .replace("d8", &format!("${n:02X}"))
+                    .replace("a8", &format!("$FF{n:02X}"))
+                    .replace("+r8", &format!("{:+}", n as i8))
+                    .replace("r8", &format!("{}", n as i8)),
+            },
+            Some(Immediate::Imm16(nn)) => self
+                .mnemonic
+                .replace("d16", &format!("${nn:04X}"))
+                .replace("a16", &format!("${nn:04X}")),
+        }
+    }
+
+    /// Returns the mnemonic of this instruction, located at `addr`, with its branch target
+    /// replaced by the matching label in `symbols`. Returns `None` if there is no such label.
+    pub fn labeled_mnemonic(&self, addr: u16, symbols: &dbg::Symbols) -> Option<String> {
+        let label = symbols.label(self.branch_target(addr)?)?;
+
+        Some(self.mnemonic.replace("a16", label).replace("r8", label))
+    }
+}
+
+impl Cpu {
+    pub fn disasm(&self, mem: &impl MemR, addr: u16) -> Result<Instruction, dbg::TraceEvent> {
+        Instruction::decode(mem, addr)
+    }
+}
+
+/// Disassembles the instructions in `start..=end` and returns them as a listing,
+/// one instruction per line, in the form `ADDR:  BYTES  MNEMONIC`.
+///
+/// Instructions are decoded linearly, without following jumps. Bytes that cannot be read
+/// are listed as `??`. Addresses found in `symbols` are preceded by their label, which also
+/// replaces the target of jumps and calls.
+pub fn disassemble_range(mem: &impl MemR, start: u16, end: u16, symbols: &dbg::Symbols) -> String {
+    let mut listing = String::new();
+    let mut addr = u32::from(start);
+
+    while addr <= u32::from(end) {
+        let pc = addr as u16;
+
+        if let Some(label) = symbols.label(pc) {
+            writeln!(listing, "{label}:").unwrap();
+        }
+
+        let (mnemonic, size) = match Instruction::decode(mem, pc) {
+            Ok(instr) => (
+                instr
+                    .labeled_mnemonic(pc, symbols)
+                    .unwrap_or_else(|| instr.resolved_mnemonic(pc)),
+                instr.size,
+            ),
+            Err(_) => (String::from("??"), 1),
+        };
+
+        let bytes = (0..u16::from(size))
+            .map(|i| match mem.read(pc.wrapping_add(i)) {
+                Ok(b) => format!("{b:02X}"),
+                Err(_) => "??".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        writeln!(listing, "{pc:04X}:  {bytes:<8}  {mnemonic}").unwrap();
+
+        addr += u32::from(size);
+    }
+
+    listing
+}
+
+/// Regions of the address space that may hold code: ROM, external RAM, work RAM and high RAM.
+const CODE_REGIONS: [(u16, u16); 4] = [
+    (0x0000, 0x7FFF),
+    (0xA000, 0xBFFF),
+    (0xC000, 0xDFFF),
+    (0xFF80, 0xFFFE),
+];
+
+/// Returns the addresses of all the occurrences of `opcode` in the regions of `mem`
+/// that may hold code. CB-prefixed opcodes are given as `0xCBxx`.
+///
+/// Each region is decoded linearly from its start, like [`disassemble_range`] does, so that
+/// the operands of multi-byte instructions are never reported as opcodes.
+/// Bytes that cannot be read are skipped.
+pub fn find_opcodes(mem: &impl MemR, opcode: u16) -> Vec<u16> {
+    let mut hits = Vec::new();
+
+    for (start, end) in CODE_REGIONS {
+        let mut addr = u32::from(start);
+
+        while addr <= u32::from(end) {
+            let pc = addr as u16;
+
+            let size = match Instruction::decode(mem, pc) {
+                Ok(instr) => {
+                    let found = match instr.imm {
+                        Some(Immediate::Imm8(op)) if instr.opcode == 0xCB => {
+                            opcode == 0xCB00 | u16::from(op)
+                        }
+                        _ => opcode == u16::from(instr.opcode),
+                    };
+                    if found {
+                        hits.push(pc);
+                    }
+                    instr.size
+                }
+                Err(_) => 1,
+            };
+
+            addr += u32::from(size);
+        }
+    }
+
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::{MemW, Memory};
+
+    #[test]
+    fn resolved_mnemonics() {
+        let mut mem = Memory::new(0x10);
+        let program = [
+            0x3E, 0x42, // LD A,$42
+            0xF0, 0x44, // LDH A,($FF44)
+            0xF8, 0xFE, // LD HL,SP-2
+            0x20, 0xFC, // JR NZ,$0004
+            0xCD, 0x34, 0x12, // CALL $1234
+            0xCB, 0x37, // SWAP A
+        ];
+        for (addr, &b) in program.iter().enumerate() {
+            mem.write(addr as u16, b).unwrap();
+        }
+
+        let mnemonics = [0, 2, 4, 6, 8, 11].map(|addr| {
+            Instruction::decode(&mem, addr)
+                .unwrap()
+                .resolved_mnemonic(addr)
+        });
+        assert_eq!(
+            mnemonics,
+            [
+                "LD A,$42",
+                "LDH A,($FF44)",
+                "LD HL,SP-2",
+                "JR NZ,$0004",
+                "CALL $1234",
+                "SWAP A",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassemble_known_range() {
+        // NOP; LD B,0x12; JP 0x0150; CALL 0x0200
+        let program = [0x00, 0x06, 0x12, 0xC3, 0x50, 0x01, 0xCD, 0x00, 0x02];
+
+        let mut mem = Memory::new(0x1000);
+        for (i, b) in program.iter().enumerate() {
+            mem.write(0x0100 + i as u16, *b).unwrap();
+        }
+
+        assert_eq!(
+            disassemble_range(&mem, 0x0100, 0x0106, &dbg::Symbols::new()),
+            "0100:  00        NOP\n\
+             0101:  06 12     LD B,$12\n\
+             0103:  C3 50 01  JP $0150\n\
+             0106:  CD 00 02  CALL $0200\n"
+        );
+    }
+
+    #[test]
+    fn find_jp_opcodes() {
+        // JP 0x0150; LD A,0xC3; LD HL,0xC3C3; JP 0x0100; SWAP A
+        let program = [
+            0xC3, 0x50, 0x01, 0x3E, 0xC3, 0x21, 0xC3, 0xC3, 0xC3, 0x00, 0x01, 0xCB, 0x37,
+        ];
+
+        // The rest of the memory is filled with single-byte RST 38H instructions
+        let mut mem = Memory::new(0xFFFF);
+        for (i, b) in program.iter().enumerate() {
+            mem.write(0x0150 + i as u16, *b).unwrap();
+        }
+
+        assert_eq!(find_opcodes(&mem, 0xC3), vec![0x0150, 0x0158]);
+        assert_eq!(find_opcodes(&mem, 0xCB37), vec![0x015B]);
+        assert!(find_opcodes(&mem, 0x37).is_empty());
+    }
 }
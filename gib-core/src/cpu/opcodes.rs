@@ -1,5 +1,8 @@
 use crate::{
-    cpu::{Cpu, MemoryAddressing::*, OpcodeInfo, OperandLocation::*, WritebackOp},
+    cpu::{
+        CallFrame, Cpu, IllegalOpcodePolicy, MemoryAddressing::*, OpcodeInfo, OperandLocation::*,
+        WritebackOp,
+    },
     dbg,
 };
 
@@ -26,9 +29,9 @@ macro_rules! call {
     ($cpu:ident, $cond:expr, $to:expr) => {{
         if $cond {
             $cpu.write_op = Some(WritebackOp::Push($cpu.pc));
+            $cpu.push_call_frame(CallFrame::Call($cpu.pc), $to);
             $cpu.pc = $to;
             $cpu.branch_taken = true;
-            $cpu.call_stack.push($cpu.pc);
         }
     }};
 }
@@ -36,7 +39,7 @@ macro_rules! call {
 macro_rules! ret {
     ($cpu:ident, $cond:expr) => {{
         if $cond {
-            $cpu.call_stack.pop();
+            $cpu.pop_call_frame();
             $cpu.write_op = Some(WritebackOp::Return);
             $cpu.branch_taken = true;
         }
@@ -60,19 +63,19 @@ macro_rules! or  { ($cpu:ident, $rhs:expr) => { logical!($cpu, |, $rhs, 0, 0, 0)
 
 macro_rules! inc {
     ($cpu:ident, $v:expr) => {{
-        $cpu.set_zf(($v + 1) == 0);
+        $cpu.set_zf($v.wrapping_add(1) == 0);
         $cpu.set_sf(false);
         $cpu.set_hc(($v & 0xF) == 0xF);
-        $v + 1
+        $v.wrapping_add(1)
     }};
 }
 
 macro_rules! dec {
     ($cpu:ident, $v:expr) => {{
-        $cpu.set_zf(($v - 1) == 0);
+        $cpu.set_zf($v.wrapping_sub(1) == 0);
         $cpu.set_sf(true);
         $cpu.set_hc($v.trailing_zeros() >= 4);
-        $v - 1
+        $v.wrapping_sub(1)
     }};
 }
 
@@ -82,7 +85,7 @@ macro_rules! add {
         let y = u16::from($v);
         let c = u16::from($cy);
 
-        let r = x + y + c;
+        let r = x.wrapping_add(y).wrapping_add(c);
         $cpu.set_a(r as u8);
 
         $cpu.set_zf($cpu.a() == 0);
@@ -98,7 +101,7 @@ macro_rules! sub {
         let y = u16::from($v);
         let c = u16::from($cy);
 
-        let r = x - y - c;
+        let r = x.wrapping_sub(y).wrapping_sub(c);
         $cpu.set_a(r as u8);
 
         $cpu.set_zf($cpu.a() == 0);
@@ -111,7 +114,7 @@ macro_rules! sub {
 macro_rules! add16 {
     ($cpu:ident, $dst: expr, $v:expr) => {{
         let old = $dst;
-        $dst += $v;
+        $dst = $dst.wrapping_add($v);
 
         $cpu.set_sf(false);
         $cpu.set_hc((old & 0x0FFF) + ($v & 0x0FFF) >= 0x1000);
@@ -123,7 +126,7 @@ macro_rules! addi16 {
     ($cpu:ident, $a:expr, $b:expr) => {{
         let b = u16::from($b as u8);
         let n = (i32::from($a) + i32::from($b)) as u16;
-        let r = ($a & 0xFF) + b;
+        let r = ($a & 0xFF).wrapping_add(b);
 
         $cpu.set_zf(false);
         $cpu.set_sf(false);
@@ -205,7 +208,7 @@ macro_rules! srl {
 
 macro_rules! swap {
     ($cpu:ident, $v:expr) => {{
-        let res = ($v >> 4) | ($v << 4);
+        let res = $v.rotate_left(4);
 
         $cpu.set_f(0);
         $cpu.set_zf(res == 0);
@@ -292,13 +295,13 @@ impl Cpu {
              */
             0x02 => self.write_op = Some(WritebackOp::Write8(self.bc, self.a())),
             0x12 => self.write_op = Some(WritebackOp::Write8(self.de, self.a())),
-            0x22 => { self.write_op = Some(WritebackOp::Write8(self.hl, self.a())); self.hl += 1; }
-            0x32 => { self.write_op = Some(WritebackOp::Write8(self.hl, self.a())); self.hl -= 1; }
+            0x22 => { self.write_op = Some(WritebackOp::Write8(self.hl, self.a())); self.hl = self.hl.wrapping_add(1); }
+            0x32 => { self.write_op = Some(WritebackOp::Write8(self.hl, self.a())); self.hl = self.hl.wrapping_sub(1); }
 
             0x0A => self.set_a(self.operand as u8),
             0x1A => self.set_a(self.operand as u8),
-            0x2A => { self.set_a(self.operand as u8); self.hl += 1; }
-            0x3A => { self.set_a(self.operand as u8); self.hl -= 1; }
+            0x2A => { self.set_a(self.operand as u8); self.hl = self.hl.wrapping_add(1); }
+            0x3A => { self.set_a(self.operand as u8); self.hl = self.hl.wrapping_sub(1); }
 
             0x06 => self.set_b(self.operand as u8),
             0x16 => self.set_d(self.operand as u8),
@@ -509,18 +512,18 @@ impl Cpu {
             0x27 => {
                 if !self.sf() {
                     if self.cy() || self.a() > 0x99 {
-                        self.set_a(self.a() + 0x60);
+                        self.set_a(self.a().wrapping_add(0x60));
                         self.set_cy(true);
                     }
                     if self.hc() || (self.a() & 0x0f) > 0x09 {
-                        self.set_a(self.a() + 0x06);
+                        self.set_a(self.a().wrapping_add(0x06));
                     }
                 } else {
                     if self.cy() {
-                        self.set_a(self.a() - 0x60);
+                        self.set_a(self.a().wrapping_sub(0x60));
                     }
                     if self.hc() {
-                        self.set_a(self.a() - 0x06);
+                        self.set_a(self.a().wrapping_sub(0x06));
                     }
                 }
 
@@ -531,15 +534,15 @@ impl Cpu {
             /*
              * 	16bit arithmetic/logical instructions
              */
-            0x03 => self.bc += 1,
-            0x13 => self.de += 1,
-            0x23 => self.hl += 1,
-            0x33 => self.sp += 1,
+            0x03 => self.bc = self.bc.wrapping_add(1),
+            0x13 => self.de = self.de.wrapping_add(1),
+            0x23 => self.hl = self.hl.wrapping_add(1),
+            0x33 => self.sp = self.sp.wrapping_add(1),
 
-            0x0B => self.bc -= 1,
-            0x1B => self.de -= 1,
-            0x2B => self.hl -= 1,
-            0x3B => self.sp -= 1,
+            0x0B => self.bc = self.bc.wrapping_sub(1),
+            0x1B => self.de = self.de.wrapping_sub(1),
+            0x2B => self.hl = self.hl.wrapping_sub(1),
+            0x3B => self.sp = self.sp.wrapping_sub(1),
 
             0x09 => add16!(self, self.hl, self.bc),
             0x19 => add16!(self, self.hl, self.de),
@@ -559,7 +562,16 @@ impl Cpu {
              * Invalid opcodes
              */
             0xCB | 0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
-                return Err(dbg::TraceEvent::IllegalInstructionFault(self.opcode));
+                // Illegal opcodes have no timing information, so only the fetch is accounted for
+                self.remaining_cycles = 0;
+
+                match self.illegal_opcode_policy() {
+                    IllegalOpcodePolicy::Halt => self.locked_up = true,
+                    IllegalOpcodePolicy::Skip => (),
+                    IllegalOpcodePolicy::Fault => {
+                        return Err(dbg::TraceEvent::IllegalInstructionFault(self.opcode));
+                    }
+                }
             }
         };
 
@@ -934,6 +946,7 @@ impl Cpu {
     }
 }
 
+/// Metadata of the unprefixed opcodes. Illegal opcodes are listed as "-", with no cycles.
 #[rustfmt::skip]
 pub const OPCODES: [OpcodeInfo; 256] = [
     OpcodeInfo("NOP",         Register,    Register,     1, 4,  4),
@@ -1194,6 +1207,279 @@ pub const OPCODES: [OpcodeInfo; 256] = [
     OpcodeInfo("RST 38H",     Register,    Register,     1, 16, 16),
 ];
 
+/// Metadata of the CB-prefixed opcodes, indexed by the byte following 0xCB.
+///
+/// Sizes and cycle counts include the prefix.
+#[rustfmt::skip]
+pub const CB_OPCODES: [OpcodeInfo; 256] = [
+    OpcodeInfo("RLC B",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RLC C",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RLC D",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RLC E",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RLC H",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RLC L",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RLC (HL)",    Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RLC A",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC B",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC C",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC D",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC E",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC H",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC L",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RRC (HL)",    Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RRC A",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL B",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL C",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL D",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL E",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL H",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL L",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RL (HL)",     Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RL A",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR B",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR C",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR D",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR E",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR H",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR L",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("RR (HL)",     Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RR A",        Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA B",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA C",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA D",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA E",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA H",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA L",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SLA (HL)",    Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SLA A",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA B",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA C",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA D",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA E",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA H",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA L",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRA (HL)",    Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SRA A",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP B",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP C",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP D",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP E",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP H",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP L",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SWAP (HL)",   Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SWAP A",      Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL B",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL C",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL D",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL E",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL H",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL L",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("SRL (HL)",    Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SRL A",       Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 0,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 0,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 1,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 1,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 2,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 2,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 3,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 3,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 4,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 4,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 5,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 5,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 6,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 6,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("BIT 7,(HL)",  Register,    Memory(HL),   2, 12, 12),
+    OpcodeInfo("BIT 7,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 0,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 0,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 1,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 1,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 2,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 2,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 3,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 3,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 4,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 4,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 5,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 5,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 6,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 6,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("RES 7,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("RES 7,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 0,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 0,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 1,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 1,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 2,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 2,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 3,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 3,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 4,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 4,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 5,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 5,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 6,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 6,A",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,B",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,C",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,D",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,E",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,H",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,L",     Register,    Register,     2, 8,  8),
+    OpcodeInfo("SET 7,(HL)",  Memory(HL),  Memory(HL),   2, 16, 16),
+    OpcodeInfo("SET 7,A",     Register,    Register,     2, 8,  8),
+];
+
+/// Returns the metadata of `opcode`, as found in [`OPCODES`].
+pub fn opcode_info(opcode: u8) -> OpcodeInfo {
+    OPCODES[usize::from(opcode)]
+}
+
+/// Returns the metadata of the CB-prefixed `opcode`, ie. the byte following 0xCB.
+pub fn cb_opcode_info(opcode: u8) -> OpcodeInfo {
+    CB_OPCODES[usize::from(opcode)]
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1204,20 +1490,20 @@ mod test {
         mem::{MemR, MemRW, MemW},
     };
 
-    impl<'a> MemR for &'a mut [u8] {
+    impl MemR for &mut [u8] {
         fn read(&self, addr: u16) -> Result<u8, dbg::TraceEvent> {
             Ok(self[addr as usize])
         }
     }
 
-    impl<'a> MemW for &'a mut [u8] {
+    impl MemW for &mut [u8] {
         fn write(&mut self, addr: u16, val: u8) -> Result<(), dbg::TraceEvent> {
             self[addr as usize] = val;
             Ok(())
         }
     }
 
-    impl<'a> MemRW for &'a mut [u8] {}
+    impl MemRW for &mut [u8] {}
 
     struct CpuTest {
         ticks: usize,
@@ -1248,9 +1534,9 @@ mod test {
             self
         }
 
-        fn setup<F: 'static>(mut self, setup: F) -> CpuTest
+        fn setup<F>(mut self, setup: F) -> CpuTest
         where
-            F: FnMut(&mut Cpu),
+            F: FnMut(&mut Cpu) + 'static,
         {
             self.setup_fn = Box::new(setup);
             self
@@ -1336,6 +1622,32 @@ mod test {
     #[test]
     fn branch_opcodes_work() {}
 
+    #[test]
+    fn call_ret_opcodes_work() {
+        // CALL a16; ...; RET
+        CpuTest::new(10, vec![0xCD, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC9])
+            .match_states(vec![
+                FetchByte0,
+                FetchByte1,
+                Writeback,
+                Writeback,
+                Writeback,
+                FetchOpcode,
+                Writeback,
+                Writeback,
+                Writeback,
+                FetchOpcode,
+            ])
+            .match_memory(vec![0xCD, 0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0xC9])
+            .setup(|cpu| {
+                cpu.sp = 0x0007;
+            })
+            .run(|cpu, _| {
+                assert_eq!(cpu.pc, 0x0003);
+                assert_eq!(cpu.sp, 0x0007);
+            });
+    }
+
     #[test]
     fn ld16_opcodes_work() {
         // LD rr,d16
@@ -1351,7 +1663,7 @@ mod test {
                 FetchByte0,
                 FetchByte1,
                 Writeback,
-                Delay(0),
+                Writeback,
                 FetchOpcode,
             ])
             .match_memory(vec![0x08, 0x03, 0x00, 0xC0, 0xBE])
@@ -1364,7 +1676,7 @@ mod test {
 
         // PUSH rr
         CpuTest::new(4, vec![0xD5, 0x00, 0x00, 0x22, 0x11])
-            .match_states(vec![Writeback, Delay(1), Delay(0), FetchOpcode])
+            .match_states(vec![Writeback, Writeback, Writeback, FetchOpcode])
             .match_memory(vec![0xD5, 0x00, 0x00, 0xBB, 0xAA])
             .setup(|cpu| {
                 cpu.sp = 0x0005;
@@ -1676,6 +1988,71 @@ mod test {
         }
     }
 
+    #[test]
+    fn arith_opcodes_wrap_around() {
+        // DEC A
+        CpuTest::new(1, vec![0x3D])
+            .match_states(vec![FetchOpcode])
+            .run(|cpu, _| {
+                assert_eq!(cpu.a(), 0xFF);
+                assert!(!cpu.zf() && cpu.sf() && cpu.hc());
+            });
+
+        // INC A
+        CpuTest::new(1, vec![0x3C])
+            .setup(|cpu| cpu.set_a(0xFF))
+            .run(|cpu, _| {
+                assert_eq!(cpu.a(), 0x00);
+                assert!(cpu.zf() && !cpu.sf() && cpu.hc());
+            });
+
+        // INC SP
+        CpuTest::new(2, vec![0x33])
+            .match_states(vec![Delay(0), FetchOpcode])
+            .setup(|cpu| cpu.sp = 0xFFFF)
+            .run(|cpu, _| {
+                assert_eq!(cpu.sp, 0x0000);
+            });
+
+        // DEC SP
+        CpuTest::new(2, vec![0x3B])
+            .match_states(vec![Delay(0), FetchOpcode])
+            .run(|cpu, _| {
+                assert_eq!(cpu.sp, 0xFFFF);
+            });
+
+        // LD (HL+),A
+        let mut memory = vec![0; 0x10000];
+        memory[0] = 0x22;
+        CpuTest::new(2, memory)
+            .setup(|cpu| cpu.hl = 0xFFFF)
+            .run(|cpu, _| {
+                assert_eq!(cpu.hl, 0x0000);
+            });
+
+        // SBC A,B
+        CpuTest::new(1, vec![0x98])
+            .setup(|cpu| {
+                cpu.set_b(0x01);
+                cpu.set_cy(true);
+            })
+            .run(|cpu, _| {
+                assert_eq!(cpu.a(), 0xFE);
+                assert!(cpu.cy());
+            });
+
+        // ADD HL,SP
+        CpuTest::new(2, vec![0x39])
+            .setup(|cpu| {
+                cpu.hl = 0xFFFF;
+                cpu.sp = 0x0002;
+            })
+            .run(|cpu, _| {
+                assert_eq!(cpu.hl, 0x0001);
+                assert!(cpu.cy());
+            });
+    }
+
     #[test]
     fn prefix_cb_opcodes_work() {
         // CB r
@@ -1695,6 +2072,37 @@ mod test {
             .run(|_, _| {});
     }
 
+    #[test]
+    fn opcode_info_lookup() {
+        let nop = opcode_info(0x00);
+        assert_eq!((nop.0, nop.3, nop.4, nop.5), ("NOP", 1, 4, 4));
+
+        let jr = opcode_info(0x20);
+        assert_eq!((jr.0, jr.3, jr.4, jr.5), ("JR NZ,r8", 2, 12, 8));
+
+        let bit = cb_opcode_info(0x46);
+        assert_eq!((bit.0, bit.3, bit.4), ("BIT 0,(HL)", 2, 12));
+
+        let set = cb_opcode_info(0xFF);
+        assert_eq!((set.0, set.3, set.4), ("SET 7,A", 2, 8));
+    }
+
+    #[test]
+    fn cb_opcode_timings_are_correct() {
+        for op in 0_u8..=255 {
+            let info = cb_opcode_info(op);
+
+            CpuTest::new(usize::from(info.4 / 4), [0xCB, op].repeat(0x8000)).run(|cpu, _| {
+                assert_eq!(
+                    cpu.state, FetchOpcode,
+                    "wrong timing for opcode CB {:02X}",
+                    op
+                );
+                assert_eq!(cpu.pc, 2);
+            });
+        }
+    }
+
     #[test]
     fn opcode_timings_are_correct() {
         for op in 0_u8..=255 {
@@ -1,8 +1,14 @@
 use std::{fmt, ops::RangeInclusive};
 
+pub use scan::*;
+pub use symbols::*;
+
+mod scan;
+mod symbols;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MemoryType {
-    RomBank(u8),
+    RomBank(u16),
     VideoRam,
     ExternalRam,
     WorkRamBank(u8),
@@ -75,6 +81,12 @@ impl MemoryType {
         }
     }
 
+    /// Returns the memory region containing `addr`.
+    ///
+    /// Since banks are not known here, switchable regions are reported with a bank number of
+    /// `0xFF`. Use [`GameBoy::memory_type_at`] to get the banks actually mapped.
+    ///
+    /// [`GameBoy::memory_type_at`]: crate::GameBoy::memory_type_at
     pub fn at(addr: u16) -> MemoryType {
         use MemoryType::*;
 
@@ -126,6 +138,22 @@ impl fmt::Display for McbOp {
     }
 }
 
+/// Direction of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "Read from"),
+            Access::Write => write!(f, "Write to"),
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug, Clone, Copy)]
 pub enum TraceEvent {
     #[error("Breakpoint reached: 0x{0:04X}")]
@@ -136,6 +164,10 @@ pub enum TraceEvent {
     BusFault(u16),
     #[error("Memory fault accessing 0x{0:04X}")]
     MemFault(u16),
+    /// Access to an unmapped address, along with the address of the offending instruction.
+    /// The bus reports a PC of 0, which is filled in by the CPU.
+    #[error("{access} unmapped 0x{addr:04X} from 0x{pc:04X}")]
+    UnmappedAccess { access: Access, addr: u16, pc: u16 },
     #[error("Unsupported MBC: {0:02X}")]
     UnsupportedMbcType(u8),
     #[error("Invalid MBC operation: {0} = {1:02X}")]
@@ -144,6 +176,14 @@ pub enum TraceEvent {
     CgbSpeedSwitchReq,
     #[error("Unsupported CGB operation: {0:04X}")]
     UnsupportedCgbOp(u16),
-    #[error("CGB mode not supported")]
-    CgbNotSupported,
+    /// SP moved outside of the bounds set by [`Cpu::set_stack_guard`](crate::cpu::Cpu::set_stack_guard).
+    #[error("Stack pointer out of bounds: 0x{0:04X}")]
+    StackBounds(u16),
+    /// The cycle budget given to [`GameBoy::run_until`](crate::GameBoy::run_until) ran out.
+    #[error("Timed out after {0} cycles")]
+    Timeout(u64),
+    /// A boot ROM passed to [`GameBoy::with_boot_rom`](crate::GameBoy::with_boot_rom) is
+    /// neither a DMG nor a CGB one.
+    #[error("Invalid boot ROM size: {0}")]
+    InvalidBootRomSize(usize),
 }
@@ -0,0 +1,224 @@
+/// Size of the values looked up by a [`MemoryScan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanWidth {
+    U8,
+    U16,
+}
+
+impl ScanWidth {
+    /// Returns the number of bytes occupied by a value of this width.
+    pub fn size(self) -> usize {
+        match self {
+            ScanWidth::U8 => 1,
+            ScanWidth::U16 => 2,
+        }
+    }
+}
+
+/// Condition used to narrow down the candidates of a [`MemoryScan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanFilter {
+    /// The value is equal to the given one.
+    Equal(u16),
+    /// The value has changed since the last scan.
+    Changed,
+    /// The value has not changed since the last scan.
+    Unchanged,
+    /// The value has increased since the last scan.
+    Increased,
+    /// The value has decreased since the last scan.
+    Decreased,
+}
+
+/// The error type returned when filtering a [`MemoryScan`] with a snapshot
+/// that does not cover the region the scan was started on.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("snapshot is {actual} bytes long, expected {expected}")]
+pub struct SnapshotSizeError {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+/// A memory scan, used to find the addresses holding a given value (eg. a game's score).
+///
+/// A scan starts from all the addresses in a memory region holding a value, and is then refined
+/// by filtering the candidate addresses against new snapshots of the same region.
+/// 16-bit values are read in little-endian order.
+#[derive(Debug, Clone)]
+pub struct MemoryScan {
+    width: ScanWidth,
+    base: u16,
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl MemoryScan {
+    /// Starts a new scan over `snapshot`, the contents of memory starting at address `base`,
+    /// keeping all the addresses holding `value`.
+    pub fn new(width: ScanWidth, base: u16, snapshot: Vec<u8>, value: u16) -> MemoryScan {
+        let mut scan = MemoryScan {
+            width,
+            base,
+            snapshot,
+            candidates: Vec::new(),
+        };
+
+        scan.candidates = (0..scan.snapshot.len().saturating_sub(width.size() - 1))
+            .map(|offset| base.wrapping_add(offset as u16))
+            .filter(|&addr| scan.value(addr) == value)
+            .collect();
+        scan
+    }
+
+    /// Narrows down the candidate addresses, comparing their values in `snapshot`
+    /// against the previous snapshot using `filter`.
+    ///
+    /// `snapshot` must cover the same memory region used to start the scan, as returned by
+    /// [`MemoryScan::base`] and [`MemoryScan::size`]. Otherwise, the scan is left untouched
+    /// and an error is returned.
+    pub fn filter(
+        &mut self,
+        snapshot: Vec<u8>,
+        filter: ScanFilter,
+    ) -> Result<(), SnapshotSizeError> {
+        if snapshot.len() != self.snapshot.len() {
+            return Err(SnapshotSizeError {
+                expected: self.snapshot.len(),
+                actual: snapshot.len(),
+            });
+        }
+
+        let old = std::mem::replace(&mut self.snapshot, snapshot);
+
+        let candidates = std::mem::take(&mut self.candidates);
+        self.candidates = candidates
+            .into_iter()
+            .filter(|&addr| {
+                let prev = self.value_at(&old, addr);
+                let curr = self.value_at(&self.snapshot, addr);
+
+                match filter {
+                    ScanFilter::Equal(v) => curr == v,
+                    ScanFilter::Changed => curr != prev,
+                    ScanFilter::Unchanged => curr == prev,
+                    ScanFilter::Increased => curr > prev,
+                    ScanFilter::Decreased => curr < prev,
+                }
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    /// Returns the addresses still matching the scan.
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /// Returns the value at `addr` in the latest snapshot.
+    pub fn value(&self, addr: u16) -> u16 {
+        self.value_at(&self.snapshot, addr)
+    }
+
+    /// Returns the first address of the memory region being scanned.
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// Returns the size in bytes of the memory region being scanned.
+    pub fn size(&self) -> usize {
+        self.snapshot.len()
+    }
+
+    /// Returns the width of the values being scanned.
+    pub fn width(&self) -> ScanWidth {
+        self.width
+    }
+
+    fn value_at(&self, snapshot: &[u8], addr: u16) -> u16 {
+        let offset = usize::from(addr.wrapping_sub(self.base));
+
+        match self.width {
+            ScanWidth::U8 => u16::from(snapshot[offset]),
+            ScanWidth::U16 => u16::from_le_bytes([snapshot[offset], snapshot[offset + 1]]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_filters() {
+        // Lives at 0xC001 and 0xC003, score at 0xC004-0xC005
+        let mut scan = MemoryScan::new(
+            ScanWidth::U8,
+            0xC000,
+            vec![0x00, 0x03, 0x07, 0x03, 0x10, 0x00],
+            3,
+        );
+        assert_eq!(scan.candidates(), [0xC001, 0xC003]);
+
+        // A life is lost
+        scan.filter(
+            vec![0x00, 0x03, 0x07, 0x02, 0x10, 0x00],
+            ScanFilter::Decreased,
+        )
+        .unwrap();
+        assert_eq!(scan.candidates(), [0xC003]);
+        assert_eq!(scan.value(0xC003), 2);
+
+        // Nothing changes
+        scan.filter(
+            vec![0x00, 0x03, 0x07, 0x02, 0x10, 0x00],
+            ScanFilter::Unchanged,
+        )
+        .unwrap();
+        assert_eq!(scan.candidates(), [0xC003]);
+
+        // 16-bit values can span across any two adjacent bytes
+        let mut scan = MemoryScan::new(
+            ScanWidth::U16,
+            0xC000,
+            vec![0x00, 0x03, 0x07, 0x03, 0x10, 0x00],
+            0x0010,
+        );
+        assert_eq!(scan.candidates(), [0xC004]);
+
+        scan.filter(
+            vec![0x00, 0x03, 0x07, 0x03, 0x20, 0x01],
+            ScanFilter::Increased,
+        )
+        .unwrap();
+        assert_eq!(scan.candidates(), [0xC004]);
+        assert_eq!(scan.value(0xC004), 0x0120);
+
+        scan.filter(
+            vec![0x00, 0x03, 0x07, 0x03, 0x20, 0x01],
+            ScanFilter::Changed,
+        )
+        .unwrap();
+        assert!(scan.candidates().is_empty());
+    }
+    #[test]
+    fn snapshot_size_mismatch() {
+        let mut scan = MemoryScan::new(ScanWidth::U8, 0xC000, vec![0x03, 0x00, 0x03, 0x00], 3);
+        assert_eq!((scan.base(), scan.size()), (0xC000, 4));
+
+        // A snapshot of a different region is rejected and the scan is left untouched
+        assert_eq!(
+            scan.filter(vec![0x00, 0x00], ScanFilter::Changed),
+            Err(SnapshotSizeError {
+                expected: 4,
+                actual: 2
+            })
+        );
+        assert_eq!(scan.candidates(), [0xC000, 0xC002]);
+        assert_eq!(scan.value(0xC002), 3);
+
+        scan.filter(vec![0x03, 0x00, 0x04, 0x00], ScanFilter::Unchanged)
+            .unwrap();
+        assert_eq!(scan.candidates(), [0xC000]);
+    }
+}
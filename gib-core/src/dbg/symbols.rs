@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+/// Labels loaded from a symbol file, as emitted by most Game Boy toolchains (eg. RGBDS).
+///
+/// Each line of a symbol file has the form `BB:AAAA Label`, where `BB` is the ROM bank and `AAAA`
+/// the address in hexadecimal. Comments start with `;`, and malformed lines are ignored.
+/// Since banks are not tracked, the first label found for a given address takes precedence.
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    labels: HashMap<u16, String>,
+}
+
+impl Symbols {
+    /// Creates an empty symbol table.
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Parses the content of a symbol file.
+    pub fn parse(sym: &str) -> Symbols {
+        let mut labels = HashMap::new();
+
+        for line in sym.lines() {
+            let line = line.split(';').next().unwrap_or_default();
+
+            let mut fields = line.split_whitespace();
+            let (location, label) = match (fields.next(), fields.next()) {
+                (Some(location), Some(label)) => (location, label),
+                _ => continue,
+            };
+
+            let addr = match location.split_once(':') {
+                Some((bank, addr)) if u8::from_str_radix(bank, 16).is_ok() => {
+                    u16::from_str_radix(addr, 16)
+                }
+                _ => continue,
+            };
+
+            if let Ok(addr) = addr {
+                labels.entry(addr).or_insert_with(|| label.to_string());
+            }
+        }
+
+        Symbols { labels }
+    }
+
+    /// Returns the label at `addr`, if any.
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// Returns the number of labels in the table.
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Returns whether the table contains no labels.
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
@@ -1,17 +1,83 @@
-use crossbeam::channel::{Receiver, Sender};
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    io::Write,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use crate::{bus::Bus, cpu::Cpu, dbg, io::JoypadState};
+use crossbeam::channel::{Receiver, Sender, TrySendError};
+
+use crate::{
+    bus::{
+        Bus, CartridgeHeader, CartridgeValidation, CgbMode, CpuBus, IoWrite, MbcType, RamBankError,
+    },
+    cpu::{Cpu, IllegalOpcodePolicy},
+    dbg,
+    io::{
+        ColorCorrection, IrqSource, JoypadState, Palette, PaletteChange, ServicedIrq, SpriteInfo,
+    },
+    mem::{MemR, MemW},
+};
 
 pub const CPU_CLOCK: u64 = 4_194_304; // Hz
+pub const SGB_CPU_CLOCK: u64 = 4_295_454; // Hz
 pub const HSYNC_CLOCK: u64 = 9_198; // Hz
 
 const CYCLES_PER_HSYNC: u64 = CPU_CLOCK / HSYNC_CLOCK;
 
+/// Number of entries kept in the interrupt log.
+pub const INTERRUPT_LOG_LEN: usize = 256;
+
+/// A function called with the frame completed at V-Blank. See [`GameBoy::set_vblank_callback`].
+pub type VBlankCallback = Box<dyn FnMut(&[u8]) + Send>;
+
+/// A snapshot of the whole emulator state, which can be restored using [`GameBoy::restore`].
+///
+/// The cartridge ROM is shared between snapshots rather than copied,
+/// so each snapshot only takes up the size of RAM and internal state.
+#[derive(Clone)]
+pub struct Snapshot {
+    cpu: Cpu,
+    bus: Bus,
+    cycles: u64,
+    instructions: u64,
+}
+
+impl Snapshot {
+    /// Returns the CPU state at the time the snapshot was taken.
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Returns the number of clock cycles elapsed at the time the snapshot was taken.
+    pub fn clock_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the approximate size of the snapshot in memory, in bytes.
+    ///
+    /// This accounts for the internal state along with the RAM and frame buffers,
+    /// which make up the bulk of it, but not for the shared cartridge ROM.
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Snapshot>() + self.bus.heap_size()
+    }
+}
+
 pub struct GameBoy {
     cpu: Cpu,
     bus: Bus,
 
     cycles: u64,
+    instructions: u64,
+    running: bool,
+
+    symbols: dbg::Symbols,
+    interrupt_log: VecDeque<ServicedIrq>,
+
+    vblank_callback: Option<VBlankCallback>,
 }
 
 impl Default for GameBoy {
@@ -21,6 +87,13 @@ impl Default for GameBoy {
             bus: Bus::new(),
 
             cycles: 0x18FCC,
+            instructions: 0,
+            running: true,
+
+            symbols: dbg::Symbols::new(),
+            interrupt_log: VecDeque::with_capacity(INTERRUPT_LOG_LEN),
+
+            vblank_callback: None,
         }
     }
 }
@@ -31,29 +104,263 @@ impl GameBoy {
         GameBoy::default()
     }
 
+    /// Maps `boot_rom` over the cartridge ROM and starts executing it from address 0x0000,
+    /// instead of skipping straight to the cartridge entry point.
+    ///
+    /// The boot ROM is unmapped as soon as the running program writes to 0xFF50, and it is
+    /// mapped again on every reset. While it is mapped, the CPU and the IO registers start from
+    /// their power-on values instead of those left by the boot ROM.
+    ///
+    /// Returns [`dbg::TraceEvent::InvalidBootRomSize`] if `boot_rom` is neither a DMG
+    /// (256 bytes) nor a CGB (2304 bytes) boot ROM.
+    pub fn with_boot_rom(mut self, boot_rom: &[u8]) -> Result<GameBoy, dbg::TraceEvent> {
+        if boot_rom.len() != 0x100 && boot_rom.len() != 0x900 {
+            return Err(dbg::TraceEvent::InvalidBootRomSize(boot_rom.len()));
+        }
+
+        self.bus.set_boot_rom(boot_rom);
+        self.init_registers();
+        Ok(self)
+    }
+
     /// Resets the Game Boy to its power-up state.
     ///
     /// The only things preserved by this operation are some debugging information related to the
     /// CPU (eg. breakpoints) and the audio channel for the APU, if one was configured.
+    /// If a boot ROM was provided, it is mapped again and executed from the start.
     pub fn reset(&mut self) {
         self.cpu.reset();
         self.bus.reset();
         self.cycles = Self::default().cycles;
+        self.instructions = 0;
+        self.interrupt_log.clear();
+        self.init_registers();
+    }
+
+    /// Enables or disables strict memory accesses.
+    ///
+    /// By default, out-of-spec memory accesses mimic the DMG hardware. In strict mode, they raise
+    /// a [`dbg::TraceEvent::UnmappedAccess`] instead, which is useful to catch bugs in games under
+    /// development. The affected regions are:
+    ///
+    /// - the unusable region (0xFEA0-0xFEFF), which reads as 0x00 and ignores writes
+    /// - unmapped IO registers (eg. 0xFF03), which read as 0xFF and ignore writes
+    pub fn set_strict_memory(&mut self, strict: bool) {
+        self.bus.set_strict_memory(strict);
+    }
+
+    pub fn strict_memory(&self) -> bool {
+        self.bus.strict_memory()
+    }
+
+    /// Same as [`GameBoy::reset`], but also preserves the contents of the cartridge RAM,
+    /// like power-cycling a cartridge with a battery would.
+    pub fn reset_preserving_ram(&mut self) {
+        self.cpu.reset();
+        self.bus.reset_preserving_ram();
+        self.cycles = Self::default().cycles;
+        self.instructions = 0;
+        self.interrupt_log.clear();
+        self.init_registers();
+    }
+
+    /// Sets the value of the 16-bit system counter at power-up, whose upper byte is read as DIV.
+    ///
+    /// Games seeding their RNG from DIV behave the same across runs with the same initial
+    /// value and input. The counter restarts from `value` right away, as well as on every reset,
+    /// and the setting is part of the snapshots taken afterwards.
+    pub fn set_initial_div(&mut self, value: u16) {
+        self.bus.tim.set_initial_counter(value);
+    }
+
+    /// Returns the value of the system counter at power-up. See [`GameBoy::set_initial_div`].
+    pub fn initial_div(&self) -> u16 {
+        self.bus.tim.initial_counter()
+    }
+
+    /// Sets how the CPU reacts to illegal opcodes.
+    ///
+    /// By default, illegal opcodes raise a [`dbg::TraceEvent::IllegalInstructionFault`], which is
+    /// useful when debugging. Otherwise, the CPU can either lock up like the real hardware does,
+    /// or ignore them altogether.
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.cpu.set_illegal_opcode_policy(policy);
     }
 
+    /// Loads a cartridge ROM, enabling CGB functions if required by its header.
+    ///
+    /// The symbols loaded for the previous cartridge, if any, are discarded.
     pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), dbg::TraceEvent> {
-        self.bus.load_rom(rom)
+        self.bus.load_rom(rom)?;
+        self.init_registers();
+        self.symbols = dbg::Symbols::new();
+        Ok(())
+    }
+
+    /// Returns the CGB mode of the loaded cartridge.
+    pub fn cgb_mode(&self) -> CgbMode {
+        self.bus.cgb_mode()
+    }
+
+    /// Returns the Memory Bank Controller of the loaded cartridge.
+    pub fn mbc_type(&self) -> MbcType {
+        self.bus.mbc_type()
+    }
+
+    /// Returns the metadata stored in the header of the loaded cartridge.
+    pub fn cartridge_header(&self) -> CartridgeHeader {
+        self.bus.cartridge_header()
+    }
+
+    /// Checks the header checksum and Nintendo logo of the loaded cartridge.
+    ///
+    /// Like on the real hardware, this is not enforced on load: it is up to the caller
+    /// to warn the user if the cartridge looks corrupt.
+    pub fn validate_cartridge(&self) -> CartridgeValidation {
+        self.bus.validate_cartridge()
+    }
+
+    /// Returns the ROM bank currently mapped to 0x4000-0x7FFF.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.bus.rom_bank() as u16
+    }
+
+    /// Returns the external RAM bank currently mapped to 0xA000-0xBFFF.
+    pub fn current_ram_bank(&self) -> u8 {
+        self.bus.ram_bank() as u8
     }
 
+    /// Returns the number of 8KB RAM banks in the cartridge, as declared by its header.
+    pub fn ram_bank_count(&self) -> usize {
+        self.bus.ram_bank_count()
+    }
+
+    /// Returns the contents of the cartridge RAM bank `index`, regardless of the mapped one.
+    ///
+    /// Returns `None` if `index` is not less than [`GameBoy::ram_bank_count`].
+    pub fn read_ram_bank(&self, index: usize) -> Option<&[u8]> {
+        self.bus.ram_bank_data(index)
+    }
+
+    /// Overwrites the start of the cartridge RAM bank `index` with `data`,
+    /// regardless of the mapped one.
+    ///
+    /// Fails without writing anything if `index` is not less than [`GameBoy::ram_bank_count`],
+    /// or if `data` is larger than a bank (8KB).
+    pub fn write_ram_bank(&mut self, index: usize, data: &[u8]) -> Result<(), RamBankError> {
+        let count = self.ram_bank_count();
+        let bank = self
+            .bus
+            .ram_bank_data_mut(index)
+            .ok_or(RamBankError::InvalidBank { index, count })?;
+
+        bank.get_mut(..data.len())
+            .ok_or(RamBankError::DataTooLarge { len: data.len() })?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Returns the memory region containing `addr`, along with the bank mapped to it.
+    pub fn memory_type_at(&self, addr: u16) -> dbg::MemoryType {
+        self.bus.memory_type_at(addr)
+    }
+
+    /// Returns whether the Game Boy is running with CGB functions enabled.
+    pub fn is_cgb(&self) -> bool {
+        self.cgb_mode().is_cgb()
+    }
+
+    /// Sets the CPU registers to the values left by the boot ROM, which differ in CGB mode.
+    ///
+    /// If the boot ROM is mapped, the registers are cleared instead, so that it can run from the
+    /// start and set them by itself.
+    fn init_registers(&mut self) {
+        if self.bus.boot_rom_mapped() {
+            self.cpu.af = 0x0000;
+            self.cpu.bc = 0x0000;
+            self.cpu.de = 0x0000;
+            self.cpu.hl = 0x0000;
+            self.cpu.sp = 0x0000;
+            self.cpu.set_entry_point(0x0000);
+        } else if self.is_cgb() {
+            self.cpu.af = 0x1180;
+            self.cpu.bc = 0x0000;
+            self.cpu.de = 0xFF56;
+            self.cpu.hl = 0x000D;
+        } else {
+            let cpu = Cpu::default();
+            self.cpu.af = cpu.af;
+            self.cpu.bc = cpu.bc;
+            self.cpu.de = cpu.de;
+            self.cpu.hl = cpu.hl;
+        }
+    }
+
+    /// Takes a snapshot of the current emulator state.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut bus = self.bus.clone();
+        bus.apu.detach_audio_source();
+
+        Snapshot {
+            cpu: self.cpu.clone(),
+            bus,
+            cycles: self.cycles,
+            instructions: self.instructions,
+        }
+    }
+
+    /// Restores a previously taken snapshot.
+    ///
+    /// Similarly to [`GameBoy::reset`], CPU debugging information and the APU audio channel
+    /// are preserved.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.cpu.restore(&snapshot.cpu);
+        self.bus.restore(&snapshot.bus);
+        self.cycles = snapshot.cycles;
+        self.instructions = snapshot.instructions;
+    }
+
+    /// Pauses the emulation, turning any further call to [`GameBoy::step`],
+    /// [`GameBoy::run_for_vblank`] and [`GameBoy::run_cycles`] into a no-op.
+    ///
+    /// This is independent of the CPU pause state used by the debugger for breakpoints.
+    pub fn pause(&mut self) {
+        self.running = false;
+    }
+
+    /// Resumes the emulation after a call to [`GameBoy::pause`].
+    pub fn resume(&mut self) {
+        self.running = true;
+    }
+
+    /// Returns whether the emulation is running, ie. it has not been paused.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Executes the next instruction, servicing any pending interrupt afterwards.
     pub fn step(&mut self) -> Result<(), dbg::TraceEvent> {
+        if !self.running {
+            return Ok(());
+        }
+
+        let locked_up = self.cpu.locked_up;
+
         // The first tick fetches the opcode
         self.tick()?;
 
+        // No instruction is executed while the CPU is halted
+        let halted = *self.cpu.halted.value() || locked_up;
+
         // The others perform the instruction itself, if necessary
         while self.cpu.executing {
             self.tick()?;
         }
 
+        if !halted {
+            self.instructions += 1;
+        }
+
         // Finally, handle any interrupts that arised
         self.handle_irqs()?;
 
@@ -61,7 +368,8 @@ impl GameBoy {
     }
 
     fn tick(&mut self) -> Result<(), dbg::TraceEvent> {
-        self.cpu.tick(&mut self.bus)?;
+        self.bus.set_io_write_cycle(self.cycles);
+        self.cpu.tick(&mut CpuBus(&mut self.bus))?;
 
         // Section 4.10 of "The Cycle-Accurate GameBoy Docs"
         // =================================================
@@ -69,23 +377,48 @@ impl GameBoy {
         // In this case, the CPU is NOT halted, and the HALT bug is triggered, causing the PC
         // to NOT be incremented when the next instruction is executed (ie. the next instruction
         // is executed twice).
-        if *self.cpu.halted.loaded()
-            && (!*self.cpu.intr_enabled.value() && self.bus.itr.pending_irqs())
+        // This only applies to a HALT that was just executed: if the CPU was already halted,
+        // a pending interrupt with IME = 0 simply wakes it up (see `handle_irqs`).
+        let halt_requested = *self.cpu.halted.loaded() && !*self.cpu.halted.value();
+
+        // In CGB mode, a STOP instruction executed after arming KEY1 switches
+        // the CPU speed instead of stopping the CPU.
+        if halt_requested && self.cpu.opcode == 0x10 && self.bus.speed_switch_armed() {
+            self.bus.switch_speed();
+            self.cpu.halted.reset(false);
+        } else if halt_requested
+            && self.cpu.opcode == 0x76
+            && !*self.cpu.intr_enabled.value()
+            && self.bus.itr.pending_irqs()
         {
-            self.cpu.halt_bug = true;
+            self.cpu.halt_bug = self.cpu.halt_bug_enabled();
             self.cpu.halted.reset(false);
         }
 
+        let frame = self.bus.ppu.frame_count();
         self.bus.tick()?;
 
+        // Only the callback itself is borrowed, so it can be invoked right away
+        if self.bus.ppu.frame_count() != frame {
+            if let Some(ref mut callback) = self.vblank_callback {
+                callback(self.bus.ppu.present());
+            }
+        }
+
         self.cycles += 4;
 
         Ok(())
     }
 
     fn handle_irqs(&mut self) -> Result<(), dbg::TraceEvent> {
-        if let Some(id) = self.bus.itr.get_pending_irq() {
-            let addr = (0x40 + 0x08 * id) as u16;
+        // Not even interrupts can recover a CPU from a lock up
+        if self.cpu.locked_up {
+            return Ok(());
+        }
+
+        if let Some(source) = self.bus.itr.get_pending_irq().and_then(IrqSource::from_id) {
+            let id = usize::from(source);
+            let addr = source.vector();
 
             self.cpu.halted.reset(false);
 
@@ -98,7 +431,16 @@ impl GameBoy {
 
                 // Jump to interrupt service routing and wait 5 cycles until
                 // the jump has been performed.
-                self.cpu.jump_to_isr(&mut self.bus, addr)?;
+                self.cpu.jump_to_isr(addr);
+
+                if self.interrupt_log.len() == INTERRUPT_LOG_LEN {
+                    self.interrupt_log.pop_front();
+                }
+                self.interrupt_log.push_back(ServicedIrq {
+                    cycle: self.cycles,
+                    source,
+                    handler: addr,
+                });
 
                 while self.cpu.executing {
                     self.tick()?;
@@ -109,7 +451,13 @@ impl GameBoy {
     }
 
     pub fn run_for_vblank(&mut self) -> Result<(), dbg::TraceEvent> {
-        let until = self.cycles + (CYCLES_PER_HSYNC * 154);
+        if !self.running {
+            return Ok(());
+        }
+
+        // In double speed mode, the CPU runs twice as many cycles per frame
+        let speed = if self.bus.double_speed() { 2 } else { 1 };
+        let until = self.cycles + (CYCLES_PER_HSYNC * 154 * speed);
 
         while self.cycles < until {
             self.step()?;
@@ -117,12 +465,207 @@ impl GameBoy {
         Ok(())
     }
 
+    /// Runs the emulation until the PPU completes the current frame.
+    ///
+    /// Execution stops early if a trace event (eg. a breakpoint) is raised. Since no frames
+    /// are produced while the LCD is off, it also stops after a frame's worth of cycles.
+    pub fn run_frame(&mut self) -> Result<(), dbg::TraceEvent> {
+        let frame = self.frame_count();
+
+        let speed = if self.bus.double_speed() { 2 } else { 1 };
+        let until = self.cycles + (CYCLES_PER_HSYNC * 154 * speed);
+
+        while self.running && self.frame_count() == frame && self.cycles < until {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Runs the emulation until at least `n` clock cycles have elapsed.
+    ///
+    /// Since instructions are executed atomically, the number of cycles actually run
+    /// may exceed `n`, and is returned on success. Execution stops early if a trace
+    /// event (eg. a breakpoint) is raised.
+    pub fn run_cycles(&mut self, n: u64) -> Result<u64, dbg::TraceEvent> {
+        let start = self.cycles;
+
+        while self.running && self.cycles - start < n {
+            self.step()?;
+        }
+        Ok(self.cycles - start)
+    }
+
+    /// Runs the emulation until `predicate` holds, checking it before every instruction.
+    ///
+    /// Returns the number of cycles run on success, or [`dbg::TraceEvent::Timeout`] if
+    /// `predicate` does not hold within `max_cycles`. Since every instruction takes at least
+    /// one M-cycle, at most `max_cycles / 4` instructions are executed, so this never hangs,
+    /// even if the emulation is paused.
+    pub fn run_until<F>(
+        &mut self,
+        mut predicate: F,
+        max_cycles: u64,
+    ) -> Result<u64, dbg::TraceEvent>
+    where
+        F: FnMut(&GameBoy) -> bool,
+    {
+        let start = self.cycles;
+
+        for _ in 0..=max_cycles / 4 {
+            if predicate(self) {
+                return Ok(self.cycles - start);
+            }
+            if self.cycles - start >= max_cycles {
+                break;
+            }
+            self.step()?;
+        }
+        Err(dbg::TraceEvent::Timeout(self.cycles - start))
+    }
+
+    /// Enables instruction tracing in gameboy-doctor format, writing to `writer`.
+    pub fn set_trace_writer(&mut self, writer: Box<dyn Write + Send>) {
+        self.cpu.set_trace_writer(Some(writer));
+    }
+
+    /// Disables instruction tracing.
+    pub fn clear_trace_writer(&mut self) {
+        self.cpu.set_trace_writer(None);
+    }
+
+    /// Enables or disables the disassembly suffix of traced instructions.
+    /// See [`Cpu::set_trace_disassembly`].
+    pub fn set_trace_disassembly(&mut self, enable: bool) {
+        self.cpu.set_trace_disassembly(enable);
+    }
+
+    /// Enables or disables the call profiler. See [`Cpu::set_call_profiling`].
+    pub fn set_call_profiling(&mut self, enable: bool) {
+        self.cpu.set_call_profiling(enable);
+    }
+
+    /// Returns the inclusive clock cycles spent in each function called since call profiling
+    /// was enabled or reset, with the slowest functions first.
+    ///
+    /// Functions are identified by their address. See [`Cpu::call_profile`] for more details.
+    pub fn call_profile(&self) -> Vec<(u16, u64)> {
+        let mut profile = self
+            .cpu
+            .call_profile()
+            .into_iter()
+            .map(|(addr, prof)| (addr, prof.inclusive))
+            .collect::<Vec<_>>();
+
+        profile.sort_by_key(|&(addr, cycles)| (Reverse(cycles), addr));
+        profile
+    }
+
+    /// Clears the call profile. See [`GameBoy::call_profile`].
+    pub fn reset_call_profile(&mut self) {
+        self.cpu.reset_call_profile();
+    }
+
+    /// Enables or disables counting the executions of each opcode.
+    pub fn set_profiling(&mut self, enable: bool) {
+        self.cpu.set_profiling(enable);
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.cpu.profiling()
+    }
+
+    /// Returns the number of executions of each opcode since profiling was enabled or reset.
+    /// All counters are zero if profiling is disabled.
+    ///
+    /// CB-prefixed opcodes are counted as 0xCB, see [`GameBoy::cb_opcode_histogram`].
+    pub fn opcode_histogram(&self) -> [u64; 256] {
+        self.cpu
+            .opcode_histogram()
+            .map_or([0; 256], |hist| hist.opcodes)
+    }
+
+    /// Same as [`GameBoy::opcode_histogram`], for the opcodes following a 0xCB prefix.
+    pub fn cb_opcode_histogram(&self) -> [u64; 256] {
+        self.cpu
+            .opcode_histogram()
+            .map_or([0; 256], |hist| hist.cb_opcodes)
+    }
+
+    /// Clears the opcode counters. See [`GameBoy::opcode_histogram`].
+    pub fn reset_opcode_histogram(&mut self) {
+        self.cpu.reset_opcode_histogram();
+    }
+
     /// Configures the audio channel for the sound peripheral, along with the required sample rate.
     pub fn configure_audio_channel(&mut self, source: AudioSource, sample_rate: f32) {
         self.bus.apu.set_sample_rate(sample_rate);
         self.bus.apu.set_audio_source(source);
     }
 
+    /// Configures a function receiving every sample produced by the sound peripheral, along
+    /// with the required sample rate.
+    ///
+    /// This replaces the audio channel, if one was configured, so that embedders can feed
+    /// their own audio pipeline directly. Audio-sync and taps only apply to audio channels.
+    pub fn configure_audio_callback(&mut self, callback: AudioCallback, sample_rate: f32) {
+        self.bus.apu.set_sample_rate(sample_rate);
+        self.bus.apu.set_audio_callback(callback);
+    }
+
+    /// Changes the sample rate at which the sound peripheral produces samples.
+    ///
+    /// When audio-sync is enabled, lowering the sample rate below the playback rate
+    /// results in the emulation running proportionally faster.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    /// Overrides the effective CPU clock rate, in Hz, which defaults to [`CPU_CLOCK`].
+    ///
+    /// This does not change the emulation itself, but how emulated time maps to real time:
+    /// audio samples are produced every `clock_rate / sample_rate` cycles, so with audio-sync
+    /// enabled the emulation runs at the given clock rate (eg. [`SGB_CPU_CLOCK`]).
+    pub fn set_clock_rate(&mut self, clock_rate: u64) {
+        self.bus.apu.set_clock_rate(clock_rate as f32);
+    }
+
+    pub fn clock_rate(&self) -> u64 {
+        self.bus.apu.clock_rate() as u64
+    }
+
+    /// Mutes or unmutes sound channel `idx` (0 to 3) in the audio output.
+    pub fn set_sound_channel_muted(&mut self, idx: usize, muted: bool) {
+        self.bus.apu.set_channel_muted(idx, muted);
+    }
+
+    /// Enables or disables capturing the recent output level of each sound channel.
+    ///
+    /// See [`Apu::set_level_capture`](crate::io::Apu::set_level_capture) for details.
+    pub fn set_sound_level_capture(&mut self, enabled: bool) {
+        self.bus.apu.set_level_capture(enabled);
+    }
+
+    /// Sets the volume of the audio output, from 0.0 (mute) to 1.0 (full volume).
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.bus.apu.set_master_volume(volume);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.bus.apu.master_volume()
+    }
+
+    /// Enables or disables audio fast-forward, in which the APU produces no samples.
+    ///
+    /// Use this when running faster than real time, to avoid flooding the audio stream.
+    pub fn set_audio_fast_forward(&mut self, fast_forward: bool) {
+        self.bus.apu.set_fast_forward(fast_forward);
+    }
+
+    /// Mutes all sound channels except `idx` (0 to 3), which gets unmuted.
+    pub fn solo_sound_channel(&mut self, idx: usize) {
+        self.bus.apu.solo_channel(idx);
+    }
+
     /// Enables or disables "sync-by-audio" emulation.
     ///
     /// When enabled, the emulation will block until one or more audio samples are requested by
@@ -141,6 +684,27 @@ impl GameBoy {
         }
     }
 
+    /// Returns the fill level of the audio stream, from 0.0 (empty) to 1.0 (full),
+    /// if one is configured. See [`AudioSource::fill_level`].
+    pub fn audio_fill_level(&self) -> Option<f32> {
+        self.bus.apu.audio_fill_level()
+    }
+
+    /// Sets a channel receiving a copy of every audio sample produced by the APU.
+    ///
+    /// This has no effect if no audio channel has been configured.
+    /// See [`GameBoy::configure_audio_channel`].
+    pub fn set_audio_tap(&mut self, tap: Option<Sender<i16>>) {
+        if let Some(source) = self.bus.apu.audio_source_mut() {
+            source.set_tap(tap);
+        }
+    }
+
+    /// Returns the overrun/underrun statistics of the audio stream, if one is configured.
+    pub fn audio_stats(&self) -> Option<AudioStats> {
+        self.bus.apu.audio_stats()
+    }
+
     /// Marks the given key as pressed.
     pub fn press_key(&mut self, key: JoypadState) {
         self.bus.joy.set_pressed_keys(key);
@@ -151,72 +715,370 @@ impl GameBoy {
         self.bus.joy.set_release_keys(key);
     }
 
+    /// Enables or disables filtering of opposing directions. See [`Joypad::set_filter_opposing`].
+    ///
+    /// [`Joypad::set_filter_opposing`]: crate::io::Joypad::set_filter_opposing
+    pub fn set_filter_opposing(&mut self, enable: bool) {
+        self.bus.joy.set_filter_opposing(enable);
+    }
+
+    /// Returns the keys currently seen as pressed by the emulator.
+    ///
+    /// The line selected by the game can be read from [`Joypad`](crate::io::Joypad).
+    pub fn joypad_state(&self) -> JoypadState {
+        self.bus.joy.pressed_keys()
+    }
+
+    /// Returns the bytes sent over the serial port so far. See [`Serial::output`].
+    ///
+    /// [`Serial::output`]: crate::io::Serial::output
+    pub fn serial_output(&self) -> &[u8] {
+        self.bus.sdt.output()
+    }
+
+    /// Discards the bytes sent over the serial port so far.
+    pub fn clear_serial_output(&mut self) {
+        self.bus.sdt.clear_output();
+    }
+
+    /// Returns the last frame completed at V-Blank, in RGBA format.
+    pub fn present(&self) -> &[u8] {
+        self.bus.ppu.present()
+    }
+
     pub fn rasterize(&self, vbuf: &mut [u8]) {
         self.bus.ppu.rasterize(vbuf);
     }
 
-    pub fn clock_cycles(&self) -> u64 {
-        self.cycles
+    /// Sets a function to be called at every V-Blank with the completed frame, in RGBA format.
+    ///
+    /// The frame is the same returned by [`GameBoy::present`], which makes it easy to present
+    /// frames without polling the emulator from the step loop.
+    pub fn set_vblank_callback(&mut self, callback: VBlankCallback) {
+        self.vblank_callback = Some(callback);
     }
 
-    pub fn cpu(&self) -> &Cpu {
-        &self.cpu
+    /// Removes the V-Blank callback, if any.
+    pub fn clear_vblank_callback(&mut self) {
+        self.vblank_callback = None;
     }
 
-    pub fn cpu_mut(&mut self) -> &mut Cpu {
-        &mut self.cpu
+    /// Sets the color correction applied when rendering CGB colors.
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.bus.ppu.set_color_correction(correction);
     }
 
-    pub fn bus(&self) -> &Bus {
-        &self.bus
+    /// Sets the colors used to render the four shades of DMG games.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.bus.ppu.set_palette(palette);
     }
-}
 
-/// The trasmitting end of an audio stream's channel.
-pub struct AudioSource {
-    channel: Sender<i16>,
-    blocking: bool,
-}
+    pub fn palette(&self) -> Palette {
+        self.bus.ppu.palette()
+    }
 
-impl AudioSource {
-    /// Sets the audio source's blocking behavior when pushing a new sample.
+    /// Returns the colors actually used to render DMG shades, which the game can override
+    /// on SGB-enhanced cartridges.
+    pub fn active_palette(&self) -> Palette {
+        self.bus.ppu.active_palette()
+    }
+
+    /// Returns whether SGB functions are enabled for the loaded cartridge.
+    pub fn is_sgb(&self) -> bool {
+        self.bus.sgb().is_some()
+    }
+
+    /// Enables or disables blending of each frame with the previous one, emulating the ghosting
+    /// of the DMG LCD. Some games rely on it for transparency effects through flickering sprites.
     ///
-    /// If non-blocking, new samples are discarded when there's no space left in the channel.
-    pub fn set_blocking(&mut self, blocking: bool) {
-        self.blocking = blocking
+    /// `weight` is the contribution of the previous frame to the output, from 0.0 to 1.0.
+    pub fn set_frame_blend(&mut self, enabled: bool, weight: f32) {
+        self.bus.ppu.set_frame_blend(enabled.then_some(weight));
     }
 
-    /// Pushes a new audio sample to the audio stream.
-    pub fn push(&mut self, sample: i16) {
-        if self.blocking {
-            self.channel.send(sample).ok();
-        } else {
-            self.channel.try_send(sample).ok();
-        }
+    /// Returns whether frame blending is enabled.
+    pub fn frame_blend(&self) -> bool {
+        self.bus.ppu.frame_blend().is_some()
     }
-}
 
-/// The receiving end of an audio stream's channel.
-pub struct AudioSink {
-    channel: Receiver<i16>,
-    blocking: bool,
-}
+    /// Returns the contents of the 40 sprites in OAM.
+    pub fn sprites(&self) -> [SpriteInfo; 40] {
+        self.bus.ppu.sprites()
+    }
 
-impl AudioSink {
-    /// Sets the audio source's blocking behavior when fetching a sample.
+    /// Overwrites the sprite at `index` (0-39) in OAM.
+    pub fn set_sprite(&mut self, index: usize, sprite: SpriteInfo) {
+        self.bus.ppu.set_sprite(index, sprite);
+    }
+
+    /// Returns the most recently serviced interrupts, oldest first.
     ///
-    /// In non-blocking mode, [`AudioSink::pop`] returns `None` if the channel is empty.
-    pub fn set_blocking(&mut self, blocking: bool) {
-        self.blocking = blocking
+    /// Only the last [`INTERRUPT_LOG_LEN`] entries are kept. The log is cleared on reset.
+    pub fn interrupt_log(&self) -> impl Iterator<Item = &ServicedIrq> {
+        self.interrupt_log.iter()
     }
 
-    /// Returns the next audio sample in the channel, or `None` in case of errors.
-    pub fn pop(&mut self) -> Option<i16> {
+    /// Enables or disables logging of all writes to IO registers (0xFF00-0xFF7F).
+    ///
+    /// This is useful to understand how a game drives the hardware. Disabling the log discards
+    /// the recorded writes, while a reset only clears it.
+    pub fn set_io_write_logging(&mut self, enable: bool) {
+        self.bus.set_io_write_logging(enable);
+    }
+
+    pub fn io_write_logging(&self) -> bool {
+        self.bus.io_write_logging()
+    }
+
+    /// Returns the most recent writes to IO registers, oldest first.
+    ///
+    /// Only the last [`IO_WRITE_LOG_LEN`] entries are kept, and nothing is recorded unless
+    /// enabled through [`GameBoy::set_io_write_logging`].
+    ///
+    /// [`IO_WRITE_LOG_LEN`]: crate::bus::IO_WRITE_LOG_LEN
+    pub fn io_write_log(&self) -> impl Iterator<Item = &IoWrite> {
+        self.bus.io_write_log()
+    }
+
+    /// Returns the most recent changes to the BGP, OBP0 and OBP1 registers, oldest first.
+    ///
+    /// This is useful to inspect palette cycling effects.
+    pub fn palette_history(&self) -> impl Iterator<Item = &PaletteChange> {
+        self.bus.ppu.palette_history().iter()
+    }
+
+    /// Returns the 384 tiles in VRAM in 2bpp format.
+    pub fn export_tiles(&self) -> Vec<u8> {
+        self.bus.ppu.export_tiles()
+    }
+
+    /// Loads tiles in 2bpp format into VRAM, starting from tile 0.
+    pub fn import_tiles(&mut self, data: &[u8]) {
+        self.bus.ppu.import_tiles(data);
+    }
+
+    /// Renders all the tiles in VRAM to an RGBA image of size [`crate::io::TILESHEET_SIZE`],
+    /// using the current background palette.
+    pub fn tilesheet(&self) -> Vec<u8> {
+        self.bus.ppu.tilesheet()
+    }
+
+    /// Renders the tile at `index` (0-383) in VRAM to an 8x8 RGBA image using `palette`.
+    pub fn tile_to_image(&self, index: usize, palette: u8) -> [u8; 8 * 8 * 4] {
+        self.bus.ppu.tile_to_image(index, palette)
+    }
+
+    pub fn clock_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the number of frames completed by the PPU since the last reset.
+    pub fn frame_count(&self) -> u64 {
+        self.bus.ppu.frame_count()
+    }
+
+    /// Returns the number of instructions executed since the last reset.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Reads the byte at `addr` for debugging purposes, without side effects.
+    ///
+    /// Access restrictions (eg. OAM during DMA) are bypassed, and addresses
+    /// that cannot be read are returned as 0xFF.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xFE00..=0xFE9F => self.bus.ppu.read_from_oam(addr),
+            _ => self.bus.read(addr).unwrap_or(0xFF),
+        }
+    }
+
+    /// Writes `val` to `addr` for debugging purposes, bypassing access restrictions.
+    ///
+    /// This is a best-effort operation: writes to unmapped addresses are ignored,
+    /// and writes to the ROM area reach the MBC, since ROM itself is read-only.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFE00..=0xFE9F => self.bus.ppu.write_to_oam(addr, val).ok(),
+            _ => self.bus.write(addr, val).ok(),
+        };
+    }
+
+    /// Reads `len` bytes of memory starting at `start`, without side effects.
+    ///
+    /// The range is clipped to the end of the address space. See [`GameBoy::peek`].
+    pub fn read_range(&self, start: u16, len: usize) -> Vec<u8> {
+        let end = (usize::from(start) + len).min(0x10000);
+
+        (usize::from(start)..end)
+            .map(|addr| self.peek(addr as u16))
+            .collect()
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// Loads the labels of a symbol file (`.sym`), replacing any previously loaded ones.
+    ///
+    /// See [`dbg::Symbols`] for details on the format.
+    pub fn load_symbols(&mut self, sym: &str) {
+        self.symbols = dbg::Symbols::parse(sym);
+    }
+
+    /// Returns the labels loaded from a symbol file.
+    pub fn symbols(&self) -> &dbg::Symbols {
+        &self.symbols
+    }
+}
+
+/// Statistics about the health of an audio stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AudioStats {
+    /// Number of samples dropped because the channel was full.
+    pub overruns: u64,
+    /// Number of times a sample was requested while the channel was empty.
+    pub underruns: u64,
+}
+
+/// Counters shared by both ends of an audio channel.
+#[derive(Debug, Default)]
+struct AudioCounters {
+    overruns: AtomicU64,
+    underruns: AtomicU64,
+    // Fill level last observed by the sink, stored as the bits of an `f32`
+    fill_level: AtomicU32,
+}
+
+impl AudioCounters {
+    fn stats(&self) -> AudioStats {
+        AudioStats {
+            overruns: self.overruns.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+        }
+    }
+
+    fn fill_level(&self) -> f32 {
+        f32::from_bits(self.fill_level.load(Ordering::Relaxed))
+    }
+}
+
+/// A function receiving every audio sample produced by the APU, as an alternative to an
+/// [`AudioSource`]. See [`GameBoy::configure_audio_callback`].
+pub type AudioCallback = Box<dyn FnMut(i16) + Send>;
+
+/// The trasmitting end of an audio stream's channel.
+#[derive(Debug, Clone)]
+pub struct AudioSource {
+    channel: Sender<i16>,
+    blocking: bool,
+    counters: Arc<AudioCounters>,
+    tap: Option<Sender<i16>>,
+}
+
+impl AudioSource {
+    /// Sets the audio source's blocking behavior when pushing a new sample.
+    ///
+    /// If non-blocking, new samples are discarded when there's no space left in the channel.
+    pub fn set_blocking(&mut self, blocking: bool) {
+        self.blocking = blocking
+    }
+
+    /// Sets a secondary channel receiving a copy of every sample pushed to the audio stream,
+    /// eg. for recording purposes. Samples are copied even if the audio stream is full.
+    pub fn set_tap(&mut self, tap: Option<Sender<i16>>) {
+        self.tap = tap;
+    }
+
+    /// Pushes a new audio sample to the audio stream.
+    pub fn push(&mut self, sample: i16) {
+        if let Some(ref tap) = self.tap {
+            tap.try_send(sample).ok();
+        }
+
         if self.blocking {
+            self.channel.send(sample).ok();
+        } else if let Err(TrySendError::Full(_)) = self.channel.try_send(sample) {
+            self.counters.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the overrun/underrun statistics of the audio stream.
+    pub fn stats(&self) -> AudioStats {
+        self.counters.stats()
+    }
+
+    /// Returns the number of samples the channel can hold.
+    pub fn capacity(&self) -> usize {
+        self.channel.capacity().unwrap_or_default()
+    }
+
+    /// Returns the fill level of the channel, from 0.0 (empty) to 1.0 (full),
+    /// as last observed by the [`AudioSink`] when fetching a sample.
+    ///
+    /// Measuring it on the receiving end makes it meaningful even when the source blocks,
+    /// as a blocked source would always find the channel full.
+    pub fn fill_level(&self) -> f32 {
+        self.counters.fill_level()
+    }
+}
+
+/// The receiving end of an audio stream's channel.
+pub struct AudioSink {
+    channel: Receiver<i16>,
+    blocking: bool,
+    counters: Arc<AudioCounters>,
+}
+
+impl AudioSink {
+    /// Sets the audio source's blocking behavior when fetching a sample.
+    ///
+    /// In non-blocking mode, [`AudioSink::pop`] returns `None` if the channel is empty.
+    pub fn set_blocking(&mut self, blocking: bool) {
+        self.blocking = blocking
+    }
+
+    /// Returns the next audio sample in the channel, or `None` in case of errors.
+    ///
+    /// Finding the channel empty is recorded as an underrun, even in blocking mode.
+    pub fn pop(&mut self) -> Option<i16> {
+        if self.channel.is_empty() {
+            self.counters.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let sample = if self.blocking {
             self.channel.recv().ok()
         } else {
             self.channel.try_recv().ok()
-        }
+        };
+
+        let fill = match self.channel.capacity() {
+            Some(cap) if cap > 0 => self.channel.len() as f32 / cap as f32,
+            _ => 0.0,
+        };
+        self.counters
+            .fill_level
+            .store(fill.to_bits(), Ordering::Relaxed);
+
+        sample
+    }
+
+    /// Returns the overrun/underrun statistics of the audio stream.
+    pub fn stats(&self) -> AudioStats {
+        self.counters.stats()
+    }
+
+    /// Returns the number of samples the channel can hold.
+    pub fn capacity(&self) -> usize {
+        self.channel.capacity().unwrap_or_default()
     }
 }
 
@@ -227,14 +1089,1203 @@ impl AudioSink {
 /// for audio playback.
 pub fn create_sound_channel(capacity: usize) -> (AudioSource, AudioSink) {
     let (sender, receiver) = crossbeam::channel::bounded(capacity);
+    let counters = Arc::new(AudioCounters::default());
     (
         AudioSource {
             channel: sender,
             blocking: true,
+            counters: counters.clone(),
+            tap: None,
         },
         AudioSink {
             channel: receiver,
             blocking: true,
+            counters,
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        bus::IO_WRITE_LOG_LEN,
+        cpu::{Breakpoint, CallFrame, Condition, Register},
+        io::IrqSource,
+    };
+
+    /// Creates a Game Boy running `program` from the cartridge entry point (0x0100).
+    fn with_program(program: &[u8]) -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+        gb
+    }
+
+    /// Trace writer whose contents can be inspected after being moved into the emulator
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cgb_mode_from_header() {
+        let mut rom = vec![0; 0x8000];
+        let mut gb = GameBoy::new();
+
+        // DMG cartridges run in DMG mode
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.cgb_mode(), CgbMode::Dmg);
+        assert!(!gb.is_cgb());
+        assert!(!gb.bus().ppu.cgb_mode());
+        assert_eq!(gb.cpu().a(), 0x01);
+
+        // CGB-compatible cartridges enable CGB functions, as they would on a CGB
+        rom[0x143] = 0x80;
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.cgb_mode(), CgbMode::CgbCompatible);
+        assert!(gb.is_cgb());
+        assert!(gb.bus().ppu.cgb_mode());
+        assert_eq!(gb.cpu().af, 0x1180);
+
+        // CGB-only cartridges enable CGB functions, with the CGB boot register values
+        rom[0x143] = 0xC0;
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.cgb_mode(), CgbMode::CgbOnly);
+        assert!(gb.is_cgb());
+        assert!(gb.bus().ppu.cgb_mode());
+        assert_eq!(gb.cpu().af, 0x1180);
+        assert_eq!(gb.cpu().bc, 0x0000);
+        assert_eq!(gb.cpu().de, 0xFF56);
+        assert_eq!(gb.cpu().hl, 0x000D);
+
+        // The mode is preserved across resets
+        gb.reset();
+        assert!(gb.is_cgb());
+        assert!(gb.bus().ppu.cgb_mode());
+        assert_eq!(gb.cpu().a(), 0x11);
+    }
+
+    #[test]
+    fn cgb_double_speed() {
+        // 114 NOPs, followed by LD A,$01; LDH ($4D),A; STOP
+        let mut rom = vec![0; 0x8000];
+        rom[0x172..0x177].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10]);
+        rom[0x143] = 0xC0;
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+
+        // One scanline takes 456 cycles at normal speed
+        let ly = gb.bus().read(0xFF44).unwrap();
+        gb.run_cycles(456).unwrap();
+        assert_eq!(gb.bus().read(0xFF44).unwrap(), (ly + 1) % 154);
+
+        while gb.cpu().pc != 0x0176 {
+            gb.step().unwrap();
+        }
+        assert_eq!(gb.bus().read(0xFF4D).unwrap(), 0x7F);
+
+        // STOP performs the speed switch, and execution continues afterwards
+        gb.step().unwrap();
+        assert!(gb.bus().double_speed());
+        assert_eq!(gb.bus().read(0xFF4D).unwrap(), 0xFE);
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, 0x0178);
+
+        // In double speed, scanlines take twice as many CPU cycles
+        let ly = gb.bus().read(0xFF44).unwrap();
+        gb.run_cycles(456 * 10).unwrap();
+        assert_eq!(gb.bus().read(0xFF44).unwrap(), (ly + 5) % 154);
+        gb.run_cycles(456 * 10).unwrap();
+        assert_eq!(gb.bus().read(0xFF44).unwrap(), (ly + 10) % 154);
+
+        // The same holds for whole frames
+        let start = gb.clock_cycles();
+        gb.run_for_vblank().unwrap();
+        assert_eq!(gb.clock_cycles() - start, 2 * 456 * 154);
+    }
+
+    #[test]
+    fn tile_to_image() {
+        let mut gb = GameBoy::new();
+
+        // Tile #1, each row has pixel values 0, 1, 2, 3, 3, 2, 1, 0
+        for row in 0..8 {
+            gb.bus.write(0x8010 + row * 2, 0b0101_1010).unwrap();
+            gb.bus.write(0x8010 + row * 2 + 1, 0b0011_1100).unwrap();
+        }
+
+        // Palette mapping 0 -> black, 1 -> dark gray, 2 -> light gray, 3 -> white
+        let img = gb.tile_to_image(1, 0b00_01_10_11);
+
+        let expected = [0x00, 0x55, 0xAA, 0xFF, 0xFF, 0xAA, 0x55, 0x00];
+        for (i, px) in img.chunks_exact(4).enumerate() {
+            let shade = expected[i % 8];
+            assert_eq!(px, [shade, shade, shade, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn halt_wakes_without_servicing_when_ime_is_off() {
+        // DI; HALT; INC A
+        let mut gb = with_program(&[0xF3, 0x76, 0x3C]);
+        gb.bus.write(0xFFFF, 0x04).unwrap();
+        let a = gb.cpu.af >> 8;
+
+        // With no pending interrupts, the CPU stays halted
+        for _ in 0..5 {
+            gb.step().unwrap();
+        }
+        assert!(*gb.cpu.halted.value());
+        assert_eq!(gb.cpu.pc, 0x0102);
+
+        // An enabled interrupt wakes the CPU up, but is not serviced
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+        gb.step().unwrap();
+        assert!(!*gb.cpu.halted.value());
+
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0103);
+        assert_eq!(gb.cpu.af >> 8, a + 1);
+        assert!(gb.bus.itr.ifg.bit(IrqSource::Timer.into()));
+    }
+
+    #[test]
+    fn halt_bug_when_irq_pending_and_ime_is_off() {
+        // DI; HALT; INC A
+        let mut gb = with_program(&[0xF3, 0x76, 0x3C]);
+        gb.bus.write(0xFFFF, 0x04).unwrap();
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+        let a = gb.cpu.af >> 8;
+
+        // The CPU does not halt, and the byte following HALT is executed twice
+        gb.step().unwrap();
+        gb.step().unwrap();
+        assert!(!*gb.cpu.halted.value());
+
+        gb.step().unwrap();
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0103);
+        assert_eq!(gb.cpu.af >> 8, a + 2);
+    }
+
+    #[test]
+    fn symbols_in_disassembly() {
+        // CALL 0x0150; JR -5
+        let mut gb = with_program(&[0xCD, 0x50, 0x01, 0x18, 0xFB]);
+        gb.load_symbols(
+            "; Symbols\n\
+             00:0100 Main\n\
+             00:0150 PrintString\n\
+             invalid line\n",
+        );
+
+        assert_eq!(gb.symbols().len(), 2);
+        assert_eq!(gb.symbols().label(0x0150), Some("PrintString"));
+
+        assert_eq!(
+            crate::cpu::disassemble_range(gb.bus(), 0x0100, 0x0103, gb.symbols()),
+            "Main:\n\
+             0100:  CD 50 01  CALL PrintString\n\
+             0103:  18 FB     JR Main\n"
+        );
+
+        // Symbols belong to the cartridge they were loaded for
+        gb.load_rom(&[0; 0x8000]).unwrap();
+        assert!(gb.symbols().is_empty());
+    }
+
+    #[test]
+    fn run_until_budget() {
+        // INC A; INC A; <illegal>
+        let mut gb = with_program(&[0x3C, 0x3C, 0xD3]);
+        gb.set_illegal_opcode_policy(IllegalOpcodePolicy::Halt);
+        let a = gb.cpu.a();
+
+        let res = gb.run_until(|gb| gb.cpu.a() == a.wrapping_add(2), 1000);
+        assert!(matches!(res, Ok(8)));
+
+        // Once locked up, the predicate can never hold
+        let res = gb.run_until(|gb| gb.cpu.a() == a, 1000);
+        assert!(matches!(res, Err(dbg::TraceEvent::Timeout(1000))));
+        assert!(gb.cpu.locked_up());
+
+        // Cycles do not advance while paused, but the helper still returns
+        gb.pause();
+        let res = gb.run_until(|gb| gb.cpu.a() == a, 1000);
+        assert!(matches!(res, Err(dbg::TraceEvent::Timeout(0))));
+    }
+
+    #[test]
+    fn initial_div() {
+        // Waits for a key press, then reads DIV into B: HALT; NOP; LDH A,(DIV); LD B,A; JR -2
+        let program = [0x76, 0x00, 0xF0, 0x04, 0x47, 0x18, 0xFE];
+
+        let run = |div| {
+            let mut gb = with_program(&program);
+            gb.set_initial_div(div);
+            gb.bus.write(0xFFFF, 0x10).unwrap();
+
+            gb.run_cycles(10_000).unwrap();
+            gb.press_key(JoypadState::A);
+            gb.run_cycles(10_000).unwrap();
+            gb
+        };
+
+        let (a, b) = (run(0x1234), run(0x1234));
+        assert_eq!(a.cpu.b(), b.cpu.b());
+        assert_ne!(a.cpu.b(), run(0x5678).cpu.b());
+
+        // The initial value is kept across resets and snapshots
+        let mut gb = run(0x5678);
+        let snapshot = gb.snapshot();
+        gb.reset();
+        assert_eq!(gb.initial_div(), 0x5678);
+        assert_eq!(gb.bus.tim.div().0, 0x56);
+
+        gb.set_initial_div(0x1234);
+        gb.restore(&snapshot);
+        assert_eq!(gb.initial_div(), 0x5678);
+    }
+
+    #[test]
+    fn boot_rom() {
+        // Like the real one, disables itself at the very end: JP 0x00FC; ...; LD A,1; LDH (0x50),A
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[..3].copy_from_slice(&[0xC3, 0xFC, 0x00]);
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+        let mut rom = vec![0; 0x8000];
+        rom[..2].copy_from_slice(&[0xAA, 0xBB]);
+        rom[0x100..0x103].copy_from_slice(&[0x18, 0xFE, 0x00]);
+
+        assert!(matches!(
+            GameBoy::new().with_boot_rom(&boot_rom[..0xFF]),
+            Err(dbg::TraceEvent::InvalidBootRomSize(0xFF))
+        ));
+
+        let mut gb = GameBoy::new().with_boot_rom(&boot_rom).unwrap();
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.cpu.pc, 0x0000);
+        assert_eq!(gb.cpu.call_stack(), [CallFrame::Call(0x0000)]);
+        assert_eq!(gb.bus.read(0x0000).unwrap(), 0xC3);
+        assert_eq!(gb.bus.read(0x0100).unwrap(), 0x18);
+
+        // The boot ROM finds the timer, LCD and APU in their power-on state
+        assert_eq!(gb.bus.read(0xFF04).unwrap(), 0x00);
+        assert_eq!(gb.bus.read(0xFF40).unwrap(), 0x00);
+        assert_eq!(gb.bus.read(0xFF26).unwrap() & 0x80, 0x00);
+
+        gb.run_cycles(100).unwrap();
+        assert!(!gb.bus.boot_rom_mapped());
+        assert_eq!(gb.cpu.pc, 0x0100);
+        assert_eq!(gb.bus.read(0x0000).unwrap(), 0xAA);
+
+        // Resetting maps the boot ROM again and restarts it from the beginning
+        gb.reset();
+        assert!(gb.bus.boot_rom_mapped());
+        assert_eq!(gb.cpu.pc, 0x0000);
+        assert_eq!(gb.bus.read(0x0000).unwrap(), 0xC3);
+        assert_eq!(gb.bus.read(0x0001).unwrap(), 0xFC);
+        assert_eq!(gb.cpu.call_stack(), [CallFrame::Call(0x0000)]);
+        assert_eq!(gb.bus.read(0xFF04).unwrap(), 0x00);
+        assert_eq!(gb.bus.read(0xFF40).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn call_profile() {
+        #[rustfmt::skip]
+        let program = [
+            0xCD, 0x10, 0x01,       // CALL $0110
+            0xCD, 0x20, 0x01,       // CALL $0120
+            0x76,                   // HALT
+            0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x00, 0xC9,             // $0110: NOP; RET
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x06, 0x10,             // $0120: LD B,16
+            0x05, 0x20, 0xFD,       //        DEC B; JR NZ,-3
+            0xC9,                   //        RET
+        ];
+        let mut gb = with_program(&program);
+        gb.set_call_profiling(true);
+        gb.run_cycles(2000).unwrap();
+
+        let profile = gb.call_profile();
+        let addrs = profile.iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+        assert_eq!(addrs, [0x0100, 0x0120, 0x0110]);
+
+        // NOP and RET take 5 M-cycles, the loop 2 + 16 * 4 - 1 + 4
+        let cycles = |addr| profile.iter().find(|p| p.0 == addr).unwrap().1;
+        assert_eq!(cycles(0x0110), 5 * 4);
+        assert_eq!(cycles(0x0120), 69 * 4);
+
+        // Only the entry point executes other code
+        let details = gb.cpu().call_profile();
+        for (addr, prof) in details {
+            assert_eq!(prof.calls, if addr == 0x0100 { 0 } else { 1 });
+            if addr != 0x0100 {
+                assert_eq!(prof.inclusive, prof.exclusive);
+            }
+        }
+        assert!(cycles(0x0100) > cycles(0x0110) + cycles(0x0120));
+
+        gb.reset_call_profile();
+        assert!(gb.call_profile().is_empty());
+    }
+
+    #[test]
+    fn opcode_histogram() {
+        // LD B,16; loop: INC A; SWAP A; DEC B; JR NZ,loop; HALT
+        let mut gb = with_program(&[0x06, 0x10, 0x3C, 0xCB, 0x37, 0x05, 0x20, 0xFA, 0x76]);
+
+        gb.run_cycles(1000).unwrap();
+        assert_eq!(gb.opcode_histogram(), [0; 256]);
+
+        gb.reset();
+        gb.set_profiling(true);
+        gb.run_cycles(1000).unwrap();
+
+        let hist = gb.opcode_histogram();
+        for op in [0x3C, 0xCB, 0x05, 0x20] {
+            assert_eq!(hist[op], 16);
+        }
+        assert_eq!(hist[0x06], 1);
+        assert_eq!(hist[0x76], 1);
+        assert_eq!(hist.iter().sum::<u64>(), 4 * 16 + 2);
+        assert_eq!(gb.cb_opcode_histogram()[0x37], 16);
+
+        gb.reset_opcode_histogram();
+        assert_eq!(gb.opcode_histogram(), [0; 256]);
+    }
+
+    #[test]
+    fn joypad_state() {
+        let mut gb = GameBoy::new();
+        assert!(gb.joypad_state().is_empty());
+
+        gb.press_key(JoypadState::UP);
+        gb.press_key(JoypadState::A);
+        assert_eq!(gb.joypad_state(), JoypadState::UP | JoypadState::A);
+
+        gb.release_key(JoypadState::UP);
+        assert_eq!(gb.joypad_state(), JoypadState::A);
+    }
+
+    #[test]
+    fn illegal_opcode_policy() {
+        // INC A; <illegal>; INC A
+        let program = [0x3C, 0xD3, 0x3C];
+
+        let run = |policy| {
+            let mut gb = with_program(&program);
+            gb.set_illegal_opcode_policy(policy);
+            gb.bus.write(0xFFFF, 0x04).unwrap();
+
+            let a = gb.cpu.a();
+            let res = (0..3).try_for_each(|_| gb.step());
+
+            (gb, a, res)
+        };
+
+        let (gb, a, res) = run(IllegalOpcodePolicy::Fault);
+        assert!(matches!(
+            res,
+            Err(dbg::TraceEvent::IllegalInstructionFault(0xD3))
+        ));
+        assert_eq!(gb.cpu.a(), a + 1);
+
+        let (gb, a, res) = run(IllegalOpcodePolicy::Skip);
+        assert!(res.is_ok());
+        assert!(!gb.cpu.locked_up());
+        assert_eq!(gb.cpu.a(), a + 2);
+
+        let (mut gb, a, res) = run(IllegalOpcodePolicy::Halt);
+        assert!(res.is_ok());
+        assert!(gb.cpu.locked_up());
+        assert_eq!(gb.cpu.pc, 0x0102);
+        assert_eq!(gb.cpu.a(), a + 1);
+
+        // Interrupts cannot wake up a locked up CPU
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0102);
+
+        // Only a reset recovers from a lock up
+        gb.reset();
+        assert!(!gb.cpu.locked_up());
+        assert_eq!(gb.cpu.illegal_opcode_policy(), IllegalOpcodePolicy::Halt);
+    }
+
+    #[test]
+    fn sprite_api() {
+        let mut gb = GameBoy::new();
+
+        let sprite = SpriteInfo {
+            y: 0x20,
+            x: 0x18,
+            tile: 0x42,
+            flip_x: true,
+            dmg_palette: true,
+            cgb_palette: 5,
+            ..Default::default()
+        };
+        gb.set_sprite(2, sprite);
+
+        // OAM entries are laid out as Y, X, tile, attributes
+        assert_eq!(gb.read_range(0xFE08, 4), [0x20, 0x18, 0x42, 0b0011_0101]);
+        assert_eq!(gb.sprites()[2], sprite);
+
+        // Writes to OAM are reflected by the API
+        gb.bus.write(0xFE0B, 0b1100_1000).unwrap();
+        let sprite = gb.sprites()[2];
+        assert!(sprite.bg_priority && sprite.flip_y && sprite.vram_bank);
+        assert!(!sprite.flip_x && !sprite.dmg_palette);
+        assert_eq!(sprite.cgb_palette, 0);
+    }
+
+    #[test]
+    fn peek_and_poke() {
+        let mut gb = GameBoy::new();
+        gb.set_strict_memory(true);
+
+        // Peeking has no side effects on IO registers
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+        let ifg = gb.bus.itr.ifg.0;
+        assert_eq!(gb.peek(0xFF0F), ifg | 0xE0);
+        assert_eq!(gb.peek(0xFF0F), ifg | 0xE0);
+        assert_eq!(gb.bus.itr.ifg.0, ifg);
+
+        // Unmapped addresses read as 0xFF, even in strict mode
+        assert_eq!(gb.peek(0xFEA0), 0xFF);
+        gb.poke(0xFEA0, 0x12);
+
+        // OAM is accessible even during DMA
+        gb.poke(0xC000, 0x42);
+        gb.bus.write(0xFF46, 0xC0).unwrap();
+        for _ in 0..3 {
+            gb.bus.tick().unwrap();
+        }
+        assert!(gb.bus.ppu.dma_source().is_some());
+        gb.poke(0xFE10, 0x24);
+        assert_eq!(gb.peek(0xFE00), 0x42);
+        assert_eq!(gb.peek(0xFE10), 0x24);
+        assert_eq!(gb.bus.read(0xFE10).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn frame_and_instruction_counters() {
+        // NOP; NOP; HALT
+        let mut gb = with_program(&[0x00, 0x00, 0x76]);
+        assert_eq!((gb.frame_count(), gb.instructions_executed()), (0, 0));
+
+        for _ in 0..3 {
+            gb.step().unwrap();
+        }
+        assert_eq!(gb.instructions_executed(), 3);
+
+        // No instructions are executed while halted, but the PPU keeps going
+        let frames = gb.frame_count();
+        gb.run_for_vblank().unwrap();
+        assert_eq!(gb.frame_count(), frames + 1);
+        assert_eq!(gb.instructions_executed(), 3);
+
+        gb.reset();
+        assert_eq!((gb.frame_count(), gb.instructions_executed()), (0, 0));
+    }
+
+    #[test]
+    fn present_only_changes_at_frame_boundaries() {
+        // LD A, 0xFF; LDH (BGP), A; JR -2
+        let mut gb = with_program(&[0x3E, 0xFF, 0xE0, 0x47, 0x18, 0xFE]);
+        let white = gb.present().to_vec();
+        assert!(white.iter().all(|&b| b == 0xFF));
+
+        // Changing the palette mid-frame has no effect on the presented frame...
+        gb.step().unwrap();
+        gb.step().unwrap();
+        assert_eq!(gb.present(), white);
+
+        let mut vbuf = vec![0xFF; 160 * 144 * 4];
+        gb.rasterize(&mut vbuf);
+        assert_ne!(vbuf, white);
+
+        // ...until the frame is complete
+        gb.run_frame().unwrap();
+        assert_eq!(gb.present(), vbuf);
+    }
+
+    #[test]
+    fn current_banks() {
+        let mut rom = vec![0; 0x20000];
+        rom[0x147] = 0x01; // MBC1
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.mbc_type(), MbcType::Mbc1);
+        assert_eq!((gb.current_rom_bank(), gb.current_ram_bank()), (1, 0));
+
+        gb.poke(0x2000, 0x05);
+        gb.poke(0x4000, 0x01);
+        assert_eq!((gb.current_rom_bank(), gb.current_ram_bank()), (5, 1));
+        assert_eq!(gb.memory_type_at(0x4000), dbg::MemoryType::RomBank(5));
+        assert_eq!(gb.memory_type_at(0x3FFF), dbg::MemoryType::RomBank(0));
+        assert_eq!(gb.memory_type_at(0xD000), dbg::MemoryType::WorkRamBank(1));
+    }
+
+    #[test]
+    fn ram_bank_access() {
+        let mut rom = vec![0; 0x20000];
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x03; // 32KB
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.ram_bank_count(), 4);
+
+        let a = vec![0xAA; 0x2000];
+        let b = (0..0x2000).map(|i| i as u8).collect::<Vec<_>>();
+        gb.write_ram_bank(1, &a).unwrap();
+        gb.write_ram_bank(3, &b).unwrap();
+        assert_eq!(gb.read_ram_bank(1), Some(&a[..]));
+        assert_eq!(gb.read_ram_bank(3), Some(&b[..]));
+
+        // Banks are accessed independently of the mapped one, and match what the CPU sees
+        assert_eq!(gb.current_ram_bank(), 0);
+        assert_ne!(gb.read_ram_bank(0), Some(&a[..]));
+        gb.poke(0x4000, 0x03);
+        assert_eq!(gb.peek(0xA000), 0x00);
+        assert_eq!(gb.peek(0xA0FF), 0xFF);
+        gb.poke(0xA000, 0x42);
+        assert_eq!(gb.read_ram_bank(3).unwrap()[0], 0x42);
+
+        // The banks survive a reset preserving RAM
+        gb.reset_preserving_ram();
+        assert_eq!(gb.ram_bank_count(), 4);
+        assert_eq!(gb.read_ram_bank(1), Some(&a[..]));
+
+        // Out of range accesses fail without touching the banks
+        assert_eq!(gb.read_ram_bank(4), None);
+        assert_eq!(
+            gb.write_ram_bank(4, &a),
+            Err(RamBankError::InvalidBank { index: 4, count: 4 })
+        );
+        assert_eq!(
+            gb.write_ram_bank(1, &[0; 0x2001]),
+            Err(RamBankError::DataTooLarge { len: 0x2001 })
+        );
+        assert_eq!(gb.read_ram_bank(1), Some(&a[..]));
+    }
+
+    #[test]
+    fn sgb_palette_command() {
+        // PAL01 with a red, green and blue palette 0
+        let mut packet = [0; 16];
+        packet[0] = 0x01;
+        for (i, color) in [0x7FFFu16, 0x001F, 0x03E0, 0x7C00].iter().enumerate() {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+        }
+
+        // Reset pulse, then each bit LSB first (P14 low for 0, P15 low for 1) and a 0 stop bit
+        let mut writes = vec![0x00, 0x30];
+        for i in 0..=128 {
+            let bit = i < 128 && (packet[i / 8] >> (i % 8)) & 1 != 0;
+            writes.extend([if bit { 0x10 } else { 0x20 }, 0x30]);
+        }
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x146] = 0x03;
+        rom[0x14B] = 0x33;
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+        assert!(gb.is_sgb());
+
+        for &val in &writes {
+            gb.poke(0xFF00, val);
+        }
+        let red_green_blue = Palette([
+            [0xFF, 0xFF, 0xFF],
+            [0xFF, 0x00, 0x00],
+            [0x00, 0xFF, 0x00],
+            [0x00, 0x00, 0xFF],
+        ]);
+        assert_eq!(gb.active_palette(), red_green_blue);
+        assert_eq!(gb.palette(), Palette::default());
+
+        // Cartridges without SGB support ignore the command
+        rom[0x146] = 0x00;
+        gb.load_rom(&rom).unwrap();
+        assert!(!gb.is_sgb());
+        for &val in &writes {
+            gb.poke(0xFF00, val);
+        }
+        assert_eq!(gb.active_palette(), Palette::default());
+    }
+
+    #[test]
+    fn irq_priority() {
+        // EI; NOP; NOP
+        let mut gb = with_program(&[0xFB, 0x00, 0x00]);
+        gb.bus.write(0xFFFF, 0x05).unwrap();
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+        gb.bus.itr.set_irq(IrqSource::VBlank.into());
+
+        // IME is only set after the instruction following EI
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0101);
+
+        // VBlank has the highest priority, and is serviced first
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0040);
+        assert!(!*gb.cpu.intr_enabled.value());
+        assert!(!gb.bus.itr.ifg.bit(IrqSource::VBlank.into()));
+        assert!(gb.bus.itr.ifg.bit(IrqSource::Timer.into()));
+    }
+
+    #[test]
+    fn ei_followed_by_di() {
+        // EI; DI; NOP
+        let mut gb = with_program(&[0xFB, 0xF3, 0x00]);
+        gb.bus.write(0xFFFF, 0x04).unwrap();
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+
+        for _ in 0..3 {
+            gb.step().unwrap();
+        }
+        assert_eq!(gb.cpu.pc, 0x0103);
+        assert!(!*gb.cpu.intr_enabled.value());
+        assert!(gb.bus.itr.ifg.bit(IrqSource::Timer.into()));
+    }
+
+    #[test]
+    fn halt_bug_fetches_next_byte_twice() {
+        // DI; HALT; LD B, 0x04 (which becomes LD B, 0x06; INC B)
+        let program = [0xF3, 0x76, 0x06, 0x04];
+
+        let mut gb = with_program(&program);
+        gb.bus.write(0xFFFF, 0x04).unwrap();
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+
+        for _ in 0..4 {
+            gb.step().unwrap();
+        }
+        assert_eq!(gb.cpu.pc, 0x0104);
+        assert_eq!(gb.cpu.b(), 0x07);
+
+        // Without the HALT bug, the HALT is skipped and execution continues normally
+        let mut gb = with_program(&program);
+        gb.cpu.set_halt_bug_enabled(false);
+        gb.bus.write(0xFFFF, 0x04).unwrap();
+        gb.bus.itr.set_irq(IrqSource::Timer.into());
+
+        for _ in 0..3 {
+            gb.step().unwrap();
+        }
+        assert!(!*gb.cpu.halted.value());
+        assert_eq!(gb.cpu.pc, 0x0104);
+        assert_eq!(gb.cpu.b(), 0x04);
+    }
+
+    #[test]
+    fn reset_preserves_breakpoints() {
+        // INC A; LD (0xA000), A
+        let mut gb = with_program(&[0x3C, 0xEA, 0x00, 0xA0]);
+        gb.cpu.set_breakpoint(0x0150);
+
+        gb.step().unwrap();
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0104);
+        assert_eq!(gb.bus.read(0xA000).unwrap(), 0x02);
+
+        gb.reset();
+        assert!(gb.cpu.breakpoint_at(0x0150));
+        assert_eq!(gb.cpu.pc, 0x0100);
+        assert_eq!(gb.cpu.af, Cpu::default().af);
+        assert_eq!(gb.clock_cycles(), GameBoy::default().clock_cycles());
+        assert_eq!(
+            gb.bus.read(0xA000).unwrap(),
+            Bus::new().read(0xA000).unwrap()
+        );
+
+        // The ROM is still loaded
+        gb.step().unwrap();
+        gb.step().unwrap();
+        assert_eq!(gb.bus.read(0xA000).unwrap(), 0x02);
+
+        // Cartridge RAM survives a reset only if asked to
+        gb.reset_preserving_ram();
+        assert!(gb.cpu.breakpoint_at(0x0150));
+        assert_eq!(gb.cpu.pc, 0x0100);
+        assert_eq!(gb.bus.read(0xA000).unwrap(), 0x02);
+    }
+
+    #[test]
+    fn disabled_breakpoints() {
+        // INC A; INC A; INC A
+        let mut gb = with_program(&[0x3C, 0x3C, 0x3C]);
+        gb.cpu.set_breakpoint(0x0101);
+        gb.cpu.set_breakpoint_enabled(0x0101, false);
+
+        // Disabled breakpoints do not pause the execution
+        gb.step().unwrap();
+        gb.step().unwrap();
+        assert_eq!(gb.cpu.pc, 0x0102);
+        assert!(gb.is_running());
+        assert!(gb.cpu.breakpoint_at(0x0101));
+
+        // Once enabled again, they do
+        gb.reset();
+        gb.cpu.set_breakpoint_enabled(0x0101, true);
+        gb.step().unwrap();
+        assert!(matches!(
+            gb.step(),
+            Err(dbg::TraceEvent::Breakpoint(0x0101))
+        ));
+        assert_eq!(gb.cpu.pc, 0x0101);
+    }
+
+    #[test]
+    fn conditional_and_one_shot_breakpoints() {
+        // INC A; INC A; JR -4
+        let mut gb = with_program(&[0x3C, 0x3C, 0x18, 0xFC]);
+        let a = gb.cpu.a();
+        gb.cpu.insert_breakpoint(
+            0x0101,
+            Breakpoint {
+                condition: Some(Condition::Equal(Register::A, u16::from(a) + 3)),
+                ..Default::default()
+            },
+        );
+
+        // Only fires on the second loop iteration, when A has been incremented three times
+        let mut steps = 0;
+        while gb.step().is_ok() {
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+        assert_eq!((gb.cpu.pc, gb.cpu.a()), (0x0101, a.wrapping_add(3)));
+
+        // One-shot breakpoints are removed once hit
+        gb.cpu.insert_breakpoint(
+            0x0100,
+            Breakpoint {
+                one_shot: true,
+                ..Default::default()
+            },
+        );
+        gb.resume();
+        while gb.step().is_ok() {}
+        assert_eq!(gb.cpu.pc, 0x0100);
+        assert!(!gb.cpu.breakpoint_at(0x0100));
+    }
+
+    #[test]
+    fn unmapped_write_reports_source() {
+        // NOP; LD (0xFEA1), A
+        let mut gb = with_program(&[0x00, 0xEA, 0xA1, 0xFE]);
+        gb.set_strict_memory(true);
+
+        gb.step().unwrap();
+        let evt = gb.step().unwrap_err();
+
+        assert!(matches!(
+            evt,
+            dbg::TraceEvent::UnmappedAccess {
+                access: dbg::Access::Write,
+                addr: 0xFEA1,
+                pc: 0x0101,
+            }
+        ));
+        assert_eq!(evt.to_string(), "Write to unmapped 0xFEA1 from 0x0101");
+    }
+
+    #[test]
+    fn strict_memory_io_holes() {
+        let mut gb = GameBoy::new();
+
+        // Lenient mode behaves like the hardware
+        assert!(!gb.strict_memory());
+        assert_eq!(gb.bus.read(0xFF03).unwrap(), 0xFF);
+        gb.bus.write(0xFF03, 0x12).unwrap();
+
+        gb.set_strict_memory(true);
+        assert!(matches!(
+            gb.bus.read(0xFF03),
+            Err(dbg::TraceEvent::UnmappedAccess {
+                access: dbg::Access::Read,
+                addr: 0xFF03,
+                ..
+            })
+        ));
+        assert!(matches!(
+            gb.bus.write(0xFF03, 0x12),
+            Err(dbg::TraceEvent::UnmappedAccess {
+                access: dbg::Access::Write,
+                addr: 0xFF03,
+                ..
+            })
+        ));
+
+        // The setting survives a reset
+        gb.reset();
+        assert!(gb.strict_memory());
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut gb = with_program(&[0x3C, 0x3C]);
+        assert!(gb.is_running());
+
+        gb.pause();
+        assert!(!gb.is_running());
+
+        // Nothing is executed while paused
+        let cycles = gb.clock_cycles();
+        gb.step().unwrap();
+        gb.run_for_vblank().unwrap();
+        assert_eq!(gb.run_cycles(100).unwrap(), 0);
+        assert_eq!(gb.cpu().pc, 0x0100);
+        assert_eq!(gb.clock_cycles(), cycles);
+
+        gb.resume();
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, 0x0101);
+    }
+
+    #[test]
+    fn paused_from_reset() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0x3C; // INC A
+
+        // The pause state survives loading a ROM and resetting
+        let mut gb = GameBoy::new();
+        gb.pause();
+        gb.load_rom(&rom).unwrap();
+        gb.reset();
+
+        let cycles = gb.clock_cycles();
+        gb.run_frame().unwrap();
+        gb.run_for_vblank().unwrap();
+        assert_eq!(gb.clock_cycles(), cycles);
+        assert_eq!((gb.cpu().pc, gb.instructions_executed()), (0x0100, 0));
+
+        // Once resumed, execution starts from the entry point
+        gb.resume();
+        gb.step().unwrap();
+        assert_eq!((gb.cpu().pc, gb.instructions_executed()), (0x0101, 1));
+        assert!(gb.clock_cycles() > cycles);
+    }
+
+    #[test]
+    fn run_cycles() {
+        // A ROM full of NOPs, plus a 24-cycle CALL starting 4 cycles before the budget expires
+        let mut rom = vec![0; 0x8000];
+        rom[0x100 + 249..0x100 + 252].copy_from_slice(&[0xCD, 0x00, 0x02]);
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+
+        let start = gb.clock_cycles();
+        let ran = gb.run_cycles(1000).unwrap();
+
+        assert_eq!(ran, 1020);
+        assert_eq!(gb.clock_cycles() - start, ran);
+        assert_eq!(gb.cpu().pc, 0x0200);
+    }
+
+    #[test]
+    fn call_stack_return_address() {
+        // CALL 0x0200; NOP, with a RET at 0x0200
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        rom[0x200] = 0xC9;
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+
+        // The frame records the address of the instruction following the CALL
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, 0x0200);
+        assert_eq!(
+            gb.cpu().call_stack(),
+            [CallFrame::Call(0x0100), CallFrame::Call(0x0103)]
+        );
+
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, 0x0103);
+        assert_eq!(gb.cpu().call_stack(), [CallFrame::Call(0x0100)]);
+    }
+
+    #[test]
+    fn interrupt_log() {
+        // EI; HALT
+        let mut gb = with_program(&[0xFB, 0x76]);
+        gb.bus.write(0xFFFF, 0x01).unwrap();
+        gb.bus.write(0xFF0F, 0x00).unwrap();
+        assert_eq!(gb.interrupt_log().count(), 0);
+
+        gb.run_for_vblank().unwrap();
+        while gb.interrupt_log().count() == 0 {
+            gb.step().unwrap();
+        }
+
+        let entry = *gb.interrupt_log().next().unwrap();
+        assert_eq!(entry.source, IrqSource::VBlank);
+        assert_eq!(entry.handler, 0x0040);
+        assert!(entry.cycle <= gb.clock_cycles());
+
+        gb.reset();
+        assert_eq!(gb.interrupt_log().count(), 0);
+    }
+
+    #[test]
+    fn io_write_log() {
+        // LD A,0x91; LDH (LCDC),A; LD A,0xE4; LDH (BGP),A; JR -2
+        let mut gb = with_program(&[0x3E, 0x91, 0xE0, 0x40, 0x3E, 0xE4, 0xE0, 0x47, 0x18, 0xFE]);
+        gb.bus.write(0xFF40, 0x00).unwrap();
+        assert_eq!(gb.io_write_log().count(), 0);
+
+        gb.set_io_write_logging(true);
+        let start = gb.clock_cycles();
+        for _ in 0..4 {
+            gb.step().unwrap();
+        }
+
+        let log = gb.io_write_log().copied().collect::<Vec<_>>();
+        assert_eq!(
+            log,
+            [
+                IoWrite {
+                    cycle: start + 16,
+                    addr: 0xFF40,
+                    value: 0x91,
+                },
+                IoWrite {
+                    cycle: start + 36,
+                    addr: 0xFF47,
+                    value: 0xE4,
+                },
+            ]
+        );
+
+        // Only IO registers are logged, and the log is bounded
+        for _ in 0..IO_WRITE_LOG_LEN {
+            gb.bus.write(0xC000, 0x00).unwrap();
+            gb.bus.write(0xFF80, 0x00).unwrap();
+            gb.bus.write(0xFF42, 0x00).unwrap();
+        }
+        assert_eq!(gb.io_write_log().count(), IO_WRITE_LOG_LEN);
+        assert!(gb.io_write_log().all(|w| w.addr == 0xFF42));
+
+        // Resetting clears the log, disabling it stops recording
+        gb.reset();
+        assert!(gb.io_write_logging());
+        assert_eq!(gb.io_write_log().count(), 0);
+
+        gb.set_io_write_logging(false);
+        gb.bus.write(0xFF42, 0x00).unwrap();
+        assert_eq!(gb.io_write_log().count(), 0);
+    }
+
+    #[test]
+    fn vblank_callback() {
+        let mut gb = with_program(&[0x18, 0xFE]);
+        let frames = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = frames.clone();
+        gb.set_vblank_callback(Box::new(move |frame| {
+            sink.lock().unwrap().push(frame.len())
+        }));
+
+        gb.run_frame().unwrap();
+        assert_eq!(*frames.lock().unwrap(), [160 * 144 * 4]);
+
+        gb.clear_vblank_callback();
+        gb.run_frame().unwrap();
+        assert_eq!(frames.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn call_stack_interrupt_frame() {
+        // EI; NOP; NOP, with a RETI at the VBlank vector
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0xFB;
+        rom[0x40] = 0xD9;
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+        gb.bus.write(0xFFFF, 0x01).unwrap();
+        gb.step().unwrap();
+        gb.bus.itr.set_irq(IrqSource::VBlank.into());
+
+        for _ in 0..4 {
+            if gb.cpu().call_stack().len() > 1 {
+                break;
+            }
+            gb.step().unwrap();
+        }
+
+        let frame = *gb.cpu().call_stack().last().unwrap();
+        let ret = frame.return_address();
+        assert_eq!(frame, CallFrame::Interrupt { vector: 0x40, ret });
+        assert_eq!(gb.cpu().pc, 0x0040);
+
+        // The frame is removed once the handler returns
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, ret);
+        assert_eq!(gb.cpu().call_stack(), [CallFrame::Call(0x0100)]);
+    }
+
+    #[test]
+    fn call_stack_underflow() {
+        // CALL 0x0200, with a RET at 0x0200 and an unmatched RET following the call
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0xCD, 0x00, 0x02, 0xC9]);
+        rom[0x200] = 0xC9;
+
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).unwrap();
+        assert_eq!(gb.cpu().call_stack(), [CallFrame::Call(0x0100)]);
+
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().call_stack().len(), 2);
+
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, 0x0103);
+        assert_eq!(gb.cpu().call_stack(), [CallFrame::Call(0x0100)]);
+
+        // The entry point frame survives a RET without a matching CALL
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().call_stack(), [CallFrame::Call(0x0100)]);
+    }
+
+    #[test]
+    fn trace_writer() {
+        // LD A,$42; LD BC,$1234; NOP
+        let mut gb = with_program(&[0x3E, 0x42, 0x01, 0x34, 0x12, 0x00]);
+
+        let buf = SharedBuf::default();
+        gb.set_trace_writer(Box::new(buf.clone()));
+
+        for _ in 0..3 {
+            gb.step().unwrap();
+        }
+
+        let trace = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,01,34",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:01,34,12,00",
+                "A:42 F:B0 B:12 C:34 D:00 E:D8 H:01 L:4D SP:FFFE PC:0105 PCMEM:00,00,00,00",
+            ]
+        );
+
+        // No more lines are traced once disabled
+        gb.clear_trace_writer();
+        gb.step().unwrap();
+        assert_eq!(trace.len(), buf.0.lock().unwrap().len());
+    }
+
+    #[test]
+    fn trace_disassembly() {
+        // LD A,$42; JR -2; SWAP A
+        let mut gb = with_program(&[0x3E, 0x42, 0x18, 0xFE, 0xCB, 0x37]);
+
+        let buf = SharedBuf::default();
+        gb.set_trace_writer(Box::new(buf.clone()));
+        gb.set_trace_disassembly(true);
+
+        for _ in 0..2 {
+            gb.step().unwrap();
+        }
+
+        let trace = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,18,FE ; LD A,$42",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:18,FE,CB,37 ; JR $0102",
+            ]
+        );
+    }
+
+    #[test]
+    fn audio_overruns_and_underruns() {
+        let (mut source, mut sink) = create_sound_channel(4);
+        source.set_blocking(false);
+        sink.set_blocking(false);
+
+        // Samples exceeding the channel capacity are dropped
+        for i in 0..6 {
+            source.push(i);
+        }
+        assert_eq!(source.stats().overruns, 2);
+
+        // Draining the channel past its contents starves the sink
+        for _ in 0..5 {
+            sink.pop();
+        }
+        assert_eq!(
+            sink.stats(),
+            AudioStats {
+                overruns: 2,
+                underruns: 1
+            }
+        );
+    }
+
+    #[test]
+    fn audio_fill_level() {
+        let (mut source, mut sink) = create_sound_channel(4);
+        source.set_blocking(false);
+        sink.set_blocking(false);
+
+        // The level is only updated when the sink fetches a sample
+        for i in 0..4 {
+            source.push(i);
+        }
+        assert_eq!(source.fill_level(), 0.0);
+
+        sink.pop();
+        assert_eq!(source.fill_level(), 0.75);
+
+        for _ in 0..3 {
+            sink.pop();
+        }
+        assert_eq!(source.fill_level(), 0.0);
+    }
+
+    #[test]
+    fn audio_tap() {
+        let (mut source, _sink) = create_sound_channel(2);
+        source.set_blocking(false);
+
+        let (tap, samples) = crossbeam::channel::unbounded();
+        source.set_tap(Some(tap));
+
+        // The tap receives every sample, even those dropped by the audio stream
+        for i in 0..4 {
+            source.push(i);
+        }
+        assert_eq!(source.stats().overruns, 2);
+        assert_eq!(samples.try_iter().collect::<Vec<_>>(), [0, 1, 2, 3]);
+
+        source.set_tap(None);
+        source.push(4);
+        assert!(samples.is_empty());
+    }
+}
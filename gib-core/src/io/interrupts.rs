@@ -5,6 +5,7 @@ use crate::{
 };
 
 /// Possible sources of interrupt in the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IrqSource {
     VBlank,
     LcdStat,
@@ -25,11 +26,40 @@ impl From<IrqSource> for usize {
     }
 }
 
+impl IrqSource {
+    /// Returns the source corresponding to bit `id` of the IE and IF registers.
+    pub fn from_id(id: usize) -> Option<IrqSource> {
+        match id {
+            0 => Some(IrqSource::VBlank),
+            1 => Some(IrqSource::LcdStat),
+            2 => Some(IrqSource::Timer),
+            3 => Some(IrqSource::Serial),
+            4 => Some(IrqSource::Joypad),
+            _ => None,
+        }
+    }
+
+    /// Returns the address of the handler of this interrupt.
+    pub fn vector(self) -> u16 {
+        0x40 + 0x08 * usize::from(self) as u16
+    }
+}
+
+/// An interrupt serviced by the CPU, as recorded in the interrupt log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServicedIrq {
+    /// Clock cycle at which the CPU jumped to the handler.
+    pub cycle: u64,
+    pub source: IrqSource,
+    /// Address of the interrupt handler.
+    pub handler: u16,
+}
+
 pub trait InterruptSource {
     fn get_and_clear_irq(&mut self) -> Option<IrqSource>;
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct IrqController {
     pub ien: IoReg<u8>,
     pub ifg: IoReg<u8>,
@@ -44,6 +74,9 @@ impl IrqController {
         self.get_pending_irq().is_some()
     }
 
+    /// Returns the enabled and requested interrupt with the highest priority, if any.
+    ///
+    /// Lower bits have higher priority, from VBlank (bit 0) to Joypad (bit 4).
     pub fn get_pending_irq(&self) -> Option<usize> {
         (0..=4).find(|&req_id| self.ien.bit(req_id) && self.ifg.bit(req_id))
     }
@@ -60,7 +93,9 @@ impl IrqController {
 impl MemR for IrqController {
     fn read(&self, addr: u16) -> Result<u8, dbg::TraceEvent> {
         Ok(match addr {
+            // The upper 3 bits of IF are unused and always read as 1
             0xFF0F => self.ifg.0 | 0xE0,
+            // Unlike IF, the upper 3 bits of IE are fully readable and writable
             0xFFFF => self.ien.0,
             _ => unreachable!(),
         })
@@ -70,7 +105,7 @@ impl MemR for IrqController {
 impl MemW for IrqController {
     fn write(&mut self, addr: u16, val: u8) -> Result<(), dbg::TraceEvent> {
         match addr {
-            0xFF0F => self.ifg.0 = val,
+            0xFF0F => self.ifg.0 = val & 0x1F,
             0xFFFF => self.ien.0 = val,
             _ => unreachable!(),
         };
@@ -79,3 +114,40 @@ impl MemW for IrqController {
 }
 
 impl MemRW for IrqController {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_upper_bits() {
+        let mut itr = IrqController::new();
+
+        itr.write(0xFF0F, 0x00).unwrap();
+        assert_eq!(itr.read(0xFF0F).unwrap(), 0xE0);
+
+        itr.write(0xFF0F, 0x1F).unwrap();
+        assert_eq!(itr.read(0xFF0F).unwrap(), 0xFF);
+
+        // Requesting an interrupt does not affect the upper bits
+        itr.write(0xFF0F, 0x00).unwrap();
+        itr.set_irq(IrqSource::Serial.into());
+        assert_eq!(itr.read(0xFF0F).unwrap(), 0xE8);
+    }
+
+    #[test]
+    fn ie_upper_bits() {
+        let mut itr = IrqController::new();
+
+        itr.write(0xFFFF, 0xFF).unwrap();
+        assert_eq!(itr.read(0xFFFF).unwrap(), 0xFF);
+
+        // Only the low 5 bits gate interrupts
+        itr.write(0xFFFF, 0xE0).unwrap();
+        itr.write(0xFF0F, 0xFF).unwrap();
+        assert_eq!(itr.get_pending_irq(), None);
+
+        itr.write(0xFFFF, 0xE4).unwrap();
+        assert_eq!(itr.get_pending_irq(), Some(IrqSource::Timer.into()));
+    }
+}
@@ -2,6 +2,7 @@ use bitflags::bitflags;
 
 use crate::{
     dbg,
+    io::{InterruptSource, IrqSource},
     mem::{MemR, MemRW, MemW},
 };
 
@@ -32,10 +33,25 @@ bitflags! {
 
 mem_rw!(JoyP, 0xC0);
 
+/// Pairs of directions that cannot be pressed at the same time on a real d-pad.
+const OPPOSING_DIRECTIONS: [(JoypadState, JoypadState); 2] = [
+    (JoypadState::LEFT, JoypadState::RIGHT),
+    (JoypadState::UP, JoypadState::DOWN),
+];
+
+#[derive(Clone)]
 pub struct Joypad {
     joyp: JoyP,
 
     state: JoypadState,
+    // Keys held down by the user, before any filtering
+    held: JoypadState,
+    // Most recently pressed direction on each axis
+    last_directions: JoypadState,
+    // Whether pressing a direction releases the opposite one
+    filter_opposing: bool,
+
+    irq_pending: bool,
 }
 
 impl Default for Joypad {
@@ -43,6 +59,10 @@ impl Default for Joypad {
         Joypad {
             joyp: JoyP::DEFAULT,
             state: JoypadState::DEFAULT,
+            held: JoypadState::empty(),
+            last_directions: JoypadState::empty(),
+            filter_opposing: true,
+            irq_pending: false,
         }
     }
 }
@@ -52,26 +72,102 @@ impl Joypad {
         Joypad::default()
     }
 
-    pub fn set_pressed_keys(&mut self, pressed: JoypadState) {
+    pub fn set_pressed_keys(&mut self, mut pressed: JoypadState) {
+        let before = self.input_lines();
+
+        let new = pressed - self.held;
+        self.held |= pressed;
+
+        for (a, b) in OPPOSING_DIRECTIONS {
+            if new.contains(b) {
+                self.last_directions.remove(a);
+                self.last_directions.insert(b);
+            } else if new.contains(a) {
+                self.last_directions.remove(b);
+                self.last_directions.insert(a);
+            }
+
+            // Keep only the direction that was pressed last
+            if self.filter_opposing && self.held.contains(a | b) {
+                let older = if self.last_directions.contains(a) {
+                    b
+                } else {
+                    a
+                };
+                pressed.remove(older);
+                self.state |= older;
+            }
+        }
+
         self.state &= !pressed;
+
+        // A high-to-low transition on any of the selected input lines requests an interrupt
+        if before & !self.input_lines() & 0x0F != 0 {
+            self.irq_pending = true;
+        }
     }
 
     pub fn set_release_keys(&mut self, released: JoypadState) {
+        self.held -= released;
         self.state |= released;
     }
-}
 
-impl MemR for Joypad {
-    fn read(&self, _addr: u16) -> Result<u8, dbg::TraceEvent> {
+    /// Enables or disables filtering of opposing directions (enabled by default).
+    ///
+    /// Since a real d-pad cannot report Left+Right or Up+Down, some games misbehave if they
+    /// see both. When enabled, only the most recently pressed direction on each axis is reported.
+    pub fn set_filter_opposing(&mut self, enable: bool) {
+        self.filter_opposing = enable;
+    }
+
+    pub fn filter_opposing(&self) -> bool {
+        self.filter_opposing
+    }
+
+    /// Returns the keys currently pressed.
+    ///
+    /// Unlike the internal state, which is active-low like the input lines, pressed keys are set.
+    pub fn pressed_keys(&self) -> JoypadState {
+        !self.state
+    }
+
+    /// Returns whether the action buttons are selected (P15 low).
+    pub fn buttons_selected(&self) -> bool {
+        !self.joyp.contains(JoyP::SEL_BTNS)
+    }
+
+    /// Returns whether the direction keys are selected (P14 low).
+    pub fn directions_selected(&self) -> bool {
+        !self.joyp.contains(JoyP::SEL_DIRS)
+    }
+
+    /// Returns the state of the P10-P13 input lines, based on the currently selected group.
+    fn input_lines(&self) -> u8 {
         // Assign upper, lower or no half of state depending on the selection bits
-        let res = if !self.joyp.contains(JoyP::SEL_BTNS) {
+        if self.buttons_selected() {
             self.state.bits()
-        } else if !self.joyp.contains(JoyP::SEL_DIRS) {
+        } else if self.directions_selected() {
             self.state.bits() >> 4
         } else {
             0x0F
-        };
+        }
+    }
+}
 
+impl InterruptSource for Joypad {
+    fn get_and_clear_irq(&mut self) -> Option<IrqSource> {
+        if self.irq_pending {
+            self.irq_pending = false;
+            Some(IrqSource::Joypad)
+        } else {
+            None
+        }
+    }
+}
+
+impl MemR for Joypad {
+    fn read(&self, _addr: u16) -> Result<u8, dbg::TraceEvent> {
+        let res = self.input_lines();
         let joyp = (self.joyp | JoyP::BTN_MASK) & JoyP::from_bits_truncate(res | 0xF0);
 
         (&joyp).read(0)
@@ -85,3 +181,75 @@ impl MemW for Joypad {
 }
 
 impl MemRW for Joypad {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opposing_directions() {
+        let mut joy = Joypad::new();
+
+        joy.set_pressed_keys(JoypadState::LEFT);
+        joy.set_pressed_keys(JoypadState::RIGHT);
+        assert_eq!(joy.pressed_keys(), JoypadState::RIGHT);
+
+        // Other directions are not affected
+        joy.set_pressed_keys(JoypadState::UP);
+        assert_eq!(joy.pressed_keys(), JoypadState::RIGHT | JoypadState::UP);
+
+        // The frontend reports all held keys at once: the newer direction wins
+        joy.set_release_keys(JoypadState::all());
+        joy.set_pressed_keys(JoypadState::LEFT);
+        assert_eq!(joy.pressed_keys(), JoypadState::LEFT);
+        joy.set_pressed_keys(JoypadState::LEFT | JoypadState::RIGHT);
+        assert_eq!(joy.pressed_keys(), JoypadState::RIGHT);
+        joy.set_pressed_keys(JoypadState::LEFT | JoypadState::RIGHT);
+        assert_eq!(joy.pressed_keys(), JoypadState::RIGHT);
+
+        // Releasing the newer direction restores the one still held
+        joy.set_release_keys(JoypadState::RIGHT);
+        joy.set_pressed_keys(JoypadState::LEFT);
+        assert_eq!(joy.pressed_keys(), JoypadState::LEFT);
+
+        joy.set_release_keys(JoypadState::all());
+        joy.set_filter_opposing(false);
+        joy.set_pressed_keys(JoypadState::LEFT);
+        joy.set_pressed_keys(JoypadState::RIGHT);
+        assert_eq!(joy.pressed_keys(), JoypadState::LEFT | JoypadState::RIGHT);
+    }
+
+    #[test]
+    fn line_selection() {
+        let mut joy = Joypad::new();
+
+        joy.write(0xFF00, 0x30).unwrap();
+        assert!(!joy.buttons_selected() && !joy.directions_selected());
+
+        joy.write(0xFF00, 0x10).unwrap();
+        assert!(joy.buttons_selected() && !joy.directions_selected());
+
+        joy.write(0xFF00, 0x00).unwrap();
+        assert!(joy.buttons_selected() && joy.directions_selected());
+    }
+
+    #[test]
+    fn button_press_requests_irq() {
+        let mut joy = Joypad::new();
+
+        // Select the action buttons
+        joy.write(0xFF00, 0x10).unwrap();
+
+        // Pressing a direction has no effect on the action lines
+        joy.set_pressed_keys(JoypadState::UP);
+        assert!(joy.get_and_clear_irq().is_none());
+
+        joy.set_pressed_keys(JoypadState::A);
+        assert!(matches!(joy.get_and_clear_irq(), Some(IrqSource::Joypad)));
+        assert!(joy.get_and_clear_irq().is_none());
+
+        // Holding the button down does not request further interrupts
+        joy.set_pressed_keys(JoypadState::A);
+        assert!(joy.get_and_clear_irq().is_none());
+    }
+}
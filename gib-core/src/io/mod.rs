@@ -2,6 +2,7 @@ pub use interrupts::*;
 pub use joypad::*;
 pub use reg::*;
 pub use serial::*;
+pub use sgb::*;
 pub use sound::*;
 pub use timer::*;
 pub use video::*;
@@ -11,6 +12,7 @@ mod reg;
 mod interrupts;
 mod joypad;
 mod serial;
+mod sgb;
 mod sound;
 mod timer;
 mod video;
@@ -4,9 +4,13 @@ use crate::{
     mem::{MemR, MemRW, MemW},
 };
 
+#[derive(Clone)]
 pub struct Serial {
     sb: IoReg<u8>,
     sc: IoReg<u8>,
+
+    // Bytes sent using the internal clock, used by test ROMs to report their results
+    output: Vec<u8>,
 }
 
 impl Default for Serial {
@@ -14,6 +18,7 @@ impl Default for Serial {
         Serial {
             sb: IoReg(0x00),
             sc: IoReg(0x00),
+            output: Vec::new(),
         }
     }
 }
@@ -22,6 +27,18 @@ impl Serial {
     pub fn new() -> Serial {
         Serial::default()
     }
+
+    /// Returns the bytes sent so far by starting a transfer with the internal clock.
+    ///
+    /// Transfers are only captured: they never complete, as if no other device was connected.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Discards the captured output.
+    pub fn clear_output(&mut self) {
+        self.output.clear();
+    }
 }
 
 impl InterruptSource for Serial {
@@ -46,7 +63,12 @@ impl MemW for Serial {
         // TODO: it's gonna be a while before serial link is implemented :)
         match addr {
             0xFF01 => self.sb.0 = val,
-            0xFF02 => self.sc.0 = val,
+            0xFF02 => {
+                if val & 0x81 == 0x81 {
+                    self.output.push(self.sb.0);
+                }
+                self.sc.0 = val;
+            }
             _ => unreachable!(),
         };
         Ok(())
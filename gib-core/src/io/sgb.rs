@@ -0,0 +1,240 @@
+use std::mem;
+
+use crate::io::Palette;
+
+/// Size of a single SGB command packet, in bytes.
+const PACKET_SIZE: usize = 16;
+
+/// Default SGB palette colors, in RGB555, before the game sets its own.
+const DEFAULT_PALETTE: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+/// A command sent by the game to the Super Game Boy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgbCommand {
+    /// PAL01, PAL23, PAL03 or PAL12: sets colors 1-3 of two palettes, along with the color 0
+    /// shared by all palettes. Colors are stored as RGB555 words, in the same order.
+    SetPalettes {
+        palettes: (usize, usize),
+        colors: [u16; 7],
+    },
+    /// Any other command, identified by its code.
+    Unsupported(u8),
+}
+
+impl SgbCommand {
+    /// Decodes a command from the packets it was transferred in.
+    fn decode(packets: &[[u8; PACKET_SIZE]]) -> SgbCommand {
+        let data = &packets[0];
+        let code = data[0] >> 3;
+
+        let palettes = match code {
+            0x00 => (0, 1),
+            0x01 => (2, 3),
+            0x02 => (0, 3),
+            0x03 => (1, 2),
+            _ => return SgbCommand::Unsupported(code),
+        };
+
+        let mut colors = [0; 7];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+        }
+
+        SgbCommand::SetPalettes { palettes, colors }
+    }
+}
+
+/// Receives packets sent bit by bit through the P14 and P15 lines of the joypad register.
+///
+/// A transfer starts with a reset pulse (both lines low), followed by 128 data bits (P14 low
+/// for a 0, P15 low for a 1), LSB first, and a 0 stop bit. Lines return high between pulses.
+#[derive(Debug, Clone, Default)]
+struct PacketReader {
+    // Number of bits received so far, or `None` while waiting for a reset pulse
+    bits: Option<usize>,
+    data: [u8; PACKET_SIZE],
+    // Whether both lines went high since the last pulse
+    idle: bool,
+}
+
+impl PacketReader {
+    /// Handles a write to P1, returning a packet once it has been completely received.
+    fn write(&mut self, val: u8) -> Option<[u8; PACKET_SIZE]> {
+        match val & 0x30 {
+            0x00 => {
+                self.bits = Some(0);
+                self.data = [0; PACKET_SIZE];
+                self.idle = false;
+                None
+            }
+            0x30 => {
+                self.idle = true;
+                None
+            }
+            lines if self.idle => {
+                self.idle = false;
+
+                let bit = lines == 0x10;
+                let n = self.bits?;
+
+                if n < PACKET_SIZE * 8 {
+                    self.data[n / 8] |= u8::from(bit) << (n % 8);
+                    self.bits = Some(n + 1);
+                    None
+                } else {
+                    // A packet is only valid if followed by a 0 stop bit
+                    self.bits = None;
+                    (!bit).then_some(self.data)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Super Game Boy functions, driven by commands sent through the joypad register.
+///
+/// Only palette commands are supported for now, with the whole screen using palette 0.
+#[derive(Debug, Clone)]
+pub struct Sgb {
+    reader: PacketReader,
+    // Packets of the command being received
+    packets: Vec<[u8; PACKET_SIZE]>,
+    palettes: [[u16; 4]; 4],
+}
+
+impl Default for Sgb {
+    fn default() -> Sgb {
+        Sgb {
+            reader: PacketReader::default(),
+            packets: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+        }
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb::default()
+    }
+
+    /// Handles a write to the joypad register (P1), returning the command executed as a result,
+    /// if any.
+    pub fn write_p1(&mut self, val: u8) -> Option<SgbCommand> {
+        let packet = self.reader.write(val)?;
+        self.packets.push(packet);
+
+        // The length of the command, in packets, is stored in the first one
+        let len = usize::from(self.packets[0][0] & 0x07).max(1);
+        if self.packets.len() < len {
+            return None;
+        }
+
+        let cmd = SgbCommand::decode(&mem::take(&mut self.packets));
+
+        tracing::debug!(?cmd, "SGB command");
+
+        if let SgbCommand::SetPalettes {
+            palettes: (a, b),
+            colors,
+        } = cmd
+        {
+            // Color 0 is shared by all palettes
+            for palette in &mut self.palettes {
+                palette[0] = colors[0];
+            }
+            self.palettes[a][1..].copy_from_slice(&colors[1..4]);
+            self.palettes[b][1..].copy_from_slice(&colors[4..7]);
+        }
+
+        Some(cmd)
+    }
+
+    /// Returns the RGB555 colors of SGB palette `idx` (0 to 3).
+    pub fn palette(&self, idx: usize) -> [u16; 4] {
+        self.palettes[idx]
+    }
+
+    /// Returns the colors of SGB palette `idx` (0 to 3), used to render DMG shades.
+    pub fn shades(&self, idx: usize) -> Palette {
+        Palette::from_rgb555(self.palettes[idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the P1 writes needed to transfer `packet`, including reset pulse and stop bit.
+    fn packet_writes(packet: &[u8; PACKET_SIZE]) -> Vec<u8> {
+        let mut writes = vec![0x00, 0x30];
+        for i in 0..PACKET_SIZE * 8 {
+            let bit = (packet[i / 8] >> (i % 8)) & 1 != 0;
+            writes.extend([if bit { 0x10 } else { 0x20 }, 0x30]);
+        }
+        writes.extend([0x20, 0x30]);
+        writes
+    }
+
+    #[test]
+    fn palette_command() {
+        let mut sgb = Sgb::new();
+
+        // PAL01, with 1 packet
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = 0x01;
+        let colors: [u16; 7] = [0x7FFF, 0x001F, 0x03E0, 0x7C00, 0x1111, 0x2222, 0x3333];
+        for (i, color) in colors.iter().enumerate() {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+        }
+
+        let cmds = packet_writes(&packet)
+            .into_iter()
+            .filter_map(|val| sgb.write_p1(val))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cmds,
+            [SgbCommand::SetPalettes {
+                palettes: (0, 1),
+                colors
+            }]
+        );
+        assert_eq!(sgb.palette(0), [0x7FFF, 0x001F, 0x03E0, 0x7C00]);
+        assert_eq!(sgb.palette(1), [0x7FFF, 0x1111, 0x2222, 0x3333]);
+        assert_eq!(sgb.palette(2)[0], 0x7FFF);
+        assert_eq!(sgb.palette(2)[1..], DEFAULT_PALETTE[1..]);
+    }
+
+    #[test]
+    fn packet_framing() {
+        let mut sgb = Sgb::new();
+        let mut send = |writes: &[u8]| {
+            writes
+                .iter()
+                .filter_map(|&val| sgb.write_p1(val))
+                .collect::<Vec<_>>()
+        };
+
+        // MASK_EN, with 1 packet
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = (0x17 << 3) | 0x01;
+        let writes = packet_writes(&packet);
+        assert_eq!(send(&writes), [SgbCommand::Unsupported(0x17)]);
+
+        // A missing stop bit discards the packet
+        let mut invalid = writes.clone();
+        let len = invalid.len();
+        invalid[len - 2] = 0x10;
+        assert!(send(&invalid).is_empty());
+
+        // Bits are only sampled after both lines went high
+        let mut repeated = writes.clone();
+        repeated.insert(3, 0x10);
+        assert_eq!(send(&repeated), [SgbCommand::Unsupported(0x17)]);
+
+        // A reset pulse restarts the transfer
+        let mut restarted = writes[..20].to_vec();
+        restarted.extend(&writes);
+        assert_eq!(send(&restarted), [SgbCommand::Unsupported(0x17)]);
+    }
+}
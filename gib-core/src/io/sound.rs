@@ -1,4 +1,8 @@
-use std::mem;
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{Arc, Mutex},
+};
 
 use bitflags::bitflags;
 
@@ -6,15 +10,21 @@ use crate::{
     dbg,
     io::{InterruptSource, IoReg, IrqSource},
     mem::{MemR, MemW},
-    AudioSource,
+    AudioCallback, AudioSource, AudioStats,
 };
 
-const FRAME_SEQUENCER_CLOCK_RELOAD: u32 = 4_194_304 / 512;
+// Frame sequencer step reached on the first clock after power on
+const FRAME_SEQUENCER_FIRST_STEP: u8 = 0;
 
 // Maximum length counter value for tone channels
 const TONE_CH_LEN_MAX: u32 = 64;
 const WAVE_CH_LEN_MAX: u32 = 256;
 
+// Channel levels are captured every LEVEL_CAPTURE_PERIOD M-cycles (32768 Hz)
+const LEVEL_CAPTURE_PERIOD: u32 = 32;
+// Number of channel levels kept in the capture history (about 31ms)
+const LEVEL_HISTORY_LEN: usize = 1024;
+
 bitflags! {
     // NRx0 - Channel x Sweep register (R/W)
     struct NRx0: u8 {
@@ -102,9 +112,148 @@ bitflags! {
     }
 }
 
+impl NRx2 {
+    /// Returns true if the channel's DAC is powered on by these register bits.
+    fn dac_on(self) -> bool {
+        (self & NRx2::DAC_ON).bits() != 0
+    }
+}
+
+/// Length counter unit, used to disable a channel after a programmable amount of time.
+#[derive(Debug, Clone)]
+struct LengthCounter {
+    counter: u32,
+    max: u32,
+    should_dec_on_enable: bool,
+}
+
+impl LengthCounter {
+    /// Creates a new length counter, loaded with its maximum value `max`.
+    fn new(max: u32) -> LengthCounter {
+        LengthCounter {
+            counter: max,
+            max,
+            // The frame sequencer starts from a step that clocks the length counter
+            should_dec_on_enable: false,
+        }
+    }
+
+    /// Reloads the counter with the length data written to NRx1.
+    fn load(&mut self, len: u8) {
+        self.counter = self.max - u32::from(len);
+    }
+
+    /// Advances the length counter unit by 1/256th of a second.
+    ///
+    /// Returns true if the channel should be disabled.
+    fn tick(&mut self, nrx4: NRx4) -> bool {
+        // When clocked while enabled by NRx4, the length counter is decremented
+        if nrx4.contains(NRx4::LEN_EN) {
+            tracing::trace!(before = self.counter, "Length counter tick");
+
+            self.counter = self.counter.saturating_sub(1);
+
+            // If it reaches zero, the channel is disabled
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    /// Handles a write to the NRx4 register, changing its value from `old` to `new`.
+    ///
+    /// Returns true if the channel should be disabled.
+    fn write_to_nr4(&mut self, old: NRx4, new: NRx4) -> bool {
+        let mut disable = false;
+
+        // If the length counter was PREVIOUSLY disabled and now enabled and the length counter
+        // is not zero, it is decremented. If this decrement makes it zero and trigger is clear,
+        // the channel is disabled.
+        let decrement = u32::from(new.contains(NRx4::LEN_EN) && self.should_dec_on_enable);
+
+        if !old.contains(NRx4::LEN_EN) {
+            self.counter = self.counter.saturating_sub(decrement);
+
+            if !new.contains(NRx4::TRIGGER) && self.counter == 0 {
+                disable = true;
+            }
+        }
+
+        // On trigger, if length counter is zero, it is set to maximum
+        if new.contains(NRx4::TRIGGER) && self.counter == 0 {
+            tracing::trace!("Resetting length counter to max on trigger");
+            self.counter = self.max - decrement;
+        }
+
+        disable
+    }
+}
+
+/// Volume envelope unit, used to automatically increase or decrease a channel's volume.
+#[derive(Debug, Clone, Default)]
+struct VolumeEnvelope {
+    volume: i16,
+    counter: u8,
+    enabled: bool,
+}
+
+impl VolumeEnvelope {
+    /// Reloads the envelope from NRx2 when the channel is triggered.
+    fn trigger(&mut self, nrx2: NRx2) {
+        // Volume envelope timer is reloaded with period and
+        // channel volume is reloaded from NRx2.
+        self.volume = i16::from((nrx2 & NRx2::START_VOL).bits() >> 4);
+        self.counter = Self::period(nrx2);
+        self.enabled = true;
+    }
+
+    /// Advances the volume envelope unit by 1/64th of a second.
+    fn tick(&mut self, nrx2: NRx2) {
+        if !self.enabled {
+            return;
+        }
+
+        self.counter = self.counter.saturating_sub(1);
+
+        if self.counter > 0 {
+            return;
+        }
+
+        // The internal timer is reloaded from NRx2 when it expires
+        self.counter = Self::period(nrx2);
+
+        // When the timer generates a clock and the envelope period is not zero,
+        // a new volume is calculated by adding or subtracting 1 from the current volume.
+        if (nrx2 & NRx2::ENV_PERIOD).bits() > 0 {
+            let new_volume = if nrx2.contains(NRx2::ENV_DIR) {
+                self.volume + 1
+            } else {
+                self.volume - 1
+            };
+
+            // If this new volume within the 0 to 15 range, the volume is updated,
+            // otherwise it is left unchanged and no further automatic increments/decrements
+            // are made to the volume until the channel is triggered again.
+            if (0..=15).contains(&new_volume) {
+                self.volume = new_volume;
+            } else {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Returns the envelope timer period. A period of 0 is treated as 8.
+    fn period(nrx2: NRx2) -> u8 {
+        match (nrx2 & NRx2::ENV_PERIOD).bits() {
+            0 => 8,
+            n => n,
+        }
+    }
+}
+
 /// A sound channel able to produce quadrangular wave patterns
 /// with optional sweep and envelope functions.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ToneChannel {
     // Channel registers
     nrx0: NRx0,
@@ -118,8 +267,7 @@ pub struct ToneChannel {
     timer_counter: u32,
 
     // Length counter unit
-    length_counter: u32,
-    should_dec_counter_on_enable: bool,
+    length: LengthCounter,
 
     // Frequency sweep unit
     sweep_support: bool,
@@ -129,9 +277,7 @@ pub struct ToneChannel {
     sweep_timer: u8,
 
     // Volume control
-    volume: i16,
-    vol_ctr: u8,
-    vol_env_enabled: bool,
+    envelope: VolumeEnvelope,
 
     // Channel output fed as input to the mixer
     waveform_level: i16,
@@ -157,8 +303,7 @@ impl ToneChannel {
             enabled: false,
             timer_counter: 0,
 
-            length_counter: TONE_CH_LEN_MAX,
-            should_dec_counter_on_enable: true,
+            length: LengthCounter::new(TONE_CH_LEN_MAX),
 
             sweep_support,
             sweep_enabled: false,
@@ -166,9 +311,7 @@ impl ToneChannel {
             sweep_freq_shadow: 0,
             sweep_timer: 0,
 
-            volume: 0,
-            vol_ctr: 0,
-            vol_env_enabled: false,
+            envelope: VolumeEnvelope::default(),
 
             waveform_level: 1,
         }
@@ -208,7 +351,7 @@ impl ToneChannel {
         let shift = (self.nrx0 & NRx0::SWEEP_SHIFT).bits();
         let period = (self.nrx0 & NRx0::SWEEP_TIME).bits() >> 4;
 
-        self.sweep_timer -= 1;
+        self.sweep_timer = self.sweep_timer.wrapping_sub(1);
 
         // Sweep timer expired -> do sweep
         if self.sweep_timer == 0 {
@@ -236,42 +379,13 @@ impl ToneChannel {
 
     /// Advances the volume envelope unit by 1/64th of a second.
     fn tick_vol_env(&mut self) {
-        let period = (self.nrx2 & NRx2::ENV_PERIOD).bits();
-
-        // When the timer generates a clock and the envelope period is not zero,
-        // a new volume is calculated by adding or subtracting 1 from the current volume.
-        if self.vol_env_enabled && period > 0 {
-            self.nrx2 = (self.nrx2 & !NRx2::ENV_PERIOD) | NRx2::from_bits_truncate(period - 1);
-
-            let new_volume = if self.nrx2.contains(NRx2::ENV_DIR) {
-                self.volume + 1
-            } else {
-                self.volume - 1
-            };
-
-            // If this new volume within the 0 to 15 range, the volume is updated,
-            // otherwise it is left unchanged and no further automatic increments/decrements
-            // are made to the volume until the channel is triggered again.
-            if new_volume <= 15 {
-                self.volume = new_volume;
-            } else {
-                self.vol_env_enabled = false;
-            }
-        }
+        self.envelope.tick(self.nrx2);
     }
 
     /// Advances the length counter unit by 1/256th of a second.
     fn tick_len_ctr(&mut self) {
-        // When clocked while enabled by NRx4, the length counter is decremented
-        if self.nrx4.contains(NRx4::LEN_EN) {
-            tracing::trace!(before = self.length_counter, "Length counter tick");
-
-            self.length_counter = self.length_counter.saturating_sub(1);
-
-            // If it reaches zero, the channel is disabled
-            if self.length_counter == 0 {
-                self.enabled = false;
-            }
+        if self.length.tick(self.nrx4) {
+            self.enabled = false;
         }
     }
 
@@ -319,7 +433,7 @@ impl ToneChannel {
 
     /// Returns the channel's current volume.
     pub fn get_volume(&self) -> i16 {
-        i16::from(self.enabled) * self.volume
+        i16::from(self.enabled) * self.envelope.volume
     }
 
     /// Returns the channel's current output level, ready to be fed to the mixer.
@@ -338,25 +452,15 @@ impl ToneChannel {
 
     /// Returns true if the channels DAC is on, false otherwise.
     pub fn dac_on(&self) -> bool {
-        (self.nrx2 & NRx2::DAC_ON).bits() != 0
+        self.nrx2.dac_on()
     }
 
     /// Handles a write to the NRx4 register.
     fn write_to_nr4(&mut self, val: u8) {
         let nrx4 = NRx4::from_bits_truncate(val);
 
-        // If the length counter was PREVIOUSLY disabled and now enabled and the length counter
-        // is not zero, it is decremented. If this decrement makes it zero and trigger is clear,
-        // the channel is disabled.
-        let length_counter_decrement =
-            u32::from(nrx4.contains(NRx4::LEN_EN) && self.should_dec_counter_on_enable);
-
-        if !self.nrx4.contains(NRx4::LEN_EN) {
-            self.length_counter = self.length_counter.saturating_sub(length_counter_decrement);
-
-            if !nrx4.contains(NRx4::TRIGGER) && self.length_counter == 0 {
-                self.enabled = false;
-            }
+        if self.length.write_to_nr4(self.nrx4, nrx4) {
+            self.enabled = false;
         }
 
         self.nrx4 = nrx4;
@@ -368,20 +472,11 @@ impl ToneChannel {
             // Channel is enabled
             self.enabled = true;
 
-            // If length counter is zero, it is set to maximum
-            if self.length_counter == 0 {
-                tracing::trace!("Resetting length counter to max on trigger");
-                self.length_counter = TONE_CH_LEN_MAX - length_counter_decrement;
-            }
-
             // Frequency timer is reloaded with period
             self.timer_counter = self.get_period();
 
-            // Volume envelope timer is reloaded with period and
-            // channel volume is reloaded from NRx2.
-            self.volume = i16::from((self.nrx2 & NRx2::START_VOL).bits() >> 4);
-            self.vol_ctr = (self.nrx2 & NRx2::ENV_PERIOD).bits();
-            self.vol_env_enabled = true;
+            // Volume envelope is reloaded
+            self.envelope.trigger(self.nrx2);
 
             // Square 1's frequency is copied to the shadow register, the sweep timer is reloaded,
             // the internal sweep enabled flag is adjusted and sweep calculations may be performed.
@@ -451,7 +546,7 @@ impl MemW for ToneChannel {
             }
             1 => {
                 self.nrx1 = NRx1::from_bits_truncate(val);
-                self.length_counter = u32::from(!val & NRx1::SOUND_LEN.bits()) + 1;
+                self.length.load(val & NRx1::SOUND_LEN.bits());
             }
             2 => {
                 self.nrx2 = NRx2::from_bits_truncate(val);
@@ -471,6 +566,7 @@ impl MemW for ToneChannel {
 
 /// A sound channel used to output digital sound
 /// from a 32-digit sample buffer (Wave RAM).
+#[derive(Clone)]
 pub struct WaveChannel {
     // Channel registers
     nrx0: NRx0,
@@ -483,11 +579,11 @@ pub struct WaveChannel {
     timer_counter: u32,
 
     // Length counter unit
-    length_counter: u32,
-    should_dec_counter_on_enable: bool,
+    length: LengthCounter,
 
     // Wave functions
     wave_ram: [u8; 16],
+    wave_ram_accessed: bool,
     sample_buffer: u8,
     position_counter: usize,
 }
@@ -503,10 +599,10 @@ impl Default for WaveChannel {
             enabled: false,
             timer_counter: 0,
 
-            length_counter: WAVE_CH_LEN_MAX,
-            should_dec_counter_on_enable: true,
+            length: LengthCounter::new(WAVE_CH_LEN_MAX),
 
             wave_ram: [0; 16],
+            wave_ram_accessed: false,
             sample_buffer: 0,
             position_counter: 0,
         }
@@ -518,9 +614,12 @@ impl Default for WaveChannel {
 impl WaveChannel {
     /// Advances the internal timer state by one M-cycle.
     fn tick(&mut self) {
+        self.wave_ram_accessed = false;
+
         // Every N input clocks, advance the position counter and latch the new sample.
         if self.timer_counter < 4 {
             self.timer_counter = self.get_period() - self.timer_counter;
+            self.wave_ram_accessed = self.enabled;
 
             self.position_counter = (self.position_counter + 1) % 32;
             self.sample_buffer = self.wave_ram[self.position_counter >> 1];
@@ -538,14 +637,8 @@ impl WaveChannel {
 
     /// Advances the length counter unit by 1/256th of a second.
     fn tick_len_ctr(&mut self) {
-        // When clocked while enabled by NRx4, the length counter is decremented
-        if self.nrx4.contains(NRx4::LEN_EN) {
-            self.length_counter = self.length_counter.saturating_sub(1);
-
-            // If it reaches zero, the channel is disabled
-            if self.length_counter == 0 {
-                self.enabled = false;
-            }
+        if self.length.tick(self.nrx4) {
+            self.enabled = false;
         }
     }
 
@@ -567,35 +660,62 @@ impl WaveChannel {
     }
 
     /// Returns the channel's current output level, ready to be fed to the mixer.
-    fn get_channel_out(&self) -> i16 {
-        if self.dac_on() {
-            i16::from(self.sample_buffer >> (self.get_volume() - 1))
-        } else {
-            0
+    pub fn get_channel_out(&self) -> i16 {
+        if !self.dac_on() {
+            return 0;
+        }
+
+        // Volume codes 1, 2 and 3 correspond to 100%, 50% and 25% respectively,
+        // while volume code 0 mutes the channel.
+        match self.get_volume() {
+            0 => 0,
+            vol => i16::from(self.sample_buffer >> (vol - 1)),
         }
     }
 
+    /// Returns whether the channel's internal enabled flag is set.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
     /// Returns true if the channels DAC is on, false otherwise.
-    fn dac_on(&self) -> bool {
+    pub fn dac_on(&self) -> bool {
         self.nrx0.contains(NRx0::WAVE_DAC_ON)
     }
 
+    /// Returns the index of the Wave RAM byte accessible by the CPU at offset `offset`, if any.
+    ///
+    /// While the channel is playing, the CPU can only access the byte currently being read
+    /// by the channel, and only on the same cycle the channel reads it.
+    fn wave_ram_index(&self, offset: usize) -> Option<usize> {
+        if !self.enabled {
+            Some(offset)
+        } else if self.wave_ram_accessed {
+            Some(self.position_counter >> 1)
+        } else {
+            None
+        }
+    }
+
+    /// Reads from Wave RAM at offset `offset`, honoring access restrictions.
+    fn read_wave_ram(&self, offset: usize) -> u8 {
+        self.wave_ram_index(offset)
+            .map_or(0xFF, |i| self.wave_ram[i])
+    }
+
+    /// Writes to Wave RAM at offset `offset`, honoring access restrictions.
+    fn write_wave_ram(&mut self, offset: usize, val: u8) {
+        if let Some(i) = self.wave_ram_index(offset) {
+            self.wave_ram[i] = val;
+        }
+    }
+
     /// Handles a write to the NRx4 register.
     fn write_to_nr4(&mut self, val: u8) {
         let nrx4 = NRx4::from_bits_truncate(val);
 
-        // If the length counter was PREVIOUSLY disabled and now enabled and the length counter
-        // is not zero, it is decremented. If this decrement makes it zero and trigger is clear,
-        // the channel is disabled.
-        let length_counter_decrement =
-            u32::from(nrx4.contains(NRx4::LEN_EN) && self.should_dec_counter_on_enable);
-
-        if !self.nrx4.contains(NRx4::LEN_EN) {
-            self.length_counter = self.length_counter.saturating_sub(length_counter_decrement);
-
-            if !nrx4.contains(NRx4::TRIGGER) && self.length_counter == 0 {
-                self.enabled = false;
-            }
+        if self.length.write_to_nr4(self.nrx4, nrx4) {
+            self.enabled = false;
         }
 
         self.nrx4 = nrx4;
@@ -607,12 +727,6 @@ impl WaveChannel {
             // Channel is enabled
             self.enabled = true;
 
-            // If length counter is zero, it is set to maximum
-            if self.length_counter == 0 {
-                tracing::trace!("Resetting length counter to max on trigger");
-                self.length_counter = WAVE_CH_LEN_MAX - length_counter_decrement;
-            }
-
             // Frequency timer is reloaded with period
             self.timer_counter = self.get_period();
 
@@ -651,7 +765,7 @@ impl MemW for WaveChannel {
                     self.enabled = false;
                 }
             }
-            1 => self.length_counter = u32::from(!val) + 1,
+            1 => self.length.load(val),
             2 => self.nrx2 = NRx2::from_bits_truncate(val),
             3 => self.nrx3.0 = val,
             4 => self.write_to_nr4(val),
@@ -663,6 +777,7 @@ impl MemW for WaveChannel {
 }
 
 /// A sound channel used to output white noise.
+#[derive(Clone)]
 pub struct NoiseChannel {
     // Channel registers
     nrx1: NRx1,
@@ -676,13 +791,10 @@ pub struct NoiseChannel {
     timer_counter: u32,
 
     // Length counter unit
-    length_counter: u32,
-    should_dec_counter_on_enable: bool,
+    length: LengthCounter,
 
     // Volume control
-    volume: i16,
-    vol_ctr: u8,
-    vol_env_enabled: bool,
+    envelope: VolumeEnvelope,
 
     // Channel output fed as input to the mixer
     waveform_level: i16,
@@ -700,19 +812,15 @@ impl Default for NoiseChannel {
             enabled: false,
             timer_counter: 0,
 
-            length_counter: TONE_CH_LEN_MAX,
-            should_dec_counter_on_enable: true,
+            length: LengthCounter::new(TONE_CH_LEN_MAX),
 
-            volume: 0,
-            vol_ctr: 0,
-            vol_env_enabled: false,
+            envelope: VolumeEnvelope::default(),
 
             waveform_level: 1,
         }
     }
 }
 
-// TODO same as above, too much duplicated code.
 impl NoiseChannel {
     /// Advances the internal timer state by one M-cycle.
     fn tick(&mut self) {
@@ -736,40 +844,13 @@ impl NoiseChannel {
 
     /// Advances the volume envelope unit by 1/64th of a second.
     fn tick_vol_env(&mut self) {
-        let period = (self.nrx2 & NRx2::ENV_PERIOD).bits();
-
-        // When the timer generates a clock and the envelope period is not zero,
-        // a new volume is calculated by adding or subtracting 1 from the current volume.
-        if self.vol_env_enabled && period > 0 {
-            self.nrx2 = (self.nrx2 & !NRx2::ENV_PERIOD) | NRx2::from_bits_truncate(period - 1);
-
-            let new_volume = if self.nrx2.contains(NRx2::ENV_DIR) {
-                self.volume + 1
-            } else {
-                self.volume - 1
-            };
-
-            // If this new volume within the 0 to 15 range, the volume is updated,
-            // otherwise it is left unchanged and no further automatic increments/decrements
-            // are made to the volume until the channel is triggered again.
-            if new_volume <= 15 {
-                self.volume = new_volume;
-            } else {
-                self.vol_env_enabled = false;
-            }
-        }
+        self.envelope.tick(self.nrx2);
     }
 
     /// Advances the length counter unit by 1/256th of a second.
     fn tick_len_ctr(&mut self) {
-        // When clocked while enabled by NRx4, the length counter is decremented
-        if self.nrx4.contains(NRx4::LEN_EN) {
-            self.length_counter = self.length_counter.saturating_sub(1);
-
-            // If it reaches zero, the channel is disabled
-            if self.length_counter == 0 {
-                self.enabled = false;
-            }
+        if self.length.tick(self.nrx4) {
+            self.enabled = false;
         }
     }
 
@@ -781,7 +862,7 @@ impl NoiseChannel {
 
     /// Returns the channel's current volume.
     pub fn get_volume(&self) -> i16 {
-        i16::from(self.enabled) * self.volume
+        i16::from(self.enabled) * self.envelope.volume
     }
 
     /// Returns the channel's current output level, ready to be fed to the mixer.
@@ -800,25 +881,28 @@ impl NoiseChannel {
 
     /// Returns true if the channels DAC is on, false otherwise.
     pub fn dac_on(&self) -> bool {
-        (self.nrx2 & NRx2::DAC_ON).bits() != 0
+        self.nrx2.dac_on()
+    }
+
+    /// Returns the current state of the LFSR.
+    ///
+    /// The noise sequence is fully determined by this state and the channel registers,
+    /// so it can be saved and restored with [`NoiseChannel::set_lfsr_state`].
+    pub fn lfsr_state(&self) -> u16 {
+        self.lfsr & 0x7FFF
+    }
+
+    /// Restores the state of the LFSR. Only the lower 15 bits are used.
+    pub fn set_lfsr_state(&mut self, state: u16) {
+        self.lfsr = state & 0x7FFF;
     }
 
     /// Handles a write to the NRx4 register.
     fn write_to_nr4(&mut self, val: u8) {
         let nrx4 = NRx4::from_bits_truncate(val);
 
-        // If the length counter was PREVIOUSLY disabled and now enabled and the length counter
-        // is not zero, it is decremented. If this decrement makes it zero and trigger is clear,
-        // the channel is disabled.
-        let length_counter_decrement =
-            u32::from(nrx4.contains(NRx4::LEN_EN) && self.should_dec_counter_on_enable);
-
-        if !self.nrx4.contains(NRx4::LEN_EN) {
-            self.length_counter = self.length_counter.saturating_sub(length_counter_decrement);
-
-            if !nrx4.contains(NRx4::TRIGGER) && self.length_counter == 0 {
-                self.enabled = false;
-            }
+        if self.length.write_to_nr4(self.nrx4, nrx4) {
+            self.enabled = false;
         }
 
         self.nrx4 = nrx4;
@@ -830,20 +914,11 @@ impl NoiseChannel {
             // Channel is enabled
             self.enabled = true;
 
-            // If length counter is zero, it is set to maximum
-            if self.length_counter == 0 {
-                tracing::trace!("Resetting length counter to max on trigger");
-                self.length_counter = TONE_CH_LEN_MAX - length_counter_decrement;
-            }
-
             // Frequency timer is reloaded with period
             self.timer_counter = self.get_period();
 
-            // Volume envelope timer is reloaded with period and
-            // channel volume is reloaded from NRx2.
-            self.volume = i16::from((self.nrx2 & NRx2::START_VOL).bits() >> 4);
-            self.vol_ctr = (self.nrx2 & NRx2::ENV_PERIOD).bits();
-            self.vol_env_enabled = true;
+            // Volume envelope is reloaded
+            self.envelope.trigger(self.nrx2);
 
             // Noise channel's LFSR bits are all set to 1.
             self.lfsr = 0xFFFF;
@@ -876,7 +951,7 @@ impl MemW for NoiseChannel {
             0 => (),
             1 => {
                 self.nrx1 = NRx1::from_bits_truncate(val);
-                self.length_counter = u32::from(!val & NRx1::SOUND_LEN.bits()) + 1;
+                self.length.load(val & NRx1::SOUND_LEN.bits());
             }
             2 => {
                 self.nrx2 = NRx2::from_bits_truncate(val);
@@ -894,6 +969,55 @@ impl MemW for NoiseChannel {
     }
 }
 
+/// Units clocked by a single step of the frame sequencer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct SequencerClocks {
+    length: bool,
+    envelope: bool,
+    sweep: bool,
+}
+
+impl SequencerClocks {
+    /// Returns the units clocked at `step` of the 8-step frame sequencer pattern.
+    ///
+    /// ```text
+    /// Step   Length Ctr  Vol Env     Sweep
+    /// ---------------------------------------
+    /// 0      Clock       -           -
+    /// 1      -           -           -
+    /// 2      Clock       -           Clock
+    /// 3      -           -           -
+    /// 4      Clock       -           -
+    /// 5      -           -           -
+    /// 6      Clock       -           Clock
+    /// 7      -           Clock       -
+    /// ```
+    fn for_step(step: u8) -> SequencerClocks {
+        SequencerClocks {
+            length: step & 0b1 == 0,
+            envelope: step == 7,
+            sweep: step & 0b11 == 2,
+        }
+    }
+}
+
+/// Destination of the samples produced by the APU.
+#[derive(Clone)]
+enum AudioOutput {
+    Source(AudioSource),
+    Callback(Arc<Mutex<AudioCallback>>),
+}
+
+impl AudioOutput {
+    fn push(&mut self, sample: i16) {
+        match self {
+            AudioOutput::Source(source) => source.push(sample),
+            AudioOutput::Callback(callback) => (callback.lock().unwrap())(sample),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Apu {
     // Channels
     pub ch1: ToneChannel,
@@ -908,12 +1032,26 @@ pub struct Apu {
 
     // Audio sample channel
     sample_rate_counter: f32,
-    sample_channel: Option<AudioSource>,
+    sample_channel: Option<AudioOutput>,
+    sample_rate: f32,
     sample_period: f32,
-
-    // Frame sequencer clocks
-    frame_sequencer_clock: u32,
-    frame_sequencer_ticks: u32,
+    // Effective CPU clock, which determines how many cycles make up a sample period
+    clock_rate: f32,
+
+    // Frame sequencer, clocked by falling edges of a DIV bit
+    frame_sequencer_step: u8,
+    div_bit: bool,
+
+    // Channels fed to the mixer, for debugging purposes
+    mixer_enabled: [bool; 4],
+    // Volume applied to the mixer output, on top of NR50
+    master_volume: f32,
+    // Whether sample generation is skipped while the emulation runs unthrottled
+    fast_forward: bool,
+
+    // Recent channel levels, for debugging purposes
+    level_history: Option<VecDeque<[i16; 4]>>,
+    level_capture_counter: u32,
 }
 
 impl Default for Apu {
@@ -947,10 +1085,19 @@ impl Default for Apu {
 
             sample_rate_counter: 0f32,
             sample_channel: None,
-            sample_period: std::f32::INFINITY,
+            sample_rate: 0.0,
+            sample_period: f32::INFINITY,
+            clock_rate: crate::CPU_CLOCK as f32,
+
+            frame_sequencer_step: FRAME_SEQUENCER_FIRST_STEP,
+            div_bit: false,
+
+            mixer_enabled: [true; 4],
+            master_volume: 1.0,
+            fast_forward: false,
 
-            frame_sequencer_clock: FRAME_SEQUENCER_CLOCK_RELOAD,
-            frame_sequencer_ticks: 7,
+            level_history: None,
+            level_capture_counter: 0,
         }
     }
 }
@@ -965,76 +1112,262 @@ impl Apu {
 
     /// Resets the audio peripheral to its power-up state.
     ///
-    /// Sound channel and sample rate are preserved.
+    /// Sound channel, sample rate, mixer settings and level history are preserved.
     pub fn reset(&mut self) {
         // Preserve audio information
         let sample_channel = mem::take(&mut self.sample_channel);
+        let sample_rate = self.sample_rate;
         let sample_period = self.sample_period;
+        let clock_rate = self.clock_rate;
+        let mixer_enabled = self.mixer_enabled;
+        let master_volume = self.master_volume;
+        let fast_forward = self.fast_forward;
+        let level_history = mem::take(&mut self.level_history);
 
         *self = Self {
             sample_channel,
+            sample_rate,
             sample_period,
+            clock_rate,
+            mixer_enabled,
+            master_volume,
+            fast_forward,
+            level_history,
             ..Default::default()
         };
     }
 
-    /// Advances the sound controller state machine by a single M-cycle.
-    pub fn tick(&mut self) {
-        // Tick the frame sequencer
-        self.frame_sequencer_clock -= 4;
-
-        let (clk_64, clk_128, clk_256) = if self.frame_sequencer_clock == 0 {
-            self.frame_sequencer_clock = FRAME_SEQUENCER_CLOCK_RELOAD;
-            self.frame_sequencer_ticks = (self.frame_sequencer_ticks + 1) % 8;
-            (
-                self.frame_sequencer_ticks == 7,
-                self.frame_sequencer_ticks & 0b11 == 2,
-                self.frame_sequencer_ticks & 0b1 == 0,
-            )
-        } else {
-            (false, false, false)
+    /// Turns the audio peripheral off and clears its registers, as found by the boot ROM.
+    ///
+    /// The values set up by [`Apu::default`] are the ones left by the boot ROM instead.
+    pub(crate) fn power_on(&mut self) {
+        self.ch1 = ToneChannel::new(
+            NRx0::empty(),
+            NRx1::empty(),
+            NRx2::empty(),
+            IoReg(0x00),
+            NRx4::empty(),
+            true,
+        );
+        self.ch2 = ToneChannel::new(
+            NRx0::empty(),
+            NRx1::empty(),
+            NRx2::empty(),
+            IoReg(0x00),
+            NRx4::empty(),
+            false,
+        );
+        self.ch3 = WaveChannel {
+            nrx0: NRx0::empty(),
+            nrx2: NRx2::empty(),
+            nrx4: NRx4::empty(),
+            ..Default::default()
+        };
+        self.ch4 = NoiseChannel {
+            nrx1: NRx1::empty(),
+            nrx4: NRx4::empty(),
+            ..Default::default()
         };
 
-        // https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Obscure_Behavior
-        // Extra length clocking occurs when writing to NRx4 when the frame sequencer's next step
-        // is one that doesn't clock the length counter.
-        self.ch1.should_dec_counter_on_enable =
-            self.frame_sequencer_clock > 4 && self.frame_sequencer_ticks & 0b1 == 0;
-        self.ch2.should_dec_counter_on_enable = self.ch1.should_dec_counter_on_enable;
-        self.ch3.should_dec_counter_on_enable = self.ch1.should_dec_counter_on_enable;
-        self.ch4.should_dec_counter_on_enable = self.ch1.should_dec_counter_on_enable;
+        self.nr50 = NR50::empty();
+        self.nr51 = NR51::empty();
+        self.nr52 = NR52::empty();
+    }
+
+    /// Restores the audio peripheral state from `other`.
+    ///
+    /// Sound channel, sample rate, mixer settings and level history are preserved.
+    pub(crate) fn restore(&mut self, other: &Apu) {
+        let sample_channel = mem::take(&mut self.sample_channel);
+        let sample_rate = self.sample_rate;
+        let sample_period = self.sample_period;
+        let clock_rate = self.clock_rate;
+        let mixer_enabled = self.mixer_enabled;
+        let master_volume = self.master_volume;
+        let fast_forward = self.fast_forward;
+        let level_history = mem::take(&mut self.level_history);
+
+        *self = Self {
+            sample_channel,
+            sample_rate,
+            sample_period,
+            clock_rate,
+            mixer_enabled,
+            master_volume,
+            fast_forward,
+            level_history,
+            ..other.clone()
+        };
+    }
+
+    /// Returns the current output level of each channel, before mixing.
+    pub fn channel_levels(&self) -> [i16; 4] {
+        [
+            self.ch1.get_channel_out(),
+            self.ch2.get_channel_out(),
+            self.ch3.get_channel_out(),
+            self.ch4.get_channel_out(),
+        ]
+    }
 
+    /// Enables or disables capturing the channel levels into a history buffer.
+    ///
+    /// While enabled, the levels are sampled at a fixed rate independent of the audio output,
+    /// keeping only the most recent ones. Disabling the capture discards the history.
+    pub fn set_level_capture(&mut self, enabled: bool) {
+        if enabled != self.level_history.is_some() {
+            self.level_history = enabled.then(|| VecDeque::with_capacity(LEVEL_HISTORY_LEN));
+            self.level_capture_counter = 0;
+        }
+    }
+
+    /// Returns the size of the level history, in bytes.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.level_history
+            .as_ref()
+            .map_or(0, |h| h.capacity() * mem::size_of::<[i16; 4]>())
+    }
+
+    /// Returns the captured channel levels, oldest first, or `None` if capture is disabled.
+    pub fn level_history(&self) -> Option<&VecDeque<[i16; 4]>> {
+        self.level_history.as_ref()
+    }
+
+    /// Mutes or unmutes channel `idx` (0 to 3) in the mixer output.
+    ///
+    /// The channel itself keeps running, so this has no effect on its state or registers.
+    pub fn set_channel_muted(&mut self, idx: usize, muted: bool) {
+        self.mixer_enabled[idx] = !muted;
+    }
+
+    /// Returns whether channel `idx` (0 to 3) is muted in the mixer output.
+    pub fn channel_muted(&self, idx: usize) -> bool {
+        !self.mixer_enabled[idx]
+    }
+
+    /// Sets the volume applied to the mixer output, from 0.0 (mute) to 1.0 (full volume).
+    ///
+    /// This is independent of the volume set by the game through NR50. Samples are still
+    /// produced at the same rate when muted, so audio sync is not affected.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    /// Enables or disables fast-forward mode, in which no audio samples are produced.
+    ///
+    /// This avoids flooding the audio channel while emulating faster than real time.
+    /// The sound channels keep running, so audio resumes in sync once disabled.
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Mutes all channels except `idx` (0 to 3), which gets unmuted.
+    pub fn solo_channel(&mut self, idx: usize) {
+        for (i, enabled) in self.mixer_enabled.iter_mut().enumerate() {
+            *enabled = i == idx;
+        }
+    }
+
+    /// Advances the sound controller state machine by a single M-cycle.
+    pub fn tick(&mut self) {
         // Internal timer clock tick
         self.ch1.tick();
         self.ch2.tick();
         self.ch3.tick();
         self.ch4.tick();
 
+        self.tick_mixer();
+        self.capture_levels();
+    }
+
+    /// Samples the DIV bit driving the frame sequencer, ie. bit 4 of DIV (bit 5 in double
+    /// speed mode). The frame sequencer steps on each falling edge of this bit, at 512Hz.
+    pub fn tick_div(&mut self, div_bit: bool) {
+        if self.div_bit && !div_bit {
+            self.step_frame_sequencer();
+        }
+        self.div_bit = div_bit;
+    }
+
+    /// Advances the frame sequencer to its next step, clocking the units of that step.
+    fn step_frame_sequencer(&mut self) -> SequencerClocks {
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) % 8;
+
+        let clocks = SequencerClocks::for_step(step);
+
         // Volume envelope clock tick
-        if clk_64 {
+        if clocks.envelope {
             self.ch1.tick_vol_env();
             self.ch2.tick_vol_env();
             self.ch4.tick_vol_env();
         }
 
         // Sweep clock tick
-        if clk_128 {
+        if clocks.sweep {
             self.ch1.tick_freq_sweep();
         }
 
         // Lenght counter clock tick
-        if clk_256 {
+        if clocks.length {
             self.ch1.tick_len_ctr();
             self.ch2.tick_len_ctr();
             self.ch3.tick_len_ctr();
             self.ch4.tick_len_ctr();
         }
 
-        self.tick_mixer();
+        self.update_length_phase();
+
+        clocks
+    }
+
+    /// Tells the length counters whether the next frame sequencer step clocks them.
+    fn update_length_phase(&mut self) {
+        // https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Obscure_Behavior
+        // Extra length clocking occurs when writing to NRx4 when the frame sequencer's next step
+        // is one that doesn't clock the length counter.
+        let should_dec_on_enable = !SequencerClocks::for_step(self.frame_sequencer_step).length;
+        self.ch1.length.should_dec_on_enable = should_dec_on_enable;
+        self.ch2.length.should_dec_on_enable = should_dec_on_enable;
+        self.ch3.length.should_dec_on_enable = should_dec_on_enable;
+        self.ch4.length.should_dec_on_enable = should_dec_on_enable;
+    }
+
+    /// Appends the channel levels to the history, if enabled, once every capture period.
+    fn capture_levels(&mut self) {
+        if self.level_history.is_none() {
+            return;
+        }
+
+        self.level_capture_counter += 1;
+        if self.level_capture_counter < LEVEL_CAPTURE_PERIOD {
+            return;
+        }
+        self.level_capture_counter = 0;
+
+        let levels = self.channel_levels();
+        if let Some(ref mut history) = self.level_history {
+            if history.len() == LEVEL_HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(levels);
+        }
     }
 
     /// Update mixer output
     fn tick_mixer(&mut self) {
+        if self.fast_forward {
+            return;
+        }
+
         self.sample_rate_counter += 4.0;
 
         // Update the audio channel
@@ -1042,10 +1375,16 @@ impl Apu {
             self.sample_rate_counter -= self.sample_period;
 
             if let Some(ref mut sink) = self.sample_channel {
-                let ch1 = self.ch1.get_channel_out();
-                let ch2 = self.ch2.get_channel_out();
-                let ch3 = self.ch3.get_channel_out();
-                let ch4 = self.ch4.get_channel_out();
+                let mut levels = [
+                    self.ch1.get_channel_out(),
+                    self.ch2.get_channel_out(),
+                    self.ch3.get_channel_out(),
+                    self.ch4.get_channel_out(),
+                ];
+                for (level, &enabled) in levels.iter_mut().zip(&self.mixer_enabled) {
+                    *level *= i16::from(enabled);
+                }
+                let [ch1, ch2, ch3, ch4] = levels;
 
                 let mut so2 = 0;
                 let mut so1 = 0;
@@ -1088,7 +1427,8 @@ impl Apu {
 
                     // Produce a sample which is an average of the two channels.
                     // TODO implement true stero sound.
-                    sink.push((so1 + so2) / 2);
+                    let sample = (so1 + so2) / 2;
+                    sink.push((f32::from(sample) * self.master_volume) as i16);
                 }
             }
         }
@@ -1140,8 +1480,8 @@ impl Apu {
             // When powered on, the frame sequencer is reset so that the next step will be 0,
             // the square duty units are reset to the first step of the waveform,
             // and the wave channel's sample buffer is reset to 0.
-            self.frame_sequencer_clock = Self::default().frame_sequencer_clock;
-            self.frame_sequencer_ticks = Self::default().frame_sequencer_ticks;
+            self.frame_sequencer_step = FRAME_SEQUENCER_FIRST_STEP;
+            self.update_length_phase();
             self.ch2.timer_counter = 0;
             self.ch3.sample_buffer = 0;
         }
@@ -1153,18 +1493,67 @@ impl Apu {
 
     /// Changes the current sample rate.
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
-        self.sample_period = (crate::CPU_CLOCK as f32) / sample_rate;
-        self.sample_rate_counter = 0f32;
+        self.sample_rate = sample_rate;
+        self.update_sample_period();
     }
 
-    /// Configures the provided audio source as audio output.
+    /// Changes the effective CPU clock rate, in Hz, that samples are produced against.
+    pub fn set_clock_rate(&mut self, clock_rate: f32) {
+        self.clock_rate = clock_rate;
+        self.update_sample_period();
+    }
+
+    pub fn clock_rate(&self) -> f32 {
+        self.clock_rate
+    }
+
+    fn update_sample_period(&mut self) {
+        self.sample_period = self.clock_rate / self.sample_rate;
+
+        // Preserve the sampling phase, so that small rate adjustments don't cause glitches
+        self.sample_rate_counter = self.sample_rate_counter.min(self.sample_period);
+    }
+
+    /// Configures the provided audio source as audio output, replacing any audio callback.
     pub fn set_audio_source(&mut self, source: AudioSource) {
-        self.sample_channel = Some(source);
+        self.sample_channel = Some(AudioOutput::Source(source));
+    }
+
+    /// Configures a function receiving every sample as audio output, replacing any audio source.
+    ///
+    /// Clones of the APU (eg. in snapshots) share the same callback.
+    pub fn set_audio_callback(&mut self, callback: AudioCallback) {
+        self.sample_channel = Some(AudioOutput::Callback(Arc::new(Mutex::new(callback))));
+    }
+
+    /// Detaches the audio source, if configured, so that no more samples are produced.
+    pub(crate) fn detach_audio_source(&mut self) {
+        self.sample_channel = None;
+    }
+
+    /// Returns the fill level of the audio source, if configured.
+    pub fn audio_fill_level(&self) -> Option<f32> {
+        self.audio_source().map(AudioSource::fill_level)
+    }
+
+    /// Returns the overrun/underrun statistics of the audio source, if configured.
+    pub fn audio_stats(&self) -> Option<AudioStats> {
+        self.audio_source().map(AudioSource::stats)
+    }
+
+    fn audio_source(&self) -> Option<&AudioSource> {
+        match self.sample_channel {
+            Some(AudioOutput::Source(ref source)) => Some(source),
+            _ => None,
+        }
     }
 
     /// Returns a mutable reference to the audio source, if configured.
     pub fn audio_source_mut(&mut self) -> Option<&mut AudioSource> {
-        self.sample_channel.as_mut()
+        match self.sample_channel {
+            Some(AudioOutput::Source(ref mut source)) => Some(source),
+            _ => None,
+        }
     }
 }
 
@@ -1186,7 +1575,7 @@ impl MemR for Apu {
             0xFF25 => self.nr51.bits(),
             0xFF26 => self.read_pwr_reg() | 0x70,
 
-            0xFF30..=0xFF3F => self.ch3.wave_ram[usize::from(addr) - 0xFF30],
+            0xFF30..=0xFF3F => self.ch3.read_wave_ram(usize::from(addr) - 0xFF30),
 
             // Unused regs in this range: 0xFF15, 0xFF1F, 0xFF27..=0xFF2F
             _ => 0xFF,
@@ -1206,7 +1595,7 @@ impl MemW for Apu {
                 0xFF1B => self.ch3.write(addr - 0xFF1A, val)?,
                 0xFF20 => self.ch4.write(addr - 0xFF1F, val & 0b_0011_1111)?,
                 0xFF26 => self.write_to_pwr_reg(val)?,
-                0xFF30..=0xFF3F => self.ch3.wave_ram[usize::from(addr) - 0xFF30] = val,
+                0xFF30..=0xFF3F => self.ch3.write_wave_ram(usize::from(addr) - 0xFF30, val),
                 _ => (),
             }
         } else {
@@ -1220,7 +1609,7 @@ impl MemW for Apu {
                 0xFF25 => self.nr51 = NR51::from_bits_truncate(val),
                 0xFF26 => self.write_to_pwr_reg(val)?,
 
-                0xFF30..=0xFF3F => self.ch3.wave_ram[usize::from(addr) - 0xFF30] = val,
+                0xFF30..=0xFF3F => self.ch3.write_wave_ram(usize::from(addr) - 0xFF30, val),
 
                 // Unused regs in this range: 0xFF15, 0xFF1F, 0xFF27..=0xFF2F
                 _ => (),
@@ -1230,3 +1619,524 @@ impl MemW for Apu {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::AudioSink;
+
+    /// Returns an APU producing a sample every M-cycle, along with the sink receiving them.
+    fn sampling_apu() -> (Apu, AudioSink) {
+        let (source, mut sink) = crate::create_sound_channel(1024);
+        sink.set_blocking(false);
+
+        let mut apu = Apu::default();
+        apu.set_sample_rate(crate::CPU_CLOCK as f32 / 2.0);
+        apu.set_audio_source(source);
+
+        (apu, sink)
+    }
+
+    /// Runs `apu` for 64 M-cycles, returning the samples produced in the meantime.
+    fn mix(apu: &mut Apu, sink: &mut AudioSink) -> Vec<i16> {
+        (0..64).for_each(|_| apu.tick());
+        std::iter::from_fn(|| sink.pop()).collect()
+    }
+
+    #[test]
+    fn noise_channel_is_deterministic() {
+        let run = |ch4: &mut NoiseChannel| -> Vec<i16> {
+            (0..10000)
+                .map(|_| {
+                    ch4.tick();
+                    ch4.get_channel_out()
+                })
+                .collect()
+        };
+
+        let mut channels = [NoiseChannel::default(), NoiseChannel::default()];
+        for ch4 in &mut channels {
+            ch4.write(2, 0xF0).unwrap();
+            ch4.write(3, 0x09).unwrap();
+            ch4.write(4, 0x80).unwrap();
+        }
+
+        let [a, b] = &mut channels;
+        let state = a.lfsr_state();
+        let out = run(a);
+        assert_eq!(out, run(b));
+        assert!(out.iter().any(|&v| v != out[0]));
+
+        // Restoring the LFSR state replays the same sequence
+        b.set_lfsr_state(state);
+        b.timer_counter = b.get_period();
+        assert_eq!(run(b), out);
+    }
+
+    #[test]
+    fn wave_channel_volume() {
+        let mut ch3 = WaveChannel::default();
+
+        // Trigger the channel with a full-scale sample
+        ch3.write(0, 0x80).unwrap();
+        ch3.write(4, 0x80).unwrap();
+        ch3.sample_buffer = 0x0F;
+        assert!(ch3.enabled);
+
+        for (code, out) in [(0b00, 0), (0b01, 15), (0b10, 7), (0b11, 3)] {
+            ch3.write(2, code << 5).unwrap();
+            assert_eq!(ch3.get_channel_out(), out, "volume code {:#04b}", code);
+        }
+    }
+
+    #[test]
+    fn wave_ram_access_while_playing() {
+        let mut apu = Apu::default();
+
+        for addr in 0xFF30..=0xFF3F {
+            apu.write(addr, addr as u8).unwrap();
+        }
+        assert_eq!(apu.read(0xFF35).unwrap(), 0x35);
+
+        // Enable and trigger channel 3 at its lowest frequency
+        apu.write(0xFF1A, 0x80).unwrap();
+        apu.write(0xFF1E, 0x80).unwrap();
+        assert!(apu.ch3.enabled);
+
+        // Reads return 0xFF and writes are ignored
+        assert_eq!(apu.read(0xFF35).unwrap(), 0xFF);
+        apu.write(0xFF35, 0xAA).unwrap();
+
+        // Once the channel is stopped, Wave RAM is accessible again
+        apu.write(0xFF1A, 0x00).unwrap();
+        assert!(!apu.ch3.enabled);
+        assert_eq!(apu.read(0xFF35).unwrap(), 0x35);
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut len = LengthCounter::new(TONE_CH_LEN_MAX);
+        len.should_dec_on_enable = false;
+
+        // NRx1 = 62 -> two ticks left
+        len.load(62);
+        assert!(!len.tick(NRx4::empty()));
+        assert!(!len.tick(NRx4::LEN_EN));
+        assert!(len.tick(NRx4::LEN_EN));
+
+        // Triggering with an expired counter reloads it to maximum
+        assert!(!len.write_to_nr4(NRx4::LEN_EN, NRx4::TRIGGER | NRx4::LEN_EN));
+        assert_eq!(len.counter, TONE_CH_LEN_MAX);
+
+        // Enabling the counter on a non-length step decrements it, possibly disabling the channel
+        len.should_dec_on_enable = true;
+        len.load(63);
+        assert!(len.write_to_nr4(NRx4::empty(), NRx4::LEN_EN));
+        assert_eq!(len.counter, 0);
+    }
+
+    #[test]
+    fn volume_envelope() {
+        // Start at volume 14, increasing every tick
+        let nrx2 = NRx2::from_bits_truncate(0xE9);
+        let mut env = VolumeEnvelope::default();
+        env.trigger(nrx2);
+        assert_eq!(env.volume, 14);
+
+        env.tick(nrx2);
+        assert_eq!(env.volume, 15);
+
+        // Volume never exceeds 15, and the envelope stops once it would
+        env.tick(nrx2);
+        assert_eq!(env.volume, 15);
+        assert!(!env.enabled);
+
+        // Volume never goes below 0 either
+        let nrx2 = NRx2::from_bits_truncate(0x11);
+        env.trigger(nrx2);
+        env.tick(nrx2);
+        env.tick(nrx2);
+        assert_eq!(env.volume, 0);
+        assert!(!env.enabled);
+
+        // A zero period disables the envelope
+        let nrx2 = NRx2::from_bits_truncate(0x50);
+        env.trigger(nrx2);
+        for _ in 0..16 {
+            env.tick(nrx2);
+        }
+        assert_eq!(env.volume, 5);
+    }
+
+    #[test]
+    fn channel_levels() {
+        let mut apu = Apu::default();
+
+        // Trigger channel 2 at full volume
+        apu.write(0xFF17, 0xF0).unwrap();
+        apu.write(0xFF19, 0x80).unwrap();
+
+        let levels = apu.channel_levels();
+        assert_ne!(levels[1], 0);
+
+        // Reading levels has no side effects
+        assert_eq!(apu.channel_levels(), levels);
+        assert!(apu.ch2.enabled());
+    }
+
+    #[test]
+    fn level_capture() {
+        let mut apu = Apu::default();
+        assert!(apu.level_history().is_none());
+
+        // Trigger channel 2 at full volume
+        apu.write(0xFF17, 0xF0).unwrap();
+        apu.write(0xFF19, 0x80).unwrap();
+
+        apu.set_level_capture(true);
+        for _ in 0..LEVEL_CAPTURE_PERIOD * 10 {
+            apu.tick();
+        }
+
+        let history = apu.level_history().unwrap();
+        assert_eq!(history.len(), 10);
+        assert!(history.iter().any(|levels| levels[1] != 0));
+        assert_eq!(history.back(), Some(&apu.channel_levels()));
+
+        // Only the most recent levels are kept
+        for _ in 0..LEVEL_CAPTURE_PERIOD * LEVEL_HISTORY_LEN as u32 {
+            apu.tick();
+        }
+        assert_eq!(apu.level_history().unwrap().len(), LEVEL_HISTORY_LEN);
+
+        // The history survives resets, but not disabling the capture
+        apu.reset();
+        assert_eq!(apu.level_history().unwrap().len(), LEVEL_HISTORY_LEN);
+        apu.set_level_capture(false);
+        assert!(apu.level_history().is_none());
+    }
+
+    #[test]
+    fn channel_mute() {
+        let (mut apu, mut sink) = sampling_apu();
+
+        // Only channel 1 is playing at first
+        apu.write(0xFF12, 0xF0).unwrap();
+        apu.write(0xFF14, 0x80).unwrap();
+        assert!(mix(&mut apu, &mut sink).iter().all(|&s| s != 0));
+
+        // Muting a channel does not affect the channel itself
+        apu.set_channel_muted(0, true);
+        assert!(apu.channel_muted(0));
+        let muted = mix(&mut apu, &mut sink);
+        assert_eq!(muted.len(), 64);
+        assert!(muted.iter().all(|&s| s == 0));
+        assert!(apu.ch1.enabled());
+        assert_ne!(apu.channel_levels()[0], 0);
+
+        // Channel 2 still contributes to the output
+        apu.write(0xFF17, 0xF0).unwrap();
+        apu.write(0xFF19, 0x80).unwrap();
+        assert!(mix(&mut apu, &mut sink).iter().all(|&s| s != 0));
+
+        // Solo mutes all the other channels
+        apu.solo_channel(0);
+        assert_eq!(
+            [0, 1, 2, 3].map(|i| apu.channel_muted(i)),
+            [false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn frame_sequencer_steps() {
+        let mut apu = Apu::default();
+
+        // Power cycle the APU, so that the next step is 0
+        apu.write(0xFF26, 0x00).unwrap();
+        apu.write(0xFF26, 0x80).unwrap();
+
+        let (none, len) = (
+            SequencerClocks::default(),
+            SequencerClocks {
+                length: true,
+                ..Default::default()
+            },
+        );
+        let expected = [
+            len,
+            none,
+            SequencerClocks { sweep: true, ..len },
+            none,
+            len,
+            none,
+            SequencerClocks { sweep: true, ..len },
+            SequencerClocks {
+                envelope: true,
+                ..none
+            },
+        ];
+
+        for (step, &clocks) in expected.iter().enumerate() {
+            assert_eq!(apu.frame_sequencer_step, step as u8);
+            assert_eq!(apu.step_frame_sequencer(), clocks, "step {step}");
+        }
+        assert_eq!(apu.frame_sequencer_step, 0);
+    }
+
+    #[test]
+    fn frame_sequencer_follows_div() {
+        let mut apu = Apu::default();
+
+        // Only falling edges of the DIV bit clock the frame sequencer
+        apu.tick_div(false);
+        apu.tick_div(true);
+        apu.tick_div(true);
+        assert_eq!(apu.frame_sequencer_step, 0);
+        apu.tick_div(false);
+        assert_eq!(apu.frame_sequencer_step, 1);
+        apu.tick_div(false);
+        assert_eq!(apu.frame_sequencer_step, 1);
+
+        // Once step 2 is next, the following falling edge clocks the length counters
+        apu.tick_div(true);
+        apu.tick_div(false);
+        assert_eq!(apu.frame_sequencer_step, 2);
+        apu.write(0xFF11, 0x3F).unwrap();
+        apu.write(0xFF12, 0xF0).unwrap();
+        apu.write(0xFF14, 0xC0).unwrap();
+        assert!(apu.ch1.enabled());
+        apu.tick_div(true);
+        apu.tick_div(false);
+        assert!(!apu.ch1.enabled());
+    }
+
+    #[test]
+    fn length_extra_clocking_on_trigger() {
+        // Runs `n` frame sequencer steps
+        fn steps(apu: &mut Apu, n: usize) {
+            for _ in 0..n {
+                apu.tick_div(true);
+                apu.tick_div(false);
+            }
+        }
+
+        // Power cycles the APU, so that the next step clocks the length counter,
+        // and enables the DAC of channel 1.
+        fn power_cycle(apu: &mut Apu) {
+            apu.write(0xFF26, 0x00).unwrap();
+            apu.write(0xFF26, 0x80).unwrap();
+            apu.write(0xFF12, 0xF0).unwrap();
+        }
+
+        let mut apu = Apu::default();
+
+        // Next step clocks length: no extra clock, so a length of 2 lasts two length clocks
+        power_cycle(&mut apu);
+        apu.write(0xFF11, 0x3E).unwrap();
+        apu.write(0xFF14, 0xC0).unwrap();
+        assert_eq!(apu.ch1.length.counter, 2);
+        steps(&mut apu, 2);
+        assert!(apu.ch1.enabled());
+        steps(&mut apu, 1);
+        assert!(!apu.ch1.enabled());
+
+        // Next step doesn't clock length: the trigger clocks it once more
+        power_cycle(&mut apu);
+        steps(&mut apu, 1);
+        apu.write(0xFF11, 0x3E).unwrap();
+        apu.write(0xFF14, 0xC0).unwrap();
+        assert_eq!(apu.ch1.length.counter, 1);
+        steps(&mut apu, 1);
+        assert!(apu.ch1.enabled());
+        steps(&mut apu, 1);
+        assert!(!apu.ch1.enabled());
+
+        // Enabling length without a trigger can disable the channel on the extra clock
+        power_cycle(&mut apu);
+        steps(&mut apu, 1);
+        apu.write(0xFF11, 0x3F).unwrap();
+        apu.write(0xFF14, 0x80).unwrap();
+        assert!(apu.ch1.enabled());
+        apu.write(0xFF14, 0x40).unwrap();
+        assert!(!apu.ch1.enabled());
+
+        // Triggering with an expired counter reloads it with 64, minus the extra clock
+        apu.write(0xFF14, 0xC0).unwrap();
+        assert_eq!(apu.ch1.length.counter, 63);
+
+        // Same as above, but without the extra clock
+        steps(&mut apu, 1);
+        apu.write(0xFF14, 0x00).unwrap();
+        apu.ch1.length.counter = 0;
+        apu.write(0xFF14, 0xC0).unwrap();
+        assert_eq!(apu.ch1.length.counter, 64);
+    }
+
+    #[test]
+    fn fast_forward() {
+        let (mut apu, mut sink) = sampling_apu();
+        apu.write(0xFF12, 0xF0).unwrap();
+        apu.write(0xFF14, 0x80).unwrap();
+
+        apu.set_fast_forward(true);
+        assert!(mix(&mut apu, &mut sink).is_empty());
+        assert!(apu.ch1.enabled());
+
+        apu.set_fast_forward(false);
+        assert_eq!(mix(&mut apu, &mut sink).len(), 64);
+    }
+
+    #[test]
+    fn audio_callback() {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let sink = samples.clone();
+
+        let mut apu = Apu::default();
+        apu.set_sample_rate(crate::CPU_CLOCK as f32 / 8.0);
+        apu.set_audio_callback(Box::new(move |s| sink.lock().unwrap().push(s)));
+
+        // 1/1024th of a second yields 512 samples, allowing for the sampling phase
+        (0..crate::CPU_CLOCK / 4 / 1024).for_each(|_| apu.tick());
+        assert!(samples.lock().unwrap().len().abs_diff(512) <= 1);
+        assert!(apu.audio_source_mut().is_none());
+
+        // Configuring an audio source replaces the callback
+        let (source, mut sink) = crate::create_sound_channel(1024);
+        sink.set_blocking(false);
+        apu.set_audio_source(source);
+        samples.lock().unwrap().clear();
+
+        (0..64).for_each(|_| apu.tick());
+        assert!(samples.lock().unwrap().is_empty());
+        assert!(std::iter::from_fn(|| sink.pop()).count() > 0);
+    }
+
+    #[test]
+    fn clock_rate() {
+        let (mut apu, mut sink) = sampling_apu();
+        apu.set_sample_rate(crate::CPU_CLOCK as f32 / 8.0);
+        assert_eq!(apu.sample_period, 8.0);
+
+        // Count the samples produced in 1/1024th of a second at the DMG clock rate,
+        // allowing for the sampling phase
+        let mut samples = |apu: &mut Apu| {
+            (0..crate::CPU_CLOCK / 4 / 1024).for_each(|_| apu.tick());
+            std::iter::from_fn(|| sink.pop()).count()
+        };
+        assert!(samples(&mut apu).abs_diff(512) <= 1);
+
+        // A faster clock runs more cycles per sample, so fewer samples are produced in as many
+        apu.set_clock_rate(crate::CPU_CLOCK as f32 * 2.0);
+        assert_eq!(apu.sample_period, 16.0);
+        assert!(samples(&mut apu).abs_diff(256) <= 1);
+
+        // The clock rate survives a reset
+        apu.reset();
+        assert_eq!(apu.clock_rate(), crate::CPU_CLOCK as f32 * 2.0);
+        assert_eq!(apu.sample_period, 16.0);
+    }
+
+    #[test]
+    fn master_volume() {
+        let (mut apu, mut sink) = sampling_apu();
+
+        apu.write(0xFF12, 0xF0).unwrap();
+        apu.write(0xFF14, 0x80).unwrap();
+        let full = mix(&mut apu, &mut sink);
+        assert_eq!(full.len(), 64);
+        assert!(full.iter().all(|&s| s != 0));
+
+        // Muting still produces the same number of samples, and the channels keep running
+        apu.set_master_volume(0.0);
+        let timer = apu.ch1.timer_counter;
+        let muted = mix(&mut apu, &mut sink);
+        assert_eq!(muted.len(), full.len());
+        assert!(muted.iter().all(|&s| s == 0));
+        assert_ne!(apu.ch1.timer_counter, timer);
+        assert!(apu.ch1.enabled());
+
+        apu.set_master_volume(0.5);
+        let half = mix(&mut apu, &mut sink);
+        assert!(half.iter().all(|&s| s != 0 && s.abs() < full[0].abs()));
+
+        // The volume survives a reset
+        apu.reset();
+        assert_eq!(apu.master_volume(), 0.5);
+    }
+
+    #[test]
+    fn volume_envelope_period() {
+        let mut apu = Apu::default();
+
+        // Volume 15, decreasing every 3 ticks
+        apu.write(0xFF12, 0xF3).unwrap();
+        apu.write(0xFF14, 0x80).unwrap();
+
+        for vol in [15, 15, 14, 14, 14, 13, 13, 13, 12] {
+            apu.ch1.tick_vol_env();
+            assert_eq!(apu.ch1.get_volume(), vol);
+
+            // The envelope period stored in NR12 is left untouched
+            assert_eq!(apu.read(0xFF12).unwrap(), 0xF3);
+        }
+    }
+
+    #[test]
+    fn tone_channels_trigger_and_length() {
+        let mut apu = Apu::default();
+
+        // Avoid extra length clocking when enabling the length counter
+        apu.ch1.length.should_dec_on_enable = false;
+        apu.ch2.length.should_dec_on_enable = false;
+
+        for (base, ch) in [(0xFF10, 1), (0xFF15, 2)] {
+            // Max volume, DAC on, length = 1 tick, trigger with length enabled
+            apu.write(base + 1, 0x3F).unwrap();
+            apu.write(base + 2, 0xF0).unwrap();
+            apu.write(base + 4, 0xC0).unwrap();
+
+            let channel = if ch == 1 { &mut apu.ch1 } else { &mut apu.ch2 };
+            assert!(channel.enabled());
+            assert_eq!(channel.get_volume(), 15);
+
+            channel.tick_len_ctr();
+            assert!(!channel.enabled());
+            assert_eq!(channel.get_volume(), 0);
+        }
+    }
+
+    #[test]
+    fn wave_channel_trigger_and_length() {
+        let mut apu = Apu::default();
+        apu.ch3.length.should_dec_on_enable = false;
+
+        // DAC on, length = 1 tick, trigger with length enabled
+        apu.write(0xFF1A, 0x80).unwrap();
+        apu.write(0xFF1B, 0xFF).unwrap();
+        apu.write(0xFF1E, 0xC0).unwrap();
+        assert!(apu.ch3.enabled);
+
+        apu.ch3.tick_len_ctr();
+        assert!(!apu.ch3.enabled);
+    }
+
+    #[test]
+    fn noise_channel_trigger_and_envelope() {
+        let mut apu = Apu::default();
+
+        // Volume 2, decreasing, period 1
+        apu.write(0xFF21, 0x21).unwrap();
+        apu.write(0xFF23, 0x80).unwrap();
+        assert!(apu.ch4.enabled());
+        assert_eq!(apu.ch4.get_volume(), 2);
+
+        apu.ch4.tick_vol_env();
+        assert_eq!(apu.ch4.get_volume(), 1);
+
+        // Triggering reloads the volume from NR42
+        apu.write(0xFF21, 0x21).unwrap();
+        apu.write(0xFF23, 0x80).unwrap();
+        assert_eq!(apu.ch4.get_volume(), 2);
+    }
+}
@@ -4,12 +4,19 @@ use crate::{
     mem::{MemR, MemRW, MemW},
 };
 
+/// Value of the system counter after the DMG boot ROM hands control to the cartridge.
+const INITIAL_COUNTER: u16 = 0xABCC;
+
+#[derive(Clone)]
 pub struct Timer {
     pub sys_counter: IoReg<u16>,
     pub tima: IoReg<u8>,
     pub tma: IoReg<u8>,
     pub tac: IoReg<u8>,
 
+    // Value of the system counter at power-up
+    initial_counter: u16,
+
     irq_pending: bool,
     tima_reload_scheduled: bool,
     tima_is_being_reloaded: bool,
@@ -18,11 +25,13 @@ pub struct Timer {
 impl Default for Timer {
     fn default() -> Timer {
         Timer {
-            sys_counter: IoReg(0xABCC),
+            sys_counter: IoReg(INITIAL_COUNTER),
             tima: IoReg(0),
             tma: IoReg(0),
             tac: IoReg(0),
 
+            initial_counter: INITIAL_COUNTER,
+
             irq_pending: false,
             tima_reload_scheduled: false,
             tima_is_being_reloaded: false,
@@ -35,6 +44,25 @@ impl Timer {
         Timer::default()
     }
 
+    /// Sets the value of the system counter at power-up, and restarts the counter from it.
+    ///
+    /// The upper 8 bits of the counter are visible as DIV.
+    pub fn set_initial_counter(&mut self, value: u16) {
+        self.initial_counter = value;
+        self.sys_counter.0 = value;
+    }
+
+    pub fn initial_counter(&self) -> u16 {
+        self.initial_counter
+    }
+
+    /// Clears the system counter, as found by the boot ROM.
+    ///
+    /// The initial counter only applies when the boot ROM is skipped, so it is not affected.
+    pub(crate) fn power_on(&mut self) {
+        self.sys_counter = IoReg(0);
+    }
+
     pub fn div(&self) -> IoReg<u8> {
         IoReg((self.sys_counter.0 >> 8) as u8)
     }
@@ -58,10 +86,10 @@ impl Timer {
         }
 
         if !self.running() {
-            self.sys_counter.0 += 4;
+            self.sys_counter.0 = self.sys_counter.0.wrapping_add(4);
         } else {
             let old = self.sys_counter;
-            self.sys_counter.0 += 4;
+            self.sys_counter.0 = self.sys_counter.0.wrapping_add(4);
             let new = self.sys_counter;
 
             // TIMA is incremented when a falling edge is detected on the rate bit.
@@ -76,7 +104,7 @@ impl Timer {
     }
 
     fn inc_timer(&mut self) {
-        self.tima.0 += 1;
+        self.tima.0 = self.tima.0.wrapping_add(1);
 
         // Wehn TIMA overflows, TMA gets loaded in it and an IRQ request is registered.
         // This happend with a full cycle delay, so for 4 clock cycles upon overflowing,
@@ -291,4 +319,96 @@ mod tests {
         timer.reset_sys_counter();
         assert_eq!(timer.tima.0, 1);
     }
+
+    /// Returns a running timer which will overflow on the next tick, with TMA = 0x42.
+    fn overflowing_timer() -> Timer {
+        let mut timer = Timer::default();
+        timer.sys_counter.0 = 0;
+        timer.write(0xFF07, 0b101).unwrap();
+        timer.write(0xFF06, 0x42).unwrap();
+        timer.write(0xFF05, 0xFF).unwrap();
+
+        for _ in 0..3 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF05).unwrap(), 0xFF);
+
+        timer
+    }
+
+    #[test]
+    fn tima_reads_zero_for_one_cycle_after_overflow() {
+        let mut timer = overflowing_timer();
+
+        // On overflow, TIMA stays 00 for a whole M-cycle before being reloaded
+        timer.tick();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x00);
+        assert!(timer.get_and_clear_irq().is_none());
+
+        // Then TMA is loaded and the interrupt is requested
+        timer.tick();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x42);
+        assert!(matches!(timer.get_and_clear_irq(), Some(IrqSource::Timer)));
+    }
+
+    #[test]
+    fn tima_write_during_overflow_cancels_reload() {
+        let mut timer = overflowing_timer();
+
+        timer.tick();
+        timer.write(0xFF05, 0x10).unwrap();
+
+        timer.tick();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x10);
+        assert!(timer.get_and_clear_irq().is_none());
+    }
+
+    #[test]
+    fn writes_during_reload() {
+        let mut timer = overflowing_timer();
+
+        timer.tick();
+        timer.tick();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x42);
+
+        // Writes to TIMA are ignored during the reload cycle...
+        timer.write(0xFF05, 0x10).unwrap();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x42);
+
+        // ...while writes to TMA are immediately reflected in TIMA
+        timer.write(0xFF06, 0x55).unwrap();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x55);
+
+        // Once the reload cycle is over, TIMA can be written again
+        timer.tick();
+        timer.write(0xFF05, 0x10).unwrap();
+        assert_eq!(timer.read(0xFF05).unwrap(), 0x10);
+    }
+
+    #[test]
+    fn div_write_on_falling_edge_increments_tima() {
+        let mut timer = Timer::default();
+        timer.sys_counter.0 = 0;
+        timer.write(0xFF07, 0b101).unwrap();
+
+        // Bit 3 of the divider is still low: resetting it causes no edge
+        timer.tick();
+        timer.write(0xFF04, 0x12).unwrap();
+        assert_eq!(timer.read(0xFF04).unwrap(), 0);
+        assert_eq!(timer.read(0xFF05).unwrap(), 0);
+
+        // Bit 3 of the divider is high: resetting it causes a falling edge
+        timer.tick();
+        timer.tick();
+        assert_eq!(timer.sys_counter.0, 8);
+        timer.write(0xFF04, 0x34).unwrap();
+        assert_eq!(timer.sys_counter.0, 0);
+        assert_eq!(timer.read(0xFF05).unwrap(), 1);
+
+        // The divider keeps counting from zero afterwards
+        for _ in 0..4 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF05).unwrap(), 2);
+    }
 }
@@ -1,3 +1,5 @@
+use std::{collections::VecDeque, mem};
+
 use bitflags::bitflags;
 
 use crate::{
@@ -6,6 +8,15 @@ use crate::{
     mem::{MemR, MemRW, MemW},
 };
 
+/// Number of tiles in each row of the sheet rendered by [`Ppu::tilesheet`].
+pub const TILESHEET_COLUMNS: usize = 16;
+
+/// Size in pixels (width, height) of the sheet rendered by [`Ppu::tilesheet`].
+pub const TILESHEET_SIZE: (usize, usize) = (TILESHEET_COLUMNS * 8, 384 / TILESHEET_COLUMNS * 8);
+
+/// Maximum number of entries kept in the palette history.
+pub const PALETTE_HISTORY_LEN: usize = 64;
+
 /// A Tile is the bit representation of an 8x8 sprite or BG tile,
 /// with a color depth of 4 colors/gray shades.
 ///
@@ -53,6 +64,8 @@ bitflags! {
         const FLIP_Y  = 0b_0100_0000;
         const FLIP_X  = 0b_0010_0000;
         const PAL_NUM = 0b_0001_0000;
+        const VRAM_BANK = 0b_0000_1000;
+        const CGB_PAL = 0b_0000_0111;
 
         const DEFAULT = 0b_0000_0000;
     }
@@ -67,7 +80,7 @@ impl Default for SpriteAttributes {
     }
 }
 
-impl<'a> MemR for &'a [Sprite] {
+impl MemR for &[Sprite] {
     fn read(&self, addr: u16) -> Result<u8, dbg::TraceEvent> {
         let s = &self[usize::from(addr >> 2)];
 
@@ -81,13 +94,13 @@ impl<'a> MemR for &'a [Sprite] {
     }
 }
 
-impl<'a> MemR for &'a mut [Sprite] {
+impl MemR for &mut [Sprite] {
     fn read(&self, addr: u16) -> Result<u8, dbg::TraceEvent> {
         (self as &[Sprite]).read(addr)
     }
 }
 
-impl<'a> MemW for &'a mut [Sprite] {
+impl MemW for &mut [Sprite] {
     fn write(&mut self, addr: u16, val: u8) -> Result<(), dbg::TraceEvent> {
         let s = &mut self[usize::from(addr >> 2)];
 
@@ -102,7 +115,77 @@ impl<'a> MemW for &'a mut [Sprite] {
     }
 }
 
-impl<'a> MemRW for &'a mut [Sprite] {}
+impl MemRW for &mut [Sprite] {}
+
+/// Public view of an OAM entry, decoupled from its in-memory representation.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SpriteInfo {
+    /// Vertical position on screen, plus 16.
+    pub y: u8,
+    /// Horizontal position on screen, plus 8.
+    pub x: u8,
+    /// Tile index.
+    pub tile: u8,
+    /// The sprite is drawn behind BG colors 1-3.
+    pub bg_priority: bool,
+    /// The sprite is mirrored vertically.
+    pub flip_y: bool,
+    /// The sprite is mirrored horizontally.
+    pub flip_x: bool,
+    /// The sprite uses OBP1 instead of OBP0 (DMG only).
+    pub dmg_palette: bool,
+    /// The tile is fetched from VRAM bank 1 (CGB only).
+    pub vram_bank: bool,
+    /// Palette number, from 0 to 7 (CGB only).
+    pub cgb_palette: u8,
+}
+
+impl From<Sprite> for SpriteInfo {
+    fn from(s: Sprite) -> SpriteInfo {
+        SpriteInfo {
+            y: s.y,
+            x: s.x,
+            tile: s.tid,
+            bg_priority: s.attributes.contains(SpriteAttributes::BG_PRIO),
+            flip_y: s.attributes.contains(SpriteAttributes::FLIP_Y),
+            flip_x: s.attributes.contains(SpriteAttributes::FLIP_X),
+            dmg_palette: s.attributes.contains(SpriteAttributes::PAL_NUM),
+            vram_bank: s.attributes.contains(SpriteAttributes::VRAM_BANK),
+            cgb_palette: (s.attributes & SpriteAttributes::CGB_PAL).bits(),
+        }
+    }
+}
+
+impl From<SpriteInfo> for Sprite {
+    fn from(s: SpriteInfo) -> Sprite {
+        let mut attributes = SpriteAttributes::from_bits_truncate(s.cgb_palette);
+        attributes.set(SpriteAttributes::BG_PRIO, s.bg_priority);
+        attributes.set(SpriteAttributes::FLIP_Y, s.flip_y);
+        attributes.set(SpriteAttributes::FLIP_X, s.flip_x);
+        attributes.set(SpriteAttributes::PAL_NUM, s.dmg_palette);
+        attributes.set(SpriteAttributes::VRAM_BANK, s.vram_bank);
+
+        Sprite {
+            y: s.y,
+            x: s.x,
+            tid: s.tile,
+            attributes,
+        }
+    }
+}
+
+bitflags! {
+    /// BG Map Attributes (CGB only), stored in VRAM bank 1 alongside the BG tile maps.
+    struct BgAttributes: u8 {
+        const BG_PRIO   = 0b_1000_0000; /// Bit 7 - BG-to-OAM Priority (0=Use OAM priority bit, 1=BG Priority)
+        const FLIP_Y    = 0b_0100_0000; /// Bit 6 - Vertical Flip      (0=Normal, 1=Mirror vertically)
+        const FLIP_X    = 0b_0010_0000; /// Bit 5 - Horizontal Flip    (0=Normal, 1=Mirror horizontally)
+        const VRAM_BANK = 0b_0000_1000; /// Bit 3 - Tile VRAM Bank     (0=Bank 0, 1=Bank 1)
+        const CGB_PAL   = 0b_0000_0111; /// Bit 2-0 - Background Palette number (BGP0-7)
+
+        const DEFAULT = 0b_0000_0000;
+    }
+}
 
 bitflags! {
     /// FF40 - LCDC - LCD Control (R/W)
@@ -144,7 +227,7 @@ bitflags! {
 mem_rw!(STAT, 0x80);
 
 bitflags! {
-    /// Used to keep track of which STAT IRQs are currently active.
+    /// Used to keep track of which STAT conditions are currently driving the STAT IRQ line.
     struct STATIRQ: u8 {
         const LYC = 0b_0100_0000;
         const OAM = 0b_0010_0000;
@@ -156,6 +239,7 @@ bitflags! {
 }
 
 /// A DMA transfer from ROM/RAM to OAM.
+#[derive(Clone)]
 struct DMATransfer {
     src: u16,
     dst: u16,
@@ -185,22 +269,278 @@ impl DMATransfer {
 
         self.src += 1;
         self.dst += 1;
-        self.remaining -= 1;
+        self.remaining = self.remaining.saturating_sub(1);
 
         xfer
     }
 }
 
+/// Line cycle at which mode 3 starts, right after the OAM scan.
+const MODE3_START: u64 = 80;
+
+/// Number of dots taken by a fetcher to read a tile (ID, low and high data bytes).
+/// Pushing the fetched row to the FIFO takes one more dot.
+const FETCH_DOTS: u8 = 5;
+
+/// Number of dots taken by the sprite fetcher, during which the pixel FIFO is stalled.
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// Maximum number of sprites selected by the OAM scan on a single line.
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+/// Timing model of the pixel FIFO during mode 3.
+///
+/// The BG fetcher pushes a row of 8 pixels whenever the FIFO runs empty, while the shifter
+/// outputs one pixel per dot. Pixels discarded for fine scrolling, window restarts and sprite
+/// fetches stall the shifter, which makes the duration of mode 3 vary from line to line.
+#[derive(Clone)]
+struct PixelFifo {
+    // Pixels currently held in the BG FIFO
+    bg_pixels: u8,
+    // Dots spent fetching the next BG row
+    fetcher: u8,
+    // Pixels still to be discarded because of SCX
+    discard: u8,
+    // X coordinate of the next pixel sent to the LCD
+    lx: u8,
+    // X coordinates (plus 8) of the sprites still to be fetched, in reverse order
+    sprites: Vec<u8>,
+    // Dots left in the sprite fetch in progress
+    sprite_fetch: u8,
+    // X coordinate at which the window starts, if visible on this line
+    window_x: Option<u8>,
+}
+
+impl PixelFifo {
+    /// Prepares the FIFO for a new line, with sprites located at `sprites` (X coordinate + 8).
+    fn new(scx: u8, sprites: &[u8], window_x: Option<u8>) -> PixelFifo {
+        let mut sprites = sprites.to_vec();
+        sprites.sort_unstable_by(|a, b| b.cmp(a));
+
+        PixelFifo {
+            bg_pixels: 0,
+            fetcher: 0,
+            discard: scx % 8,
+            lx: 0,
+            sprites,
+            sprite_fetch: 0,
+            window_x,
+        }
+    }
+
+    /// Runs the FIFO until the whole line has been output, returning the number of dots taken.
+    fn run(&mut self) -> u64 {
+        // The first tile of a line is fetched twice, and the first fetch is discarded
+        let mut dots = u64::from(FETCH_DOTS) + 1;
+
+        while self.lx < 160 {
+            self.step();
+            dots += 1;
+        }
+        dots
+    }
+
+    /// Advances the FIFO by a single dot.
+    fn step(&mut self) {
+        if self.sprite_fetch > 0 {
+            self.sprite_fetch -= 1;
+            if self.sprite_fetch == 0 {
+                self.sprites.pop();
+            }
+            return;
+        }
+
+        // The window restarts the BG fetcher with an empty FIFO
+        if self.window_x == Some(self.lx) && self.discard == 0 {
+            self.window_x = None;
+            self.bg_pixels = 0;
+            self.fetcher = 0;
+        }
+
+        // A sprite is fetched as soon as the BG fetcher is done with the current tile
+        let sprite_hit = self
+            .sprites
+            .last()
+            .is_some_and(|&x| x.saturating_sub(8) <= self.lx);
+
+        if sprite_hit && self.bg_pixels > 0 {
+            if self.fetcher < FETCH_DOTS {
+                self.fetcher += 1;
+            } else {
+                self.sprite_fetch = SPRITE_FETCH_DOTS - 1;
+            }
+            return;
+        }
+
+        // Shift out a pixel, either to the LCD or to the bin if scrolled out
+        if self.bg_pixels > 0 {
+            self.bg_pixels -= 1;
+            if self.discard > 0 {
+                self.discard -= 1;
+            } else {
+                self.lx += 1;
+            }
+        }
+
+        if self.fetcher < FETCH_DOTS {
+            self.fetcher += 1;
+        } else if self.bg_pixels == 0 {
+            self.bg_pixels = 8;
+            self.fetcher = 0;
+        }
+    }
+}
+
+/// CGB color palette memory, accessed through a pair of index/data registers (eg. BCPS/BCPD).
+///
+/// It holds 8 palettes of 4 colors each, with each color stored as a little-endian RGB555 word.
+#[derive(Clone)]
+struct ColorPalettes {
+    data: [u8; 64],
+    index: u8,
+    auto_inc: bool,
+}
+
+impl Default for ColorPalettes {
+    fn default() -> ColorPalettes {
+        ColorPalettes {
+            // All colors are white at power-up
+            data: [0xFF; 64],
+            index: 0,
+            auto_inc: false,
+        }
+    }
+}
+
+impl ColorPalettes {
+    /// Reads the palette specification register (eg. BCPS). Bit 6 is unused and reads as 1.
+    fn read_spec(&self) -> u8 {
+        (u8::from(self.auto_inc) << 7) | 0x40 | self.index
+    }
+
+    /// Writes the palette specification register (eg. BCPS).
+    fn write_spec(&mut self, val: u8) {
+        self.auto_inc = val & 0x80 != 0;
+        self.index = val & 0x3F;
+    }
+
+    /// Reads the palette byte currently selected by the specification register.
+    fn read_data(&self) -> u8 {
+        self.data[usize::from(self.index)]
+    }
+
+    /// Writes the palette byte currently selected by the specification register,
+    /// advancing the index if auto-increment is enabled.
+    fn write_data(&mut self, val: u8) {
+        self.data[usize::from(self.index)] = val;
+
+        if self.auto_inc {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    /// Returns the RGB555 color associated with a pixel value in a palette.
+    fn color(&self, palette: u8, pixel: u8) -> u16 {
+        let i = usize::from(palette & 0x7) * 8 + usize::from(pixel) * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]])
+    }
+}
+
+/// Color correction applied when converting CGB colors to RGB.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCorrection {
+    /// RGB555 colors are scaled linearly to RGB888.
+    Off,
+    /// Emulates the color response of the CGB LCD on modern sRGB displays.
+    #[default]
+    ModernLcd,
+}
+
+/// RGB colors used to render the four DMG shades, from lightest to darkest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    /// Plain grayscale, similar to the Game Boy Pocket LCD.
+    pub const GRAYSCALE: Palette = Palette([
+        [0xFF, 0xFF, 0xFF],
+        [0xAA, 0xAA, 0xAA],
+        [0x55, 0x55, 0x55],
+        [0x00, 0x00, 0x00],
+    ]);
+
+    /// Shades of green of the original Game Boy LCD.
+    pub const GREEN: Palette = Palette([
+        [0x9B, 0xBC, 0x0F],
+        [0x8B, 0xAC, 0x0F],
+        [0x30, 0x62, 0x30],
+        [0x0F, 0x38, 0x0F],
+    ]);
+
+    /// Creates a palette from four RGB555 colors, eg. as set by SGB commands.
+    pub fn from_rgb555(colors: [u16; 4]) -> Palette {
+        let scale = |c: u16| (((c & 0x1F) << 3) | ((c & 0x1F) >> 2)) as u8;
+        Palette(colors.map(|c| [scale(c), scale(c >> 5), scale(c >> 10)]))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::GRAYSCALE
+    }
+}
+
+/// Blends each frame with the previous one, emulating the slow response of the DMG LCD.
+#[derive(Clone)]
+struct FrameBlend {
+    weight: f32,
+    // The last two frames completed at V-Blank, most recent first
+    frames: [Vec<u8>; 2],
+}
+
+impl FrameBlend {
+    fn new(weight: f32) -> FrameBlend {
+        FrameBlend {
+            weight,
+            frames: [vec![0xFF; 160 * 144 * 4], vec![0xFF; 160 * 144 * 4]],
+        }
+    }
+}
+
+/// Monochrome palette registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteRegister {
+    Bgp,
+    Obp0,
+    Obp1,
+}
+
+/// A change to one of the monochrome palette registers, as recorded in the palette history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteChange {
+    pub register: PaletteRegister,
+    pub value: u8,
+    /// Number of frames completed when the change happened.
+    pub frame: u64,
+    /// Scanline (LY) being drawn when the change happened.
+    pub line: u8,
+}
+
+#[derive(Clone)]
 pub struct Ppu {
     tdt: [Tile; 384],  // Tile Data Table
+    tdt1: [Tile; 384], // Tile Data Table, VRAM bank 1 (CGB only)
     oam: [Sprite; 40], // Object Attribute Memory
     bgtm0: [u8; 1024], // Background Tile Map #0
     bgtm1: [u8; 1024], // Background Tile Map #1
+    bgam0: [u8; 1024], // Background Attribute Map #0 (CGB only)
+    bgam1: [u8; 1024], // Background Attribute Map #1 (CGB only)
 
     // Ctrl/status IO registes
     lcdc_reg: LCDC,
     stat_reg: STAT,
-    stat_irq: STATIRQ,
+    stat_line: STATIRQ,
+    stat_irq_pending: bool,
 
     // Position/scrolling registers
     scx_reg: IoReg<u8>,
@@ -215,6 +555,13 @@ pub struct Ppu {
     obp1_reg: IoReg<u8>,
     bgp_reg: IoReg<u8>,
 
+    // VRAM bank register (CGB only)
+    vbk_reg: IoReg<u8>,
+
+    // Color palettes (CGB only)
+    bg_palettes: ColorPalettes,
+    obj_palettes: ColorPalettes,
+
     // DMA register & counter
     dma_reg: IoReg<u8>,
     dma_xfer: Option<DMATransfer>,
@@ -222,22 +569,48 @@ pub struct Ppu {
 
     // Timings
     tstate: u64,
+    // Line cycle at which the current mode 3 ends
+    mode3_end: u64,
+    frame_count: u64,
+    // LY as seen by the LYC comparator, which lags one M-cycle behind
+    lyc_compare_ly: u8,
+    // Set while drawing the first frame after the LCD is turned on
+    first_frame: bool,
+
+    // Debug information
+    palette_history: VecDeque<PaletteChange>,
 
     // IRQ handling
     vblank_irq_pending: bool,
+
+    // Rendering options
+    cgb_mode: bool,
+    color_correction: ColorCorrection,
+    palette: Palette,
+    // Palette set by the SGB, taking precedence over the one above
+    sgb_palette: Option<Palette>,
+    frame_blend: Option<FrameBlend>,
+
+    // Completed frame and the one being rendered, swapped at V-Blank
+    front_buffer: Vec<u8>,
+    back_buffer: Vec<u8>,
 }
 
 impl Default for Ppu {
     fn default() -> Ppu {
         Ppu {
             tdt: [Tile::default(); 384],
+            tdt1: [Tile::default(); 384],
             oam: [Sprite::default(); 40],
             bgtm0: [0; 1024],
             bgtm1: [0; 1024],
+            bgam0: [0; 1024],
+            bgam1: [0; 1024],
 
             lcdc_reg: LCDC::DEFAULT,
             stat_reg: STAT::DEFAULT,
-            stat_irq: STATIRQ::DEFAULT,
+            stat_line: STATIRQ::DEFAULT,
+            stat_irq_pending: false,
 
             scx_reg: IoReg(0x00),
             scy_reg: IoReg(0x00),
@@ -250,13 +623,33 @@ impl Default for Ppu {
             obp0_reg: IoReg(0xFF),
             obp1_reg: IoReg(0xFF),
 
+            vbk_reg: IoReg(0x00),
+
+            bg_palettes: ColorPalettes::default(),
+            obj_palettes: ColorPalettes::default(),
+
             dma_reg: IoReg(0x00),
             dma_xfer: None,
             dma_xfer_queue: [None, None],
 
             tstate: 70164,
+            mode3_end: MODE3_START + 172,
+            frame_count: 0,
+            lyc_compare_ly: 0x99,
+            first_frame: false,
+
+            palette_history: VecDeque::with_capacity(PALETTE_HISTORY_LEN),
 
             vblank_irq_pending: true,
+
+            cgb_mode: false,
+            color_correction: ColorCorrection::default(),
+            palette: Palette::default(),
+            sgb_palette: None,
+            frame_blend: None,
+
+            front_buffer: vec![0xFF; 160 * 144 * 4],
+            back_buffer: vec![0xFF; 160 * 144 * 4],
         }
     }
 }
@@ -266,8 +659,23 @@ impl Ppu {
         Ppu::default()
     }
 
+    /// Returns the size of the frame buffers owned by the PPU, in bytes.
+    pub(crate) fn heap_size(&self) -> usize {
+        let blend = self
+            .frame_blend
+            .as_ref()
+            .map_or(0, |b| b.frames.iter().map(Vec::len).sum());
+
+        self.front_buffer.len() + self.back_buffer.len() + blend
+    }
+
     /// Advances the LCD controller state machine by a single M-cycle.
     pub fn tick(&mut self) {
+        // LY and the LCD mode are held at 0 while the display is off
+        if !self.lcdc_reg.contains(LCDC::DISP_EN) {
+            return;
+        }
+
         // Update ticks
         self.tstate = (self.tstate + 4) % 70224;
         let tstate = self.tstate % 456;
@@ -275,9 +683,23 @@ impl Ppu {
 
         self.ly_reg.0 = v_line as u8;
 
+        // The length of mode 3 depends on the contents of the line being drawn
+        if v_line < 144 && tstate == MODE3_START {
+            self.mode3_end = MODE3_START + self.mode3_duration(v_line as u8);
+        }
+
         // V-Blank IRQ happens at the beginning of the 144th line
         if v_line == 144 && tstate == 0 {
             self.vblank_irq_pending = true;
+            self.frame_count += 1;
+
+            // The first frame after turning on the LCD is not displayed
+            if self.first_frame {
+                self.first_frame = false;
+                self.present_blank_frame();
+            } else {
+                self.capture_frame();
+            }
         }
 
         // This should be called last, after every other counter has been updated!
@@ -309,6 +731,18 @@ impl Ppu {
         ret
     }
 
+    /// Returns the source address of the OAM DMA transfer in progress, if any.
+    pub fn dma_source(&self) -> Option<u16> {
+        self.dma_xfer.as_ref().map(|xfer| xfer.src)
+    }
+
+    /// Reads a byte from OAM. `addr` should be in range 0xFE00..=0xFE9F.
+    ///
+    /// Like [`Ppu::write_to_oam`], this bypasses the OAM DMA access checks.
+    pub fn read_from_oam(&self, addr: u16) -> u8 {
+        (&self.oam[..]).read(addr - 0xFE00).unwrap_or(0xFF)
+    }
+
     /// Writes `val` to OAM. `addr` should be in range 0xFE00..=0xFE9F.
     ///
     /// This is a utility function that bypassed the OAM DMA access checks
@@ -317,15 +751,81 @@ impl Ppu {
         (&mut self.oam[..]).write(addr - 0xFE00, val)
     }
 
+    /// Returns the contents of the 40 OAM entries.
+    pub fn sprites(&self) -> [SpriteInfo; 40] {
+        self.oam.map(SpriteInfo::from)
+    }
+
+    /// Overwrites the OAM entry at `index`, bypassing the OAM DMA access checks.
+    ///
+    /// Panics if `index` is not in range 0..40.
+    pub fn set_sprite(&mut self, index: usize, sprite: SpriteInfo) {
+        self.oam[index] = sprite.into();
+    }
+
     /// Rasterizes the current contents of the Video RAM to the provided video buffer.
     ///
+    /// If frame blending is enabled, the last frame completed at V-Blank is blended
+    /// with the previous one instead.
+    ///
     /// NOTE: the buffer is assumed to be in U8U8U8U8 RGBA format.
     pub fn rasterize(&self, vbuf: &mut [u8]) {
+        let Some(ref blend) = self.frame_blend else {
+            self.rasterize_frame(vbuf);
+            return;
+        };
+
+        let [curr, prev] = &blend.frames;
+        for ((out, &c), &p) in vbuf.iter_mut().zip(curr).zip(prev) {
+            *out = (f32::from(c) * (1.0 - blend.weight) + f32::from(p) * blend.weight) as u8;
+        }
+    }
+
+    /// Returns the last frame completed at V-Blank, in RGBA format.
+    ///
+    /// Unlike [`Ppu::rasterize`], the content of the returned buffer only changes at frame
+    /// boundaries, so it never contains a partially updated frame.
+    pub fn present(&self) -> &[u8] {
+        &self.front_buffer
+    }
+
+    /// Renders the frame just completed and makes it the presented one.
+    fn capture_frame(&mut self) {
+        if let Some(mut blend) = self.frame_blend.take() {
+            blend.frames.swap(0, 1);
+            self.rasterize_frame(&mut blend.frames[0]);
+            self.frame_blend = Some(blend);
+        }
+
+        let mut back = mem::take(&mut self.back_buffer);
+        self.rasterize(&mut back);
+        self.back_buffer = mem::replace(&mut self.front_buffer, back);
+    }
+
+    /// Turns the LCD off and clears the palettes, as found by the boot ROM.
+    pub(crate) fn power_on(&mut self) {
+        self.lcdc_reg = LCDC::empty();
+        self.bgp_reg = IoReg(0x00);
+        self.obp0_reg = IoReg(0x00);
+        self.obp1_reg = IoReg(0x00);
+        self.vblank_irq_pending = false;
+
+        self.reset_ly();
+        self.present_blank_frame();
+    }
+
+    /// Makes a white frame the current one, as shown while the LCD is not displaying anything.
+    fn present_blank_frame(&mut self) {
+        let mut back = mem::take(&mut self.back_buffer);
+        self.fill(&mut back, self.active_palette().0[0]);
+        self.back_buffer = mem::replace(&mut self.front_buffer, back);
+    }
+
+    /// Rasterizes the current contents of the Video RAM, without any post-processing.
+    fn rasterize_frame(&self, vbuf: &mut [u8]) {
         // When the LCD display is disabled, show a white screen
         if !self.lcdc_reg.contains(LCDC::DISP_EN) {
-            for b in vbuf.iter_mut() {
-                *b = 0xFF;
-            }
+            self.fill(vbuf, self.active_palette().0[0]);
             return;
         }
 
@@ -335,13 +835,19 @@ impl Ppu {
         self.rasterize_sprites(vbuf);
     }
 
+    /// Fills the whole video buffer with `color`.
+    fn fill(&self, vbuf: &mut [u8], color: [u8; 3]) {
+        for px in vbuf.chunks_exact_mut(4) {
+            px[..3].copy_from_slice(&color);
+            px[3] = 0xFF;
+        }
+    }
+
     /// Rasterizes the current background map to the video buffer.
     fn rasterize_bg(&self, vbuf: &mut [u8]) {
         if !self.lcdc_reg.contains(LCDC::BG_DISP) {
             // When BG displaying is disabled, show a white background
-            for b in vbuf.iter_mut() {
-                *b = 0xFF;
-            }
+            self.fill(vbuf, self.active_palette().0[0]);
             return;
         }
 
@@ -357,12 +863,9 @@ impl Ppu {
                 let ly = usize::from(py + scy) % 256;
                 let lx = usize::from(px + scx) % 256;
 
-                self.rasterize_tile(
-                    self.get_bg_tile(lx, ly),
-                    (lx, ly),
-                    (px as usize, py as usize),
-                    vbuf,
-                );
+                let (tile, attrs) = self.get_bg_tile(lx, ly);
+
+                self.rasterize_tile(tile, attrs, (lx, ly), (px as usize, py as usize), vbuf);
             }
         }
     }
@@ -384,35 +887,48 @@ impl Ppu {
                 let ly = (py - wy) as usize % 256;
                 let lx = (px - wx) as usize % 256;
 
-                self.rasterize_tile(
-                    self.get_win_tile(lx, ly),
-                    (lx, ly),
-                    (px as usize, py as usize),
-                    vbuf,
-                );
+                let (tile, attrs) = self.get_win_tile(lx, ly);
+
+                self.rasterize_tile(tile, attrs, (lx, ly), (px as usize, py as usize), vbuf);
             }
         }
     }
 
-    /// Rasterizes the `tile` located at logical coordinates `(lx, ly)` to the video buffer
-    /// at physical coordinates `(px, py)`.
+    /// Rasterizes the `tile` located at logical coordinates `(lx, ly)` to the video buffer
+    /// at physical coordinates `(px, py)`, using the CGB BG map attributes `attrs`.
     fn rasterize_tile(
         &self,
         tile: &Tile,
+        attrs: BgAttributes,
         (lx, ly): (usize, usize),
         (px, py): (usize, usize),
         vbuf: &mut [u8],
     ) {
+        // Flip the tile horizontally and/or vertically, if requested
+        let mut tx = (lx & 0x07) as u8;
+        let mut ty = (ly & 0x07) as u8;
+
+        if attrs.contains(BgAttributes::FLIP_X) {
+            tx = 7 - tx;
+        }
+        if attrs.contains(BgAttributes::FLIP_Y) {
+            ty = 7 - ty;
+        }
+
         // Obtain the color of the tile's pixel corresponding to (lx, ly)
-        let pixel = tile.pixel((lx & 0x07) as u8, (ly & 0x7) as u8);
-        let shade = self.get_shade(self.bgp_reg.0, pixel);
+        let pixel = tile.pixel(tx, ty);
+
+        let color = if self.cgb_mode {
+            let palette = (attrs & BgAttributes::CGB_PAL).bits();
+            self.get_color(&self.bg_palettes, palette, pixel)
+        } else {
+            self.get_shade(self.bgp_reg.0, pixel)
+        };
 
         // Compute the index in the video buffer
         let pid = py * 160 * 4 + px * 4;
 
-        vbuf[pid] = shade;
-        vbuf[pid + 1] = shade;
-        vbuf[pid + 2] = shade;
+        vbuf[pid..pid + 3].copy_from_slice(&color);
     }
 
     /// Rasterizes any visible sprite to the video buffer.
@@ -432,16 +948,16 @@ impl Ppu {
             // In 8x16 mode, the upper 8x8 tile is "tid & 0xFE",
             // and the lower 8x8 tile is "tid | 0x01".
             let tile = if is_8x16 {
-                self.get_sprite_tile((sprite.tid & 0xFE).into())
+                self.get_sprite_tile((sprite.tid & 0xFE).into(), attr)
             } else {
-                self.get_sprite_tile(sprite.tid.into())
+                self.get_sprite_tile(sprite.tid.into(), attr)
             };
 
             self.rasterize_sprite(tile, x, y, attr, vbuf);
 
             // In 8x16 mode, rasterize the lower sprite too
             if is_8x16 {
-                let tile = self.get_sprite_tile((sprite.tid | 0x01).into());
+                let tile = self.get_sprite_tile((sprite.tid | 0x01).into(), attr);
 
                 self.rasterize_sprite(tile, x, y + 8, attr, vbuf);
             }
@@ -463,6 +979,7 @@ impl Ppu {
         } else {
             self.obp0_reg.0
         };
+        let cgb_palette = (attr & SpriteAttributes::CGB_PAL).bits();
 
         // Flip sprite horizontally
         let off_x = if attr.contains(SpriteAttributes::FLIP_X) {
@@ -488,47 +1005,106 @@ impl Ppu {
                 let y = (off_y - (py - y)).unsigned_abs() as u8;
 
                 let pixel = tile.pixel(x, y);
-                let shade = self.get_shade(palette, pixel);
+
+                let color = if self.cgb_mode {
+                    self.get_color(&self.obj_palettes, cgb_palette, pixel)
+                } else {
+                    self.get_shade(palette, pixel)
+                };
 
                 let pid = (py as usize) * 160 * 4 + (px as usize) * 4;
 
                 if pixel != 0 {
-                    vbuf[pid] = shade;
-                    vbuf[pid + 1] = shade;
-                    vbuf[pid + 2] = shade;
+                    vbuf[pid..pid + 3].copy_from_slice(&color);
                 }
             }
         }
     }
 
+    /// Restarts the frame from the beginning of line 0, as done when the LCD is turned off
+    /// or LY is written to. The LCD mode is reset to 0 and the STAT IRQ line is released.
+    fn reset_ly(&mut self) {
+        self.tstate = 0;
+        self.ly_reg.0 = 0;
+        self.lyc_compare_ly = 0;
+        self.stat_line = STATIRQ::DEFAULT;
+
+        self.stat_reg.set(STAT::LYC_FLAG, self.lyc_reg.0 == 0);
+        self.stat_reg = (self.stat_reg & !STAT::MOD_FLAG) | STAT::MOD_0;
+    }
+
+    /// Returns the number of dots spent in mode 3 while drawing line `ly`.
+    fn mode3_duration(&self, ly: u8) -> u64 {
+        let height = if self.lcdc_reg.contains(LCDC::OBJ_SIZE) {
+            16
+        } else {
+            8
+        };
+
+        // The OAM scan selects the first sprites overlapping the line
+        let sprites: Vec<u8> = if self.lcdc_reg.contains(LCDC::OBJ_DISP_EN) {
+            self.oam
+                .iter()
+                .filter(|s| (s.y..s.y.saturating_add(height)).contains(&(ly + 16)))
+                .take(MAX_SPRITES_PER_LINE)
+                .map(|s| s.x)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let window_x = (self.lcdc_reg.contains(LCDC::WIN_DISP_EN)
+            && ly >= self.wy_reg.0
+            && self.wx_reg.0 <= 166)
+            .then(|| self.wx_reg.0.saturating_sub(7));
+
+        PixelFifo::new(self.scx_reg.0, &sprites, window_x).run()
+    }
+
     /// Update the STAT register and set any relevant interrupts.
     fn tick_stat(&mut self, tstate: u64, v_line: u64) {
         // Compute current LCD mode
         let mode = if v_line < 144 {
             match tstate {
+                0..=79 if self.first_frame && v_line == 0 => STAT::MOD_0,
                 0..=79 => STAT::MOD_2,
-                80..=253 => STAT::MOD_3,
+                _ if tstate <= self.mode3_end => STAT::MOD_3,
                 _ => STAT::MOD_0,
             }
         } else {
             STAT::MOD_1
         };
 
-        let lyc_coinc = self.ly_reg == self.lyc_reg;
-
-        // Set STAT interrupt flags depending on the enable bits in STAT
-        if self.stat_reg.contains(STAT::LYC_INTR) && lyc_coinc && tstate == 0 {
-            self.stat_irq |= STATIRQ::LYC;
-        }
-        if self.stat_reg.contains(STAT::OAM_INTR) && mode == STAT::MOD_2 && tstate == 0 {
-            self.stat_irq |= STATIRQ::OAM;
-        }
-        if self.stat_reg.contains(STAT::VBK_INTR) && v_line == 144 && tstate == 0 {
-            self.stat_irq |= STATIRQ::VBK;
-        }
-        if self.stat_reg.contains(STAT::HBK_INTR) && mode == STAT::MOD_0 && tstate == 256 {
-            self.stat_irq |= STATIRQ::HBK;
+        // The comparison uses the LY value of the previous M-cycle, so the coincidence flag
+        // is updated one M-cycle after LY changes
+        let lyc_coinc = self.lyc_compare_ly == self.lyc_reg.0;
+        self.lyc_compare_ly = self.ly_reg.0;
+
+        // All the enabled STAT conditions are ORed into a single IRQ line, and an interrupt
+        // is only requested on its rising edge. As a consequence, a condition becoming true
+        // while another one is still active does not trigger a new interrupt (STAT blocking).
+        let mut line = STATIRQ::DEFAULT;
+        line.set(
+            STATIRQ::LYC,
+            self.stat_reg.contains(STAT::LYC_INTR) && lyc_coinc,
+        );
+        line.set(
+            STATIRQ::OAM,
+            self.stat_reg.contains(STAT::OAM_INTR) && mode == STAT::MOD_2,
+        );
+        line.set(
+            STATIRQ::VBK,
+            self.stat_reg.contains(STAT::VBK_INTR) && mode == STAT::MOD_1,
+        );
+        line.set(
+            STATIRQ::HBK,
+            self.stat_reg.contains(STAT::HBK_INTR) && mode == STAT::MOD_0,
+        );
+
+        if self.stat_line.is_empty() && !line.is_empty() {
+            self.stat_irq_pending = true;
         }
+        self.stat_line = line;
 
         // Update coincidence flag
         if lyc_coinc {
@@ -558,55 +1134,244 @@ impl Ppu {
         self.dma_xfer_queue[1] = Some(DMATransfer::new(u16::from(val) << 8));
     }
 
-    /// Returns the actual gray shade associated with a pixel value in a palette.
-    fn get_shade(&self, palette: u8, pixel: u8) -> u8 {
-        match (palette >> (pixel * 2)) & 0x3 {
-            0b00 => 0xFF, // White
-            0b01 => 0xAA, // Light gray
-            0b10 => 0x55, // Dark gray
-            0b11 => 0x00, // Black
-            _ => unreachable!(),
+    /// Renders the tile at `index` in the tile data table to an 8x8 image using `palette`.
+    ///
+    /// NOTE: the image is in U8U8U8U8 RGBA format.
+    pub fn tile_to_image(&self, index: usize, palette: u8) -> [u8; 8 * 8 * 4] {
+        let tile = &self.tdt[index];
+        let mut img = [0xFF; 8 * 8 * 4];
+
+        for (i, px) in img.chunks_exact_mut(4).enumerate() {
+            let shade = self.get_shade(palette, tile.pixel(i as u8 % 8, i as u8 / 8));
+            px[..3].copy_from_slice(&shade);
+        }
+
+        img
+    }
+
+    /// Returns the number of frames completed since power-up.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Returns the most recent changes to the monochrome palette registers, oldest first.
+    ///
+    /// Only writes that actually change a register are recorded, and at most
+    /// [`PALETTE_HISTORY_LEN`] entries are kept.
+    pub fn palette_history(&self) -> &VecDeque<PaletteChange> {
+        &self.palette_history
+    }
+
+    fn write_palette(&mut self, register: PaletteRegister, value: u8) {
+        let reg = match register {
+            PaletteRegister::Bgp => &mut self.bgp_reg,
+            PaletteRegister::Obp0 => &mut self.obp0_reg,
+            PaletteRegister::Obp1 => &mut self.obp1_reg,
+        };
+
+        if reg.0 == value {
+            return;
         }
+        reg.0 = value;
+
+        if self.palette_history.len() == PALETTE_HISTORY_LEN {
+            self.palette_history.pop_front();
+        }
+        self.palette_history.push_back(PaletteChange {
+            register,
+            value,
+            frame: self.frame_count,
+            line: self.ly_reg.0,
+        });
+    }
+
+    /// Returns the 384 tiles of the tile data table (VRAM bank 0) in 2bpp format.
+    pub fn export_tiles(&self) -> Vec<u8> {
+        self.tdt
+            .iter()
+            .flat_map(|tile| tile.data().to_vec())
+            .collect()
+    }
+
+    /// Loads tiles in 2bpp format into the tile data table (VRAM bank 0), starting from tile 0.
+    ///
+    /// Tiles exceeding the size of the table, as well as trailing incomplete tiles, are ignored.
+    pub fn import_tiles(&mut self, data: &[u8]) {
+        for (tile, data) in self.tdt.iter_mut().zip(data.chunks_exact(16)) {
+            tile.data_mut().copy_from_slice(data);
+        }
+    }
+
+    /// Renders the whole tile data table to a sheet of [`TILESHEET_COLUMNS`] tiles per row,
+    /// using the current background palette.
+    ///
+    /// NOTE: the image is in U8U8U8U8 RGBA format.
+    pub fn tilesheet(&self) -> Vec<u8> {
+        let (width, height) = TILESHEET_SIZE;
+        let mut img = vec![0xFF; width * height * 4];
+
+        for index in 0..self.tdt.len() {
+            let tile = self.tile_to_image(index, self.bgp_reg.0);
+            let (tx, ty) = (index % TILESHEET_COLUMNS * 8, index / TILESHEET_COLUMNS * 8);
+
+            for (y, row) in tile.chunks_exact(8 * 4).enumerate() {
+                let start = ((ty + y) * width + tx) * 4;
+                img[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+
+        img
     }
 
-    /// Returns the BG tile corresponding to the given ID.
-    fn get_bg_tile(&self, x: usize, y: usize) -> &Tile {
+    /// Enables or disables CGB functions, such as color palettes.
+    pub fn set_cgb_mode(&mut self, enable: bool) {
+        self.cgb_mode = enable;
+    }
+
+    /// Returns whether CGB functions are enabled.
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    /// Sets the color correction applied to CGB colors.
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+    }
+
+    /// Returns the color correction applied to CGB colors.
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+
+    /// Sets the colors used to render DMG shades.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Returns the colors used to render DMG shades.
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Sets the colors used to render DMG shades by the SGB, overriding the palette set with
+    /// [`Ppu::set_palette`], or restores the latter if `None`.
+    pub fn set_sgb_palette(&mut self, palette: Option<Palette>) {
+        self.sgb_palette = palette;
+    }
+
+    /// Returns the colors actually used to render DMG shades.
+    pub fn active_palette(&self) -> Palette {
+        self.sgb_palette.unwrap_or(self.palette)
+    }
+
+    /// Enables frame blending, with the previous frame contributing `weight` (0.0 to 1.0)
+    /// to the rasterized output, or disables it if `None`.
+    pub fn set_frame_blend(&mut self, weight: Option<f32>) {
+        self.frame_blend = weight.map(|w| FrameBlend::new(w.clamp(0.0, 1.0)));
+    }
+
+    /// Returns the weight of the previous frame if frame blending is enabled, or `None`.
+    pub fn frame_blend(&self) -> Option<f32> {
+        self.frame_blend.as_ref().map(|blend| blend.weight)
+    }
+
+    /// Converts a CGB RGB555 color to RGBA, applying the current color correction.
+    pub fn rgb555_to_rgba(&self, color: u16) -> [u8; 4] {
+        let r = u32::from(color & 0x1F);
+        let g = u32::from((color >> 5) & 0x1F);
+        let b = u32::from((color >> 10) & 0x1F);
+
+        match self.color_correction {
+            ColorCorrection::Off => {
+                let scale = |c: u32| ((c << 3) | (c >> 2)) as u8;
+                [scale(r), scale(g), scale(b), 0xFF]
+            }
+            ColorCorrection::ModernLcd => {
+                // Color curve from byuu's/higan's CGB color emulation
+                let clamp = |c: u32| (c.min(960) >> 2) as u8;
+                [
+                    clamp(r * 26 + g * 4 + b * 2),
+                    clamp(g * 24 + b * 8),
+                    clamp(r * 6 + g * 4 + b * 22),
+                    0xFF,
+                ]
+            }
+        }
+    }
+
+    /// Returns the RGB color of the shade associated with a pixel value in a DMG palette.
+    fn get_shade(&self, palette: u8, pixel: u8) -> [u8; 3] {
+        self.active_palette().0[usize::from((palette >> (pixel * 2)) & 0x3)]
+    }
+
+    /// Returns the RGB color associated with a pixel value in one of the CGB `palettes`.
+    fn get_color(&self, palettes: &ColorPalettes, palette: u8, pixel: u8) -> [u8; 3] {
+        let [r, g, b, _] = self.rgb555_to_rgba(palettes.color(palette, pixel));
+        [r, g, b]
+    }
+
+    /// Returns the BG tile corresponding to the given ID, along with its attributes.
+    fn get_bg_tile(&self, x: usize, y: usize) -> (&Tile, BgAttributes) {
         self.get_bg_win_tile(
             ((y >> 3) << 5) + (x >> 3), // coords to 8x8 tile ID
             self.lcdc_reg.contains(LCDC::BG_DISP_SEL),
         )
     }
 
-    /// Returns the Window tile corresponding to the given ID.
-    fn get_win_tile(&self, x: usize, y: usize) -> &Tile {
+    /// Returns the Window tile corresponding to the given ID, along with its attributes.
+    fn get_win_tile(&self, x: usize, y: usize) -> (&Tile, BgAttributes) {
         self.get_bg_win_tile(
             ((y >> 3) << 5) + (x >> 3), // coords to 8x8 tile ID
             self.lcdc_reg.contains(LCDC::WIN_DISP_SEL),
         )
     }
 
-    /// Returns the BG or Window tile corresponding to the given ID.
+    /// Returns the BG or Window tile corresponding to the given ID, along with its attributes.
     ///
     /// The resulting Tile depends on the selected BG/Window Tile Map
-    /// and addressing mode in LCDC register.
-    fn get_bg_win_tile(&self, id: usize, disp_sel: bool) -> &Tile {
-        let tile_id = if disp_sel {
-            self.bgtm1[id]
+    /// and addressing mode in LCDC register. In CGB mode, the attributes also select
+    /// the VRAM bank of the tile, while in DMG mode they are always empty.
+    fn get_bg_win_tile(&self, id: usize, disp_sel: bool) -> (&Tile, BgAttributes) {
+        let (tile_id, attrs) = if disp_sel {
+            (self.bgtm1[id], self.bgam1[id])
+        } else {
+            (self.bgtm0[id], self.bgam0[id])
+        };
+
+        let attrs = if self.cgb_mode {
+            BgAttributes::from_bits_truncate(attrs)
         } else {
-            self.bgtm0[id]
+            BgAttributes::DEFAULT
+        };
+
+        let tdt = if attrs.contains(BgAttributes::VRAM_BANK) {
+            &self.tdt1
+        } else {
+            &self.tdt
         };
 
         if self.lcdc_reg.contains(LCDC::BG_WIN_DATA_SEL) {
-            &self.tdt[usize::from(tile_id)]
+            (&tdt[usize::from(tile_id)], attrs)
         } else {
-            &self.tdt[(256 + i32::from(tile_id as i8)) as usize]
+            (&tdt[(256 + i32::from(tile_id as i8)) as usize], attrs)
         }
     }
 
     /// Returns the sprite tile corresponding to the given ID.
-    fn get_sprite_tile(&self, id: usize) -> &Tile {
+    ///
+    /// In CGB mode, the VRAM bank is selected by the sprite attributes.
+    fn get_sprite_tile(&self, id: usize, attr: SpriteAttributes) -> &Tile {
         // TODO support loading 8x16 sprites
-        &self.tdt[id]
+        if self.cgb_mode && attr.contains(SpriteAttributes::VRAM_BANK) {
+            &self.tdt1[id]
+        } else {
+            &self.tdt[id]
+        }
+    }
+
+    /// Returns whether VRAM bank 1 is currently mapped to 0x8000-0x9FFF.
+    fn vram_bank1_selected(&self) -> bool {
+        self.cgb_mode && self.vbk_reg.bit(0)
     }
 }
 
@@ -615,9 +1380,8 @@ impl InterruptSource for Ppu {
         if self.vblank_irq_pending {
             self.vblank_irq_pending = false;
             Some(IrqSource::VBlank)
-        } else if !self.stat_irq.is_empty() {
-            // TODO: resetting everything might not be the correct behavior.
-            self.stat_irq = STATIRQ::DEFAULT;
+        } else if self.stat_irq_pending {
+            self.stat_irq_pending = false;
             Some(IrqSource::LcdStat)
         } else {
             None
@@ -632,8 +1396,15 @@ impl MemR for Ppu {
                 let addr = addr - 0x8000;
                 let tid = usize::from(addr >> 4);
                 let bid = usize::from(addr & 0xF);
-                self.tdt[tid].data()[bid]
+
+                if self.vram_bank1_selected() {
+                    self.tdt1[tid].data()[bid]
+                } else {
+                    self.tdt[tid].data()[bid]
+                }
             }
+            0x9800..=0x9BFF if self.vram_bank1_selected() => self.bgam0[usize::from(addr - 0x9800)],
+            0x9C00..=0x9FFF if self.vram_bank1_selected() => self.bgam1[usize::from(addr - 0x9C00)],
             0x9800..=0x9BFF => self.bgtm0[usize::from(addr - 0x9800)],
             0x9C00..=0x9FFF => self.bgtm1[usize::from(addr - 0x9C00)],
 
@@ -660,6 +1431,17 @@ impl MemR for Ppu {
             0xFF4A => self.wy_reg.0,
             0xFF4B => self.wx_reg.0,
 
+            // VRAM bank select, only bit 0 is used
+            0xFF4F if !self.cgb_mode => 0xFF,
+            0xFF4F => self.vbk_reg.0 | 0xFE,
+
+            // CGB color palettes
+            0xFF68..=0xFF6B if !self.cgb_mode => 0xFF,
+            0xFF68 => self.bg_palettes.read_spec(),
+            0xFF69 => self.bg_palettes.read_data(),
+            0xFF6A => self.obj_palettes.read_spec(),
+            0xFF6B => self.obj_palettes.read_data(),
+
             _ => unreachable!(),
         })
     }
@@ -672,7 +1454,18 @@ impl MemW for Ppu {
                 let addr = addr - 0x8000;
                 let tid = usize::from(addr >> 4);
                 let bid = usize::from(addr & 0xF);
-                self.tdt[tid].data_mut()[bid] = val;
+
+                if self.vram_bank1_selected() {
+                    self.tdt1[tid].data_mut()[bid] = val;
+                } else {
+                    self.tdt[tid].data_mut()[bid] = val;
+                }
+            }
+            0x9800..=0x9BFF if self.vram_bank1_selected() => {
+                self.bgam0[usize::from(addr - 0x9800)] = val
+            }
+            0x9C00..=0x9FFF if self.vram_bank1_selected() => {
+                self.bgam1[usize::from(addr - 0x9C00)] = val
             }
             0x9800..=0x9BFF => self.bgtm0[usize::from(addr - 0x9800)] = val,
             0x9C00..=0x9FFF => self.bgtm1[usize::from(addr - 0x9C00)] = val,
@@ -684,22 +1477,447 @@ impl MemW for Ppu {
                 }
             }
 
-            0xFF40 => (&mut self.lcdc_reg).write(0, val)?,
+            0xFF40 => {
+                let was_enabled = self.lcdc_reg.contains(LCDC::DISP_EN);
+                (&mut self.lcdc_reg).write(0, val)?;
+
+                match (was_enabled, self.lcdc_reg.contains(LCDC::DISP_EN)) {
+                    // The screen goes blank as soon as the LCD is turned off
+                    (true, false) => {
+                        self.reset_ly();
+                        self.present_blank_frame();
+                    }
+                    // When turned back on, line 0 is 4 cycles shorter than usual
+                    // and starts without an OAM scan
+                    (false, true) => {
+                        self.tstate = 4;
+                        self.first_frame = true;
+                    }
+                    _ => (),
+                }
+            }
             0xFF41 => (&mut self.stat_reg).write(0, val)?,
             0xFF42 => self.scy_reg.0 = val,
             0xFF43 => self.scx_reg.0 = val,
-            0xFF44 => (),
+            0xFF44 => self.reset_ly(),
             0xFF45 => self.lyc_reg.0 = val,
             0xFF46 => self.prepare_dma_xfer(val),
-            0xFF47 => self.bgp_reg.0 = val,
-            0xFF48 => self.obp0_reg.0 = val,
-            0xFF49 => self.obp1_reg.0 = val,
+            0xFF47 => self.write_palette(PaletteRegister::Bgp, val),
+            0xFF48 => self.write_palette(PaletteRegister::Obp0, val),
+            0xFF49 => self.write_palette(PaletteRegister::Obp1, val),
             0xFF4A => self.wy_reg.0 = val,
             0xFF4B => self.wx_reg.0 = val,
 
+            // VRAM bank select
+            0xFF4F if !self.cgb_mode => (),
+            0xFF4F => self.vbk_reg.0 = val & 0x01,
+
+            // CGB color palettes
+            0xFF68..=0xFF6B if !self.cgb_mode => (),
+            0xFF68 => self.bg_palettes.write_spec(val),
+            0xFF69 => self.bg_palettes.write_data(val),
+            0xFF6A => self.obj_palettes.write_spec(val),
+            0xFF6B => self.obj_palettes.write_data(val),
+
             _ => unreachable!(),
         };
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_history() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x80).unwrap();
+
+        let run_to_line = |ppu: &mut Ppu, line| {
+            while ppu.read(0xFF44).unwrap() != line {
+                ppu.tick();
+            }
+        };
+
+        run_to_line(&mut ppu, 10);
+        ppu.write(0xFF47, 0xE4).unwrap();
+        run_to_line(&mut ppu, 80);
+        ppu.write(0xFF47, 0x1B).unwrap();
+
+        // Writing the same value again is not a change
+        ppu.write(0xFF47, 0x1B).unwrap();
+
+        let history: Vec<_> = ppu.palette_history().iter().copied().collect();
+        assert_eq!(
+            history,
+            [
+                PaletteChange {
+                    register: PaletteRegister::Bgp,
+                    value: 0xE4,
+                    frame: 0,
+                    line: 10
+                },
+                PaletteChange {
+                    register: PaletteRegister::Bgp,
+                    value: 0x1B,
+                    frame: 0,
+                    line: 80
+                },
+            ]
+        );
+
+        // The history is bounded
+        for i in 0..=PALETTE_HISTORY_LEN {
+            ppu.write(0xFF48, i as u8).unwrap();
+        }
+        assert_eq!(ppu.palette_history().len(), PALETTE_HISTORY_LEN);
+        assert_eq!(
+            ppu.palette_history().back().unwrap().value,
+            PALETTE_HISTORY_LEN as u8
+        );
+    }
+
+    #[test]
+    fn stat_irq_blocking() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x80).unwrap();
+        ppu.write(0xFF45, 10).unwrap();
+
+        // Enable both the LYC and OAM interrupts
+        ppu.write(0xFF41, 0x60).unwrap();
+
+        // Count the STAT interrupts requested during each line
+        let mut irqs = [0; 154];
+        for _ in 0..70224 / 4 {
+            ppu.tick();
+            if ppu.get_and_clear_irq() == Some(IrqSource::LcdStat) {
+                irqs[usize::from(ppu.read(0xFF44).unwrap())] += 1;
+            }
+        }
+
+        // Line 10 fires once, even though both conditions become true at the same time.
+        // Line 11 fires none, since LYC is still true when mode 2 starts.
+        assert_eq!(irqs[9..=12], [1, 1, 0, 1]);
+
+        // No interrupts are requested during V-Blank, since it's not enabled
+        assert!(irqs[144..].iter().all(|&n| n == 0));
+    }
+
+    #[test]
+    fn lcd_disable_resets_ly() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x80).unwrap();
+
+        while ppu.read(0xFF44).unwrap() != 42 {
+            ppu.tick();
+        }
+        for _ in 0..30 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read(0xFF41).unwrap() & 0x03, 0x03);
+
+        // Turning off the LCD resets LY and the mode, which stay there while it's off
+        ppu.write(0xFF40, 0x00).unwrap();
+        for _ in 0..1000 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read(0xFF44).unwrap(), 0);
+        assert_eq!(ppu.read(0xFF41).unwrap() & 0x03, 0x00);
+
+        // Once turned back on, the frame restarts from line 0
+        ppu.write(0xFF40, 0x80).unwrap();
+        for _ in 0..456 / 4 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read(0xFF44).unwrap(), 1);
+
+        // Writing to LY resets it as well
+        ppu.write(0xFF44, 0x55).unwrap();
+        assert_eq!(ppu.read(0xFF44).unwrap(), 0);
+    }
+
+    #[test]
+    fn lcd_enable_first_frame() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x80).unwrap();
+        for _ in 0..5000 {
+            ppu.tick();
+        }
+        while ppu.get_and_clear_irq().is_some() {}
+
+        ppu.write(0xFF40, 0x00).unwrap();
+        ppu.write(0xFF40, 0x80).unwrap();
+        assert_eq!(ppu.read(0xFF44).unwrap(), 0);
+
+        // Line 0 starts in mode 0, without an OAM scan
+        ppu.tick();
+        assert_eq!(ppu.read(0xFF44).unwrap(), 0);
+        assert_eq!(ppu.read(0xFF41).unwrap() & 0x03, 0x00);
+
+        // The first V-Blank comes 144 lines later, minus the 4 cycles skipped on line 0
+        let mut ticks = 1;
+        while ppu.get_and_clear_irq() != Some(IrqSource::VBlank) {
+            ppu.tick();
+            ticks += 1;
+        }
+        assert_eq!(ticks * 4, 144 * 456 - 4);
+        assert_eq!(ppu.read(0xFF44).unwrap(), 144);
+
+        // The first frame is blank, the following ones are regular
+        assert!(ppu
+            .present()
+            .chunks(4)
+            .all(|px| px[..3] == ppu.palette.0[0]));
+        while ppu.read(0xFF44).unwrap() != 0 {
+            ppu.tick();
+        }
+        ppu.tick();
+        assert_eq!(ppu.read(0xFF41).unwrap() & 0x03, 0x02);
+    }
+
+    #[test]
+    fn lyc_flag_lags_ly() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x80).unwrap();
+        ppu.write(0xFF45, 5).unwrap();
+
+        while ppu.read(0xFF44).unwrap() != 5 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read(0xFF41).unwrap() & 0x04, 0x00);
+
+        ppu.tick();
+        assert_eq!(ppu.read(0xFF41).unwrap() & 0x04, 0x04);
+    }
+
+    #[test]
+    fn pixel_fifo_timing() {
+        let duration = |scx, sprites: &[u8], window_x| PixelFifo::new(scx, sprites, window_x).run();
+
+        assert_eq!(duration(0, &[], None), 172);
+
+        // Pixels scrolled out are discarded one per dot
+        assert_eq!(duration(3, &[], None), 175);
+
+        // A sprite aligned to a BG tile waits for the BG fetch to complete
+        assert_eq!(duration(0, &[8], None), 183);
+        assert_eq!(duration(0, &[0], None), 183);
+        assert_eq!(duration(0, &[13], None), 178);
+
+        // Sprites in the same tile only wait once
+        assert_eq!(duration(0, &[8, 8], None), 189);
+
+        // Sprites out of the screen are not fetched
+        assert_eq!(duration(0, &[168], None), 172);
+
+        // The window restarts the BG fetcher
+        assert_eq!(duration(0, &[], Some(80)), 178);
+    }
+
+    #[test]
+    fn mode3_sprite_penalty() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x82).unwrap();
+
+        // Place 10 sprites on line 2, plus one that is not selected by the OAM scan
+        for i in 0..11 {
+            let sprite = SpriteInfo {
+                y: 16 + 2,
+                x: 8 + 8 * i,
+                ..SpriteInfo::default()
+            };
+            ppu.set_sprite(usize::from(i), sprite);
+        }
+
+        // Returns the next line to be drawn and the length of its mode 3
+        let next_mode3 = |ppu: &mut Ppu| {
+            let mut cycles = 0;
+            while ppu.read(0xFF41).unwrap() & 0x03 != 0x03 {
+                ppu.tick();
+            }
+            let line = ppu.read(0xFF44).unwrap();
+            while ppu.read(0xFF41).unwrap() & 0x03 != 0x00 {
+                ppu.tick();
+                cycles += 4;
+            }
+            (line, cycles)
+        };
+
+        while ppu.read(0xFF44).unwrap() != 1 {
+            ppu.tick();
+        }
+        assert_eq!(next_mode3(&mut ppu), (1, 176));
+
+        // Mode 3 extends by 11 dots for each sprite, so H-Blank starts later
+        assert_eq!(next_mode3(&mut ppu), (2, 284));
+
+        // Sprites are not fetched if disabled
+        ppu.write(0xFF40, 0x80).unwrap();
+        assert_eq!(next_mode3(&mut ppu), (3, 176));
+    }
+
+    #[test]
+    fn tile_import_export() {
+        let mut ppu = Ppu::new();
+
+        // Draw a diagonal line of color 3 in tile 5
+        for row in 0..8u16 {
+            let bits = 0x80 >> row;
+            ppu.write(0x8050 + row * 2, bits).unwrap();
+            ppu.write(0x8050 + row * 2 + 1, bits).unwrap();
+        }
+
+        let tiles = ppu.export_tiles();
+        assert_eq!(tiles.len(), 384 * 16);
+        assert_eq!(tiles[0x50..0x54], [0x80, 0x80, 0x40, 0x40]);
+
+        // Edit the exported tile, and load it into a fresh PPU
+        let mut tiles = tiles;
+        tiles[0x50] = 0x81;
+
+        let mut ppu = Ppu::new();
+        ppu.import_tiles(&tiles);
+
+        assert_eq!(ppu.tdt[5].pixel(0, 0), 3);
+        assert_eq!(ppu.tdt[5].pixel(7, 0), 1);
+        assert_eq!(ppu.tdt[5].pixel(1, 1), 3);
+        assert_eq!(ppu.tdt[5].pixel(0, 1), 0);
+
+        // The tilesheet places tile 5 in the first row
+        let sheet = ppu.tilesheet();
+        assert_eq!(sheet.len(), TILESHEET_SIZE.0 * TILESHEET_SIZE.1 * 4);
+        assert_eq!(sheet[5 * 8 * 4..5 * 8 * 4 + 4], [0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn color_correction() {
+        let mut ppu = Ppu::new();
+        assert_eq!(ppu.color_correction(), ColorCorrection::ModernLcd);
+
+        // Pure red and white, as seen on a CGB LCD
+        assert_eq!(ppu.rgb555_to_rgba(0x001F), [201, 0, 46, 0xFF]);
+        assert_eq!(ppu.rgb555_to_rgba(0x7FFF), [240, 240, 240, 0xFF]);
+
+        // Without correction, colors are passed through
+        ppu.set_color_correction(ColorCorrection::Off);
+        assert_eq!(ppu.rgb555_to_rgba(0x001F), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(ppu.rgb555_to_rgba(0x7FFF), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(ppu.rgb555_to_rgba(0x2108), [0x42, 0x42, 0x42, 0xFF]);
+    }
+
+    #[test]
+    fn dmg_palette() {
+        let mut ppu = Ppu::new();
+        ppu.set_palette(Palette::GREEN);
+
+        // All BG colors map to black
+        ppu.write(0xFF47, 0xFF).unwrap();
+
+        let mut vbuf = vec![0; 160 * 144 * 4];
+        ppu.rasterize(&mut vbuf);
+        assert_eq!(vbuf[..3], [0x0F, 0x38, 0x0F]);
+
+        // A disabled LCD shows the lightest shade
+        ppu.write(0xFF40, 0x00).unwrap();
+        ppu.rasterize(&mut vbuf);
+        assert_eq!(vbuf[..3], [0x9B, 0xBC, 0x0F]);
+    }
+
+    #[test]
+    fn frame_blend() {
+        let mut ppu = Ppu::new();
+        ppu.set_frame_blend(Some(0.5));
+
+        let run_frame = |ppu: &mut Ppu, bgp| {
+            ppu.write(0xFF47, bgp).unwrap();
+            for _ in 0..70224 / 4 {
+                ppu.tick();
+            }
+        };
+
+        // A black frame followed by a white one
+        run_frame(&mut ppu, 0xFF);
+        run_frame(&mut ppu, 0x00);
+
+        let mut vbuf = vec![0; 160 * 144 * 4];
+        ppu.rasterize(&mut vbuf);
+        assert_eq!(vbuf[..4], [0x7F, 0x7F, 0x7F, 0xFF]);
+
+        // Without blending, the current contents are shown
+        ppu.set_frame_blend(None);
+        ppu.rasterize(&mut vbuf);
+        assert_eq!(vbuf[..3], [0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn cgb_palettes() {
+        let mut ppu = Ppu::new();
+        ppu.set_color_correction(ColorCorrection::Off);
+
+        // Palette registers are not accessible in DMG mode
+        ppu.write(0xFF68, 0x80).unwrap();
+        assert_eq!(ppu.read(0xFF68).unwrap(), 0xFF);
+
+        ppu.set_cgb_mode(true);
+
+        // Tile #0 only uses color #3
+        for addr in 0x8000..0x8010 {
+            ppu.write(addr, 0xFF).unwrap();
+        }
+
+        // Set BG palette #0, color #3 to pure red, with auto-increment
+        ppu.write(0xFF68, 0x80 | 0x06).unwrap();
+        ppu.write(0xFF69, 0x1F).unwrap();
+        ppu.write(0xFF69, 0x00).unwrap();
+        assert_eq!(ppu.read(0xFF68).unwrap(), 0xC8);
+
+        ppu.write(0xFF68, 0x06).unwrap();
+        assert_eq!(ppu.read(0xFF69).unwrap(), 0x1F);
+
+        let mut vbuf = vec![0; 160 * 144 * 4];
+        ppu.rasterize(&mut vbuf);
+        assert_eq!(vbuf[..3], [0xFF, 0x00, 0x00]);
+
+        // OBJ palettes are separate from BG palettes
+        ppu.write(0xFF6A, 0x06).unwrap();
+        assert_eq!(ppu.read(0xFF6B).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn vram_banks() {
+        let mut ppu = Ppu::new();
+
+        // VBK is not accessible in DMG mode, and bank 0 is always selected
+        ppu.write(0xFF4F, 0x01).unwrap();
+        assert_eq!(ppu.read(0xFF4F).unwrap(), 0xFF);
+        ppu.write(0x8000, 0x11).unwrap();
+        assert_eq!(ppu.read(0x8000).unwrap(), 0x11);
+
+        ppu.set_cgb_mode(true);
+        assert_eq!(ppu.read(0xFF4F).unwrap(), 0xFE);
+
+        // Write to both banks
+        ppu.write(0xFF4F, 0x01).unwrap();
+        assert_eq!(ppu.read(0xFF4F).unwrap(), 0xFF);
+        ppu.write(0x8000, 0x22).unwrap();
+        ppu.write(0x9800, 0x08).unwrap();
+
+        ppu.write(0xFF4F, 0x00).unwrap();
+        ppu.write(0x9800, 0x01).unwrap();
+
+        // Reads reflect the selected bank
+        assert_eq!(ppu.read(0x8000).unwrap(), 0x11);
+        assert_eq!(ppu.read(0x9800).unwrap(), 0x01);
+
+        ppu.write(0xFF4F, 0x01).unwrap();
+        assert_eq!(ppu.read(0x8000).unwrap(), 0x22);
+        assert_eq!(ppu.read(0x9800).unwrap(), 0x08);
+
+        // The first BG tile is fetched from tile #1 in bank 1, as specified by its attributes
+        let (tile, attrs) = ppu.get_bg_tile(0, 0);
+        assert!(attrs.contains(BgAttributes::VRAM_BANK));
+        assert!(std::ptr::eq(tile, &ppu.tdt1[1]));
+    }
+}
@@ -1,4 +1,5 @@
 pub use gameboy::*;
+pub use rewind::*;
 
 pub mod bus;
 pub mod cpu;
@@ -7,3 +8,4 @@ pub mod io;
 pub mod mem;
 
 mod gameboy;
+mod rewind;
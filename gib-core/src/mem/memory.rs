@@ -13,6 +13,16 @@ impl Memory {
             data: vec![0xff; usize::from(size)],
         }
     }
+
+    /// Returns the whole contents of the memory.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the whole contents of the memory, mutably.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
 }
 
 impl MemR for Memory {
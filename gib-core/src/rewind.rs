@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use crate::Snapshot;
+
+/// A bounded ring buffer of emulator snapshots, used to step back in time.
+///
+/// The buffer is bounded both in the number of snapshots and in the memory they take up,
+/// as reported by [`Snapshot::size`]. When either limit is reached, pushing a new snapshot
+/// evicts the oldest ones.
+pub struct RewindBuffer {
+    snapshots: VecDeque<(Snapshot, usize)>,
+    capacity: usize,
+    max_bytes: usize,
+    bytes: usize,
+}
+
+impl RewindBuffer {
+    /// Creates an empty rewind buffer holding at most `capacity` snapshots,
+    /// taking up at most `max_bytes` bytes.
+    pub fn new(capacity: usize, max_bytes: usize) -> RewindBuffer {
+        RewindBuffer {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    /// Pushes a new snapshot, evicting the oldest ones if the buffer is full.
+    ///
+    /// A snapshot larger than the whole buffer is not stored at all.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        let size = snapshot.size();
+        if self.capacity == 0 || size > self.max_bytes {
+            return;
+        }
+
+        while self.snapshots.len() >= self.capacity || self.bytes + size > self.max_bytes {
+            self.evict();
+        }
+
+        self.bytes += size;
+        self.snapshots.push_back((snapshot, size));
+    }
+
+    /// Removes and returns the most recent snapshot, if any.
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        let (snapshot, size) = self.snapshots.pop_back()?;
+        self.bytes -= size;
+        Some(snapshot)
+    }
+
+    /// Discards all the snapshots in the buffer.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.bytes = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the memory taken up by the snapshots in the buffer, in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Discards the oldest snapshot.
+    fn evict(&mut self) {
+        if let Some((_, size)) = self.snapshots.pop_front() {
+            self.bytes -= size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameBoy;
+
+    #[test]
+    fn rewind_restores_earlier_state() {
+        // A ROM full of NOPs
+        let mut gb = GameBoy::new();
+        gb.load_rom(&[0; 0x8000]).unwrap();
+
+        let mut rewind = RewindBuffer::new(3, usize::MAX);
+
+        // Take a snapshot every two instructions
+        for _ in 0..5 {
+            rewind.push(gb.snapshot());
+            gb.step().unwrap();
+            gb.step().unwrap();
+        }
+        assert_eq!(gb.cpu().pc, 0x010A);
+
+        // Only the three most recent snapshots are kept
+        assert_eq!(rewind.len(), 3);
+
+        for pc in [0x0108, 0x0106, 0x0104] {
+            gb.restore(&rewind.pop().unwrap());
+            assert_eq!(gb.cpu().pc, pc);
+        }
+        assert!(rewind.pop().is_none());
+
+        // Execution resumes from the restored state
+        gb.step().unwrap();
+        assert_eq!(gb.cpu().pc, 0x0105);
+    }
+
+    #[test]
+    fn rewind_memory_bound() {
+        let mut gb = GameBoy::new();
+        gb.load_rom(&[0; 0x8000]).unwrap();
+
+        let size = gb.snapshot().size();
+        assert!(size > 0x2000);
+
+        // Room for two and a half snapshots
+        let mut rewind = RewindBuffer::new(10, size * 5 / 2);
+        for _ in 0..5 {
+            rewind.push(gb.snapshot());
+            gb.step().unwrap();
+        }
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.size(), size * 2);
+        assert!(rewind.size() <= rewind.max_bytes());
+
+        // The most recent snapshots are the ones kept
+        gb.restore(&rewind.pop().unwrap());
+        assert_eq!(gb.cpu().pc, 0x0104);
+        assert_eq!(rewind.size(), size);
+
+        rewind.clear();
+        assert_eq!(rewind.size(), 0);
+
+        // Snapshots not fitting at all are not stored
+        let mut rewind = RewindBuffer::new(10, size - 1);
+        rewind.push(gb.snapshot());
+        assert!(rewind.is_empty());
+    }
+}
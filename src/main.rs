@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
-use crate::ui::EmuUi;
+use crate::ui::{EmuUi, DEFAULT_STEPS_PER_LOCK};
 
 mod ui;
 
@@ -13,6 +13,14 @@ struct Cli {
     #[arg(short, long)]
     devel: bool,
 
+    /// Number of instructions executed under a single lock of the emulator
+    #[arg(long, default_value_t = DEFAULT_STEPS_PER_LOCK)]
+    steps_per_lock: usize,
+
+    /// Load the ROM without running it, to step from the first instruction
+    #[arg(long)]
+    paused: bool,
+
     /// ROM file to run
     rom: Option<PathBuf>,
 }
@@ -41,6 +49,8 @@ fn main() -> Result<(), eframe::Error> {
         options,
         Box::new(move |cc| match EmuUi::new(cc, cli.devel) {
             Ok(mut app) => {
+                app.set_steps_per_lock(cli.steps_per_lock);
+                app.set_start_paused(cli.paused);
                 if let Some(rom) = cli.rom {
                     app.load_rom(rom).expect("failed to load rom");
                 }
@@ -0,0 +1,267 @@
+use std::{mem, path::PathBuf, sync::Arc};
+
+use gib_core::{
+    io::{JoypadState, Palette},
+    AudioSource,
+};
+use parking_lot::Mutex;
+
+use crate::ui::{state::Performance, EMU_X_RES, EMU_Y_RES};
+
+/// Requests sent by the UI to the emulation thread.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Loads a ROM file and starts running it, unless `paused` is set.
+    LoadRom { path: PathBuf, paused: bool },
+    /// Loads the labels of a symbol file.
+    LoadSymbols(PathBuf),
+    /// Resets the emulator.
+    Reset,
+    /// Resets the emulator, keeping the contents of the cartridge RAM.
+    ResetPreservingRam,
+    /// Presses the given joypad keys.
+    PressKey(JoypadState),
+    /// Releases the given joypad keys.
+    ReleaseKey(JoypadState),
+    /// Enables or disables turbo mode.
+    SetTurbo(bool),
+    /// Suspends the emulation while rewinding.
+    SetRewinding(bool),
+    /// Restores the most recent rewind point.
+    RewindStep,
+    /// Pauses the emulation.
+    Pause,
+    /// Runs the emulation until the next breakpoint.
+    Run,
+    /// Sets a breakpoint at the given address.
+    SetBreakpoint(u16),
+    /// Removes the breakpoint at the given address.
+    ClearBreakpoint(u16),
+    /// Enables or disables the breakpoint at the given address.
+    SetBreakpointEnabled(u16, bool),
+    /// Starts recording the screen to the given file.
+    StartVideoRecording(PathBuf),
+    /// Stops the current screen recording, saving it to file.
+    StopVideoRecording,
+    /// Starts recording the audio output to the given file.
+    StartAudioRecording(PathBuf),
+    /// Stops the current audio recording.
+    StopAudioRecording,
+    /// Replaces the audio channel, producing samples at the given sample rate.
+    ConfigureAudio(AudioSource, f32),
+    /// Sets the emulation speed as a multiple of the original hardware speed.
+    SetSpeed(f32),
+    /// Sets the number of frames between two rewind points.
+    SetRewindInterval(u32),
+    /// Sets the DMG palette.
+    SetPalette(Palette),
+    /// Sets the volume of the audio output, from 0.0 to 1.0.
+    SetMasterVolume(f32),
+    /// Enables or disables frame blending.
+    SetFrameBlend(bool),
+    /// Enables or disables the strict memory access mode.
+    SetStrictMemory(bool),
+}
+
+/// Emulator settings and activities shown in the menus.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MenuState {
+    pub speed: f32,
+    pub rewind_interval: u32,
+    pub palette: Palette,
+    pub frame_blend: bool,
+    pub strict_memory: bool,
+    pub recording_video: bool,
+    pub recording_audio: bool,
+}
+
+/// State published by the emulation thread for the UI.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Last rendered frame, in RGBA format
+    pub frame: Vec<u8>,
+    /// Emulation performance at the time of the snapshot
+    pub performance: Performance,
+    /// Whether the emulation is paused
+    pub paused: bool,
+    /// Path of the last ROM loaded successfully, if any
+    pub rom: Option<PathBuf>,
+    /// Problems found in the cartridge header, if any
+    pub cartridge_warning: Option<String>,
+    /// State of the emulator settings shown in the menus
+    pub menu: MenuState,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            frame: vec![0xFF; EMU_X_RES * EMU_Y_RES * 4],
+            performance: Performance::default(),
+            paused: true,
+            rom: None,
+            cartridge_warning: None,
+            menu: MenuState::default(),
+        }
+    }
+}
+
+/// Buffer shared between a [`Publisher`] and a [`Reader`], along with its freshness.
+type Shared<T> = Arc<Mutex<(T, bool)>>;
+
+/// Creates a triple buffer, used to hand over values from one thread to another.
+///
+/// The publisher and the reader each own a buffer, which they exchange with a third, shared one.
+/// Only the exchange requires synchronization, so neither side ever waits for the other to
+/// finish writing or reading, and the reader always sees the most recently published value.
+pub fn triple_buffer<T: Clone>(init: T) -> (Publisher<T>, Reader<T>) {
+    let shared = Arc::new(Mutex::new((init.clone(), false)));
+
+    (
+        Publisher {
+            back: init.clone(),
+            shared: shared.clone(),
+        },
+        Reader {
+            front: init,
+            shared,
+        },
+    )
+}
+
+/// Writing side of a [`triple_buffer`].
+pub struct Publisher<T> {
+    back: T,
+    shared: Shared<T>,
+}
+
+impl<T> Publisher<T> {
+    /// Returns the buffer to be filled before the next call to [`Publisher::publish`].
+    ///
+    /// Since buffers are recycled, it may contain any previously published value.
+    pub fn back(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    /// Makes the content of the back buffer available to the reader.
+    pub fn publish(&mut self) {
+        let mut shared = self.shared.lock();
+        mem::swap(&mut shared.0, &mut self.back);
+        shared.1 = true;
+    }
+}
+
+/// Reading side of a [`triple_buffer`].
+pub struct Reader<T> {
+    front: T,
+    shared: Shared<T>,
+}
+
+impl<T> Reader<T> {
+    /// Returns the most recently published value.
+    pub fn read(&mut self) -> &T {
+        let mut shared = self.shared.lock();
+        if shared.1 {
+            mem::swap(&mut shared.0, &mut self.front);
+            shared.1 = false;
+        }
+        &self.front
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam::channel;
+
+    use super::*;
+    use crate::ui::state::Emulator;
+
+    #[test]
+    fn command_round_trip() {
+        let (tx, rx) = channel::unbounded();
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+
+        let mut emu = Emulator::default();
+        emu.gameboy_mut().load_rom(&rom).unwrap();
+
+        tx.send(Command::SetBreakpoint(0x0150)).unwrap();
+        tx.send(Command::Run).unwrap();
+        tx.send(Command::PressKey(JoypadState::A | JoypadState::B))
+            .unwrap();
+        tx.send(Command::ReleaseKey(JoypadState::B)).unwrap();
+        drop(tx);
+
+        for cmd in rx.iter() {
+            emu.execute(cmd).unwrap();
+        }
+
+        assert!(emu.cpu().breakpoint_at(0x0150));
+        assert!(!emu.paused());
+
+        // Select the action buttons and check that only A is pressed
+        emu.gameboy_mut().poke(0xFF00, 0x10);
+        assert_eq!(emu.gameboy().peek(0xFF00) & 0x0F, 0b1110);
+
+        emu.execute(Command::ClearBreakpoint(0x0150)).unwrap();
+        emu.execute(Command::Pause).unwrap();
+        assert!(!emu.cpu().breakpoint_at(0x0150));
+        assert!(emu.paused());
+
+        assert!(emu
+            .execute(Command::LoadRom {
+                path: "/nonexistent.gb".into(),
+                paused: false,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn menu_commands() {
+        let mut emu = Emulator::default();
+
+        let cmds = [
+            Command::SetSpeed(2.0),
+            Command::SetRewindInterval(10),
+            Command::SetPalette(Palette::GREEN),
+            Command::SetFrameBlend(true),
+            Command::SetStrictMemory(true),
+        ];
+        for cmd in cmds {
+            emu.execute(cmd).unwrap();
+        }
+
+        let menu = emu.menu_state();
+        assert_eq!(menu.speed, 2.0);
+        assert_eq!(menu.rewind_interval, 10);
+        assert_eq!(menu.palette, Palette::GREEN);
+        assert!(menu.frame_blend && menu.strict_memory);
+        assert!(!menu.recording_video && !menu.recording_audio);
+
+        // Nothing to save without any recorded frame
+        let path = std::env::temp_dir().join("gib-menu-commands.gif");
+        emu.execute(Command::StartVideoRecording(path)).unwrap();
+        assert!(emu.menu_state().recording_video);
+        assert!(emu.execute(Command::StopVideoRecording).is_err());
+        assert!(!emu.menu_state().recording_video);
+    }
+
+    #[test]
+    fn triple_buffer_keeps_latest_value() {
+        let (mut publisher, mut reader) = triple_buffer(0);
+        assert_eq!(*reader.read(), 0);
+
+        for i in 1..=3 {
+            *publisher.back() = i;
+            publisher.publish();
+        }
+        assert_eq!(*reader.read(), 3);
+
+        // Reading again without new values returns the same one
+        assert_eq!(*reader.read(), 3);
+
+        *publisher.back() = 4;
+        publisher.publish();
+        assert_eq!(*reader.read(), 4);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::ui::keymap::KeyMap;
+
+/// Default number of entries kept in the recent ROMs list
+const DEFAULT_MAX_RECENT_ROMS: usize = 10;
+
+/// Persistent emulator settings, stored in the user's configuration directory.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub keymap: KeyMap,
+    pub bookmarks: Vec<Bookmark>,
+    pub recent_roms: RecentRoms,
+    /// Number of samples buffered for audio playback, if not the default one
+    pub audio_buffer_size: Option<usize>,
+}
+
+/// A named memory address, saved from the memory editor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub addr: u16,
+}
+
+/// Most recently used ROM files, most recent first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecentRoms {
+    pub paths: Vec<PathBuf>,
+    pub max_len: usize,
+}
+
+impl Default for RecentRoms {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            max_len: DEFAULT_MAX_RECENT_ROMS,
+        }
+    }
+}
+
+impl RecentRoms {
+    /// Moves `path` to the top of the list, dropping the least recent entries if it gets too long.
+    pub fn push<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+
+        // Compare canonical paths, so that the same file is never listed twice
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(self.max_len);
+    }
+
+    /// Removes `path` from the list.
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) {
+        self.paths.retain(|p| p != path.as_ref());
+    }
+}
+
+impl Config {
+    /// Returns the location of the configuration file, if one can be determined.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gib").join("config.toml"))
+    }
+
+    /// Loads the configuration from disk.
+    ///
+    /// A missing or malformed configuration file results in the default configuration.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            tracing::warn!(%e, path = %path.display(), "Invalid configuration file");
+            Self::default()
+        })
+    }
+
+    /// Writes the configuration to disk, creating the parent directory if needed.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = Self::path().ok_or_else(|| anyhow!("no configuration directory available"))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Applies `f` to the configuration stored on disk and saves the result.
+    ///
+    /// Re-reading the file first ensures that settings owned by other components are preserved.
+    pub fn update<F: FnOnce(&mut Self)>(f: F) -> Result<(), Error> {
+        let mut config = Self::load();
+        f(&mut config);
+        config.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_roms() {
+        let mut recent = RecentRoms {
+            max_len: 3,
+            ..Default::default()
+        };
+
+        for rom in ["a.gb", "b.gb", "a.gb", "c.gb", "d.gb"] {
+            recent.push(rom);
+        }
+
+        // Duplicates are moved to the top, and the oldest entries are dropped
+        assert_eq!(
+            recent.paths,
+            [Path::new("d.gb"), Path::new("c.gb"), Path::new("a.gb")]
+        );
+
+        recent.remove("c.gb");
+        assert_eq!(recent.paths, [Path::new("d.gb"), Path::new("a.gb")]);
+    }
+}
@@ -0,0 +1,87 @@
+use gib_core::io::JoypadState;
+use gilrs::{Button, Gilrs};
+
+/// Mapping between gamepad buttons and joypad action buttons.
+///
+/// The D-pad is always mapped to the joypad directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GamepadMapping {
+    pub a: Button,
+    pub b: Button,
+    pub start: Button,
+    pub select: Button,
+}
+
+impl Default for GamepadMapping {
+    fn default() -> Self {
+        // Match the physical layout of the Game Boy, where A sits to the right of B
+        Self {
+            a: Button::East,
+            b: Button::South,
+            start: Button::Start,
+            select: Button::Select,
+        }
+    }
+}
+
+impl GamepadMapping {
+    /// Returns the list of gamepad buttons along with the joypad button they are bound to.
+    fn bindings(&self) -> [(Button, JoypadState); 8] {
+        [
+            (Button::DPadUp, JoypadState::UP),
+            (Button::DPadDown, JoypadState::DOWN),
+            (Button::DPadLeft, JoypadState::LEFT),
+            (Button::DPadRight, JoypadState::RIGHT),
+            (self.b, JoypadState::B),
+            (self.a, JoypadState::A),
+            (self.select, JoypadState::SELECT),
+            (self.start, JoypadState::START),
+        ]
+    }
+}
+
+/// Component responsible for polling connected gamepads.
+pub struct GamepadInput {
+    gilrs: Option<Gilrs>,
+    mapping: GamepadMapping,
+}
+
+impl GamepadInput {
+    /// Creates a new gamepad input handler using the provided button mapping.
+    ///
+    /// If the gamepad subsystem cannot be initialized, the handler will simply report
+    /// no pressed buttons.
+    pub fn new(mapping: GamepadMapping) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                tracing::warn!(%e, "Gamepad support unavailable");
+                None
+            }
+        };
+
+        Self { gilrs, mapping }
+    }
+
+    /// Returns the joypad buttons currently held down on any connected gamepad.
+    pub fn pressed_keys(&mut self) -> JoypadState {
+        let mut pressed = JoypadState::empty();
+
+        let Some(ref mut gilrs) = self.gilrs else {
+            return pressed;
+        };
+
+        // Drain pending events to keep the cached gamepad state up-to-date
+        while gilrs.next_event().is_some() {}
+
+        for (_, gamepad) in gilrs.gamepads() {
+            for (button, js) in self.mapping.bindings() {
+                if gamepad.is_pressed(button) {
+                    pressed |= js;
+                }
+            }
+        }
+
+        pressed
+    }
+}
@@ -0,0 +1,79 @@
+use egui::Key;
+use gib_core::io::JoypadState;
+use serde::{Deserialize, Serialize};
+
+/// Mapping between keyboard keys and joypad buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyMap {
+    pub up: Key,
+    pub down: Key,
+    pub left: Key,
+    pub right: Key,
+    pub b: Key,
+    pub a: Key,
+    pub select: Key,
+    pub start: Key,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            up: Key::ArrowUp,
+            down: Key::ArrowDown,
+            left: Key::ArrowLeft,
+            right: Key::ArrowRight,
+            b: Key::Z,
+            a: Key::X,
+            select: Key::Backspace,
+            start: Key::Enter,
+        }
+    }
+}
+
+impl KeyMap {
+    /// Returns the list of keys along with the joypad button they are bound to.
+    pub fn bindings(&self) -> [(Key, JoypadState); 8] {
+        [
+            (self.up, JoypadState::UP),
+            (self.down, JoypadState::DOWN),
+            (self.left, JoypadState::LEFT),
+            (self.right, JoypadState::RIGHT),
+            (self.b, JoypadState::B),
+            (self.a, JoypadState::A),
+            (self.select, JoypadState::SELECT),
+            (self.start, JoypadState::START),
+        ]
+    }
+
+    /// Binds `key` to the joypad button `js`.
+    ///
+    /// If the key is already bound to a different button, the binding is rejected
+    /// and the conflicting button is returned instead.
+    pub fn bind(&mut self, js: JoypadState, key: Key) -> Result<(), JoypadState> {
+        if let Some((_, other)) = self
+            .bindings()
+            .into_iter()
+            .find(|&(k, other)| k == key && other != js)
+        {
+            return Err(other);
+        }
+
+        *self.key_mut(js) = key;
+        Ok(())
+    }
+
+    fn key_mut(&mut self, js: JoypadState) -> &mut Key {
+        match js {
+            JoypadState::UP => &mut self.up,
+            JoypadState::DOWN => &mut self.down,
+            JoypadState::LEFT => &mut self.left,
+            JoypadState::RIGHT => &mut self.right,
+            JoypadState::B => &mut self.b,
+            JoypadState::A => &mut self.a,
+            JoypadState::SELECT => &mut self.select,
+            JoypadState::START => &mut self.start,
+            _ => unreachable!("not a single joypad button: {:?}", js),
+        }
+    }
+}
@@ -1,16 +1,27 @@
 use std::{
-    path::Path,
-    sync::atomic::{AtomicBool, Ordering},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     thread,
+    time::Duration,
 };
 
 use anyhow::Error;
+use command::{Command, MenuState, Reader, Snapshot};
+use config::{Config, RecentRoms};
+use crossbeam::channel::{self, Sender, TryRecvError};
 use egui::Key;
-use gib_core::{self, io::JoypadState};
+use gamepad::{GamepadInput, GamepadMapping};
+use gib_core::io::{JoypadState, Palette};
+use keymap::KeyMap;
 use parking_lot::Mutex;
-use sound::SoundEngine;
-use state::Emulator;
-
+use sound::{SoundEngine, DEFAULT_BUFFER_SIZE, LATENCIES};
+use state::{Emulator, Performance};
+
+mod command;
+mod config;
+mod gamepad;
+mod keymap;
+mod recording;
 mod sound;
 mod state;
 mod utils;
@@ -24,33 +35,64 @@ const EMU_WIN_X_RES: f32 = (EMU_X_RES * 2) as f32;
 /// Emulator window height (in gaming mode)
 const EMU_WIN_Y_RES: f32 = (EMU_Y_RES * 2) as f32 + 24.;
 
-/// Mapping between keycode and joypad button
-const KEYMAP: [(Key, JoypadState); 8] = [
-    (Key::ArrowUp, JoypadState::UP),
-    (Key::ArrowDown, JoypadState::DOWN),
-    (Key::ArrowLeft, JoypadState::LEFT),
-    (Key::ArrowRight, JoypadState::RIGHT),
-    (Key::Z, JoypadState::B),
-    (Key::X, JoypadState::A),
-    (Key::Backspace, JoypadState::SELECT),
-    (Key::Enter, JoypadState::START),
+/// Emulation speeds selectable from the menu
+const SPEEDS: [(&str, f32); 4] = [
+    ("1x", 1.0),
+    ("2x", 2.0),
+    ("4x", 4.0),
+    ("Unlimited", f32::INFINITY),
 ];
 
+/// Intervals (in frames) between rewind points selectable from the menu
+const REWIND_INTERVALS: [u32; 4] = [1, 5, 10, 30];
+
+/// Number of instructions executed each time the emulation thread takes the emulator lock.
+/// A frame spans several thousand instructions, so this keeps input latency well below a frame.
+pub const DEFAULT_STEPS_PER_LOCK: usize = 1024;
+
+/// Time the emulation thread waits for commands while the emulation is paused
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// DMG palettes selectable from the menu
+const PALETTES: [(&str, Palette); 2] =
+    [("Grayscale", Palette::GRAYSCALE), ("Green", Palette::GREEN)];
+
 use std::sync::Arc;
 
-use crate::ui::views::WindowManager;
+use crate::ui::views::{keybindings::KeyBindings, WindowManager};
 
 pub struct EmuUi {
+    // The emulator is owned by the emulation thread, and only shared with the UI so that the
+    // debugging windows can inspect and edit its state. Everything else goes through the
+    // command channel and the published snapshots.
     emu: Arc<Mutex<Emulator>>,
     vpu_buffer: Vec<u8>,
     vpu_texture: egui::TextureHandle,
 
-    #[allow(dead_code)] // not actually used, but we can't drop it
     sound_engine: SoundEngine,
+    volume: f32,
+    muted: bool,
+    gamepad: GamepadInput,
+    keymap: Arc<Mutex<KeyMap>>,
+    key_bindings: KeyBindings,
+    key_bindings_open: bool,
 
     debug_mode: bool,
     window_manager: WindowManager,
     close_requested: Arc<AtomicBool>,
+    steps_per_lock: Arc<AtomicUsize>,
+    start_paused: bool,
+
+    // Channels to and from the emulation thread
+    commands: Sender<Command>,
+    snapshots: Reader<Snapshot>,
+    performance: Performance,
+    paused: bool,
+    loaded_rom: Option<PathBuf>,
+    cartridge_warning: Option<String>,
+    menu: MenuState,
+
+    recent_roms: RecentRoms,
 }
 
 impl EmuUi {
@@ -59,15 +101,14 @@ impl EmuUi {
     pub const DEVEL_WINDOW_SIZE: [f32; 2] = [1440., 720.];
 
     pub fn new(cc: &eframe::CreationContext<'_>, debug_mode: bool) -> Result<Self, Error> {
-        // Create a sample channel that can hold up to 1024 samples.
-        // At 44.1KHz, this is about 23ms worth of audio.
-        let (source, sink) = gib_core::create_sound_channel(1024);
+        let config = Config::load();
 
         // Start audio thread.
         // NOTE(windows): this needs to happen before the GUI is created, or the process
         // will throw an error regarding thread creation.
         let mut sound_engine = SoundEngine::new()?;
-        sound_engine.start(sink)?;
+        let source = sound_engine
+            .set_buffer_size(config.audio_buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE))?;
 
         // Allocate a blank screen
         let vpu_buffer = vec![0xFFu8; EMU_X_RES * EMU_Y_RES * 4];
@@ -77,72 +118,162 @@ impl EmuUi {
             egui::TextureOptions::NEAREST,
         );
 
-        // Create and configure the emulator instance
+        // Create and configure the emulator instance, which stays paused until a ROM is loaded
         let mut emu = Emulator::default();
         emu.configure_audio_channel(source, sound_engine.get_sample_rate());
+        emu.pause();
+        if debug_mode {
+            emu.cpu_mut().allow_rollback_on_error(true);
+        }
+
+        let keymap = Arc::new(Mutex::new(config.keymap));
+        let menu = emu.menu_state();
+
+        let emu = Arc::new(Mutex::new(emu));
+        let close_requested = Arc::new(AtomicBool::new(false));
+        let steps_per_lock = Arc::new(AtomicUsize::new(DEFAULT_STEPS_PER_LOCK));
+        let (commands, snapshots) =
+            spawn_emulation_thread(emu.clone(), close_requested.clone(), steps_per_lock.clone());
 
         Ok(EmuUi {
-            emu: Arc::new(Mutex::new(emu)),
+            emu,
             vpu_buffer,
             vpu_texture,
 
             sound_engine,
+            volume: 1.0,
+            muted: false,
+            gamepad: GamepadInput::new(GamepadMapping::default()),
+            key_bindings: KeyBindings::new(keymap.clone()),
+            key_bindings_open: false,
+            keymap,
 
             debug_mode,
             window_manager: Default::default(),
-            close_requested: Arc::new(AtomicBool::new(false)),
+            close_requested,
+            steps_per_lock,
+            start_paused: false,
+
+            commands,
+            snapshots,
+            performance: Performance::default(),
+            paused: true,
+            loaded_rom: None,
+            cartridge_warning: None,
+            menu,
+
+            recent_roms: config.recent_roms,
         })
     }
 
-    /// Loads the ROM file and starts the emulation.
-    pub fn load_rom<P: AsRef<Path>>(&mut self, rom: P) -> Result<(), Error> {
-        let mut emu = self.emu.lock();
+    /// Sets the number of instructions executed under a single lock of the emulator.
+    ///
+    /// Larger batches reduce lock contention, at the cost of a higher input latency.
+    pub fn set_steps_per_lock(&mut self, steps: usize) {
+        self.steps_per_lock.store(steps.max(1), Ordering::Relaxed);
+    }
 
-        emu.load_rom(rom)?;
+    /// Sets whether ROMs are loaded paused at the entry point, rather than running right away.
+    pub fn set_start_paused(&mut self, paused: bool) {
+        self.start_paused = paused;
+    }
 
-        if self.debug_mode {
-            emu.cpu_mut().allow_rollback_on_error(true);
-        }
+    /// Loads the ROM file and starts the emulation, unless started paused.
+    ///
+    /// The ROM is loaded asynchronously by the emulation thread.
+    /// Errors occurring while loading the ROM are logged by the emulation thread,
+    /// and the ROM is only added to the recent ROMs once loaded successfully.
+    pub fn load_rom<P: AsRef<Path>>(&mut self, rom: P) -> Result<(), Error> {
+        self.commands.send(Command::LoadRom {
+            path: rom.as_ref().to_path_buf(),
+            paused: self.start_paused,
+        })?;
+        Ok(())
+    }
 
-        emu.set_running();
+    /// Restarts the audio playback buffering up to `size` samples, and saves the setting.
+    fn set_audio_buffer_size(&mut self, size: usize) {
+        // Replacing the stream drops the old channel, so the emulation thread cannot stay
+        // blocked on it before receiving the new one
+        let source = match self.sound_engine.set_buffer_size(size) {
+            Ok(source) => source,
+            Err(e) => {
+                tracing::error!(%e, "Could not restart audio playback");
+                return;
+            }
+        };
 
-        let emu = self.emu.clone();
-        let close_requested = self.close_requested.clone();
+        let sample_rate = self.sound_engine.get_sample_rate();
+        self.send(Command::ConfigureAudio(source, sample_rate));
 
-        thread::spawn(move || {
-            while !close_requested.load(Ordering::Relaxed) {
-                let mut emu = emu.lock();
+        if let Err(e) = Config::update(|cfg| cfg.audio_buffer_size = Some(size)) {
+            tracing::warn!(%e, "Could not save the audio buffer size");
+        }
+    }
 
-                emu.do_step();
+    /// Sends a command to the emulation thread.
+    fn send(&self, cmd: Command) {
+        self.commands.send(cmd).ok();
+    }
 
-                // Stop the emulation to prevent the CPU spiking to 100%
-                // (not much we can do at this point anyway)
-                if emu.last_event().is_some() {
-                    break;
-                }
-            }
-        });
+    /// Persists the recent ROMs list to the configuration file.
+    fn save_recent_roms(&self) {
+        let recent_roms = self.recent_roms.clone();
 
-        Ok(())
+        if let Err(e) = Config::update(|cfg| cfg.recent_roms = recent_roms) {
+            tracing::warn!(%e, "Could not save recent ROMs");
+        }
     }
 
     fn update_emulation(&mut self, ctx: &egui::Context) {
-        let mut emu = self.emu.lock();
-
-        // Forward keypresses to the emulator
-        for &(vk, js) in KEYMAP.iter() {
-            if ctx.input(|i| i.key_down(vk)) {
-                emu.gameboy_mut().press_key(js);
-            } else {
-                emu.gameboy_mut().release_key(js);
+        let gamepad_keys = self.gamepad.pressed_keys();
+        let bindings = self.keymap.lock().bindings();
+
+        // Forward keypresses to the emulator, merging keyboard and gamepad input
+        let mut pressed = JoypadState::empty();
+        for (vk, js) in bindings {
+            if ctx.input(|i| i.key_down(vk)) || gamepad_keys.contains(js) {
+                pressed |= js;
             }
         }
 
         // Enable/disable turbo mode
-        emu.set_turbo(ctx.input(|i| i.key_down(Key::Space)));
+        let turbo = ctx.input(|i| i.key_down(Key::Space));
+
+        // Step back in time one rewind point per frame while the rewind key is held
+        let rewinding = ctx.input(|i| i.key_down(Key::R));
+
+        let mut cmds = vec![
+            Command::PressKey(pressed),
+            Command::ReleaseKey(JoypadState::all() - pressed),
+            Command::SetTurbo(turbo),
+            Command::SetRewinding(rewinding),
+        ];
+        if rewinding {
+            cmds.push(Command::RewindStep);
+        }
 
-        // Render to texture
-        emu.gameboy().rasterize(&mut self.vpu_buffer[..]);
+        for cmd in cmds {
+            self.send(cmd);
+        }
+
+        // Render the last published frame to texture
+        let snapshot = self.snapshots.read();
+        self.vpu_buffer.copy_from_slice(&snapshot.frame);
+        self.performance = snapshot.performance;
+        self.paused = snapshot.paused;
+        self.cartridge_warning = snapshot.cartridge_warning.clone();
+        self.menu = snapshot.menu;
+
+        // Remember ROMs only once the emulation thread has managed to load them
+        if snapshot.rom != self.loaded_rom {
+            self.loaded_rom = snapshot.rom.clone();
+
+            if let Some(ref rom) = self.loaded_rom {
+                self.recent_roms.push(rom);
+                self.save_recent_roms();
+            }
+        }
 
         // Update texture data
         ctx.tex_manager().write().set(
@@ -167,6 +298,10 @@ impl eframe::App for EmuUi {
             self.game_ui(ctx, frame);
         }
 
+        if self.key_bindings_open {
+            self.key_bindings.show(ctx, &mut self.key_bindings_open);
+        }
+
         // The UI needs to be continuously refreshed, since the emulator updates in backgronud
         ctx.request_repaint();
     }
@@ -212,6 +347,59 @@ impl EmuUi {
             });
     }
 
+    fn recent_roms_ui(&mut self, ui: &mut egui::Ui) {
+        let mut selected = None;
+        let mut missing = None;
+
+        for path in &self.recent_roms.paths {
+            let name = path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy();
+
+            // ROMs that have been moved or deleted since are grayed out, and removed when clicked
+            if path.is_file() {
+                if ui
+                    .button(name)
+                    .on_hover_text(path.display().to_string())
+                    .clicked()
+                {
+                    selected = Some(path.clone());
+                }
+            } else if ui
+                .button(egui::RichText::new(name).weak())
+                .on_hover_text(format!("{} not found, click to remove", path.display()))
+                .clicked()
+            {
+                missing = Some(path.clone());
+            }
+        }
+
+        if self.recent_roms.paths.is_empty() {
+            ui.add_enabled(false, egui::Button::new("No recent ROMs"));
+        }
+
+        ui.separator();
+
+        if ui.button("Clear list").clicked() {
+            self.recent_roms.paths.clear();
+            self.save_recent_roms();
+            ui.close_menu();
+        }
+
+        if let Some(path) = missing {
+            self.recent_roms.remove(path);
+            self.save_recent_roms();
+        }
+
+        if let Some(path) = selected {
+            if let Err(e) = self.load_rom(&path) {
+                tracing::error!(%e, path = %path.display(), "Could not load ROM");
+            }
+            ui.close_menu();
+        }
+    }
+
     fn emulation_menu_ui(&mut self, ui: &mut egui::Ui, frame: &mut eframe::Frame) {
         egui::menu::bar(ui, |ui| {
             ui.menu_button("Emulator", |ui| {
@@ -222,6 +410,18 @@ impl EmuUi {
                     ui.close_menu();
                 }
 
+                ui.menu_button("Recent ROMs", |ui| self.recent_roms_ui(ui));
+
+                if ui.button("Load symbols...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("sym", &["sym"])
+                        .pick_file()
+                    {
+                        self.send(Command::LoadSymbols(path));
+                    }
+                    ui.close_menu();
+                }
+
                 ui.separator();
 
                 if ui.button("Save screen").clicked() {
@@ -237,15 +437,212 @@ impl EmuUi {
                     ui.close_menu();
                 }
 
+                if self.menu.recording_video {
+                    if ui.button("Stop screen recording").clicked() {
+                        self.send(Command::StopVideoRecording);
+                        ui.close_menu();
+                    }
+                } else if ui.button("Record screen...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("gif", &["gif"])
+                        .set_file_name("recording.gif")
+                        .save_file()
+                    {
+                        self.send(Command::StartVideoRecording(path));
+                    }
+                    ui.close_menu();
+                }
+
+                if self.menu.recording_audio {
+                    if ui.button("Stop audio recording").clicked() {
+                        self.send(Command::StopAudioRecording);
+                        ui.close_menu();
+                    }
+                } else if ui.button("Record audio...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .set_file_name("recording.wav")
+                        .save_file()
+                    {
+                        self.send(Command::StartAudioRecording(path));
+                    }
+                    ui.close_menu();
+                }
+
+                if self.loaded_rom.is_some() {
+                    let (label, cmd) = if self.paused {
+                        ("Resume", Command::Run)
+                    } else {
+                        ("Pause", Command::Pause)
+                    };
+
+                    if ui.button(label).clicked() {
+                        self.send(cmd);
+                        ui.close_menu();
+                    }
+                }
+
                 if ui.button("Reset").clicked() {
-                    self.emu.lock().reset();
+                    self.send(Command::Reset);
+                    ui.close_menu();
+                }
+
+                if ui.button("Reset (keep RAM)").clicked() {
+                    self.send(Command::ResetPreservingRam);
                     ui.close_menu();
                 }
 
+                ui.menu_button("Speed", |ui| {
+                    for (label, speed) in SPEEDS {
+                        if ui.radio(self.menu.speed == speed, label).clicked() {
+                            self.send(Command::SetSpeed(speed));
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                ui.menu_button("Rewind interval", |ui| {
+                    for frames in REWIND_INTERVALS {
+                        let label = format!("{frames} frames");
+                        if ui
+                            .radio(self.menu.rewind_interval == frames, label)
+                            .clicked()
+                        {
+                            self.send(Command::SetRewindInterval(frames));
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                ui.menu_button("Palette", |ui| {
+                    for (label, palette) in PALETTES {
+                        if ui.radio(self.menu.palette == palette, label).clicked() {
+                            self.send(Command::SetPalette(palette));
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                ui.menu_button("Audio latency", |ui| {
+                    for (label, size) in LATENCIES {
+                        let label = format!("{label} ({size} samples)");
+                        if ui
+                            .radio(self.sound_engine.buffer_size() == size, label)
+                            .clicked()
+                        {
+                            self.set_audio_buffer_size(size);
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                let volume = ui.add(egui::Slider::new(&mut self.volume, 0.0..=1.0).text("Volume"));
+                let mute = ui.checkbox(&mut self.muted, "Mute");
+                if volume.changed() || mute.changed() {
+                    let volume = if self.muted { 0.0 } else { self.volume };
+                    self.send(Command::SetMasterVolume(volume));
+                }
+
+                let mut blend = self.menu.frame_blend;
+                if ui.checkbox(&mut blend, "Frame blending").clicked() {
+                    self.send(Command::SetFrameBlend(blend));
+                }
+
+                let mut strict = self.menu.strict_memory;
+                if ui.checkbox(&mut strict, "Strict memory").clicked() {
+                    self.send(Command::SetStrictMemory(strict));
+                }
+
+                ui.separator();
+
+                if ui.button("Key bindings...").clicked() {
+                    self.key_bindings_open = true;
+                    ui.close_menu();
+                }
+
+                ui.separator();
+
                 if ui.button("Quit").clicked() {
                     frame.close();
                 }
-            })
+            });
+
+            // Show emulation performance on the right side of the menu bar
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let perf = self.performance;
+                ui.label(format!("{:5.1} FPS  {:5.2} MIPS", perf.fps, perf.ips / 1e6));
+
+                if let Some(ref warning) = self.cartridge_warning {
+                    ui.colored_label(egui::Color32::RED, warning);
+                }
+            });
         });
     }
 }
+
+/// Starts the thread running the emulation, returning the channel used to control it
+/// and the reader of the snapshots it publishes.
+///
+/// The thread takes the emulator lock only while executing commands and emulation steps,
+/// and publishes the rendered frames through a triple buffer, so that the UI never needs
+/// to lock the emulator outside of the debugging windows.
+fn spawn_emulation_thread(
+    emu: Arc<Mutex<Emulator>>,
+    close_requested: Arc<AtomicBool>,
+    steps_per_lock: Arc<AtomicUsize>,
+) -> (Sender<Command>, Reader<Snapshot>) {
+    let (tx, commands) = channel::unbounded();
+    let (mut publisher, reader) = command::triple_buffer(Snapshot::default());
+
+    thread::spawn(move || {
+        let mut published_frame = None;
+
+        while !close_requested.load(Ordering::Relaxed) {
+            let mut emu = emu.lock();
+
+            loop {
+                match commands.try_recv() {
+                    Ok(cmd) => {
+                        if let Err(e) = emu.execute(cmd) {
+                            tracing::error!(%e, "Emulator command failed");
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            emu.do_steps(steps_per_lock.load(Ordering::Relaxed));
+
+            // While paused, the state can still be changed from the debugging windows,
+            // so keep publishing snapshots
+            let paused = emu.paused();
+            let frame = emu.gameboy().frame_count();
+
+            if paused || published_frame != Some(frame) {
+                let snapshot = publisher.back();
+                snapshot.frame.copy_from_slice(emu.gameboy().present());
+                snapshot.performance = emu.performance();
+                snapshot.paused = paused;
+                snapshot.rom = emu.rom_path().map(Path::to_path_buf);
+                snapshot.cartridge_warning = emu.cartridge_warning().map(str::to_string);
+                snapshot.menu = emu.menu_state();
+                publisher.publish();
+
+                published_frame = Some(frame);
+            }
+
+            let delay = emu.pacing_delay();
+            drop(emu);
+
+            // Prevent the CPU from spiking to 100% when there is nothing to run,
+            // and keep the emulation in sync with real time otherwise
+            if paused {
+                thread::sleep(PAUSED_POLL_INTERVAL);
+            } else if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+        }
+    });
+
+    (tx, reader)
+}
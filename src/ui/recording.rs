@@ -0,0 +1,208 @@
+use std::io::{self, Seek, Write};
+
+use gib_core::io::Palette;
+
+use crate::ui::{sound::SAMPLE_SCALE, EMU_X_RES, EMU_Y_RES};
+
+/// Writer of 16-bit PCM WAV files, fed with APU samples.
+pub struct WavWriter<W: Write + Seek> {
+    writer: hound::WavWriter<W>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Creates a new writer and emits the WAV header.
+    pub fn new(writer: W, sample_rate: u32, channels: u16) -> hound::Result<Self> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        Ok(Self {
+            writer: hound::WavWriter::new(writer, spec)?,
+        })
+    }
+
+    /// Appends an APU sample to the file, converting it to 16-bit PCM.
+    pub fn write_sample(&mut self, sample: i16) -> hound::Result<()> {
+        let value = (sample as f32 * SAMPLE_SCALE).clamp(-1.0, 1.0) * i16::MAX as f32;
+        self.writer.write_sample(value as i16)
+    }
+
+    /// Patches the sizes in the header and flushes the file.
+    pub fn finalize(self) -> hound::Result<()> {
+        self.writer.finalize()
+    }
+}
+
+/// Approximate refresh rate of the Game Boy LCD, in frames per second.
+const LCD_REFRESH_RATE: u64 = 60;
+
+/// Screen recorder producing an animated GIF.
+///
+/// Frames are reduced to the four shades of the palette in use and written to the
+/// encoder as soon as they are captured, so memory usage does not grow with the
+/// length of the recording. Only one every `decimation` frames is recorded.
+pub struct VideoRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    palette: Palette,
+    decimation: u32,
+    skipped: u32,
+    frames: usize,
+    pixels: Vec<u8>,
+}
+
+impl<W: Write> VideoRecorder<W> {
+    /// Creates a new recorder for frames rendered with `palette`, writing the GIF header.
+    pub fn new(writer: W, palette: Palette, decimation: u32) -> Result<Self, gif::EncodingError> {
+        let mut encoder = gif::Encoder::new(
+            writer,
+            EMU_X_RES as u16,
+            EMU_Y_RES as u16,
+            &palette.0.concat(),
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        Ok(Self {
+            encoder,
+            palette,
+            decimation: decimation.max(1),
+            skipped: 0,
+            frames: 0,
+            pixels: vec![0; EMU_X_RES * EMU_Y_RES],
+        })
+    }
+
+    /// Records a frame in RGBA format, as produced by [`gib_core::GameBoy::present`].
+    pub fn push_frame(&mut self, rgba: &[u8]) -> Result<(), gif::EncodingError> {
+        self.skipped += 1;
+        if self.skipped < self.decimation && self.frames > 0 {
+            return Ok(());
+        }
+        self.skipped = 0;
+
+        for (index, pixel) in self.pixels.iter_mut().zip(rgba.chunks(4)) {
+            *index = nearest_shade(&self.palette, pixel);
+        }
+
+        let frame = gif::Frame {
+            width: EMU_X_RES as u16,
+            height: EMU_Y_RES as u16,
+            delay: self.frame_delay(self.frames as u64),
+            buffer: (&self.pixels[..]).into(),
+            ..Default::default()
+        };
+        self.encoder.write_frame(&frame)?;
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Returns the number of frames recorded so far.
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    /// Terminates the GIF stream and returns the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.encoder.into_inner()
+    }
+
+    /// Returns the delay of the `n`-th recorded frame, in hundredths of a second.
+    ///
+    /// GIF delays are too coarse to represent the frame period exactly, so each
+    /// delay is rounded such that the total duration does not drift.
+    fn frame_delay(&self, n: u64) -> u16 {
+        let period = u64::from(self.decimation) * 100;
+        let delay = ((n + 1) * period / LCD_REFRESH_RATE) - (n * period / LCD_REFRESH_RATE);
+        u16::try_from(delay).unwrap_or(u16::MAX)
+    }
+}
+
+/// Returns the index of the palette shade closest to an RGBA pixel.
+fn nearest_shade(palette: &Palette, pixel: &[u8]) -> u8 {
+    let distance = |shade: &[u8; 3]| -> u32 {
+        shade
+            .iter()
+            .zip(pixel)
+            .map(|(&a, &b)| (i32::from(a) - i32::from(b)).pow(2) as u32)
+            .sum()
+    };
+
+    (0..4)
+        .min_by_key(|&i| distance(&palette.0[i]))
+        .unwrap_or_default() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn wav_round_trip() {
+        let mut data = Cursor::new(Vec::new());
+
+        let mut wav = WavWriter::new(&mut data, 48000, 1).unwrap();
+        for sample in [0, 500, -500, 1000, -1000, 2000] {
+            wav.write_sample(sample).unwrap();
+        }
+        wav.finalize().unwrap();
+
+        data.set_position(0);
+        let mut reader = hound::WavReader::new(data).unwrap();
+
+        let spec = reader.spec();
+        assert_eq!(
+            (spec.channels, spec.sample_rate, spec.bits_per_sample),
+            (1, 48000, 16)
+        );
+        assert_eq!(spec.sample_format, hound::SampleFormat::Int);
+        assert_eq!(reader.len(), 6);
+
+        let samples = reader
+            .samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(samples, [0, 16383, -16383, 32767, -32767, 32767]);
+    }
+
+    #[test]
+    fn gif_frame_count() {
+        let palette = Palette::GRAYSCALE;
+        let mut recorder = VideoRecorder::new(Vec::new(), palette, 2).unwrap();
+
+        // Synthetic frames, each filled with one of the palette shades
+        for i in 0..7 {
+            let [r, g, b] = palette.0[i % 4];
+            recorder
+                .push_frame(&[r, g, b, 0xFF].repeat(EMU_X_RES * EMU_Y_RES))
+                .unwrap();
+        }
+        assert_eq!(recorder.frame_count(), 4);
+
+        let data = recorder.finish().unwrap();
+        let mut decoder = gif::DecodeOptions::new().read_info(&data[..]).unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        assert_eq!(frames.len(), 4);
+
+        // Every other frame is recorded, so the second one has the third shade
+        assert!(frames[1].1.iter().all(|&b| b == 2));
+
+        // Four frames at 30 fps last 13 hundredths of a second, once rounded
+        let duration: u16 = frames.iter().map(|(delay, _)| delay).sum();
+        assert_eq!(duration, 13);
+    }
+
+    #[test]
+    fn gif_frame_delay_saturates() {
+        let recorder = VideoRecorder::new(Vec::new(), Palette::GRAYSCALE, u32::MAX).unwrap();
+        assert_eq!(recorder.frame_delay(0), u16::MAX);
+    }
+}
@@ -3,13 +3,85 @@ use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Device, OutputCallbackInfo, Stream, StreamConfig,
 };
-use gib_core::AudioSink;
+use gib_core::{AudioSink, AudioSource};
+
+/// Maximum relative deviation from the nominal sample rate applied by [`RateController`].
+const MAX_RATE_DEVIATION: f32 = 0.005;
+
+/// Fill level of the audio channel [`RateController`] aims for.
+const TARGET_FILL: f32 = 0.5;
+
+/// Weight of a new fill level measurement in the low-pass filter of [`RateController`].
+const FILL_SMOOTHING: f32 = 0.1;
+
+/// Proportional and integral gains of [`RateController`], as fractions of the maximum deviation.
+const RATE_KP: f32 = 2.0;
+const RATE_KI: f32 = 0.005;
+
+/// Scale factor converting APU samples to the `[-1.0, 1.0]` range.
+pub const SAMPLE_SCALE: f32 = 0.001;
+
+/// Number of samples buffered for playback by default.
+/// At 44.1KHz, this is about 23ms worth of audio.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+/// Audio latency presets selectable from the menu, as the number of buffered samples.
+///
+/// Smaller buffers reduce latency, but are more likely to underrun on slow machines.
+pub const LATENCIES: [(&str, usize); 3] = [
+    ("Low", DEFAULT_BUFFER_SIZE / 2),
+    ("Medium", DEFAULT_BUFFER_SIZE),
+    ("High", DEFAULT_BUFFER_SIZE * 4),
+];
+
+/// Dynamic rate controller, used to keep the audio channel half full.
+///
+/// The emulated and playback clocks are never exactly in sync, so the channel slowly drifts
+/// towards being empty or full. This is a PI controller nudging the APU sample rate based on
+/// the distance of the (low-pass filtered) fill level from [`TARGET_FILL`]: the integral term
+/// settles on the offset between the two clocks, so latency stays stable over time.
+/// The adjustment is small enough not to be perceived as a change in pitch.
+///
+/// The fill level should be measured by the consumer, ie. the playback thread.
+#[derive(Debug, Clone, Copy)]
+pub struct RateController {
+    max_deviation: f32,
+    fill: f32,
+    integral: f32,
+}
+
+impl Default for RateController {
+    fn default() -> Self {
+        Self {
+            max_deviation: MAX_RATE_DEVIATION,
+            fill: TARGET_FILL,
+            integral: 0.0,
+        }
+    }
+}
+
+impl RateController {
+    /// Returns the sample rate the emulator should produce samples at, given the nominal
+    /// sample `rate` and the current `fill` level of the channel (from 0.0 to 1.0).
+    ///
+    /// This is meant to be called at regular intervals, eg. once per frame.
+    pub fn adjust(&mut self, rate: f32, fill: f32) -> f32 {
+        self.fill += FILL_SMOOTHING * (fill.clamp(0.0, 1.0) - self.fill);
+
+        let error = TARGET_FILL - self.fill;
+        self.integral = (self.integral + RATE_KI * error).clamp(-1.0, 1.0);
+
+        let correction = (RATE_KP * error + self.integral).clamp(-1.0, 1.0);
+        rate * (1.0 + correction * self.max_deviation)
+    }
+}
 
 /// Component responsible for audio playback.
 pub struct SoundEngine {
     device: Device,
     config: StreamConfig,
     stream: Option<Stream>,
+    buffer_size: usize,
 }
 
 impl SoundEngine {
@@ -26,9 +98,27 @@ impl SoundEngine {
             device,
             config,
             stream: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
         })
     }
 
+    /// Returns the number of samples buffered for playback.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Restarts the playback with a new channel buffering up to `size` samples.
+    ///
+    /// Returns the transmitting end of the channel, which must be handed to the emulator
+    /// in place of the previous one.
+    pub fn set_buffer_size(&mut self, size: usize) -> Result<AudioSource, Error> {
+        let (source, sink) = create_channel(size);
+        self.start(sink)?;
+        self.buffer_size = size;
+
+        Ok(source)
+    }
+
     /// Returns the engine's current sample rate.
     pub fn get_sample_rate(&self) -> f32 {
         self.config.sample_rate.0 as f32
@@ -44,7 +134,7 @@ impl SoundEngine {
         let mut last_sample = 0f32;
         let mut next_sample = move || {
             if let Some(sample) = sink.pop() {
-                last_sample = sample as f32 * 0.001;
+                last_sample = sample as f32 * SAMPLE_SCALE;
             }
             last_sample
         };
@@ -75,3 +165,76 @@ impl SoundEngine {
         Ok(())
     }
 }
+
+/// Creates a sample channel holding up to `size` samples, with a minimum of one.
+fn create_channel(size: usize) -> (AudioSource, AudioSink) {
+    gib_core::create_sound_channel(size.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_controller_feedback() {
+        let rate = 48000.0;
+
+        // At the target fill level, the nominal rate is used
+        assert_eq!(RateController::default().adjust(rate, 0.5), rate);
+
+        // An emptying channel requires more samples, a filling one less
+        assert!(RateController::default().adjust(rate, 0.25) > rate);
+        assert!(RateController::default().adjust(rate, 0.75) < rate);
+
+        // The adjustment is always bounded, even for out-of-range fill levels
+        // and once the integral term has settled
+        let mut rc = RateController::default();
+        for fill in [-1.0, 0.0, 0.1, 0.9, 1.0, 2.0] {
+            for _ in 0..1000 {
+                let adjusted = rc.adjust(rate, fill);
+                assert!((adjusted - rate).abs() <= rate * MAX_RATE_DEVIATION + f32::EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn rate_controller_convergence() {
+        const CAPACITY: f32 = 1024.0;
+        const FPS: f32 = 60.0;
+        let rate = 48000.0;
+
+        // Playback clocks running slightly slower and faster than the emulated one
+        for drift in [0.997, 1.0, 1.003] {
+            let mut rc = RateController::default();
+            let mut queued = 0.0;
+            let mut fills = Vec::new();
+
+            // One minute worth of frames, each producing samples at the adjusted rate
+            // while the consumer plays them back at its own pace
+            for _ in 0..60 * FPS as usize {
+                let produced = rc.adjust(rate, queued / CAPACITY) / FPS;
+                let consumed = rate * drift / FPS;
+                queued = (queued + produced - consumed).clamp(0.0, CAPACITY);
+                fills.push(queued / CAPACITY);
+            }
+
+            // The fill level settles around the target over the last ten seconds
+            let settled = &fills[fills.len() - 10 * FPS as usize..];
+            assert!(
+                settled.iter().all(|f| (f - TARGET_FILL).abs() < 0.01),
+                "drift {drift}: fill level did not converge"
+            );
+        }
+    }
+
+    #[test]
+    fn channel_capacity() {
+        for (_, size) in LATENCIES {
+            let (source, sink) = create_channel(size);
+            assert_eq!(sink.capacity(), size);
+            assert_eq!(source.capacity(), size);
+        }
+
+        assert_eq!(create_channel(0).1.capacity(), 1);
+    }
+}
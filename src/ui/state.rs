@@ -1,14 +1,88 @@
-use std::{fs, path::Path};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use anyhow::Error;
-use gib_core::{bus::Bus, cpu::Cpu, dbg, AudioSource, GameBoy};
+use anyhow::{anyhow, Error};
+use crossbeam::channel::{self, Receiver};
+use gib_core::{bus::Bus, cpu::Cpu, dbg, AudioSource, GameBoy, RewindBuffer};
+
+use crate::ui::{
+    command::{Command, MenuState},
+    recording::{VideoRecorder, WavWriter},
+    sound::RateController,
+};
+
+/// Maximum number of rewind points kept in memory
+const REWIND_CAPACITY: usize = 120;
+
+/// Maximum memory taken up by the rewind points, in bytes
+const REWIND_MAX_BYTES: usize = 32 * 1024 * 1024;
+
+/// Only one every `VIDEO_DECIMATION` frames is captured in screen recordings
+const VIDEO_DECIMATION: u32 = 2;
+
+/// Interval between two updates of the performance counters
+const PERF_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Slowest emulation speed accepted by [`Emulator::set_speed`]
+const MIN_SPEED: f32 = 0.01;
+
+/// Number of CPU cycles in a frame
+const FRAME_CYCLES: f64 = 70224.0;
+
+/// How far the emulation can fall behind real time before giving up on catching up
+const MAX_PACING_LAG: Duration = Duration::from_millis(50);
+
+/// An audio recording in progress, fed by the audio tap of the emulator.
+struct AudioRecording {
+    samples: Receiver<i16>,
+    wav: WavWriter<BufWriter<File>>,
+}
+
+/// A screen recording in progress, streamed to the file at `path`.
+struct VideoRecording {
+    path: PathBuf,
+    recorder: VideoRecorder<BufWriter<File>>,
+}
+
+/// Emulation performance, measured over the last [`PERF_INTERVAL`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Performance {
+    /// Frames per second
+    pub fps: f32,
+    /// Instructions per second
+    pub ips: f32,
+}
 
 pub struct Emulator {
     gameboy: GameBoy,
     turbo_mode: bool,
+    speed: f32,
+    sample_rate: f32,
+    rate_control: RateController,
+    frame_deadline: Instant,
     step_to_next: bool,
     run_to_breakpoint: bool,
     trace_event: Option<dbg::TraceEvent>,
+    disasm_target: Option<u16>,
+    symbols_changed: bool,
+    cartridge_warning: Option<String>,
+    rom_path: Option<PathBuf>,
+
+    rewind: RewindBuffer,
+    rewind_interval: u32,
+    frames_since_rewind_point: u32,
+    rewinding: bool,
+
+    perf: Performance,
+    perf_start: Instant,
+    perf_counters: (u64, u64),
+
+    audio_recording: Option<AudioRecording>,
+    video_recording: Option<VideoRecording>,
 }
 
 impl Default for Emulator {
@@ -16,22 +90,146 @@ impl Default for Emulator {
         Self {
             gameboy: GameBoy::new(),
             turbo_mode: false,
+            speed: 1.0,
+            sample_rate: 0.0,
+            rate_control: RateController::default(),
+            frame_deadline: Instant::now(),
             step_to_next: false,
             run_to_breakpoint: false,
             trace_event: None,
+            disasm_target: None,
+            symbols_changed: false,
+            cartridge_warning: None,
+            rom_path: None,
+
+            rewind: RewindBuffer::new(REWIND_CAPACITY, REWIND_MAX_BYTES),
+            rewind_interval: 5,
+            frames_since_rewind_point: 0,
+            rewinding: false,
+
+            perf: Performance::default(),
+            perf_start: Instant::now(),
+            perf_counters: (0, 0),
+
+            audio_recording: None,
+            video_recording: None,
         }
     }
 }
 
 impl Emulator {
     pub fn load_rom<P: AsRef<Path>>(&mut self, rom: P) -> Result<(), Error> {
-        self.gameboy.load_rom(&(fs::read(rom)?)[..])?;
+        self.gameboy.load_rom(&(fs::read(&rom)?)[..])?;
+        self.rom_path = Some(rom.as_ref().to_path_buf());
         self.reset();
+
+        // A corrupt header is not fatal, but the user should know about it
+        let validation = self.gameboy.validate_cartridge();
+        self.cartridge_warning = match (validation.header_checksum_valid, validation.logo_valid) {
+            (true, true) => None,
+            (false, true) => Some("Invalid header checksum".to_string()),
+            (true, false) => Some("Invalid Nintendo logo".to_string()),
+            (false, false) => Some("Invalid header checksum and Nintendo logo".to_string()),
+        };
+        if let Some(ref warning) = self.cartridge_warning {
+            tracing::warn!(%warning, "Cartridge validation failed");
+        }
+
+        // Loading the ROM discarded the symbols of the previous one. Pick up the symbol file
+        // generated alongside the ROM, if any: failing to read it does not prevent running it.
+        self.symbols_changed = true;
+
+        let sym = rom.as_ref().with_extension("sym");
+        if sym.is_file() {
+            if let Err(e) = self.load_symbols(&sym) {
+                tracing::warn!(%e, path = %sym.display(), "Could not load symbols");
+            }
+        }
+
         Ok(())
     }
 
+    /// Returns the path of the last ROM loaded successfully, if any.
+    pub fn rom_path(&self) -> Option<&Path> {
+        self.rom_path.as_deref()
+    }
+
+    /// Returns a description of the problems found in the cartridge header, if any.
+    pub fn cartridge_warning(&self) -> Option<&str> {
+        self.cartridge_warning.as_deref()
+    }
+
+    /// Executes a command received from the UI.
+    pub fn execute(&mut self, cmd: Command) -> Result<(), Error> {
+        match cmd {
+            Command::LoadRom { path, paused } => {
+                self.load_rom(path)?;
+                if paused {
+                    self.pause();
+                }
+            }
+            Command::LoadSymbols(path) => self.load_symbols(path)?,
+            Command::Reset => self.reset(),
+            Command::ResetPreservingRam => self.reset_preserving_ram(),
+            Command::PressKey(keys) => self.gameboy.press_key(keys),
+            Command::ReleaseKey(keys) => self.gameboy.release_key(keys),
+            Command::SetTurbo(turbo) => self.set_turbo(turbo),
+            Command::SetRewinding(rewinding) => self.set_rewinding(rewinding),
+            Command::RewindStep => {
+                self.rewind_step();
+            }
+            Command::Pause => self.pause(),
+            Command::Run => self.set_running(),
+            Command::SetBreakpoint(addr) => self.gameboy.cpu_mut().set_breakpoint(addr),
+            Command::ClearBreakpoint(addr) => self.gameboy.cpu_mut().clear_breakpoint(addr),
+            Command::SetBreakpointEnabled(addr, enabled) => {
+                self.gameboy.cpu_mut().set_breakpoint_enabled(addr, enabled);
+            }
+            Command::StartVideoRecording(path) => self.start_video_recording(path)?,
+            Command::StopVideoRecording => self.stop_video_recording()?,
+            Command::StartAudioRecording(path) => self.start_audio_recording(path)?,
+            Command::StopAudioRecording => self.stop_audio_recording()?,
+            Command::ConfigureAudio(source, sample_rate) => {
+                self.configure_audio_channel(source, sample_rate);
+            }
+            Command::SetSpeed(speed) => self.set_speed(speed),
+            Command::SetRewindInterval(frames) => self.set_rewind_interval(frames),
+            Command::SetPalette(palette) => self.gameboy.set_palette(palette),
+            Command::SetMasterVolume(volume) => self.gameboy.set_master_volume(volume),
+            Command::SetFrameBlend(enabled) => self.gameboy.set_frame_blend(enabled, 0.5),
+            Command::SetStrictMemory(strict) => self.gameboy.set_strict_memory(strict),
+        }
+        Ok(())
+    }
+
+    /// Returns the state of the settings shown in the menus.
+    pub fn menu_state(&self) -> MenuState {
+        MenuState {
+            speed: self.speed,
+            rewind_interval: self.rewind_interval,
+            palette: self.gameboy.palette(),
+            frame_blend: self.gameboy.frame_blend(),
+            strict_memory: self.gameboy.strict_memory(),
+            recording_video: self.is_recording_video(),
+            recording_audio: self.is_recording_audio(),
+        }
+    }
+
+    /// Loads the labels of a symbol file, used to annotate the disassembly.
+    pub fn load_symbols<P: AsRef<Path>>(&mut self, sym: P) -> Result<(), Error> {
+        self.gameboy.load_symbols(&fs::read_to_string(sym)?);
+        self.symbols_changed = true;
+        Ok(())
+    }
+
+    /// Returns whether new symbols have been loaded since the last call.
+    pub fn take_symbols_changed(&mut self) -> bool {
+        std::mem::take(&mut self.symbols_changed)
+    }
+
     pub fn pause(&mut self) {
         self.turbo_mode = false;
+        self.gameboy.set_audio_fast_forward(false);
         self.step_to_next = false;
         self.run_to_breakpoint = false;
         self.gameboy.cpu_mut().pause();
@@ -39,27 +237,69 @@ impl Emulator {
 
     /// Performs a single emulation step, depending on the emulator's state:
     ///
-    /// * if we are in step mode, execute a single instruction
-    /// * if we are in run mode, run to audio sync (ie. audio queue full)
+    /// * if we are in step mode, execute a single instruction, then pause
+    /// * if we are in run mode, execute a single instruction, bounded by audio sync
     ///
     /// In both cases, if an event happens, pause the emulator.
     pub fn do_step(&mut self) {
-        if self.paused() {
+        self.do_steps(1);
+    }
+
+    /// Performs up to `n` emulation steps, stopping early if the emulator gets paused.
+    ///
+    /// See [`Emulator::do_step`] for details on what a single step consists of.
+    pub fn do_steps(&mut self, n: usize) {
+        self.update_performance();
+
+        if self.paused() || self.rewinding {
             return;
         }
 
         self.trace_event = None;
 
-        let res = if self.step_to_next {
-            let r = self.gameboy.step();
-            self.pause();
-            r
-        } else if self.run_to_breakpoint {
-            self.gameboy.run_for_vblank()
-        } else {
-            Ok(())
-        };
+        for _ in 0..n {
+            let res = if self.step_to_next {
+                let r = self.gameboy.step();
+                self.pause();
+                r
+            } else if self.run_to_breakpoint {
+                let frame = self.gameboy.frame_count();
+                let r = self.gameboy.step();
 
+                if self.gameboy.frame_count() != frame {
+                    self.record_frame();
+                    self.capture_frame();
+                    self.pace_frame();
+                    self.apply_speed();
+                }
+                r
+            } else {
+                Ok(())
+            };
+
+            self.handle_result(res);
+
+            if self.paused() {
+                break;
+            }
+        }
+
+        self.drain_audio_recording();
+    }
+
+    /// Advances the emulation by a single frame, then pauses.
+    ///
+    /// The frame step is interrupted if a breakpoint is hit before the frame is complete.
+    pub fn step_frame(&mut self) {
+        self.trace_event = None;
+
+        let res = self.gameboy.run_frame();
+        self.pause();
+        self.handle_result(res);
+    }
+
+    /// Pauses the emulator if an event happened during the last step.
+    fn handle_result(&mut self, res: Result<(), dbg::TraceEvent>) {
         if let Err(ref evt) = res {
             tracing::error!(%evt, "Trace event occurred");
             self.trace_event = Some(*evt);
@@ -67,15 +307,210 @@ impl Emulator {
         };
     }
 
+    /// Starts recording the audio output to a WAV file at `path`.
+    ///
+    /// An error is returned if no audio channel is configured or the file cannot be created.
+    pub fn start_audio_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        if self.sample_rate <= 0.0 {
+            return Err(anyhow!("no audio channel configured"));
+        }
+
+        self.stop_audio_recording()?;
+
+        let wav = WavWriter::new(
+            BufWriter::new(File::create(path)?),
+            self.sample_rate as u32,
+            1,
+        )?;
+
+        let (tap, samples) = channel::unbounded();
+        self.gameboy.set_audio_tap(Some(tap));
+        self.audio_recording = Some(AudioRecording { samples, wav });
+
+        Ok(())
+    }
+
+    /// Stops the current audio recording, if any, finalizing the WAV file.
+    pub fn stop_audio_recording(&mut self) -> Result<(), Error> {
+        self.gameboy.set_audio_tap(None);
+        self.drain_audio_recording();
+
+        if let Some(recording) = self.audio_recording.take() {
+            recording.wav.finalize()?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the audio output is being recorded.
+    pub fn is_recording_audio(&self) -> bool {
+        self.audio_recording.is_some()
+    }
+
+    /// Starts recording the screen to an animated GIF at `path`.
+    ///
+    /// Frames are written to the file as they are captured.
+    pub fn start_video_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.stop_video_recording()?;
+
+        let file = BufWriter::new(File::create(path.as_ref())?);
+        self.video_recording = Some(VideoRecording {
+            path: path.as_ref().to_path_buf(),
+            recorder: VideoRecorder::new(file, self.gameboy.palette(), VIDEO_DECIMATION)?,
+        });
+
+        Ok(())
+    }
+
+    /// Stops the current screen recording, if any, and finalizes the file.
+    ///
+    /// An error is returned if no frame has been recorded, in which case the file
+    /// is removed, or if the file cannot be written.
+    pub fn stop_video_recording(&mut self) -> Result<(), Error> {
+        if let Some(recording) = self.video_recording.take() {
+            let frames = recording.recorder.frame_count();
+            recording.recorder.finish()?.flush()?;
+
+            if frames == 0 {
+                fs::remove_file(recording.path)?;
+                return Err(anyhow!("no frames recorded"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the screen is being recorded.
+    pub fn is_recording_video(&self) -> bool {
+        self.video_recording.is_some()
+    }
+
+    /// Adds the last rendered frame to the current screen recording.
+    fn capture_frame(&mut self) {
+        if let Some(ref mut recording) = self.video_recording {
+            if let Err(e) = recording.recorder.push_frame(self.gameboy.present()) {
+                tracing::error!(%e, "Screen recording aborted");
+                self.video_recording = None;
+            }
+        }
+    }
+
+    /// Writes the samples received so far to the current recording.
+    fn drain_audio_recording(&mut self) {
+        if let Some(ref mut recording) = self.audio_recording {
+            let res = recording
+                .samples
+                .try_iter()
+                .try_for_each(|sample| recording.wav.write_sample(sample));
+
+            if let Err(err) = res {
+                tracing::error!(%err, "Audio recording failed");
+                self.gameboy.set_audio_tap(None);
+                self.audio_recording = None;
+            }
+        }
+    }
+
+    /// Updates the performance counters once every [`PERF_INTERVAL`].
+    fn update_performance(&mut self) {
+        let elapsed = self.perf_start.elapsed();
+        if elapsed < PERF_INTERVAL {
+            return;
+        }
+
+        let counters = (
+            self.gameboy.frame_count(),
+            self.gameboy.instructions_executed(),
+        );
+
+        // Counters can go back in time after a reset or a rewind
+        let secs = elapsed.as_secs_f32();
+        self.perf = Performance {
+            fps: counters.0.saturating_sub(self.perf_counters.0) as f32 / secs,
+            ips: counters.1.saturating_sub(self.perf_counters.1) as f32 / secs,
+        };
+
+        self.perf_start = Instant::now();
+        self.perf_counters = counters;
+    }
+
+    /// Returns the emulation performance, updated once per second.
+    pub fn performance(&self) -> Performance {
+        self.perf
+    }
+
+    /// Takes a rewind point every `rewind_interval` frames.
+    fn record_frame(&mut self) {
+        self.frames_since_rewind_point += 1;
+
+        if self.frames_since_rewind_point >= self.rewind_interval {
+            self.push_rewind_point();
+        }
+    }
+
+    /// Records the current state as a rewind point.
+    ///
+    /// Once the rewind buffer is full, the oldest rewind points are discarded.
+    pub fn push_rewind_point(&mut self) {
+        self.frames_since_rewind_point = 0;
+        self.rewind.push(self.gameboy.snapshot());
+    }
+
+    /// Restores the most recent rewind point, returning `false` if there is none left.
+    pub fn rewind_step(&mut self) -> bool {
+        match self.rewind.pop() {
+            Some(snapshot) => {
+                self.gameboy.restore(&snapshot);
+                self.frames_since_rewind_point = 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets how many frames elapse between two consecutive rewind points.
+    pub fn set_rewind_interval(&mut self, frames: u32) {
+        self.rewind_interval = frames.max(1);
+    }
+
+    pub fn rewind_interval(&self) -> u32 {
+        self.rewind_interval
+    }
+
+    /// Suspends emulation while rewinding, so that no new rewind points are recorded.
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+    }
+
     /// Configures the emulator's audio channel.
     pub fn configure_audio_channel(&mut self, source: AudioSource, sample_rate: f32) {
+        // Samples tapped from the old source must be saved before it's replaced
+        self.drain_audio_recording();
+
         self.gameboy.configure_audio_channel(source, sample_rate);
+        self.sample_rate = sample_rate;
+        self.apply_speed();
+
+        // The new source has no tap, so hand a new one to the recording in progress
+        if let Some(ref mut recording) = self.audio_recording {
+            let (tap, samples) = channel::unbounded();
+            self.gameboy.set_audio_tap(Some(tap));
+            recording.samples = samples;
+        }
     }
 
     pub fn last_event(&self) -> &Option<dbg::TraceEvent> {
         &self.trace_event
     }
 
+    /// Requests the disassembly view to scroll to `addr`.
+    pub fn show_in_disassembly(&mut self, addr: u16) {
+        self.disasm_target = Some(addr);
+    }
+
+    /// Returns the address the disassembly view should scroll to, if any was requested.
+    pub fn take_disassembly_target(&mut self) -> Option<u16> {
+        self.disasm_target.take()
+    }
+
     pub fn set_single_step(&mut self) {
         self.step_to_next = true;
     }
@@ -87,9 +522,77 @@ impl Emulator {
     /// Sets or resets turbo mode.
     ///
     /// In turbo mode, the emulator runs to video-sync rather than audio-sync,
-    /// likely dropping audio samples.
+    /// without producing audio samples. This overrides the speed set with [`Emulator::set_speed`].
     pub fn set_turbo(&mut self, turbo: bool) {
-        self.gameboy.enable_audio_sync(!turbo);
+        self.turbo_mode = turbo;
+        self.gameboy.set_audio_fast_forward(turbo);
+        self.apply_speed();
+    }
+
+    /// Sets the emulation speed as a multiple of the original hardware speed.
+    ///
+    /// Finite speeds are achieved by pacing frames against real time, see
+    /// [`Emulator::pacing_delay`], and scaling down the APU sample rate accordingly.
+    /// An infinite speed disables pacing and audio-sync altogether.
+    /// Speeds below the minimum supported one, as well as NaN, are clamped to it.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(MIN_SPEED);
+        self.apply_speed();
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Returns the effective emulation speed, which is infinite in turbo mode.
+    fn effective_speed(&self) -> f32 {
+        if self.turbo_mode {
+            f32::INFINITY
+        } else {
+            self.speed
+        }
+    }
+
+    /// Schedules the end of the next frame, one frame time at the current speed from now.
+    ///
+    /// If the emulation fell too far behind, eg. after being paused, the schedule restarts
+    /// from the current time rather than running faster to catch up.
+    fn pace_frame(&mut self) {
+        let now = Instant::now();
+        let speed = f64::from(self.effective_speed());
+        let frame_time = FRAME_CYCLES / self.gameboy.clock_rate() as f64 / speed;
+
+        let earliest = now.checked_sub(MAX_PACING_LAG).unwrap_or(now);
+        self.frame_deadline =
+            self.frame_deadline.max(earliest) + Duration::from_secs_f64(frame_time);
+    }
+
+    /// Returns how long the emulation should wait to keep in sync with real time.
+    ///
+    /// Emulation steps run ahead of real time up to the end of the current frame, after which
+    /// the emulation thread sleeps for the returned duration. No pacing happens in turbo mode.
+    pub fn pacing_delay(&self) -> Duration {
+        if self.effective_speed().is_finite() {
+            self.frame_deadline
+                .saturating_duration_since(Instant::now())
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn apply_speed(&mut self) {
+        let speed = self.effective_speed();
+
+        // The emulation is paced against real time, but in case the playback clock runs slower,
+        // audio-sync keeps the channel from overflowing
+        self.gameboy.enable_audio_sync(speed.is_finite());
+
+        // Compensate for drift between emulated and playback clocks
+        if speed.is_finite() && self.sample_rate > 0.0 {
+            let fill = self.gameboy.audio_fill_level().unwrap_or(0.5);
+            let rate = self.rate_control.adjust(self.sample_rate, fill);
+            self.gameboy.set_sample_rate(rate / speed);
+        }
     }
 
     pub fn paused(&mut self) -> bool {
@@ -99,6 +602,14 @@ impl Emulator {
     /// Reset the emulator's sate.
     pub fn reset(&mut self) {
         self.gameboy.reset();
+        self.rewind.clear();
+        self.set_running();
+    }
+
+    /// Reset the emulator's state, keeping the contents of the cartridge RAM.
+    pub fn reset_preserving_ram(&mut self) {
+        self.gameboy.reset_preserving_ram();
+        self.rewind.clear();
         self.set_running();
     }
 
@@ -122,3 +633,152 @@ impl Emulator {
         self.gameboy.bus()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an emulator running `program` from the cartridge entry point (0x0100).
+    fn with_program(program: &[u8]) -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+
+        let mut emu = Emulator::default();
+        emu.gameboy_mut().load_rom(&rom).unwrap();
+        emu.reset();
+        emu
+    }
+
+    #[test]
+    fn batched_steps() {
+        // INC A; JR -3
+        let program = [0x3C, 0x18, 0xFD];
+
+        let mut batched = with_program(&program);
+        let mut single = with_program(&program);
+
+        batched.do_steps(100);
+        for _ in 0..100 {
+            single.do_step();
+        }
+
+        assert_eq!(batched.gameboy().instructions_executed(), 100);
+        assert_eq!(
+            batched.gameboy().clock_cycles(),
+            single.gameboy().clock_cycles()
+        );
+        assert_eq!(batched.cpu().pc, single.cpu().pc);
+        assert_eq!(batched.cpu().af, single.cpu().af);
+    }
+
+    #[test]
+    fn rewind_points() {
+        // INC A; JR -3; ...
+        let mut emu = with_program(&[0x3C, 0x18, 0xFD]);
+
+        let mut states = Vec::new();
+        for _ in 0..3 {
+            emu.push_rewind_point();
+            states.push((emu.cpu().pc, emu.cpu().a()));
+            emu.do_steps(3);
+        }
+        assert_eq!(emu.cpu().pc, 0x0101);
+        assert_eq!(emu.cpu().a(), states[0].1.wrapping_add(5));
+
+        // Rewind points are restored from the most recent one
+        for &(pc, a) in states.iter().rev() {
+            assert!(emu.rewind_step());
+            assert_eq!((emu.cpu().pc, emu.cpu().a()), (pc, a));
+        }
+        assert!(!emu.rewind_step());
+        assert_eq!(emu.cpu().pc, 0x0100);
+    }
+
+    #[test]
+    fn speed_clamping() {
+        let mut emu = Emulator::default();
+
+        emu.set_speed(0.0);
+        assert_eq!(emu.speed(), MIN_SPEED);
+
+        emu.set_speed(f32::NAN);
+        assert_eq!(emu.speed(), MIN_SPEED);
+
+        emu.set_speed(f32::INFINITY);
+        assert_eq!(emu.speed(), f32::INFINITY);
+    }
+
+    #[test]
+    fn step_frame() {
+        let mut emu = with_program(&[
+            0x3E, 0x00, // LD A, 0x00
+            0x3C, // INC A
+            0xFE, 0xFF, // CP 0xFF
+            0x20, 0xFB, // JR NZ, -5
+            0x00, // NOP
+            0x18, 0xFE, // JR -2
+        ]);
+        emu.cpu_mut().set_breakpoint(0x0107);
+
+        // The loop ends well before the first frame is complete
+        emu.step_frame();
+        assert_eq!(emu.gameboy().frame_count(), 0);
+        assert!(matches!(
+            emu.last_event(),
+            Some(dbg::TraceEvent::Breakpoint(0x0107))
+        ));
+        assert!(emu.paused());
+
+        emu.step_frame();
+        assert_eq!(emu.gameboy().frame_count(), 1);
+        assert!(emu.last_event().is_none());
+
+        emu.step_frame();
+        assert_eq!(emu.gameboy().frame_count(), 2);
+    }
+
+    #[test]
+    fn symbols_follow_rom() {
+        let dir = std::env::temp_dir().join("gib-symbols-follow-rom");
+        fs::create_dir_all(&dir).unwrap();
+
+        let rom = dir.join("game.gb");
+        fs::write(&rom, vec![0; 0x8000]).unwrap();
+        fs::write(dir.join("game.sym"), "00:0150 PrintString\n").unwrap();
+
+        let mut emu = Emulator::default();
+        emu.load_rom(&rom).unwrap();
+        assert_eq!(emu.gameboy().symbols().label(0x0150), Some("PrintString"));
+        assert!(emu.take_symbols_changed());
+
+        // An unreadable symbol file does not prevent loading the ROM,
+        // and the previous symbols are gone anyway
+        fs::write(dir.join("game.sym"), [0xFF, 0xFE]).unwrap();
+        emu.load_rom(&rom).unwrap();
+        assert!(emu.gameboy().symbols().is_empty());
+        assert!(emu.take_symbols_changed());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rom_path_after_load() {
+        let dir = std::env::temp_dir().join("gib-rom-path-after-load");
+        fs::create_dir_all(&dir).unwrap();
+
+        let rom = dir.join("game.gb");
+        fs::write(&rom, vec![0; 0x8000]).unwrap();
+
+        let mut emu = Emulator::default();
+        assert_eq!(emu.rom_path(), None);
+
+        emu.load_rom(&rom).unwrap();
+        assert_eq!(emu.rom_path(), Some(rom.as_path()));
+
+        // Failed loads keep the previous ROM
+        assert!(emu.load_rom(dir.join("missing.gb")).is_err());
+        assert_eq!(emu.rom_path(), Some(rom.as_path()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
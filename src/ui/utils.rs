@@ -34,3 +34,14 @@ pub fn format_ascii(data: &[u8]) -> String {
 pub fn hexify(n: impl Into<u16>) -> String {
     format!("{:04X}", n.into())
 }
+
+/// Parses an hexadecimal address, optionally prefixed by `0x`.
+pub fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+
+    u16::from_str_radix(s, 16).ok()
+}
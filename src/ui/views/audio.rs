@@ -0,0 +1,133 @@
+use egui::{Color32, Pos2, Sense, Stroke, Vec2};
+
+use crate::ui::state::Emulator;
+
+/// Width of the waveform plots, in points
+const PLOT_WIDTH: f32 = 256.;
+
+/// Channel names, in the order they are displayed
+const CHANNELS: [&str; 4] = [
+    "Sweep Channel",
+    "Tone Channel",
+    "Wave Channel",
+    "Noise Channel",
+];
+
+/// Window plotting the recent output level of each sound channel.
+///
+/// The levels are captured by the emulator at a fixed rate while the window is open.
+#[derive(Default)]
+pub struct AudioScope;
+
+impl super::Window for AudioScope {
+    fn name(&self) -> &'static str {
+        "Audio Scope"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        if state.bus().apu.level_history().is_some() != *open {
+            state.gameboy_mut().set_sound_level_capture(*open);
+        }
+
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([915.0, 400.0])
+            .resizable(false)
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for AudioScope {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        let apu = &state.bus().apu;
+        let status = [
+            (apu.ch1.enabled(), apu.ch1.dac_on()),
+            (apu.ch2.enabled(), apu.ch2.dac_on()),
+            (apu.ch3.enabled(), apu.ch3.dac_on()),
+            (apu.ch4.enabled(), apu.ch4.dac_on()),
+        ];
+
+        for (idx, name) in CHANNELS.into_iter().enumerate() {
+            let (enabled, dac_on) = status[idx];
+
+            ui.horizontal(|ui| {
+                ui.label(name);
+
+                ui.add_space(10.);
+                ui.colored_label(
+                    if enabled {
+                        Color32::GREEN
+                    } else {
+                        Color32::DARK_GREEN
+                    },
+                    "ENABLED",
+                );
+                ui.colored_label(
+                    if dac_on {
+                        Color32::GREEN
+                    } else {
+                        Color32::DARK_GREEN
+                    },
+                    "DAC",
+                );
+
+                let apu = &state.bus().apu;
+                let mut muted = apu.channel_muted(idx);
+                let mut solo = (0..4).all(|i| apu.channel_muted(i) == (i != idx));
+
+                if ui.checkbox(&mut muted, "Mute").changed() {
+                    state.gameboy_mut().set_sound_channel_muted(idx, muted);
+                }
+
+                // Leaving solo mode unmutes all the channels
+                if ui.checkbox(&mut solo, "Solo").changed() {
+                    if solo {
+                        state.gameboy_mut().solo_sound_channel(idx);
+                    } else {
+                        for i in 0..4 {
+                            state.gameboy_mut().set_sound_channel_muted(i, false);
+                        }
+                    }
+                }
+            });
+
+            self.waveform_ui(ui, state, idx);
+
+            ui.add_space(4.);
+        }
+    }
+}
+
+impl AudioScope {
+    fn waveform_ui(&self, ui: &mut egui::Ui, state: &Emulator, idx: usize) {
+        let size = Vec2::new(PLOT_WIDTH, 40.);
+        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0., Color32::BLACK);
+
+        let history = match state.bus().apu.level_history() {
+            Some(history) if history.len() > 1 => history,
+            _ => return,
+        };
+
+        // Channel outputs range from -15 to 15
+        let step = PLOT_WIDTH / (history.len() - 1) as f32;
+        let points = history
+            .iter()
+            .enumerate()
+            .map(|(x, levels)| {
+                let y = f32::from(levels[idx].clamp(-15, 15)) / 15.;
+                Pos2::new(
+                    rect.left() + x as f32 * step,
+                    rect.center().y - y * rect.height() / 2.,
+                )
+            })
+            .collect();
+
+        painter.add(egui::Shape::line(points, Stroke::new(1., Color32::GREEN)));
+    }
+}
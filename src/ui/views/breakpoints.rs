@@ -0,0 +1,106 @@
+use egui::{Color32, RichText};
+
+use crate::ui::{command::Command, state::Emulator, utils};
+
+/// Window listing the breakpoints, allowing to add, remove, enable and disable them.
+#[derive(Default)]
+pub struct Breakpoints {
+    new_addr: String,
+}
+
+impl super::Window for Breakpoints {
+    fn name(&self) -> &'static str {
+        "Breakpoints"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([660.0, 350.0])
+            .default_size([220.0, 200.0])
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for Breakpoints {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        let mut commands = Vec::new();
+        let mut selected = None;
+
+        ui.horizontal(|ui| {
+            let submitted = utils::address_edit_ui(ui, "Address", &mut self.new_addr, true);
+
+            if ui.button("Add").clicked() || submitted {
+                if let Some(addr) = utils::parse_addr(&self.new_addr) {
+                    commands.push(Command::SetBreakpoint(addr));
+                    self.new_addr.clear();
+                }
+            }
+        });
+
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height())
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                let symbols = state.gameboy().symbols();
+
+                let mut breakpoints = state
+                    .cpu()
+                    .breakpoints()
+                    .iter()
+                    .map(|(&addr, bp)| (addr, *bp))
+                    .collect::<Vec<_>>();
+                breakpoints.sort_unstable_by_key(|&(addr, _)| addr);
+
+                for (addr, bp) in breakpoints {
+                    let mut enabled = bp.enabled;
+
+                    ui.horizontal(|ui| {
+                        if ui.checkbox(&mut enabled, "").changed() {
+                            commands.push(Command::SetBreakpointEnabled(addr, enabled));
+                        }
+
+                        let mut text = match symbols.label(addr) {
+                            Some(label) => format!("{addr:04X}  {label}"),
+                            None => format!("{addr:04X}"),
+                        };
+                        if let Some(cond) = bp.condition {
+                            text.push_str(&format!("  if {cond}"));
+                        }
+                        if bp.one_shot {
+                            text.push_str("  (once)");
+                        }
+                        let color = if enabled {
+                            Color32::WHITE
+                        } else {
+                            Color32::DARK_GRAY
+                        };
+
+                        if ui
+                            .selectable_label(false, RichText::new(text).color(color))
+                            .clicked()
+                        {
+                            selected = Some(addr);
+                        }
+
+                        if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                            commands.push(Command::ClearBreakpoint(addr));
+                        }
+                    });
+                }
+            });
+
+        for cmd in commands {
+            state.execute(cmd).ok();
+        }
+
+        if let Some(addr) = selected {
+            state.show_in_disassembly(addr);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::cmp::Reverse;
+
+use egui::{Color32, RichText};
+use gib_core::cpu::{CallFrame, Immediate};
+
+use crate::ui::state::Emulator;
+
+/// Window listing the active call frames, innermost first.
+#[derive(Default)]
+pub struct CallStack;
+
+impl super::Window for CallStack {
+    fn name(&self) -> &'static str {
+        "Call Stack"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([660.0, 30.0])
+            .default_size([220.0, 300.0])
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for CallStack {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        let mut selected = None;
+
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height())
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                let frames = state.cpu().call_stack();
+
+                for (i, &frame) in frames.iter().rev().enumerate() {
+                    let (addr, instr) = match frame {
+                        // The outermost frame is the entry point, which has no call site
+                        CallFrame::Call(ret) if i + 1 == frames.len() => {
+                            (ret, instruction_at(state, ret))
+                        }
+                        CallFrame::Call(ret) => {
+                            let addr = call_site(state, ret).unwrap_or(ret);
+                            (addr, instruction_at(state, addr))
+                        }
+                        CallFrame::Interrupt { vector, ret } => {
+                            (ret, format!("<interrupt {vector:04X}>"))
+                        }
+                    };
+
+                    let color = if i == 0 {
+                        Color32::WHITE
+                    } else {
+                        Color32::DARK_GRAY
+                    };
+
+                    let text = RichText::new(format!("{addr:04X}:  {instr}")).color(color);
+                    if ui.selectable_label(false, text).clicked() {
+                        selected = Some(addr);
+                    }
+                }
+            });
+
+        if let Some(addr) = selected {
+            state.show_in_disassembly(addr);
+        }
+    }
+}
+
+/// Window listing the cycles spent in each function, as measured by the call profiler.
+#[derive(Default)]
+pub struct CallProfiler;
+
+impl super::Window for CallProfiler {
+    fn name(&self) -> &'static str {
+        "Call Profiler"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([660.0, 350.0])
+            .default_size([320.0, 300.0])
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for CallProfiler {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        let mut selected = None;
+
+        ui.horizontal(|ui| {
+            let mut enabled = state.cpu().call_profiling();
+            if ui.checkbox(&mut enabled, "Enabled").changed() {
+                state.cpu_mut().set_call_profiling(enabled);
+            }
+
+            if ui.button("Reset").clicked() {
+                state.cpu().reset_call_profile();
+            }
+        });
+
+        ui.separator();
+
+        let mut profile = state.cpu().call_profile();
+        profile.sort_by_key(|&(addr, prof)| (Reverse(prof.inclusive), addr));
+
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height())
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                let symbols = state.gameboy().symbols();
+
+                egui::Grid::new("call-profiler")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Function");
+                        ui.label("Calls");
+                        ui.label("Inclusive");
+                        ui.label("Exclusive");
+                        ui.end_row();
+
+                        for (addr, prof) in profile {
+                            let name = match symbols.label(addr) {
+                                Some(label) => format!("{addr:04X}  {label}"),
+                                None => format!("{addr:04X}"),
+                            };
+
+                            if ui.selectable_label(false, name).clicked() {
+                                selected = Some(addr);
+                            }
+                            ui.monospace(format!("{:8}", prof.calls));
+                            ui.monospace(format!("{:12}", prof.inclusive));
+                            ui.monospace(format!("{:12}", prof.exclusive));
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if let Some(addr) = selected {
+            state.show_in_disassembly(addr);
+        }
+    }
+}
+
+/// Returns the disassembly of the instruction at `addr`, using labels for branch targets.
+fn instruction_at(state: &Emulator, addr: u16) -> String {
+    let symbols = state.gameboy().symbols();
+
+    match state.cpu().disasm(state.bus(), addr) {
+        Ok(instr) => instr
+            .labeled_mnemonic(addr, symbols)
+            .unwrap_or_else(|| match instr.imm {
+                Some(Immediate::Imm8(d8)) => format!("{} ({:02X})", instr.mnemonic, d8),
+                Some(Immediate::Imm16(d16)) => format!("{} ({:04X})", instr.mnemonic, d16),
+                None => instr.mnemonic.to_string(),
+            }),
+        Err(_) => String::from("??"),
+    }
+}
+
+/// Returns the address of the CALL or RST instruction preceding the return address `ret`, if any.
+fn call_site(state: &Emulator, ret: u16) -> Option<u16> {
+    let code = state.gameboy().read_range(ret.wrapping_sub(3), 3);
+
+    match code[..] {
+        [0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC, _, _] => Some(ret.wrapping_sub(3)),
+        [_, _, op] if op & 0xC7 == 0xC7 => Some(ret.wrapping_sub(1)),
+        _ => None,
+    }
+}
@@ -0,0 +1,96 @@
+use egui::Color32;
+
+use crate::ui::state::Emulator;
+
+/// Window showing the metadata stored in the cartridge header.
+#[derive(Default)]
+pub struct CartridgeInfo;
+
+impl super::Window for CartridgeInfo {
+    fn name(&self) -> &'static str {
+        "Cartridge Info"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([1235.0, 450.0])
+            .resizable(false)
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for CartridgeInfo {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        let header = state.gameboy().cartridge_header();
+        let validation = state.gameboy().validate_cartridge();
+
+        let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "Unknown".to_string());
+
+        egui::Grid::new("cartridge-header")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Title");
+                ui.label(&header.title);
+                ui.end_row();
+
+                ui.label("Licensee");
+                ui.label(&header.licensee);
+                ui.end_row();
+
+                ui.label("Type");
+                ui.label(format!(
+                    "{:02X} ({})",
+                    header.cartridge_type,
+                    or_unknown(header.mbc_type.map(|mbc| format!("{mbc:?}")))
+                ));
+                ui.end_row();
+
+                ui.label("ROM size");
+                ui.label(or_unknown(
+                    header.rom_banks.map(|n| format!("{} KB", n * 16)),
+                ));
+                ui.end_row();
+
+                ui.label("RAM size");
+                ui.label(or_unknown(
+                    header.ram_banks.map(|n| format!("{} KB", n * 8)),
+                ));
+                ui.end_row();
+
+                ui.label("CGB mode");
+                ui.label(format!("{:?}", header.cgb_mode));
+                ui.end_row();
+
+                ui.label("SGB support");
+                ui.label(if header.sgb_support { "Yes" } else { "No" });
+                ui.end_row();
+
+                ui.label("Header checksum");
+                if header.header_checksum_valid {
+                    ui.colored_label(
+                        Color32::GREEN,
+                        format!("{:02X} (OK)", header.header_checksum),
+                    );
+                } else {
+                    ui.colored_label(
+                        Color32::RED,
+                        format!("{:02X} (BAD)", header.header_checksum),
+                    );
+                }
+                ui.end_row();
+
+                ui.label("Nintendo logo");
+                if validation.logo_valid {
+                    ui.colored_label(Color32::GREEN, "OK");
+                } else {
+                    ui.colored_label(Color32::RED, "BAD");
+                }
+                ui.end_row();
+            });
+    }
+}
@@ -1,10 +1,25 @@
 use egui::Color32;
+use gib_core::cpu::Cpu;
 
 use crate::ui::{state::Emulator, utils};
 
+type RegisterSetter = fn(&mut Cpu, u16);
+
+/// Register names, along with their setters, in the order they are displayed.
+const REGISTERS: [(&str, RegisterSetter); 6] = [
+    ("AF", Cpu::set_af),
+    ("BC", Cpu::set_bc),
+    ("DE", Cpu::set_de),
+    ("HL", Cpu::set_hl),
+    ("SP", Cpu::set_sp),
+    ("PC", Cpu::set_pc),
+];
+
 #[derive(Default)]
 pub struct Debugger {
     registers: [String; 6],
+    // Register values last copied to the edit buffers
+    shown: [Option<u16>; 6],
 }
 
 impl super::Window for Debugger {
@@ -25,25 +40,16 @@ impl super::Window for Debugger {
 
 impl super::View for Debugger {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
-        egui::Grid::new("debugger-grid")
-            .num_columns(2)
-            .show(ui, |ui| {
-                ui.vertical(|ui| {
-                    self.left_column_ui(ui, state);
-                });
-                ui.vertical(|ui| {
-                    self.call_stack_ui(ui, state);
-                });
-            });
-    }
-}
-
-impl Debugger {
-    fn left_column_ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
         self.cpu_state_ui(ui, state);
 
         ui.separator();
 
+        self.controls_ui(ui, state);
+    }
+}
+
+impl Debugger {
+    fn controls_ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
         ui.horizontal(|ui| {
             if ui.button("Run").clicked() {
                 state.set_running();
@@ -54,6 +60,9 @@ impl Debugger {
             if ui.button("Step").clicked() {
                 state.set_single_step();
             }
+            if ui.button("Frame step").clicked() {
+                state.step_frame();
+            }
         });
 
         ui.separator();
@@ -65,7 +74,10 @@ impl Debugger {
         }
     }
 
-    fn cpu_state_ui(&mut self, ui: &mut egui::Ui, state: &Emulator) {
+    fn cpu_state_ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        // Registers can only be edited while the emulation is paused
+        let editable = state.paused();
+
         let cpu = state.cpu();
 
         ui.horizontal(|ui| {
@@ -97,55 +109,74 @@ impl Debugger {
 
         ui.separator();
 
-        // Update register state
-        for (reg, buf) in [cpu.af, cpu.bc, cpu.de, cpu.hl, cpu.sp, cpu.pc]
+        // Update register state, unless the user might be editing it
+        for (i, reg) in [cpu.af, cpu.bc, cpu.de, cpu.hl, cpu.sp, cpu.pc]
             .into_iter()
-            .zip(self.registers.iter_mut())
+            .enumerate()
         {
-            *buf = format!("{reg:04X}");
+            if !editable || self.shown[i] != Some(reg) {
+                self.registers[i] = format!("{reg:04X}");
+                self.shown[i] = Some(reg);
+            }
         }
 
+        let mut edited = None;
+
         egui::Grid::new("debugger-registers")
             .num_columns(3)
             .spacing([5., 2.])
             .min_col_width(70.)
             .show(ui, |ui| {
-                utils::address_edit_ui(ui, "AF", &mut self.registers[0], false);
-                utils::address_edit_ui(ui, "BC", &mut self.registers[1], false);
-                utils::address_edit_ui(ui, "DE", &mut self.registers[2], false);
-                ui.end_row();
-                utils::address_edit_ui(ui, "HL", &mut self.registers[3], false);
-                utils::address_edit_ui(ui, "SP", &mut self.registers[4], false);
-                utils::address_edit_ui(ui, "PC", &mut self.registers[5], false);
+                for (i, (name, _)) in REGISTERS.iter().enumerate() {
+                    if utils::address_edit_ui(ui, name, &mut self.registers[i], editable) {
+                        edited = Some(i);
+                    }
+                    if i == 2 {
+                        ui.end_row();
+                    }
+                }
             });
 
-        ui.label(format!(
-            "Flags: {} {} {} {}",
-            if cpu.zf() { 'Z' } else { '-' },
-            if cpu.sf() { 'N' } else { '-' },
-            if cpu.hc() { 'H' } else { '-' },
-            if cpu.cy() { 'C' } else { '-' },
-        ));
-    }
+        let mut flags = [cpu.zf(), cpu.sf(), cpu.hc(), cpu.cy()];
+
+        let flags_changed = if editable {
+            ui.horizontal(|ui| {
+                ui.label("Flags:");
+                ["Z", "N", "H", "C"]
+                    .into_iter()
+                    .zip(flags.iter_mut())
+                    .fold(false, |changed, (name, flag)| {
+                        ui.checkbox(flag, name).changed() || changed
+                    })
+            })
+            .inner
+        } else {
+            ui.label(format!(
+                "Flags: {} {} {} {}",
+                if cpu.zf() { 'Z' } else { '-' },
+                if cpu.sf() { 'N' } else { '-' },
+                if cpu.hc() { 'H' } else { '-' },
+                if cpu.cy() { 'C' } else { '-' },
+            ));
+            false
+        };
 
-    fn call_stack_ui(&mut self, ui: &mut egui::Ui, state: &Emulator) {
-        egui::CollapsingHeader::new("Call stack")
-            .default_open(true)
-            .show(ui, |ui| {
-                egui::ScrollArea::vertical()
-                    .max_height(ui.available_height())
-                    .auto_shrink([false; 2])
-                    .show(ui, |ui| {
-                        for (i, addr) in state.cpu().call_stack.iter().rev().enumerate() {
-                            let c = if i == 0 {
-                                Color32::WHITE
-                            } else {
-                                Color32::DARK_GRAY
-                            };
-
-                            ui.colored_label(c, format!("0x{addr:04X}"));
-                        }
-                    });
-            });
+        // Write back any edited value. Invalid values restore the current register content.
+        if let Some(i) = edited {
+            let (_, set) = REGISTERS[i];
+
+            if let Ok(val) = u16::from_str_radix(self.registers[i].trim(), 16) {
+                set(state.cpu_mut(), val);
+            }
+            self.shown[i] = None;
+        }
+
+        if flags_changed {
+            let cpu = state.cpu_mut();
+            cpu.set_zf(flags[0]);
+            cpu.set_sf(flags[1]);
+            cpu.set_hc(flags[2]);
+            cpu.set_cy(flags[3]);
+        }
     }
 }
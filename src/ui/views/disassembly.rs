@@ -1,14 +1,19 @@
-use std::{cmp::Ordering, collections::BTreeMap};
+use std::{cmp::Ordering, collections::BTreeMap, fs};
 
 use egui::{Color32, RichText};
-use gib_core::{cpu::Immediate, dbg};
+use gib_core::{
+    cpu::{self, Immediate},
+    dbg,
+};
 
-use crate::ui::{state::Emulator, utils};
+use crate::ui::{command::Command, state::Emulator, utils};
 
 pub struct Disassembly {
     section: dbg::MemoryType,
     disasm: BTreeMap<u16, String>,
     follow_pc: bool,
+    // PC the listing was last recentered on while following execution
+    followed_pc: Option<u16>,
     goto_addr: String,
     scroll_offset: f32,
 }
@@ -19,6 +24,7 @@ impl Default for Disassembly {
             section: dbg::MemoryType::RomBank(0),
             disasm: BTreeMap::new(),
             follow_pc: false,
+            followed_pc: None,
             goto_addr: String::new(),
             scroll_offset: 0.0,
         }
@@ -44,6 +50,11 @@ impl super::Window for Disassembly {
 
 impl super::View for Disassembly {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        // Labels are baked into the disassembly, so it must be rebuilt when they change
+        if state.take_symbols_changed() {
+            self.disasm.clear();
+        }
+
         // Most of the times this call does nothing, so it's cool to have it called every frame
         self.realign_disasm(state, state.cpu().pc);
 
@@ -67,19 +78,17 @@ impl Disassembly {
         let cpu = state.cpu();
         let bus = state.bus();
 
-        if self.disasm.contains_key(&from) {
+        // Reload the disassembly when moving to another section, or when its bank is switched
+        let section = state.gameboy().memory_type_at(from);
+        if section != self.section {
+            self.section = section;
+            self.disasm.clear();
+            from = *section.range().start();
+        } else if self.disasm.contains_key(&from) {
             return;
         }
 
-        let mut mem_range = self.section.range();
-
-        if !mem_range.contains(&from) {
-            self.section = dbg::MemoryType::at(from);
-            self.disasm.clear();
-
-            mem_range = self.section.range();
-            from = *mem_range.start();
-        }
+        let mem_range = self.section.range();
 
         while from < *mem_range.end() {
             let instr = match cpu.disasm(bus, from) {
@@ -89,13 +98,15 @@ impl Disassembly {
 
             let next = from + u16::from(instr.size);
 
-            if self.disasm.get(&from).is_some() {
+            if self.disasm.contains_key(&from) {
                 break;
             }
             for addr in from..next {
                 self.disasm.remove(&addr);
             }
 
+            let symbols = state.gameboy().symbols();
+
             self.disasm.insert(
                 from,
                 format!(
@@ -107,27 +118,47 @@ impl Disassembly {
                         Some(Immediate::Imm16(d16)) => format!("{:04X}", d16),
                         None => String::new(),
                     },
-                    instr.mnemonic
+                    instr
+                        .labeled_mnemonic(from, symbols)
+                        .unwrap_or_else(|| instr.mnemonic.to_string())
                 ),
             );
             from = next;
         }
     }
 
-    fn goto_bar_ui(&mut self, ui: &mut egui::Ui, state: &Emulator) -> Option<u16> {
+    fn goto_bar_ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) -> Option<u16> {
         ui.horizontal(|ui| {
             let goto_addr = utils::address_edit_ui(ui, "Address", &mut self.goto_addr, true);
             let goto_addr = ui.button("Goto").clicked() || goto_addr;
 
             let goto_pc = ui.button("Goto PC").clicked();
 
-            ui.checkbox(&mut self.follow_pc, "Follow");
+            if ui
+                .checkbox(&mut self.follow_pc, "Follow PC")
+                .on_hover_text("Keep the current instruction visible while stepping")
+                .changed()
+            {
+                self.followed_pc = None;
+            }
+
+            if ui.button("Export...").clicked() {
+                self.export_disasm(state);
+            }
 
             // Build response
             if goto_addr {
                 u16::from_str_radix(&self.goto_addr, 16).ok()
-            } else if goto_pc || self.follow_pc {
+            } else if let Some(addr) = state.take_disassembly_target() {
+                self.goto_addr = format!("{addr:04X}");
+                Some(addr)
+            } else if goto_pc {
                 Some(state.cpu().pc)
+            } else if self.follow_pc && self.followed_pc != Some(state.cpu().pc) {
+                // Only recenter when PC moves, so that the listing can still be scrolled
+                // while the emulation is paused
+                self.followed_pc = Some(state.cpu().pc);
+                self.followed_pc
             } else {
                 None
             }
@@ -135,6 +166,25 @@ impl Disassembly {
         .inner
     }
 
+    /// Writes the disassembly of the current memory section to a file chosen by the user.
+    fn export_disasm(&self, state: &Emulator) {
+        let dialog = rfd::FileDialog::new().set_file_name("disassembly.txt");
+
+        if let Some(path) = dialog.save_file() {
+            let range = self.section.range();
+            let listing = cpu::disassemble_range(
+                state.bus(),
+                *range.start(),
+                *range.end(),
+                state.gameboy().symbols(),
+            );
+
+            if let Err(e) = fs::write(&path, listing) {
+                tracing::error!(%e, path = %path.display(), "Could not export disassembly");
+            }
+        }
+    }
+
     fn disassembly_ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator, goto_addr: Option<u16>) {
         let pc = state.cpu().pc;
 
@@ -158,8 +208,6 @@ impl Disassembly {
             .auto_shrink([false; 2])
             .always_show_scroll(true)
             .show_rows(ui, row_height, self.disasm.len(), |ui, row_range| {
-                let cpu = state.cpu_mut();
-
                 for (addr, instr) in self
                     .disasm
                     .iter()
@@ -173,18 +221,19 @@ impl Disassembly {
                     };
 
                     // Render breakpoing and instruction
-                    let mut bk = cpu.breakpoint_at(*addr);
+                    let mut bk = state.cpu().breakpoint_at(*addr);
 
                     // Set/unset breakpoint
                     if ui
                         .checkbox(&mut bk, RichText::new(instr).color(color))
                         .changed()
                     {
-                        if bk {
-                            cpu.set_breakpoint(*addr);
+                        let cmd = if bk {
+                            Command::SetBreakpoint(*addr)
                         } else {
-                            cpu.clear_breakpoint(*addr);
-                        }
+                            Command::ClearBreakpoint(*addr)
+                        };
+                        state.execute(cmd).ok();
                     }
                 }
             });
@@ -0,0 +1,54 @@
+use gib_core::io::IrqSource;
+
+use crate::ui::state::Emulator;
+
+/// Window listing the most recently serviced interrupts.
+#[derive(Default)]
+pub struct InterruptLog;
+
+impl super::Window for InterruptLog {
+    fn name(&self) -> &'static str {
+        "Interrupt Log"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([900.0, 30.0])
+            .default_size([260.0, 300.0])
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for InterruptLog {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height())
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("interrupt-log")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for irq in state.gameboy().interrupt_log() {
+                            let source = match irq.source {
+                                IrqSource::VBlank => "VBlank",
+                                IrqSource::LcdStat => "LCD STAT",
+                                IrqSource::Timer => "Timer",
+                                IrqSource::Serial => "Serial",
+                                IrqSource::Joypad => "Joypad",
+                            };
+
+                            ui.monospace(format!("{:12}", irq.cycle));
+                            ui.label(source);
+                            ui.monospace(format!("{:04X}", irq.handler));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use egui::{Event, Key};
+use gib_core::io::JoypadState;
+use parking_lot::Mutex;
+
+use crate::ui::{config::Config, keymap::KeyMap};
+
+/// Joypad buttons in the order they are listed in the window.
+const BUTTONS: [(&str, JoypadState); 8] = [
+    ("Up", JoypadState::UP),
+    ("Down", JoypadState::DOWN),
+    ("Left", JoypadState::LEFT),
+    ("Right", JoypadState::RIGHT),
+    ("B", JoypadState::B),
+    ("A", JoypadState::A),
+    ("Select", JoypadState::SELECT),
+    ("Start", JoypadState::START),
+];
+
+/// Settings window used to rebind the keyboard keys mapped to the joypad.
+pub struct KeyBindings {
+    keymap: Arc<Mutex<KeyMap>>,
+    pending: Option<JoypadState>,
+    error: Option<String>,
+}
+
+impl KeyBindings {
+    pub fn new(keymap: Arc<Mutex<KeyMap>>) -> Self {
+        Self {
+            keymap,
+            pending: None,
+            error: None,
+        }
+    }
+
+    fn bind(&mut self, js: JoypadState, key: Key) {
+        let mut keymap = self.keymap.lock();
+
+        if let Err(other) = keymap.bind(js, key) {
+            let (name, _) = BUTTONS.iter().find(|&&(_, b)| b == other).unwrap();
+            self.error = Some(format!("{} is already bound to {}", key.name(), name));
+            return;
+        }
+
+        self.error = None;
+
+        let keymap = *keymap;
+        if let Err(e) = Config::update(|cfg| cfg.keymap = keymap) {
+            tracing::warn!(%e, "Failed to save key bindings");
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Shows the window. Unlike the debugging windows, it does not need the emulator state.
+    pub fn show(&mut self, ctx: &egui::Context, open: &mut bool) {
+        egui::Window::new("Key Bindings")
+            .resizable(false)
+            .collapsible(false)
+            .open(open)
+            .show(ctx, |ui| self.ui(ui));
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        // Capture the next key press if a button is waiting to be rebound
+        if let Some(js) = self.pending {
+            let key = ui.input(|i| {
+                i.events.iter().find_map(|e| match *e {
+                    Event::Key {
+                        key, pressed: true, ..
+                    } => Some(key),
+                    _ => None,
+                })
+            });
+
+            match key {
+                Some(Key::Escape) => self.pending = None,
+                Some(key) => {
+                    self.pending = None;
+                    self.bind(js, key);
+                }
+                None => (),
+            }
+        }
+
+        let bindings = self.keymap.lock().bindings();
+
+        egui::Grid::new("key_bindings_grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                for (&(name, js), (key, _)) in BUTTONS.iter().zip(bindings) {
+                    ui.label(name);
+
+                    let text = if self.pending == Some(js) {
+                        "Press a key..."
+                    } else {
+                        key.name()
+                    };
+
+                    if ui
+                        .add_sized([120.0, 20.0], egui::Button::new(text))
+                        .clicked()
+                    {
+                        self.pending = Some(js);
+                        self.error = None;
+                    }
+
+                    ui.end_row();
+                }
+            });
+
+        if let Some(ref error) = self.error {
+            ui.separator();
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+}
@@ -1,17 +1,67 @@
 use std::{fmt::Write, ops::Range};
 
-use gib_core::{dbg, mem::MemR};
+use gib_core::dbg::{self, MemoryScan, ScanFilter, ScanWidth};
+
+use crate::ui::{
+    config::{Bookmark, Config},
+    state::Emulator,
+    utils,
+};
+
+/// Base used to display the bytes in the memory dump, and to edit them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum DisplayMode {
+    #[default]
+    Hex,
+    Decimal,
+    Ascii,
+}
+
+impl DisplayMode {
+    /// Formats a byte in this base, padded so that all bytes have the same width.
+    fn format(self, d: u8) -> String {
+        match self {
+            DisplayMode::Hex => format!("{d:02X}"),
+            DisplayMode::Decimal => format!("{d:3}"),
+            DisplayMode::Ascii => format!("{:>2}", &utils::format_ascii(&[d])[1..2]),
+        }
+    }
 
-use crate::ui::{state::Emulator, utils};
+    /// Parses a byte in this base. ASCII input must be a single printable character.
+    fn parse(self, s: &str) -> Option<u8> {
+        match self {
+            DisplayMode::Hex => u8::from_str_radix(s.trim(), 16).ok(),
+            DisplayMode::Decimal => s.trim().parse().ok(),
+            DisplayMode::Ascii => match s.as_bytes() {
+                &[c] if c.is_ascii() && !c.is_ascii_control() => Some(c),
+                _ => None,
+            },
+        }
+    }
+}
 
 /// View containing an hexadecimal dump of a selectable memory region.
 pub struct MemoryView {
     section: dbg::MemoryType,
     buffer: MemoryBuffer,
+    mode: DisplayMode,
+
+    selected_addr: String,
+    new_value: String,
 
     search_string: String,
     matched_ranges: Vec<Range<usize>>,
     highlighted_line_id: Option<usize>,
+
+    goto_addr: String,
+    scroll_to_line: Option<usize>,
+
+    bookmarks: Vec<Bookmark>,
+    new_bookmark: (String, String),
+
+    scan: Option<MemoryScan>,
+    scan_width: ScanWidth,
+    scan_value: String,
 }
 
 impl Default for MemoryView {
@@ -21,10 +71,24 @@ impl Default for MemoryView {
         MemoryView {
             section: dbg::MemoryType::RomBank(0),
             buffer: MemoryBuffer::with_capacity(256 * max_bank_size),
+            mode: DisplayMode::default(),
+
+            selected_addr: String::with_capacity(8),
+            new_value: String::with_capacity(8),
 
             search_string: String::with_capacity(128),
             matched_ranges: Vec::with_capacity(max_bank_size),
             highlighted_line_id: None,
+
+            goto_addr: String::with_capacity(8),
+            scroll_to_line: None,
+
+            bookmarks: Config::load().bookmarks,
+            new_bookmark: Default::default(),
+
+            scan: None,
+            scan_width: ScanWidth::U8,
+            scan_value: String::with_capacity(8),
         }
     }
 }
@@ -50,7 +114,7 @@ impl super::View for MemoryView {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
         // Refresh automatically the first time
         if self.buffer.as_ref().is_empty() {
-            self.buffer.refresh(self.section, state);
+            self.buffer.refresh(self.section, self.mode, state);
         }
 
         let find_next = self.toolbar_ui(ui, state);
@@ -60,6 +124,14 @@ impl super::View for MemoryView {
 
         ui.separator();
 
+        self.inspect_ui(ui, state);
+        self.bookmarks_ui(ui, state);
+        self.scan_ui(ui, state);
+
+        ui.separator();
+
+        let bookmarked_rows = self.bookmarked_rows();
+
         let mut layouter = |ui: &egui::Ui, s: &str, wrap_width: f32| {
             use egui::{
                 text::{LayoutJob, TextFormat},
@@ -74,19 +146,36 @@ impl super::View for MemoryView {
                 background: Color32::YELLOW,
                 ..Default::default()
             };
+            let bookmark = TextFormat {
+                font_id: FONT,
+                color: Color32::WHITE,
+                background: Color32::from_rgb(30, 60, 110),
+                ..Default::default()
+            };
+
+            // Assign a format to each character, with search matches taking precedence
+            // over bookmarked rows
+            let mut formats = vec![&simple; s.len()];
+            for rng in &bookmarked_rows {
+                formats[rng.start.min(s.len())..rng.end.min(s.len())].fill(&bookmark);
+            }
+            for rng in &self.matched_ranges {
+                formats[rng.start.min(s.len())..rng.end.min(s.len())].fill(&highlight);
+            }
 
             let mut layout_job = LayoutJob::default();
 
-            if self.matched_ranges.is_empty() {
-                layout_job.append(s, 0., simple);
-            } else {
-                let mut cursor_pos = 0;
-                for rng in &self.matched_ranges {
-                    layout_job.append(&s[cursor_pos..rng.start], 0., simple.clone());
-                    layout_job.append(&s[rng.clone()], 0., highlight.clone());
-                    cursor_pos = rng.end;
-                }
-                layout_job.append(&s[cursor_pos..], 0., simple);
+            // Append runs of characters sharing the same format
+            let mut cursor_pos = 0;
+            while cursor_pos < formats.len() {
+                let format = formats[cursor_pos];
+                let end = formats[cursor_pos..]
+                    .iter()
+                    .position(|f| !std::ptr::eq(*f, format))
+                    .map_or(formats.len(), |len| cursor_pos + len);
+
+                layout_job.append(&s[cursor_pos..end], 0., format.clone());
+                cursor_pos = end;
             }
 
             layout_job.wrap.max_width = wrap_width;
@@ -108,10 +197,13 @@ impl super::View for MemoryView {
                     )
                     .rect;
 
-                // Scroll to the next occurrence
-                if let Some(i) = find_next.then_some(self.highlighted_line_id).flatten() {
-                    let rng = &self.matched_ranges[i];
-                    let line = rng.start / self.buffer.line_len;
+                // Scroll to the next occurrence, or to the requested address
+                let scroll_to_line = match find_next.then_some(self.highlighted_line_id).flatten() {
+                    Some(i) => Some(self.buffer.row_of(self.matched_ranges[i].start)),
+                    None => self.scroll_to_line.take(),
+                };
+
+                if let Some(line) = scroll_to_line {
                     let line_height = rect.height() / self.buffer.lines as f32;
 
                     let y_start = rect.y_range().start() + line as f32 * line_height;
@@ -147,6 +239,238 @@ impl MemoryView {
         }
     }
 
+    /// Switches to the memory region containing `addr` and scrolls to its row.
+    fn goto(&mut self, addr: u16, state: &Emulator) {
+        let section = state.gameboy().memory_type_at(addr);
+
+        if section != self.section {
+            self.section = section;
+            self.buffer.refresh(self.section, self.mode, state);
+            self.find_search_pattern();
+        }
+
+        self.selected_addr = format!("{addr:04X}");
+        self.scroll_to_line = Some(usize::from(addr - *self.section.range().start()) / 16);
+    }
+
+    /// Draws the interpretation panel, showing the value at the selected address as different
+    /// types, along with a field to overwrite it in the current display base.
+    fn inspect_ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        egui::CollapsingHeader::new("Inspect").show(ui, |ui| {
+            utils::address_edit_ui(ui, "Address", &mut self.selected_addr, true);
+
+            let addr = match utils::parse_addr(&self.selected_addr) {
+                Some(addr) => addr,
+                None => return,
+            };
+
+            let data = state.gameboy().read_range(addr, 2);
+            let bytes = [data[0], data.get(1).copied().unwrap_or_default()];
+
+            egui::Grid::new("memedit-inspect").show(ui, |ui| {
+                for (name, value) in interpret(bytes) {
+                    ui.label(name);
+                    ui.monospace(value);
+                    ui.end_row();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Value");
+
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.new_value)
+                        .hint_text(self.mode.format(bytes[0]).trim())
+                        .desired_width(37.),
+                );
+                let submitted =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+                let value = self.mode.parse(&self.new_value);
+                if ui
+                    .add_enabled(value.is_some(), egui::Button::new("Write"))
+                    .clicked()
+                    || (submitted && value.is_some())
+                {
+                    state.gameboy_mut().poke(addr, value.unwrap());
+                    self.new_value.clear();
+                    self.buffer.refresh(self.section, self.mode, state);
+                }
+            });
+        });
+    }
+
+    /// Returns the ranges of the buffer rows containing a bookmarked address.
+    fn bookmarked_rows(&self) -> Vec<Range<usize>> {
+        let range = self.section.range();
+
+        self.bookmarks
+            .iter()
+            .filter(|b| range.contains(&b.addr))
+            .map(|b| {
+                let row = usize::from(b.addr - *range.start()) / 16;
+                self.buffer.row_range(row)
+            })
+            .collect()
+    }
+
+    /// Saves the bookmarks to the configuration file.
+    fn save_bookmarks(&self) {
+        let bookmarks = self.bookmarks.clone();
+
+        if let Err(e) = Config::update(|cfg| cfg.bookmarks = bookmarks) {
+            tracing::warn!(%e, "Failed to save bookmarks");
+        }
+    }
+
+    /// Draws the list of bookmarks, along with the controls to add new ones.
+    fn bookmarks_ui(&mut self, ui: &mut egui::Ui, state: &Emulator) {
+        egui::CollapsingHeader::new("Bookmarks").show(ui, |ui| {
+            let mut goto = None;
+            let mut remove = None;
+
+            for (i, bookmark) in self.bookmarks.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button(format!("{:04X}", bookmark.addr)).clicked() {
+                        goto = Some(bookmark.addr);
+                    }
+                    ui.label(&bookmark.name);
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("x").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                });
+            }
+
+            ui.horizontal(|ui| {
+                let (name, addr) = &mut self.new_bookmark;
+
+                ui.add(
+                    egui::TextEdit::singleline(name)
+                        .hint_text("Name")
+                        .desired_width(150.),
+                );
+                ui.add(
+                    egui::TextEdit::singleline(addr)
+                        .hint_text("Address")
+                        .desired_width(50.),
+                );
+
+                let addr = utils::parse_addr(addr);
+
+                if ui
+                    .add_enabled(
+                        addr.is_some() && !name.trim().is_empty(),
+                        egui::Button::new("Add"),
+                    )
+                    .clicked()
+                {
+                    self.bookmarks.push(Bookmark {
+                        name: name.trim().to_owned(),
+                        addr: addr.unwrap(),
+                    });
+                    self.new_bookmark = Default::default();
+                    self.save_bookmarks();
+                }
+            });
+
+            if let Some(i) = remove {
+                self.bookmarks.remove(i);
+                self.save_bookmarks();
+            }
+            if let Some(addr) = goto {
+                self.goto(addr, state);
+            }
+        });
+    }
+
+    /// Draws the memory scan panel, used to look for addresses holding a given value.
+    ///
+    /// New scans start from the currently selected memory region, and keep scanning
+    /// that region even if a different section is selected afterwards.
+    fn scan_ui(&mut self, ui: &mut egui::Ui, state: &Emulator) {
+        /// Maximum number of candidates listed in the panel
+        const MAX_LISTED: usize = 64;
+
+        egui::CollapsingHeader::new("Scan").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("memedit-scan-width")
+                    .width(50.)
+                    .selected_text(format!("{:?}", self.scan_width))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.scan_width, ScanWidth::U8, "U8");
+                        ui.selectable_value(&mut self.scan_width, ScanWidth::U16, "U16");
+                    });
+
+                ui.add(egui::TextEdit::singleline(&mut self.scan_value).desired_width(50.));
+
+                let value = parse_value(&self.scan_value);
+
+                if ui
+                    .add_enabled(value.is_some(), egui::Button::new("New scan"))
+                    .clicked()
+                {
+                    let range = self.section.range();
+                    let len = usize::from(*range.end() - *range.start()) + 1;
+
+                    self.scan = Some(MemoryScan::new(
+                        self.scan_width,
+                        *range.start(),
+                        state.gameboy().read_range(*range.start(), len),
+                        value.unwrap(),
+                    ));
+                }
+
+                if let Some(ref mut scan) = self.scan {
+                    let mut filters = vec![
+                        ("Changed", ScanFilter::Changed),
+                        ("Unchanged", ScanFilter::Unchanged),
+                        ("Increased", ScanFilter::Increased),
+                        ("Decreased", ScanFilter::Decreased),
+                    ];
+                    if let Some(v) = value {
+                        filters.insert(0, ("Equal", ScanFilter::Equal(v)));
+                    }
+
+                    for (label, filter) in filters {
+                        if ui.button(label).clicked() {
+                            // Always re-read the region the scan was started on, which might
+                            // differ from the currently selected section
+                            let snapshot = state.gameboy().read_range(scan.base(), scan.size());
+                            if let Err(e) = scan.filter(snapshot, filter) {
+                                tracing::error!(%e, "Memory scan failed");
+                            }
+                        }
+                    }
+
+                    if ui.button("Clear").clicked() {
+                        self.scan = None;
+                    }
+                }
+            });
+
+            if let Some(ref scan) = self.scan {
+                let candidates = scan.candidates();
+
+                ui.label(format!("{} candidate(s)", candidates.len()));
+
+                egui::ScrollArea::vertical()
+                    .id_source("memedit-scan-results")
+                    .max_height(100.)
+                    .show(ui, |ui| {
+                        for &addr in candidates.iter().take(MAX_LISTED) {
+                            ui.monospace(format!("{addr:04X}: {}", scan.value(addr)));
+                        }
+                        if candidates.len() > MAX_LISTED {
+                            ui.label(egui::RichText::new("...").weak());
+                        }
+                    });
+            }
+        });
+    }
+
     /// Cycles to the next occurrence of the search pattern in the search results.
     fn find_next_match(&mut self) {
         self.highlighted_line_id = match self.highlighted_line_id {
@@ -165,27 +489,38 @@ impl MemoryView {
     ///
     /// Returns whether the "Find next match" button was pressed.
     fn toolbar_ui(&mut self, ui: &mut egui::Ui, state: &Emulator) -> bool {
-        use dbg::MemoryType::*;
-
         ui.horizontal(|ui| {
-            for (label, region) in [
-                ("ROM00", RomBank(0)),
-                ("ROM01", RomBank(1)),
-                ("VRAM", VideoRam),
-                ("ERAM", ExternalRam),
-                ("WRAM00", WorkRamBank(0)),
-                ("WRAM01", WorkRamBank(1)),
-                ("HRAM", HighRam),
-            ]
-            .into_iter()
-            {
-                if ui.button(label).clicked() {
+            // Switchable regions are labeled with the bank currently mapped
+            for addr in [0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xFF80] {
+                let region = state.gameboy().memory_type_at(addr);
+
+                if ui.button(region.to_string()).clicked() {
                     self.section = region;
-                    self.buffer.refresh(self.section, state);
+                    self.buffer.refresh(self.section, self.mode, state);
                     self.find_search_pattern();
                 }
             }
 
+            let mode = self.mode;
+            egui::ComboBox::from_id_source("memedit-display-mode")
+                .width(60.)
+                .selected_text(format!("{:?}", self.mode))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.mode, DisplayMode::Hex, "Hex");
+                    ui.selectable_value(&mut self.mode, DisplayMode::Decimal, "Decimal");
+                    ui.selectable_value(&mut self.mode, DisplayMode::Ascii, "Ascii");
+                });
+            if self.mode != mode {
+                self.buffer.refresh(self.section, self.mode, state);
+                self.find_search_pattern();
+            }
+
+            if utils::address_edit_ui(ui, "Goto", &mut self.goto_addr, true) {
+                if let Some(addr) = utils::parse_addr(&self.goto_addr) {
+                    self.goto(addr, state);
+                }
+            }
+
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                 let find_next = ui.button(">").clicked();
 
@@ -205,6 +540,29 @@ impl MemoryView {
     }
 }
 
+/// Interprets the two bytes at an address as unsigned and signed 8 and 16-bit values,
+/// the latter in little-endian order.
+fn interpret(bytes: [u8; 2]) -> [(&'static str, String); 4] {
+    let word = u16::from_le_bytes(bytes);
+
+    [
+        ("u8", format!("{0} (0x{0:02X})", bytes[0])),
+        ("i8", format!("{}", bytes[0] as i8)),
+        ("u16 LE", format!("{word} (0x{word:04X})")),
+        ("i16 LE", format!("{}", word as i16)),
+    ]
+}
+
+/// Parses a scan value, either in decimal or in hexadecimal if prefixed by `0x`.
+fn parse_value(s: &str) -> Option<u16> {
+    let s = s.trim();
+
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
 struct MemoryBuffer {
     contents: String,
     line_len: usize,
@@ -239,8 +597,8 @@ impl MemoryBuffer {
     }
 
     /// Rebuilds the buffer contents, by reading and rasterizing the whole memory section.
-    fn refresh(&mut self, section: dbg::MemoryType, state: &Emulator) {
-        let bus = state.bus();
+    fn refresh(&mut self, section: dbg::MemoryType, mode: DisplayMode, state: &Emulator) {
+        let gameboy = state.gameboy();
 
         let (mut ptr, end): (u32, u32) = {
             let mem_range = section.range();
@@ -251,24 +609,26 @@ impl MemoryBuffer {
         };
 
         self.contents.clear();
+        self.line_len = 0;
+        self.lines = 0;
 
         while ptr < end {
             let mut data = [0u8; 16];
 
             for addr in ptr..(ptr + 16).min(end) {
-                match bus.read(addr as u16) {
-                    Ok(b) => data[(addr - ptr) as usize] = b,
-                    Err(e) => panic!("unexpected trace event during memory access: {}", e),
-                };
+                data[(addr - ptr) as usize] = gameboy.peek(addr as u16);
             }
 
             // Eg: "0xFF00:  00 01 02 03 04 05  |...123|"
             let mut content = format!("{:04X}:  ", ptr);
-            for d in data.iter() {
-                write!(content, "{d:02X} ").unwrap();
+            for &d in data.iter() {
+                write!(content, "{} ", mode.format(d)).unwrap();
+            }
+            // The ASCII column would only repeat the bytes in ASCII mode
+            if mode != DisplayMode::Ascii {
+                content.push(' ');
+                content.push_str(&utils::format_ascii(&data));
             }
-            content.push(' ');
-            content.push_str(&utils::format_ascii(&data));
             content.push('\n');
 
             self.contents.push_str(&content);
@@ -279,4 +639,62 @@ impl MemoryBuffer {
             ptr += 16;
         }
     }
+
+    /// Returns the row containing the character at `offset` in the buffer.
+    fn row_of(&self, offset: usize) -> usize {
+        offset / self.line_len
+    }
+
+    /// Returns the range of characters making up `row` in the buffer.
+    fn row_range(&self, row: usize) -> Range<usize> {
+        row * self.line_len..(row + 1) * self.line_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_interpretation() {
+        let values = interpret([0xFE, 0xFF]);
+
+        assert_eq!(
+            values.map(|(_, v)| v),
+            ["254 (0xFE)", "-2", "65534 (0xFFFE)", "-2"]
+        );
+        assert_eq!(interpret([0x34, 0x12])[2].1, "4660 (0x1234)");
+        assert_eq!(interpret([0x34, 0x12])[3].1, "4660");
+    }
+
+    #[test]
+    fn display_modes() {
+        for mode in [DisplayMode::Hex, DisplayMode::Decimal, DisplayMode::Ascii] {
+            assert_eq!(mode.parse(mode.format(b'A').trim()), Some(b'A'));
+        }
+        assert_eq!(DisplayMode::Hex.format(0x41), "41");
+        assert_eq!(DisplayMode::Decimal.format(0x41), " 65");
+        assert_eq!(DisplayMode::Ascii.format(0x0A), " .");
+        assert_eq!(DisplayMode::Decimal.parse("256"), None);
+    }
+
+    #[test]
+    fn rows_after_mode_change() {
+        let emu = Emulator::default();
+        let section = dbg::MemoryType::WorkRamBank(0);
+        let mut buffer = MemoryBuffer::with_capacity(0);
+
+        // Hex rows are longer than ASCII ones
+        buffer.refresh(section, DisplayMode::Hex, &emu);
+        let hex_len = buffer.line_len;
+
+        for mode in [DisplayMode::Ascii, DisplayMode::Hex, DisplayMode::Ascii] {
+            buffer.refresh(section, mode, &emu);
+
+            let offset = buffer.contents.find("C120:").unwrap();
+            assert_eq!(buffer.row_of(offset), 0x12);
+            assert_eq!(&buffer.contents[buffer.row_range(0x12)][..5], "C120:");
+        }
+        assert!(buffer.line_len < hex_len);
+    }
 }
@@ -41,15 +41,27 @@ impl super::Window for MemoryMap {
 impl super::View for MemoryMap {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
         let pc = state.cpu().pc;
+        let gb = state.gameboy();
 
         for (mt, s) in &self.map {
-            let color = if MemoryType::at(pc) == *mt {
+            let color = if mt.range().contains(&pc) {
                 Color32::GREEN
             } else {
                 Color32::WHITE
             };
 
-            ui.colored_label(color, s);
+            // Show which bank is mapped in the switchable regions
+            let bank = match mt {
+                MemoryType::RomBank(n) if *n > 0 => Some(gb.current_rom_bank()),
+                MemoryType::ExternalRam => Some(gb.current_ram_bank().into()),
+                MemoryType::WorkRamBank(n) if *n > 0 => Some(state.bus().wram_bank() as u16),
+                _ => None,
+            };
+
+            match bank {
+                Some(bank) => ui.colored_label(color, format!("{s}    [bank {bank:02X}]")),
+                None => ui.colored_label(color, s),
+            };
         }
     }
 }
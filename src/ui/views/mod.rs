@@ -2,11 +2,19 @@ use std::collections::BTreeSet;
 
 use crate::ui::state::Emulator;
 
+pub mod audio;
+pub mod breakpoints;
+pub mod callstack;
+pub mod cartridge;
 pub mod debugger;
 pub mod disassembly;
+pub mod interrupts;
+pub mod keybindings;
 pub mod memedit;
 pub mod memmap;
+pub mod opcodes;
 pub mod peripherals;
+pub mod tiles;
 
 pub trait View {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator);
@@ -28,11 +36,20 @@ pub struct WindowManager {
 impl Default for WindowManager {
     fn default() -> Self {
         let windows: Vec<Box<dyn Window>> = vec![
+            Box::<audio::AudioScope>::default(),
+            Box::<breakpoints::Breakpoints>::default(),
+            Box::<callstack::CallStack>::default(),
+            Box::<callstack::CallProfiler>::default(),
+            Box::<cartridge::CartridgeInfo>::default(),
             Box::<debugger::Debugger>::default(),
             Box::<disassembly::Disassembly>::default(),
+            Box::<interrupts::InterruptLog>::default(),
             Box::<memedit::MemoryView>::default(),
             Box::<memmap::MemoryMap>::default(),
+            Box::<opcodes::FindOpcode>::default(),
+            Box::<opcodes::OpcodeProfiler>::default(),
             Box::<peripherals::Peripherals>::default(),
+            Box::<tiles::TileViewer>::default(),
         ];
         let open = BTreeSet::from_iter(windows.iter().map(|w| w.name().to_owned()));
 
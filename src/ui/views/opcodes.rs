@@ -0,0 +1,153 @@
+use std::cmp::Reverse;
+
+use gib_core::cpu;
+
+use crate::ui::{state::Emulator, utils};
+
+/// Number of opcodes listed by the profiler window.
+const PROFILER_TOP_N: usize = 16;
+
+/// Window listing the occurrences of an instruction in the mapped ROM and RAM.
+#[derive(Default)]
+pub struct FindOpcode {
+    opcode: String,
+    hits: Vec<u16>,
+}
+
+impl super::Window for FindOpcode {
+    fn name(&self) -> &'static str {
+        "Find Opcode"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([900.0, 350.0])
+            .default_size([220.0, 300.0])
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for FindOpcode {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        let mut selected = None;
+
+        ui.horizontal(|ui| {
+            // CB-prefixed opcodes are entered as CBxx
+            let submitted = utils::address_edit_ui(ui, "Opcode", &mut self.opcode, true);
+
+            let opcode =
+                utils::parse_addr(&self.opcode).filter(|&op| op <= 0xFF || op >> 8 == 0xCB);
+
+            if ui
+                .add_enabled(opcode.is_some(), egui::Button::new("Find"))
+                .clicked()
+                || submitted
+            {
+                if let Some(opcode) = opcode {
+                    self.hits = cpu::find_opcodes(state.bus(), opcode);
+                }
+            }
+        });
+
+        ui.label(format!("{} occurrence(s)", self.hits.len()));
+        ui.separator();
+
+        let row_height = ui.spacing().interact_size.y;
+
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height())
+            .auto_shrink([false; 2])
+            .show_rows(ui, row_height, self.hits.len(), |ui, row_range| {
+                let symbols = state.gameboy().symbols();
+
+                for &addr in &self.hits[row_range] {
+                    let text = match symbols.label(addr) {
+                        Some(label) => format!("{addr:04X}  {label}"),
+                        None => format!("{addr:04X}"),
+                    };
+
+                    if ui.selectable_label(false, text).clicked() {
+                        selected = Some(addr);
+                    }
+                }
+            });
+
+        if let Some(addr) = selected {
+            state.show_in_disassembly(addr);
+        }
+    }
+}
+
+/// Window listing the most executed opcodes, as counted by the opcode profiler.
+#[derive(Default)]
+pub struct OpcodeProfiler;
+
+impl super::Window for OpcodeProfiler {
+    fn name(&self) -> &'static str {
+        "Opcode Profiler"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([900.0, 680.0])
+            .default_size([260.0, 300.0])
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for OpcodeProfiler {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        ui.horizontal(|ui| {
+            let mut enabled = state.gameboy().profiling();
+            if ui.checkbox(&mut enabled, "Enabled").changed() {
+                state.gameboy_mut().set_profiling(enabled);
+            }
+
+            if ui.button("Reset").clicked() {
+                state.gameboy_mut().reset_opcode_histogram();
+            }
+        });
+
+        ui.separator();
+
+        // The 0xCB prefix is left out in favor of the actual CB-prefixed opcodes
+        let gb = state.gameboy();
+        let mut counts =
+            gb.opcode_histogram()
+                .iter()
+                .enumerate()
+                .filter(|&(op, _)| op != 0xCB)
+                .map(|(op, &n)| (format!("{op:02X}"), cpu::opcode_info(op as u8).0, n))
+                .chain(
+                    gb.cb_opcode_histogram().iter().enumerate().map(|(op, &n)| {
+                        (format!("CB{op:02X}"), cpu::cb_opcode_info(op as u8).0, n)
+                    }),
+                )
+                .filter(|&(_, _, n)| n > 0)
+                .collect::<Vec<_>>();
+
+        let total = counts.iter().map(|&(_, _, n)| n).sum::<u64>();
+        counts.sort_by_key(|&(_, _, n)| Reverse(n));
+
+        egui::Grid::new("opcode-profiler")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                for (op, mnemonic, n) in counts.into_iter().take(PROFILER_TOP_N) {
+                    ui.monospace(op);
+                    ui.monospace(mnemonic);
+                    ui.monospace(format!("{n:10}"));
+                    ui.monospace(format!("{:5.1}%", n as f64 * 100.0 / total as f64));
+                    ui.end_row();
+                }
+            });
+    }
+}
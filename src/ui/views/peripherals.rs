@@ -1,4 +1,5 @@
 use egui::Color32;
+use gib_core::io::{JoypadState, PaletteRegister};
 
 use crate::ui::{state::Emulator, utils};
 
@@ -25,7 +26,7 @@ impl super::Window for Peripherals {
 impl super::View for Peripherals {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
         egui::CollapsingHeader::new("Video Display").show(ui, |ui| {
-            ui.label("NOT IMPLEMENTED YET!");
+            self.palette_history_ui(ui, state);
         });
 
         egui::CollapsingHeader::new("Sound Controller")
@@ -35,7 +36,7 @@ impl super::View for Peripherals {
             });
 
         egui::CollapsingHeader::new("Joypad Input").show(ui, |ui| {
-            ui.label("NOT IMPLEMENTED YET!");
+            self.joypad_ui(ui, state);
         });
 
         egui::CollapsingHeader::new("Link Cable").show(ui, |ui| {
@@ -127,6 +128,105 @@ impl Peripherals {
         ui.separator();
 
         ui.label("Noise Channel");
+
+        ui.separator();
+
+        let stats = state.gameboy().audio_stats().unwrap_or_default();
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Overruns: {:8}", stats.overruns));
+            ui.add_space(20.0);
+            ui.label(format!("Underruns: {:8}", stats.underruns));
+        });
+    }
+
+    fn palette_history_ui(&self, ui: &mut egui::Ui, state: &Emulator) {
+        let palette = state.gameboy().palette();
+
+        ui.label("Palette History");
+
+        egui::ScrollArea::vertical()
+            .max_height(150.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("palette-history")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for change in state.gameboy().palette_history() {
+                            let reg = match change.register {
+                                PaletteRegister::Bgp => "BGP",
+                                PaletteRegister::Obp0 => "OBP0",
+                                PaletteRegister::Obp1 => "OBP1",
+                            };
+
+                            ui.label(format!("Frame {:6}", change.frame));
+                            ui.label(format!("LY {:3}", change.line));
+                            ui.label(format!("{reg:4} = {:02X}", change.value));
+
+                            // Show the shade mapped to each color number
+                            ui.horizontal(|ui| {
+                                for i in 0..4 {
+                                    let [r, g, b] =
+                                        palette.0[usize::from(change.value >> (i * 2) & 0x3)];
+                                    ui.colored_label(Color32::from_rgb(r, g, b), "■");
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    fn joypad_ui(&self, ui: &mut egui::Ui, state: &Emulator) {
+        let joy = &state.bus().joy;
+        let pressed = state.gameboy().joypad_state();
+
+        let lines = [
+            (
+                joy.directions_selected(),
+                "DIRS:",
+                [
+                    (JoypadState::UP, "UP"),
+                    (JoypadState::DOWN, "DOWN"),
+                    (JoypadState::LEFT, "LEFT"),
+                    (JoypadState::RIGHT, "RIGHT"),
+                ],
+            ),
+            (
+                joy.buttons_selected(),
+                "BTNS:",
+                [
+                    (JoypadState::A, "A"),
+                    (JoypadState::B, "B"),
+                    (JoypadState::SELECT, "SELECT"),
+                    (JoypadState::START, "START"),
+                ],
+            ),
+        ];
+
+        for (selected, name, keys) in lines {
+            ui.horizontal(|ui| {
+                // The label of the lines selected by the game is highlighted
+                if selected {
+                    ui.colored_label(Color32::GREEN, name);
+                } else {
+                    ui.label(name);
+                }
+
+                for (key, s) in keys {
+                    ui.add_space(15.0);
+                    ui.colored_label(
+                        if pressed.contains(key) {
+                            Color32::GREEN
+                        } else {
+                            Color32::DARK_GREEN
+                        },
+                        s,
+                    );
+                }
+            });
+        }
     }
 
     fn timers_ui(&self, ui: &mut egui::Ui, state: &Emulator) {
@@ -0,0 +1,95 @@
+use std::fs;
+
+use gib_core::io::TILESHEET_SIZE;
+
+use crate::ui::state::Emulator;
+
+/// Window showing the tiles in VRAM, which can also be exported and imported.
+#[derive(Default)]
+pub struct TileViewer {
+    texture: Option<egui::TextureHandle>,
+}
+
+impl super::Window for TileViewer {
+    fn name(&self) -> &'static str {
+        "Tile Data"
+    }
+
+    fn show(&mut self, ctx: &egui::Context, state: &mut Emulator, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_pos([1235.0, 30.0])
+            .resizable(false)
+            .show(ctx, |ui| {
+                use super::View;
+                self.ui(ui, state);
+            });
+    }
+}
+
+impl super::View for TileViewer {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut Emulator) {
+        ui.horizontal(|ui| {
+            if ui.button("Export PNG...").clicked() {
+                export_png(state);
+            }
+
+            if ui.button("Export 2bpp...").clicked() {
+                let dialog = rfd::FileDialog::new().set_file_name("tiles.2bpp");
+
+                if let Some(path) = dialog.save_file() {
+                    if let Err(e) = fs::write(&path, state.gameboy().export_tiles()) {
+                        tracing::error!(%e, path = %path.display(), "Could not export tiles");
+                    }
+                }
+            }
+
+            if ui.button("Import 2bpp...").clicked() {
+                let dialog = rfd::FileDialog::new().add_filter("2bpp", &["2bpp", "bin"]);
+
+                if let Some(path) = dialog.pick_file() {
+                    match fs::read(&path) {
+                        Ok(data) => state.gameboy_mut().import_tiles(&data),
+                        Err(e) => {
+                            tracing::error!(%e, path = %path.display(), "Could not import tiles")
+                        }
+                    }
+                }
+            }
+        });
+
+        ui.separator();
+
+        let (width, height) = TILESHEET_SIZE;
+        let image =
+            egui::ColorImage::from_rgba_unmultiplied([width, height], &state.gameboy().tilesheet());
+
+        let texture = self.texture.get_or_insert_with(|| {
+            ui.ctx()
+                .load_texture("tile-data", image.clone(), egui::TextureOptions::NEAREST)
+        });
+        texture.set(image, egui::TextureOptions::NEAREST);
+
+        ui.image(&*texture, texture.size_vec2() * 2.);
+    }
+}
+
+/// Saves the tilesheet to a PNG file chosen by the user.
+fn export_png(state: &Emulator) {
+    let dialog = rfd::FileDialog::new().set_file_name("tiles.png");
+
+    if let Some(path) = dialog.save_file() {
+        let (width, height) = TILESHEET_SIZE;
+
+        let result = image::save_buffer(
+            &path,
+            &state.gameboy().tilesheet(),
+            width as u32,
+            height as u32,
+            image::ColorType::Rgba8,
+        );
+        if let Err(e) = result {
+            tracing::error!(%e, path = %path.display(), "Could not export tiles");
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use std::fs;
+use std::{env, fs, path::PathBuf};
 
 use gib_core::GameBoy;
 
+mod common;
+
 macro_rules! test_cases {
     (
         $(
@@ -55,8 +57,12 @@ fn run_test(name: &str, seconds: u64) {
         gameboy.run_for_vblank().expect("unexpected trace event");
     }
 
-    let mut buffer = vec![0xff; 160 * 144 * 4];
+    let mut buffer = vec![0xff; common::SCREEN_WIDTH * common::SCREEN_HEIGHT * 4];
     gameboy.rasterize(&mut buffer);
 
-    assert_eq!(buffer, image.to_rgba8().to_vec());
+    // Set GIB_FRAME_DIFF_DIR to get a side-by-side image of the mismatching frames
+    let diff_path = env::var_os("GIB_FRAME_DIFF_DIR")
+        .map(|dir| PathBuf::from(dir).join(format!("{}.png", name.replace('/', "_"))));
+
+    common::assert_frames_match(&image.to_rgba8(), &buffer, diff_path.as_deref());
 }
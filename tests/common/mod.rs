@@ -0,0 +1,240 @@
+// Not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::{fmt, fs, path::Path};
+
+use gib_core::GameBoy;
+
+/// Width of the Game Boy screen, in pixels.
+pub const SCREEN_WIDTH: usize = 160;
+
+/// Height of the Game Boy screen, in pixels.
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// First pixel differing between two RGBA framebuffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelDiff {
+    pub x: usize,
+    pub y: usize,
+    pub expected: [u8; 4],
+    pub actual: [u8; 4],
+}
+
+impl fmt::Display for PixelDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "first mismatch at ({}, {}): expected {:02X?}, got {:02X?}",
+            self.x, self.y, self.expected, self.actual
+        )
+    }
+}
+
+/// Compares two RGBA framebuffers of `width` pixels per row, returning the first pixel
+/// (in row-major order) that differs between them, if any.
+pub fn diff_frames(expected: &[u8], actual: &[u8], width: usize) -> Option<PixelDiff> {
+    assert_eq!(expected.len(), actual.len(), "framebuffer sizes differ");
+
+    expected
+        .chunks_exact(4)
+        .zip(actual.chunks_exact(4))
+        .position(|(e, a)| e != a)
+        .map(|i| PixelDiff {
+            x: i % width,
+            y: i / width,
+            expected: expected[i * 4..i * 4 + 4].try_into().unwrap(),
+            actual: actual[i * 4..i * 4 + 4].try_into().unwrap(),
+        })
+}
+
+/// Panics with the location of the first mismatch if the two screen-sized framebuffers differ.
+///
+/// If `diff_path` is set, a PNG showing the expected frame, the actual one and the differing
+/// pixels (in red) side by side is written there before panicking.
+pub fn assert_frames_match(expected: &[u8], actual: &[u8], diff_path: Option<&Path>) {
+    let diff = match diff_frames(expected, actual, SCREEN_WIDTH) {
+        Some(diff) => diff,
+        None => return,
+    };
+
+    if let Some(path) = diff_path {
+        let mut img = image::RgbaImage::new(SCREEN_WIDTH as u32 * 3, SCREEN_HEIGHT as u32);
+
+        for (i, (e, a)) in expected
+            .chunks_exact(4)
+            .zip(actual.chunks_exact(4))
+            .enumerate()
+        {
+            let (x, y) = ((i % SCREEN_WIDTH) as u32, (i / SCREEN_WIDTH) as u32);
+            let mask = if e == a {
+                [0x00, 0x00, 0x00, 0xFF]
+            } else {
+                [0xFF, 0x00, 0x00, 0xFF]
+            };
+
+            img.put_pixel(x, y, image::Rgba(e.try_into().unwrap()));
+            img.put_pixel(
+                x + SCREEN_WIDTH as u32,
+                y,
+                image::Rgba(a.try_into().unwrap()),
+            );
+            img.put_pixel(x + 2 * SCREEN_WIDTH as u32, y, image::Rgba(mask));
+        }
+
+        match img.save(path) {
+            Ok(()) => eprintln!("frame diff written to {}", path.display()),
+            Err(e) => eprintln!("failed to write frame diff to {}: {e}", path.display()),
+        }
+    }
+
+    panic!("output buffers not matching: {diff}");
+}
+
+/// Default amount of emulated time given to a test ROM, in seconds.
+pub const DEFAULT_BUDGET_SECONDS: u64 = 60;
+
+/// Headless runner for test ROMs, reporting through the serial port or the screen.
+///
+/// Since the emulation is deterministic, so are the captured output and the frame hashes.
+pub struct RomTest {
+    gameboy: GameBoy,
+    cycle_budget: u64,
+}
+
+impl RomTest {
+    /// Creates a runner for `rom`, with a budget of [`DEFAULT_BUDGET_SECONDS`].
+    pub fn new(rom: &[u8]) -> RomTest {
+        let mut gameboy = GameBoy::new();
+        gameboy.load_rom(rom).expect("failed to load test ROM");
+
+        RomTest {
+            gameboy,
+            cycle_budget: DEFAULT_BUDGET_SECONDS * gib_core::CPU_CLOCK,
+        }
+    }
+
+    /// Creates a runner for the ROM stored at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> RomTest {
+        RomTest::new(&fs::read(path).expect("failed to load test binary"))
+    }
+
+    /// Sets the number of clock cycles the ROM is allowed to run for.
+    pub fn with_cycle_budget(mut self, cycles: u64) -> RomTest {
+        self.cycle_budget = cycles;
+        self
+    }
+
+    pub fn gameboy(&mut self) -> &mut GameBoy {
+        &mut self.gameboy
+    }
+
+    /// Runs the ROM until `expected` appears in its serial output, or until the cycle budget
+    /// is exhausted. Returns the output captured so far in either case.
+    ///
+    /// Since the budget also caps the number of instructions executed, this returns even if
+    /// the ROM stops the emulation.
+    pub fn run_until_serial(&mut self, expected: &str) -> String {
+        let budget = self
+            .cycle_budget
+            .saturating_sub(self.gameboy.clock_cycles());
+
+        // Only search the output again when it changes
+        let mut checked_len = None;
+        let res = self.gameboy.run_until(
+            |gb| {
+                let output = gb.serial_output();
+                if checked_len == Some(output.len()) {
+                    return false;
+                }
+                checked_len = Some(output.len());
+                String::from_utf8_lossy(output).contains(expected)
+            },
+            budget,
+        );
+
+        match res {
+            Ok(_) | Err(gib_core::dbg::TraceEvent::Timeout(_)) => {
+                String::from_utf8_lossy(self.gameboy.serial_output()).into_owned()
+            }
+            Err(evt) => panic!("unexpected trace event: {evt}"),
+        }
+    }
+
+    /// Runs the ROM for `frames` frames.
+    pub fn run_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.gameboy
+                .run_for_vblank()
+                .expect("unexpected trace event");
+        }
+    }
+
+    /// Returns a hash of the current screen contents in RGBA format, stable across runs
+    /// and toolchain versions.
+    pub fn hash_frame(&self) -> u64 {
+        let mut buffer = vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        self.gameboy.rasterize(&mut buffer);
+
+        fnv1a(&buffer)
+    }
+}
+
+/// 64-bit FNV-1a hash, which unlike `DefaultHasher` is guaranteed not to change over time.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[test]
+fn rom_test_harness() {
+    // Sends "OK" over the serial port, then loops forever
+    #[rustfmt::skip]
+    let program = [
+        0x3E, b'O', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, // LD A,'O'; LDH (SB),A; LD A,$81; LDH (SC),A
+        0x3E, b'K', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, // LD A,'K'; LDH (SB),A; LD A,$81; LDH (SC),A
+        0x18, 0xFE,                                     // JR -2
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+
+    let mut test = RomTest::new(&rom).with_cycle_budget(gib_core::CPU_CLOCK);
+    assert_eq!(test.run_until_serial("OK"), "OK");
+
+    // Running out of budget returns whatever was captured
+    assert_eq!(test.run_until_serial("FAILED"), "OK");
+    assert!(test.gameboy().clock_cycles() >= gib_core::CPU_CLOCK);
+
+    // Nothing runs while the emulation is stopped, but the budget is still enforced
+    let mut stopped = RomTest::new(&rom).with_cycle_budget(gib_core::CPU_CLOCK);
+    stopped.gameboy().pause();
+    assert_eq!(stopped.run_until_serial("OK"), "");
+
+    // The same ROM always renders the same frames
+    let mut other = RomTest::new(&rom);
+    other.run_frames(60);
+    assert_eq!(test.hash_frame(), other.hash_frame());
+    assert_eq!(fnv1a(b"a"), 0xAF63_DC4C_8601_EC8C);
+}
+
+#[test]
+fn single_pixel_diff() {
+    let expected = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+    let mut actual = expected.clone();
+    assert_eq!(diff_frames(&expected, &actual, SCREEN_WIDTH), None);
+
+    let (x, y) = (17, 42);
+    let offset = (y * SCREEN_WIDTH + x) * 4;
+    actual[offset..offset + 3].fill(0x55);
+
+    assert_eq!(
+        diff_frames(&expected, &actual, SCREEN_WIDTH),
+        Some(PixelDiff {
+            x,
+            y,
+            expected: [0xFF; 4],
+            actual: [0x55, 0x55, 0x55, 0xFF],
+        })
+    );
+}
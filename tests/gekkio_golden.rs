@@ -26,7 +26,9 @@ test_cases! {
     boot_regs_dmg_abc("acceptance/boot_regs-dmgABC");
     boot_hwio_dmg_abc_mgb("acceptance/boot_hwio-dmgABCmgb");
     call_cc_timing("acceptance/call_cc_timing");
+    call_cc_timing2("acceptance/call_cc_timing2");
     call_timing("acceptance/call_timing");
+    call_timing2("acceptance/call_timing2");
     di_timing_gs("acceptance/di_timing-GS");
     div_timing("acceptance/div_timing");
     ei_sequence("acceptance/ei_sequence");
@@ -44,8 +46,13 @@ test_cases! {
     oam_dma_start("acceptance/oam_dma_start");
     oam_dma_timing("acceptance/oam_dma_timing");
     pop_timing("acceptance/pop_timing");
+    push_timing("acceptance/push_timing");
     rapid_di_ei("acceptance/rapid_di_ei");
+    ret_cc_timing("acceptance/ret_cc_timing");
+    ret_timing("acceptance/ret_timing");
     reti_intr_timing("acceptance/reti_intr_timing");
+    reti_timing("acceptance/reti_timing");
+    rst_timing("acceptance/rst_timing");
 
     bits_mem_oam("acceptance/bits/mem_oam");
     bits_reg_f("acceptance/bits/reg_f");